@@ -4,6 +4,9 @@ use bit_field::BitField;
 use bitflags::bitflags;
 use embedded_hal::{blocking::delay::DelayUs, digital::v2::OutputPin};
 
+mod tuning;
+pub use tuning::Tuning;
+
 /// A device driver for the AD9959 direct digital synthesis (DDS) chip.
 ///
 /// This chip provides four independently controllable digital-to-analog output sinusoids with
@@ -45,6 +48,7 @@ pub enum Mode {
 
 bitflags! {
     /// Specifies an output channel of the AD9959 DDS chip.
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
     pub struct Channel: u8 {
         const ONE   = 0b00010000;
         const TWO   = 0b00100000;
@@ -86,6 +90,87 @@ pub enum Register {
     CW15 = 0x18,
 }
 
+impl Register {
+    /// Get the width of the register in bytes.
+    pub fn width(&self) -> usize {
+        match self {
+            Register::CSR => 1,
+            Register::FR1 => 3,
+            Register::FR2 => 2,
+            Register::CFR => 3,
+            Register::CFTW0 => 4,
+            Register::CPOW0 => 2,
+            Register::ACR => 3,
+            Register::LSRR => 2,
+            Register::RDW => 4,
+            Register::FDW => 4,
+            // The channel word registers CW1 to CW15.
+            _ => 4,
+        }
+    }
+
+    /// Get a channel word register.
+    ///
+    /// # Args
+    /// * `index` - The index of the channel word register, from 1 to 15.
+    ///
+    /// # Returns
+    /// The register, or `None` if the index is out of range.
+    pub fn channel_word(index: usize) -> Option<Self> {
+        let register = match index {
+            1 => Register::CW1,
+            2 => Register::CW2,
+            3 => Register::CW3,
+            4 => Register::CW4,
+            5 => Register::CW5,
+            6 => Register::CW6,
+            7 => Register::CW7,
+            8 => Register::CW8,
+            9 => Register::CW9,
+            10 => Register::CW10,
+            11 => Register::CW11,
+            12 => Register::CW12,
+            13 => Register::CW13,
+            14 => Register::CW14,
+            15 => Register::CW15,
+            _ => return None,
+        };
+        Some(register)
+    }
+}
+
+/// The contents of the configuration registers of a single channel.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct ChannelRegisters {
+    /// The channel function register.
+    pub cfr: [u8; 3],
+
+    /// The channel frequency tuning word.
+    pub cftw0: u32,
+
+    /// The channel phase offset word.
+    pub cpow0: u16,
+
+    /// The amplitude control register.
+    pub acr: [u8; 3],
+}
+
+/// The contents of the AD9959 configuration registers.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct RegisterDump {
+    /// The channel select register.
+    pub csr: u8,
+
+    /// Function register 1.
+    pub fr1: [u8; 3],
+
+    /// Function register 2.
+    pub fr2: [u8; 2],
+
+    /// The configuration registers of channels 0 to 3.
+    pub channels: [ChannelRegisters; 4],
+}
+
 /// Possible errors generated by the AD9959 driver.
 #[derive(Debug)]
 pub enum Error {
@@ -110,7 +195,7 @@ impl<I: Interface> Ad9959<I> {
     ///   `clock_frequency` to generate the system clock.
     pub fn new(
         interface: I,
-        mut reset_pin: impl OutputPin,
+        reset_pin: &mut impl OutputPin,
         io_update: &mut impl OutputPin,
         delay: &mut impl DelayUs<u8>,
         desired_mode: Mode,
@@ -192,6 +277,10 @@ impl<I: Interface> Ad9959<I> {
     }
 
     fn read(&mut self, reg: Register, data: &mut [u8]) -> Result<(), Error> {
+        if data.len() != reg.width() {
+            return Err(Error::Bounds);
+        }
+
         self.interface
             .read(reg as u8, data)
             .or(Err(Error::Interface))
@@ -300,6 +389,12 @@ impl<I: Interface> Ad9959<I> {
         self.system_clock_multiplier as f32 * self.reference_clock_frequency
     }
 
+    /// Get the conversions between physical units and tuning registers for the current system
+    /// clock.
+    pub fn tuning(&self) -> Tuning {
+        Tuning::new(self.system_clock_frequency())
+    }
+
     /// Update an output channel configuration register.
     ///
     /// Args:
@@ -363,8 +458,8 @@ impl<I: Interface> Ad9959<I> {
         channel: Channel,
         phase_turns: f32,
     ) -> Result<f32, Error> {
-        let phase_offset: u16 =
-            (phase_turns * (1 << 14) as f32) as u16 & 0x3FFFu16;
+        let tuning = self.tuning();
+        let phase_offset = tuning.pow(phase_turns);
 
         self.modify_channel(
             channel,
@@ -372,7 +467,7 @@ impl<I: Interface> Ad9959<I> {
             &phase_offset.to_be_bytes(),
         )?;
 
-        Ok((phase_offset as f32) / ((1 << 14) as f32))
+        Ok(tuning.phase(phase_offset))
     }
 
     /// Get the current phase of a specified channel.
@@ -386,9 +481,7 @@ impl<I: Interface> Ad9959<I> {
         let mut phase_offset: [u8; 2] = [0; 2];
         self.read_channel(channel, Register::CPOW0, &mut phase_offset)?;
 
-        let phase_offset = u16::from_be_bytes(phase_offset) & 0x3FFFu16;
-
-        Ok((phase_offset as f32) / ((1 << 14) as f32))
+        Ok(self.tuning().phase(u16::from_be_bytes(phase_offset)))
     }
 
     /// Configure the amplitude of a specified channel.
@@ -408,25 +501,13 @@ impl<I: Interface> Ad9959<I> {
             return Err(Error::Bounds);
         }
 
-        let amplitude_control: u16 = (amplitude * (1 << 10) as f32) as u16;
-
-        let mut acr: [u8; 3] = [0; 3];
-
-        // Enable the amplitude multiplier for the channel if required. The amplitude control has
-        // full-scale at 0x3FF (amplitude of 1), so the multiplier should be disabled whenever
-        // full-scale is used.
-        if amplitude_control < (1 << 10) {
-            let masked_control = amplitude_control & 0x3FF;
-            acr[1] = masked_control.to_be_bytes()[0];
-            acr[2] = masked_control.to_be_bytes()[1];
-
-            // Enable the amplitude multiplier
-            acr[1].set_bit(4, true);
-        }
+        let tuning = self.tuning();
+        let amplitude_control = tuning.acr(amplitude);
+        let [high, low] = amplitude_control.to_be_bytes();
 
-        self.modify_channel(channel, Register::ACR, &acr)?;
+        self.modify_channel(channel, Register::ACR, &[0, high, low])?;
 
-        Ok(amplitude_control as f32 / (1 << 10) as f32)
+        Ok(tuning.amplitude(amplitude_control))
     }
 
     /// Get the configured amplitude of a channel.
@@ -440,20 +521,17 @@ impl<I: Interface> Ad9959<I> {
         let mut acr: [u8; 3] = [0; 3];
         self.read_channel(channel, Register::ACR, &mut acr)?;
 
-        if acr[1].get_bit(4) {
-            let amplitude_control: u16 =
-                (((acr[1] as u16) << 8) | (acr[2] as u16)) & 0x3FF;
-            Ok(amplitude_control as f32 / (1 << 10) as f32)
-        } else {
-            Ok(1.0)
-        }
+        Ok(self
+            .tuning()
+            .amplitude(u16::from_be_bytes([acr[1], acr[2]])))
     }
 
     /// Configure the frequency of a specified channel.
     ///
     /// Arguments:
     /// * `channel` - The channel to configure the frequency of.
-    /// * `frequency` - The desired output frequency in Hz.
+    /// * `frequency` - The desired output frequency in Hz. Frequencies above the Nyquist frequency
+    ///   are clamped to it.
     ///
     /// Returns:
     /// The actual programmed frequency of the channel.
@@ -466,19 +544,15 @@ impl<I: Interface> Ad9959<I> {
             return Err(Error::Bounds);
         }
 
-        // The function for channel frequency is `f_out = FTW * f_s / 2^32`, where FTW is the
-        // frequency tuning word and f_s is the system clock rate.
-        let tuning_word: u32 = ((frequency / self.system_clock_frequency())
-            * 1u64.wrapping_shl(32) as f32)
-            as u32;
+        let tuning = self.tuning();
+        let tuning_word = tuning.ftw(frequency);
 
         self.modify_channel(
             channel,
             Register::CFTW0,
             &tuning_word.to_be_bytes(),
         )?;
-        Ok((tuning_word as f32 / 1u64.wrapping_shl(32) as f32)
-            * self.system_clock_frequency())
+        Ok(tuning.frequency(tuning_word))
     }
 
     /// Get the frequency of a channel.
@@ -495,8 +569,45 @@ impl<I: Interface> Ad9959<I> {
         let tuning_word = u32::from_be_bytes(tuning_word);
 
         // Convert the tuning word into a frequency.
-        Ok((tuning_word as f32 * self.system_clock_frequency())
-            / (1u64 << 32) as f32)
+        Ok(self.tuning().frequency(tuning_word))
+    }
+
+    /// Read back all configuration registers for diagnostics.
+    ///
+    /// Note:
+    /// The per-channel registers are read by temporarily selecting each channel in the CSR. The
+    /// CSR is restored afterwards.
+    ///
+    /// Returns:
+    /// The contents of the global and per-channel configuration registers.
+    pub fn dump_registers(&mut self) -> Result<RegisterDump, Error> {
+        let mut dump = RegisterDump::default();
+
+        let mut csr = [0];
+        self.read(Register::CSR, &mut csr)?;
+        dump.csr = csr[0];
+        self.read(Register::FR1, &mut dump.fr1)?;
+        self.read(Register::FR2, &mut dump.fr2)?;
+
+        let channels =
+            [Channel::ONE, Channel::TWO, Channel::THREE, Channel::FOUR];
+        for (channel, registers) in
+            channels.iter().zip(dump.channels.iter_mut())
+        {
+            self.read_channel(*channel, Register::CFR, &mut registers.cfr)?;
+
+            let mut cftw0 = [0; 4];
+            self.read_channel(*channel, Register::CFTW0, &mut cftw0)?;
+            registers.cftw0 = u32::from_be_bytes(cftw0);
+
+            let mut cpow0 = [0; 2];
+            self.read_channel(*channel, Register::CPOW0, &mut cpow0)?;
+            registers.cpow0 = u16::from_be_bytes(cpow0);
+
+            self.read_channel(*channel, Register::ACR, &mut registers.acr)?;
+        }
+
+        Ok(dump)
     }
 
     /// Finalize DDS configuration
@@ -566,6 +677,73 @@ impl ProfileSerializer {
         }
     }
 
+    /// Update the channel function register of a number of channels.
+    ///
+    /// # Args
+    /// * `channels` - A set of channels to apply the configuration to.
+    /// * `cfr` - The contents of the channel function register.
+    pub fn update_channel_function(&mut self, channels: Channel, cfr: [u8; 3]) {
+        let csr = [self.mode as u8 | channels.bits()];
+        self.add_write(Register::CSR, &csr);
+        self.add_write(Register::CFR, &cfr);
+    }
+
+    /// Update a channel word register of a number of channels.
+    ///
+    /// # Note
+    /// The channel words hold the additional levels of a modulated channel, e.g. frequency tuning
+    /// words for frequency modulation. The level is selected with the profile pins.
+    ///
+    /// # Args
+    /// * `channels` - A set of channels to apply the configuration to.
+    /// * `index` - The index of the channel word register, from 1 to 15.
+    /// * `word` - The contents of the channel word register.
+    pub fn update_channel_word(
+        &mut self,
+        channels: Channel,
+        index: usize,
+        word: u32,
+    ) -> Result<(), Error> {
+        let register = Register::channel_word(index).ok_or(Error::Bounds)?;
+        let csr = [self.mode as u8 | channels.bits()];
+        self.add_write(Register::CSR, &csr);
+        self.add_write(register, &word.to_be_bytes());
+        Ok(())
+    }
+
+    /// Configure the internal system clock of the DDS.
+    ///
+    /// # Args
+    /// * `reference_clock_frequency` - The reference clock frequency provided to the AD9959 core.
+    /// * `multiplier` - The frequency multiplier of the system clock. Must be 1 or 4-20.
+    ///
+    /// # Returns
+    /// The resulting system clock frequency. Nothing is serialized if the configuration is
+    /// invalid.
+    pub fn configure_system_clock(
+        &mut self,
+        reference_clock_frequency: f32,
+        multiplier: u8,
+    ) -> Result<f32, Error> {
+        if multiplier != 1 && !(4..=20).contains(&multiplier) {
+            return Err(Error::Bounds);
+        }
+
+        let frequency = multiplier as f32 * reference_clock_frequency;
+        if frequency > 500_000_000.0f32 {
+            return Err(Error::Frequency);
+        }
+
+        // The remaining FR1 bits are left at their power-on default of zero, matching the
+        // configuration of `Ad9959::new()`.
+        let mut fr1: [u8; 3] = [0, 0, 0];
+        fr1[0].set_bits(2..=6, multiplier);
+        fr1[0].set_bit(7, frequency > 255e6);
+        self.add_write(Register::FR1, &fr1);
+
+        Ok(frequency)
+    }
+
     /// Add a register write to the serialization data.
     fn add_write(&mut self, register: Register, value: &[u8]) {
         let data = &mut self.data[self.index..];
@@ -604,3 +782,128 @@ impl ProfileSerializer {
         bytemuck::cast_slice(&self.data[..self.index])
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::convert::Infallible;
+
+    const CHANNELS: [Channel; 4] =
+        [Channel::ONE, Channel::TWO, Channel::THREE, Channel::FOUR];
+
+    /// A register model of the AD9959 serial interface.
+    #[derive(Default)]
+    struct MockInterface {
+        csr: u8,
+        fr1: [u8; 3],
+        fr2: [u8; 2],
+        /// The CFR, CFTW0, CPOW0 and ACR registers of each channel.
+        channels: [[[u8; 4]; 4]; 4],
+    }
+
+    impl Interface for MockInterface {
+        type Error = ();
+
+        fn configure_mode(&mut self, _mode: Mode) -> Result<(), ()> {
+            Ok(())
+        }
+
+        fn write(&mut self, addr: u8, data: &[u8]) -> Result<(), ()> {
+            match addr {
+                0x00 => self.csr = data[0],
+                0x01 => self.fr1.copy_from_slice(data),
+                0x02 => self.fr2.copy_from_slice(data),
+                0x03..=0x06 => {
+                    // Channel registers are written to all enabled channels.
+                    for (channel, registers) in
+                        CHANNELS.iter().zip(self.channels.iter_mut())
+                    {
+                        if self.csr & channel.bits() != 0 {
+                            registers[addr as usize - 3][..data.len()]
+                                .copy_from_slice(data);
+                        }
+                    }
+                }
+                _ => return Err(()),
+            }
+            Ok(())
+        }
+
+        fn read(&mut self, addr: u8, dest: &mut [u8]) -> Result<(), ()> {
+            match addr {
+                0x00 => dest.copy_from_slice(&[self.csr]),
+                0x01 => dest.copy_from_slice(&self.fr1),
+                0x02 => dest.copy_from_slice(&self.fr2),
+                0x03..=0x06 => {
+                    // Channel registers can only be read with a single channel enabled.
+                    let index = CHANNELS
+                        .iter()
+                        .position(|channel| {
+                            self.csr & Channel::ALL.bits() == channel.bits()
+                        })
+                        .ok_or(())?;
+                    dest.copy_from_slice(
+                        &self.channels[index][addr as usize - 3][..dest.len()],
+                    );
+                }
+                _ => return Err(()),
+            }
+            Ok(())
+        }
+    }
+
+    struct MockPin;
+
+    impl OutputPin for MockPin {
+        type Error = Infallible;
+
+        fn set_low(&mut self) -> Result<(), Infallible> {
+            Ok(())
+        }
+
+        fn set_high(&mut self) -> Result<(), Infallible> {
+            Ok(())
+        }
+    }
+
+    struct NoDelay;
+
+    impl DelayUs<u8> for NoDelay {
+        fn delay_us(&mut self, _us: u8) {}
+    }
+
+    fn dds() -> Ad9959<MockInterface> {
+        Ad9959::new(
+            MockInterface::default(),
+            &mut MockPin,
+            &mut MockPin,
+            &mut NoDelay,
+            Mode::FourBitSerial,
+            100e6,
+            5,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn dump_registers_reads_every_channel() {
+        let mut dds = dds();
+        for (index, channel) in CHANNELS.iter().enumerate() {
+            dds.set_frequency(*channel, 10e6 * (index + 1) as f32)
+                .unwrap();
+        }
+        let csr = dds.interface.csr;
+
+        let dump = dds.dump_registers().unwrap();
+        assert_eq!(dump.csr, csr);
+        assert_eq!(dump.fr1, dds.interface.fr1);
+        assert_eq!(dump.fr2, dds.interface.fr2);
+        for (index, registers) in dump.channels.iter().enumerate() {
+            let ftw = dds.tuning().ftw(10e6 * (index + 1) as f32);
+            assert_eq!(registers.cftw0, ftw);
+        }
+
+        // The channel selection is restored after the dump.
+        assert_eq!(dds.interface.csr, csr);
+    }
+}
@@ -0,0 +1,168 @@
+/// Conversion between physical units and the AD9959 channel tuning registers.
+///
+/// The conversions do not access the device, so they can be used to precompute profiles for
+/// streaming and to interpret register contents that were read back.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Tuning {
+    system_clock: f32,
+}
+
+impl Tuning {
+    /// Construct the conversions for a system clock.
+    ///
+    /// Args:
+    /// * `system_clock` - The DDS system clock frequency in Hz.
+    pub fn new(system_clock: f32) -> Self {
+        Self { system_clock }
+    }
+
+    /// Get the system clock frequency in Hz.
+    pub fn system_clock(&self) -> f32 {
+        self.system_clock
+    }
+
+    /// Get the highest frequency that can be synthesized without aliasing in Hz.
+    pub fn nyquist(&self) -> f32 {
+        self.system_clock / 2.0
+    }
+
+    /// Compute the frequency tuning word of an output frequency.
+    ///
+    /// Note:
+    /// The frequency is clamped to the range from zero to the Nyquist frequency.
+    ///
+    /// Args:
+    /// * `hz` - The output frequency in Hz.
+    ///
+    /// Returns:
+    /// The frequency tuning word for the CFTW0 register.
+    pub fn ftw(&self, hz: f32) -> u32 {
+        // The function for channel frequency is `f_out = FTW * f_s / 2^32`, where FTW is the
+        // frequency tuning word and f_s is the system clock rate.
+        let hz = hz.clamp(0.0, self.nyquist());
+        ((hz / self.system_clock) * (1u64 << 32) as f32) as u32
+    }
+
+    /// Compute the output frequency of a frequency tuning word.
+    ///
+    /// Args:
+    /// * `ftw` - The frequency tuning word of the CFTW0 register.
+    ///
+    /// Returns:
+    /// The output frequency in Hz.
+    pub fn frequency(&self, ftw: u32) -> f32 {
+        (ftw as f32 / (1u64 << 32) as f32) * self.system_clock
+    }
+
+    /// Compute the phase offset word of a phase.
+    ///
+    /// Args:
+    /// * `turns` - The phase offset in turns. The phase wraps every turn.
+    ///
+    /// Returns:
+    /// The 14-bit phase offset word for the CPOW0 register.
+    pub fn pow(&self, turns: f32) -> u16 {
+        ((turns * (1 << 14) as f32) as i32 & 0x3FFF) as u16
+    }
+
+    /// Compute the phase of a phase offset word.
+    ///
+    /// Args:
+    /// * `pow` - The phase offset word of the CPOW0 register.
+    ///
+    /// Returns:
+    /// The phase offset in turns.
+    pub fn phase(&self, pow: u16) -> f32 {
+        (pow & 0x3FFF) as f32 / (1 << 14) as f32
+    }
+
+    /// Compute the amplitude control of a normalized amplitude.
+    ///
+    /// Note:
+    /// The amplitude control has full-scale at 0x3FF (amplitude of 1), so the amplitude
+    /// multiplier is disabled whenever full-scale is requested.
+    ///
+    /// Args:
+    /// * `scale` - The normalized amplitude [0, 1].
+    ///
+    /// Returns:
+    /// The lower 16 bits of the ACR register, including the amplitude multiplier enable bit.
+    pub fn acr(&self, scale: f32) -> u16 {
+        let amplitude_control = (scale * (1 << 10) as f32) as u16;
+        if amplitude_control < (1 << 10) {
+            // Enable the amplitude multiplier.
+            (1 << 12) | amplitude_control
+        } else {
+            0
+        }
+    }
+
+    /// Compute the normalized amplitude of an amplitude control.
+    ///
+    /// Args:
+    /// * `acr` - The lower 16 bits of the ACR register.
+    ///
+    /// Returns:
+    /// The normalized amplitude relative to full-scale range.
+    pub fn amplitude(&self, acr: u16) -> f32 {
+        if acr & (1 << 12) != 0 {
+            (acr & 0x3FF) as f32 / (1 << 10) as f32
+        } else {
+            1.0
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SYSTEM_CLOCK: f32 = 500e6;
+
+    #[test]
+    fn ftw_round_trip() {
+        let tuning = Tuning::new(SYSTEM_CLOCK);
+        assert_eq!(tuning.ftw(0.0), 0);
+        assert_eq!(tuning.ftw(SYSTEM_CLOCK / 4.0), 1 << 30);
+
+        // The tuning word resolution is about 0.12 Hz, on top of the single precision rounding.
+        let resolution = SYSTEM_CLOCK / (1u64 << 32) as f32;
+        for hz in [1e3, 10e6, 100e6, 249e6] {
+            let error = tuning.frequency(tuning.ftw(hz)) - hz;
+            assert!(error.abs() <= resolution + hz * 1e-6, "{}: {}", hz, error);
+        }
+    }
+
+    #[test]
+    fn ftw_clamps_to_nyquist() {
+        let tuning = Tuning::new(SYSTEM_CLOCK);
+        assert_eq!(tuning.nyquist(), 250e6);
+        assert_eq!(tuning.ftw(-1e6), 0);
+        assert_eq!(tuning.ftw(300e6), tuning.ftw(250e6));
+        assert_eq!(tuning.ftw(250e6), 1 << 31);
+    }
+
+    #[test]
+    fn pow_wraps_every_turn() {
+        let tuning = Tuning::new(SYSTEM_CLOCK);
+        assert_eq!(tuning.pow(0.0), 0);
+        assert_eq!(tuning.pow(0.25), 1 << 12);
+        assert_eq!(tuning.pow(1.25), 1 << 12);
+        assert_eq!(tuning.pow(-0.25), 3 << 12);
+        assert_eq!(tuning.phase(tuning.pow(0.5)), 0.5);
+        assert_eq!(tuning.phase(0xFFFF), tuning.phase(0x3FFF));
+    }
+
+    #[test]
+    fn acr_round_trip() {
+        let tuning = Tuning::new(SYSTEM_CLOCK);
+        assert_eq!(tuning.acr(0.5), (1 << 12) | (1 << 9));
+        assert_eq!(tuning.amplitude(tuning.acr(0.5)), 0.5);
+        assert_eq!(tuning.amplitude(tuning.acr(0.0)), 0.0);
+
+        // Full-scale disables the amplitude multiplier.
+        assert_eq!(tuning.acr(1.0), 0);
+        assert_eq!(tuning.acr(2.0), 0);
+        assert_eq!(tuning.amplitude(0), 1.0);
+    }
+}
@@ -29,32 +29,51 @@
 #![no_std]
 #![no_main]
 
+use core::fmt::Write;
 use core::mem::MaybeUninit;
 use core::sync::atomic::{fence, Ordering};
 
 use fugit::ExtU64;
-use mutex_trait::prelude::*;
+use serde::Serialize;
 
 use idsp::iir;
 
 use stabilizer::{
     hardware::{
         self,
-        adc::{Adc0Input, Adc1Input, AdcCode},
+        adc::{Adc0Input, Adc1Input, AdcCalibration, AdcCode},
         afe::Gain,
-        dac::{Dac0Output, Dac1Output, DacCode},
+        cpu_temp_sensor::CpuTempSensor,
+        dac::{Dac0Output, Dac1Output, DacCalibration, DacCode, ManualOutput},
+        delay::AsmDelay,
+        eeprom::{self, ServoConfig},
         hal,
-        serial_terminal::SerialTerminal,
+        pounder::{
+            self, attenuators::AttenuatorInterface, Channel, ClockInterface,
+            FlatnessCalibration, FlatnessPoint, PounderStatus,
+        },
+        self_test::{self, Outcome},
+        serial_terminal::{
+            self, ClockSource, Command, Key, OutputBuffer, RegisterData,
+            SerialTerminal, Setting,
+        },
+        setup::ReferenceClock,
         signal_generator::{self, SignalGenerator},
+        status_leds::{FrontPanelLeds, Led, Pattern},
         timers::SamplingTimer,
+        uptime::Uptime,
+        watchdog::{Watchdog, WatchdogEvent},
         DigitalInput0, DigitalInput1, SystemTimer, Systick, AFE0, AFE1,
     },
     net::{
+        adc_stream::AdcStreamGenerator,
+        cpu_telemetry::CpuTelemetry,
         data_stream::{FrameGenerator, StreamFormat, StreamTarget},
         miniconf::Tree,
         telemetry::{Telemetry, TelemetryBuffer},
-        NetworkState, NetworkUsers,
+        LinkChange, NetworkState, NetworkUsers,
     },
+    servo::{LockConfig, LockController, LockState, PiController},
 };
 
 const SCALE: f32 = i16::MAX as _;
@@ -72,6 +91,56 @@ const SAMPLE_TICKS: u32 = 1 << SAMPLE_TICKS_LOG2;
 const SAMPLE_PERIOD: f32 =
     SAMPLE_TICKS as f32 * hardware::design_parameters::TIMER_PERIOD;
 
+// The time in milliseconds within which the DSP routine must run before the servo is considered
+// stalled. It must exceed the batch period and the worst-case latency of the priority 1 watchdog
+// task. The independent watchdog resets the device if the check does not run for
+// `watchdog::IWDG_TIMEOUT_PERIODS` times this period. Long priority 1 work delays the check and
+// counts against this margin, such as EEPROM saves and the `selftest` command of the serial
+// terminal. This is fixed at build time as the independent watchdog cannot be stopped once
+// started.
+const SERVO_WATCHDOG_TIMEOUT_MS: u32 = 100;
+
+// The number of CPU cycles to wait for a fresh beat note measurement after the DDS reference clock
+// input is switched (1 ms at 400 MHz). This spans many gate periods of the beat timer.
+const BEAT_DETECT_CYCLES: u32 = 400_000;
+
+// The period in milliseconds at which the status LED patterns are advanced.
+const LED_UPDATE_PERIOD_MS: u64 = 20;
+
+// The LED pattern indicating a stalled servo.
+const SERVO_STALLED_PATTERN: Pattern = Pattern::Blink {
+    on_ms: 100,
+    off_ms: 100,
+};
+
+// The time in milliseconds to wait for the analog supplies and clocks to settle after setup.
+const READY_TIMEOUT_MS: u32 = 100;
+
+// Whether to provide the USB serial terminal. Headless deployments can disable it.
+const USB_ENABLED: bool = true;
+
+// The period in milliseconds at which the lock controller is updated.
+const LOCK_PERIOD_MS: u64 = 10;
+
+// The period in milliseconds at which the pounder attenuator scan is advanced.
+const ATTENUATOR_SCAN_PERIOD_MS: u64 = 100;
+
+// The pounder DDS channel actuated by the lock controller.
+const LOCK_CHANNEL: Channel = Channel::Out0;
+
+// The lock acquisition parameters. The servo gains and setpoint are taken from the servo
+// configuration.
+const LOCK_CONFIG: LockConfig = LockConfig {
+    search_range: (10e6, 200e6),
+    search_step: 100e3,
+    capture_range: 1e6,
+    lock_threshold: 1e3,
+    lock_cycles: 100,
+};
+
+// The (lower, upper) limits of the servo correction around the captured DDS frequency in Hz.
+const LOCK_SERVO_LIMITS: (f32, f32) = (-5e6, 5e6);
+
 #[derive(Clone, Copy, Debug, Tree)]
 pub struct Settings {
     /// Configure the Analog Front End (AFE) gain.
@@ -135,6 +204,42 @@ pub struct Settings {
     /// See [StreamTarget#miniconf]
     stream_target: StreamTarget,
 
+    /// Specifies the target for CPU temperature telemetry over UDP.
+    ///
+    /// # Path
+    /// `cpu_telemetry_target`
+    ///
+    /// # Value
+    /// See [StreamTarget#miniconf]
+    cpu_telemetry_target: StreamTarget,
+
+    /// Specifies the CPU temperature telemetry output period in seconds.
+    ///
+    /// # Path
+    /// `cpu_telemetry_period`
+    ///
+    /// # Value
+    /// Any non-zero value less than 65536.
+    cpu_telemetry_period: u16,
+
+    /// Specifies the target for raw ADC sample streaming.
+    ///
+    /// # Path
+    /// `adc_stream_target`
+    ///
+    /// # Value
+    /// See [StreamTarget#miniconf]
+    adc_stream_target: StreamTarget,
+
+    /// Specifies the decimation of the data livestream.
+    ///
+    /// # Path
+    /// `stream_decimation`
+    ///
+    /// # Value
+    /// Only one in every `stream_decimation` batches is streamed. 0 and 1 stream every batch.
+    stream_decimation: u32,
+
     /// Specifies the config for signal generators to add on to DAC0/DAC1 outputs.
     ///
     /// # Path
@@ -146,6 +251,20 @@ pub struct Settings {
     /// See [signal_generator::BasicConfig#miniconf]
     #[tree(depth(2))]
     signal_generator: [signal_generator::BasicConfig; 2],
+
+    /// Specifies if the beat note is locked by the pounder DDS.
+    ///
+    /// # Path
+    /// `lock`
+    ///
+    /// # Value
+    /// "true" or "false"
+    ///
+    /// # Note
+    /// The lock controller sweeps the DDS frequency until the beat note is captured and then
+    /// servos it to the setpoint of the servo configuration. Disabling the lock restarts the
+    /// search the next time it is enabled.
+    lock: bool,
 }
 
 impl Default for Settings {
@@ -170,6 +289,586 @@ impl Default for Settings {
             signal_generator: [signal_generator::BasicConfig::default(); 2],
 
             stream_target: StreamTarget::default(),
+
+            cpu_telemetry_target: StreamTarget::default(),
+            // The default CPU telemetry period in seconds.
+            cpu_telemetry_period: 1,
+
+            adc_stream_target: StreamTarget::default(),
+
+            stream_decimation: 1,
+
+            lock: false,
+        }
+    }
+}
+
+/// The device status reported by the `status` serial terminal command.
+#[derive(Serialize)]
+struct Status {
+    /// The pounder RF state, or `null` if pounder is not present.
+    pounder: Option<PounderStatus>,
+
+    /// The latest beat note frequency in Hz.
+    beat_frequency: f32,
+
+    /// The clock source of the reference timer.
+    reference_clock: ReferenceClock,
+}
+
+/// Capture the current state of pounder for status reporting.
+///
+/// # Args
+/// * `pounder` - The pounder devices.
+fn pounder_status(
+    pounder: &mut hardware::setup::PounderDevices,
+) -> Result<PounderStatus, pounder::Error> {
+    let attenuation = match pounder.pounder.attenuation_snapshot() {
+        Some(attenuation) => attenuation,
+        None => pounder.pounder.attenuations()?,
+    };
+    let mut frequency = [0.; 4];
+    let mut power_dbm = [0.; 4];
+    for channel in enum_iterator::all::<Channel>() {
+        frequency[channel as usize] = pounder.dds_output.frequency(channel);
+        power_dbm[channel as usize] = pounder.pounder.dds_power_dbm(
+            &pounder.dds_output,
+            channel,
+            attenuation[channel as usize],
+        );
+    }
+
+    Ok(PounderStatus {
+        attenuation,
+        frequency,
+        clock: pounder.pounder.clock_config(),
+        clock_fault: pounder.pounder.clock_fault(),
+        power_dbm,
+    })
+}
+
+/// Write the device status as a single line of JSON.
+///
+/// # Args
+/// * `pounder` - The pounder devices, if pounder is present.
+/// * `beat_frequency` - The latest beat note frequency in Hz.
+/// * `reference_clock` - The clock source of the reference timer.
+/// * `output` - The terminal output to write the status into.
+fn write_status(
+    pounder: Option<&mut hardware::setup::PounderDevices>,
+    beat_frequency: f32,
+    reference_clock: ReferenceClock,
+    output: &mut OutputBuffer,
+) -> core::fmt::Result {
+    let pounder = match pounder.map(pounder_status).transpose() {
+        Ok(pounder) => pounder,
+        Err(err) => return writeln!(output, "error: {err:?}"),
+    };
+
+    let status = Status {
+        pounder,
+        beat_frequency,
+        reference_clock,
+    };
+    match serde_json_core::to_string::<_, 512>(&status) {
+        Ok(json) => writeln!(output, "{json}"),
+        Err(_) => writeln!(output, "error: status too long"),
+    }
+}
+
+/// Test the major subsystems and write a line per subsystem and the overall result.
+///
+/// # Note
+/// The subsystems are tested independently, so a failure does not abort the remaining tests.
+///
+/// # Args
+/// * `pounder` - The pounder devices, if pounder is present.
+/// * `eeprom_i2c` - The I2C bus connected to the EEPROM.
+/// * `cpu_temp_sensor` - The CPU die temperature sensor.
+/// * `link_up` - The ethernet link is up.
+/// * `output` - The terminal output to write the results into.
+fn write_self_test(
+    pounder: Option<&mut hardware::setup::PounderDevices>,
+    eeprom_i2c: &mut hal::i2c::I2c<hal::stm32::I2C2>,
+    cpu_temp_sensor: &mut CpuTempSensor,
+    link_up: bool,
+    output: &mut OutputBuffer,
+) -> core::fmt::Result {
+    let (attenuators, dds) = match pounder {
+        Some(devices) => (
+            Some(matches!(devices.pounder.test_attenuators(), Ok(true))),
+            Some(devices.pounder.dds_present()),
+        ),
+        None => (None, None),
+    };
+
+    let results = [
+        ("attenuators", Outcome::optional(attenuators)),
+        ("dds", Outcome::optional(dds)),
+        (
+            "eeprom",
+            Outcome::check(eeprom::check_eui48(eeprom_i2c).is_ok()),
+        ),
+        ("network", Outcome::check(link_up)),
+        (
+            "cpu temperature",
+            Outcome::check(
+                cpu_temp_sensor
+                    .get_temperature()
+                    .map_or(false, self_test::cpu_temperature_plausible),
+            ),
+        ),
+    ];
+
+    for (subsystem, outcome) in results {
+        writeln!(output, "{subsystem}: {outcome}")?;
+    }
+    writeln!(
+        output,
+        "overall: {}",
+        self_test::overall(results.map(|(_, outcome)| outcome))
+    )
+}
+
+/// The state accessible through the USB serial terminal.
+struct TerminalContext<'a> {
+    /// The pounder devices, if pounder is present.
+    pounder: Option<&'a mut hardware::setup::PounderDevices>,
+
+    /// The servo configuration.
+    servo_config: &'a mut ServoConfig,
+
+    /// The I2C bus connected to the EEPROM.
+    eeprom_i2c: &'a mut hal::i2c::I2c<hal::stm32::I2C2>,
+
+    /// A delay to wait for EEPROM writes.
+    delay: &'a mut AsmDelay,
+
+    /// The CPU die temperature sensor.
+    cpu_temp_sensor: &'a mut CpuTempSensor,
+
+    /// The latest beat note frequency in Hz.
+    beat_frequency: f32,
+
+    /// Waits for a fresh beat note measurement and checks whether a beat note is present.
+    ///
+    /// # Note
+    /// There is no dedicated detector of the external DDS reference clock. A measured beat note
+    /// is taken as the indication that the reference is present.
+    beat_present: &'a mut dyn FnMut() -> bool,
+
+    /// The clock source of the reference timer.
+    reference_clock: ReferenceClock,
+
+    /// The ethernet link is up.
+    link_up: bool,
+
+    /// The manual overrides of the DAC outputs.
+    dac_manual: &'a mut [ManualOutput; 2],
+
+    /// The calibrations of the ADC inputs.
+    adc_calibration: &'a mut [AdcCalibration; 2],
+
+    /// Set to request the lock task to tare the servo at the latest beat note.
+    lock_tare: &'a mut bool,
+}
+
+/// Persist the calibrations in use to EEPROM.
+///
+/// # Args
+/// * `pounder` - The pounder devices holding the pounder calibrations, if pounder is present.
+/// * `eeprom_i2c` - The I2C bus connected to the EEPROM.
+/// * `delay` - A delay to wait for EEPROM writes.
+/// * `dac_manual` - The DAC outputs holding the DAC calibrations.
+/// * `adc_calibration` - The ADC calibrations.
+/// * `output` - The terminal output to write the response into.
+fn save_calibrations(
+    pounder: Option<&hardware::setup::PounderDevices>,
+    eeprom_i2c: &mut hal::i2c::I2c<hal::stm32::I2C2>,
+    delay: &mut AsmDelay,
+    dac_manual: &[ManualOutput; 2],
+    adc_calibration: &[AdcCalibration; 2],
+    output: &mut OutputBuffer,
+) -> core::fmt::Result {
+    let mut write = || -> Result<(), hal::i2c::Error> {
+        let dac = [dac_manual[0].calibration(), dac_manual[1].calibration()];
+        eeprom::write_dac_calibration(eeprom_i2c, delay, &dac)?;
+        eeprom::write_adc_calibration(eeprom_i2c, delay, adc_calibration)?;
+
+        if let Some(pounder) = pounder {
+            eeprom::write_power_calibration(
+                eeprom_i2c,
+                delay,
+                &pounder.pounder.power_calibration(),
+            )?;
+            eeprom::write_flatness_calibration(
+                eeprom_i2c,
+                delay,
+                pounder.pounder.flatness_calibration(),
+            )?;
+        }
+
+        Ok(())
+    };
+
+    match write() {
+        Ok(()) => writeln!(output, "saved"),
+        Err(err) => writeln!(output, "error: EEPROM write failed: {err:?}"),
+    }
+}
+
+/// Select the pounder DDS reference clock source.
+///
+/// # Args
+/// * `pounder` - The pounder devices.
+/// * `source` - The clock source to select.
+/// * `beat_present` - Detects the external reference clock, see [TerminalContext].
+/// * `output` - The terminal output to write the response into.
+fn select_clock(
+    pounder: &mut hardware::setup::PounderDevices,
+    source: ClockSource,
+    beat_present: &mut dyn FnMut() -> bool,
+    output: &mut OutputBuffer,
+) -> core::fmt::Result {
+    let result = match source {
+        ClockSource::Onboard => pounder
+            .pounder
+            .select_onboard_clock(&mut pounder.dds_output),
+        // Note: The reference input of TIM1 (PE7) is used by QSPI when pounder is present, so
+        // the presence of the external reference is inferred from the beat note.
+        ClockSource::External(frequency, multiplier) => {
+            pounder.pounder.select_external_clock(
+                &mut pounder.dds_output,
+                frequency,
+                multiplier.unwrap_or(1),
+                beat_present,
+            )
+        }
+    };
+
+    match result {
+        Ok(clock) => writeln!(output, "system clock: {clock} Hz"),
+        Err(err) => writeln!(output, "error: {err}"),
+    }
+}
+
+/// Write the value of a setting as `<key> = <value>`.
+///
+/// # Args
+/// * `key` - The key of the setting.
+/// * `servo_config` - The servo configuration.
+/// * `pounder` - The pounder devices, if pounder is present.
+/// * `output` - The terminal output to write the value into.
+fn write_setting(
+    key: Key,
+    servo_config: &ServoConfig,
+    pounder: Option<&mut hardware::setup::PounderDevices>,
+    output: &mut OutputBuffer,
+) -> core::fmt::Result {
+    match (key, pounder) {
+        (Key::Setpoint, _) => {
+            writeln!(output, "{key} = {}", servo_config.setpoint)
+        }
+        (Key::Kp, _) => writeln!(output, "{key} = {}", servo_config.kp),
+        (Key::Ki, _) => writeln!(output, "{key} = {}", servo_config.ki),
+        (_, None) => writeln!(output, "error: pounder not present"),
+        (Key::Attenuation(channel), Some(pounder)) => {
+            match pounder.pounder.get_attenuation(channel) {
+                Ok(attenuation) => writeln!(output, "{key} = {attenuation}"),
+                Err(err) => writeln!(output, "error: {err}"),
+            }
+        }
+        (Key::Clock, Some(pounder)) => {
+            let clock = pounder.pounder.clock_config();
+            if clock.external_clock {
+                writeln!(
+                    output,
+                    "{key} = external {} {}",
+                    clock.reference_clock, clock.multiplier
+                )
+            } else {
+                writeln!(output, "{key} = onboard")
+            }
+        }
+    }
+}
+
+/// Change the value of a setting.
+///
+/// # Args
+/// * `setting` - The setting and its new value.
+/// * `servo_config` - The servo configuration.
+/// * `pounder` - The pounder devices, if pounder is present.
+/// * `output` - The terminal output to write the response into.
+fn apply_setting(
+    setting: Setting,
+    servo_config: &mut ServoConfig,
+    pounder: Option<&mut hardware::setup::PounderDevices>,
+    beat_present: &mut dyn FnMut() -> bool,
+    output: &mut OutputBuffer,
+) -> core::fmt::Result {
+    match (setting, pounder) {
+        (Setting::Setpoint(setpoint), _) => {
+            servo_config.setpoint = setpoint;
+            write_setting(Key::Setpoint, servo_config, None, output)
+        }
+        (Setting::Kp(kp), _) => {
+            servo_config.kp = kp;
+            write_setting(Key::Kp, servo_config, None, output)
+        }
+        (Setting::Ki(ki), _) => {
+            servo_config.ki = ki;
+            write_setting(Key::Ki, servo_config, None, output)
+        }
+        (_, None) => writeln!(output, "error: pounder not present"),
+        (Setting::Attenuation(channel, attenuation), Some(pounder)) => {
+            match pounder.pounder.set_attenuation(channel, attenuation) {
+                Ok(attenuation) => writeln!(
+                    output,
+                    "{} = {attenuation}",
+                    Key::Attenuation(channel)
+                ),
+                Err(err) => writeln!(output, "error: {err}"),
+            }
+        }
+        (Setting::Clock(source), Some(pounder)) => {
+            select_clock(pounder, source, beat_present, output)
+        }
+    }
+}
+
+/// Read a pounder DDS register and report its contents in hexadecimal.
+///
+/// # Args
+/// * `pounder` - The pounder devices.
+/// * `addr` - The register address.
+/// * `len` - The number of bytes to read.
+/// * `output` - The terminal output to write the response into.
+fn read_dds_register(
+    pounder: &mut hardware::setup::PounderDevices,
+    addr: u8,
+    len: usize,
+    output: &mut OutputBuffer,
+) -> core::fmt::Result {
+    let mut data = [0; RegisterData::CAPACITY];
+    let data = &mut data[..len];
+    if let Err(err) = pounder.dds_output.read_register(addr, data) {
+        return writeln!(output, "error: {err}");
+    }
+
+    write!(output, "{addr:#04x}: ")?;
+    data.iter()
+        .try_for_each(|byte| write!(output, "{byte:02x}"))?;
+    writeln!(output)
+}
+
+/// Execute a command received over the USB serial terminal.
+///
+/// # Args
+/// * `command` - The command to execute.
+/// * `context` - The state accessible through the terminal.
+/// * `output` - The terminal output to write the response into.
+///
+/// # Returns
+/// An error if the response could not be formatted. The response may then be incomplete.
+fn handle_command(
+    command: Command,
+    context: &mut TerminalContext,
+    output: &mut OutputBuffer,
+) -> core::fmt::Result {
+    match (command, context.pounder.as_deref_mut()) {
+        (Command::Status, pounder) => write_status(
+            pounder,
+            context.beat_frequency,
+            context.reference_clock,
+            output,
+        ),
+        (Command::Get(key), pounder) => {
+            write_setting(key, context.servo_config, pounder, output)
+        }
+        (Command::Set(setting), pounder) => apply_setting(
+            setting,
+            context.servo_config,
+            pounder,
+            context.beat_present,
+            output,
+        ),
+        (Command::List, mut pounder) => {
+            let pounder_present = pounder.is_some();
+            Key::ALL
+                .iter()
+                .filter(|key| pounder_present || !key.is_pounder())
+                .try_for_each(|&key| {
+                    write_setting(
+                        key,
+                        context.servo_config,
+                        pounder.as_deref_mut(),
+                        output,
+                    )
+                })
+        }
+        (Command::LogLevel(level), _) => {
+            log::set_max_level(level);
+            writeln!(output, "log level: {level}")
+        }
+        (Command::Temperature, pounder) => {
+            let cpu = context.cpu_temp_sensor.get_temperature();
+            serial_terminal::write_temperature(output, "cpu", cpu)?;
+            match pounder {
+                Some(devices) => serial_terminal::write_temperature(
+                    output,
+                    "pounder",
+                    devices.pounder.temperature(),
+                ),
+                None => Ok(()),
+            }
+        }
+        (Command::SelfTest, pounder) => write_self_test(
+            pounder,
+            context.eeprom_i2c,
+            context.cpu_temp_sensor,
+            context.link_up,
+            output,
+        ),
+        (Command::LogLevelQuery, _) => {
+            writeln!(output, "log level: {}", log::max_level())
+        }
+        (Command::LogDump, _) => serial_terminal::write_log_dump(output),
+        // Note: Only the servo configuration is persisted, and the calibrations by `CalSave`.
+        // The pounder attenuation and clock source are restored to their defaults on boot.
+        (Command::Save, _) => match eeprom::write_servo_config(
+            context.eeprom_i2c,
+            context.delay,
+            context.servo_config,
+        ) {
+            Ok(()) => writeln!(output, "saved"),
+            Err(err) => writeln!(output, "error: EEPROM write failed: {err:?}"),
+        },
+        (Command::Load, _) => {
+            match eeprom::read_servo_config(context.eeprom_i2c) {
+                Some(config) => {
+                    *context.servo_config = config;
+                    writeln!(output, "loaded")
+                }
+                None => writeln!(
+                    output,
+                    "error: no valid servo configuration in EEPROM"
+                ),
+            }
+        }
+        (Command::DacSet(channel, Some(volts)), _) => {
+            let volts = context.dac_manual[channel].set(volts);
+            log::info!("DAC{channel} overridden at {volts} V");
+            writeln!(output, "dac{channel}: {volts} V (servo overridden)")
+        }
+        (Command::DacSet(channel, None), _) => {
+            if context.dac_manual[channel].release() {
+                log::info!("DAC{channel} returned to the servo");
+            }
+            writeln!(output, "dac{channel}: servo")
+        }
+        (Command::CalDac(channel, gain, offset), _) => {
+            context.dac_manual[channel]
+                .set_calibration(DacCalibration { gain, offset });
+            writeln!(output, "dac{channel}: gain {gain}, offset {offset} V")
+        }
+        (Command::CalAdc(channel, gain, offset), _) => {
+            context.adc_calibration[channel] = AdcCalibration { gain, offset };
+            writeln!(output, "adc{channel}: gain {gain}, offset {offset} V")
+        }
+        (Command::CalSave, pounder) => save_calibrations(
+            pounder.as_deref(),
+            context.eeprom_i2c,
+            context.delay,
+            context.dac_manual,
+            context.adc_calibration,
+            output,
+        ),
+        (Command::LockTare, _) => {
+            if context.beat_frequency.is_finite() {
+                // The lock task clears the integrator and takes its latest beat note as the
+                // setpoint, which refines the value reported here.
+                context.servo_config.setpoint = context.beat_frequency;
+                *context.lock_tare = true;
+                write_setting(Key::Setpoint, context.servo_config, None, output)
+            } else {
+                writeln!(output, "error: no beat note measured")
+            }
+        }
+        (_, None) => writeln!(output, "error: pounder not present"),
+        (Command::DdsFrequency(channel, frequency), Some(pounder)) => {
+            match pounder.pounder.set_dds_frequency(
+                &mut pounder.dds_output,
+                channel,
+                frequency,
+            ) {
+                Ok((actual, None)) => {
+                    writeln!(output, "{channel:?}: {actual} Hz")
+                }
+                Ok((actual, Some(warning))) => {
+                    writeln!(
+                        output,
+                        "{channel:?}: {actual} Hz (warning: {warning})"
+                    )
+                }
+                Err(err) => writeln!(output, "error: {err}"),
+            }
+        }
+        (Command::CalPower(channel, dbm), Some(pounder)) => {
+            let mut calibration = pounder.pounder.power_calibration();
+            calibration.full_scale_dbm[channel as usize] = dbm;
+            pounder.pounder.set_power_calibration(calibration);
+            writeln!(output, "{channel:?}: full-scale {dbm} dBm")
+        }
+        (Command::CalFlatness(frequency, correction_db), Some(pounder)) => {
+            let mut calibration =
+                pounder.pounder.flatness_calibration().clone();
+            let point = FlatnessPoint {
+                frequency,
+                correction_db,
+            };
+            match calibration.insert(point) {
+                Ok(()) => {
+                    let points = calibration.points().len();
+                    pounder.pounder.set_flatness_calibration(calibration);
+                    writeln!(output, "flatness: {points} points")
+                }
+                Err(err) => writeln!(output, "error: {err}"),
+            }
+        }
+        (Command::CalFlatnessClear, Some(pounder)) => {
+            pounder
+                .pounder
+                .set_flatness_calibration(FlatnessCalibration::default());
+            writeln!(output, "flatness: 0 points")
+        }
+        (Command::DdsRead(channel), Some(pounder)) => writeln!(
+            output,
+            "{channel:?}: {} Hz",
+            pounder.dds_output.frequency(channel)
+        ),
+        (Command::DdsRegisterWrite(addr, data), Some(pounder)) => {
+            match pounder.dds_output.write_register(addr, data.as_slice()) {
+                Ok(()) => writeln!(output, "ok"),
+                Err(err) => writeln!(output, "error: {err}"),
+            }
+        }
+        (Command::DdsRegisterRead(addr, len), Some(pounder)) => {
+            read_dds_register(pounder, addr, len, output)
+        }
+        (Command::ClockOnboard, Some(pounder)) => select_clock(
+            pounder,
+            ClockSource::Onboard,
+            context.beat_present,
+            output,
+        ),
+        (Command::ClockExternal(frequency, multiplier), Some(pounder)) => {
+            select_clock(
+                pounder,
+                ClockSource::External(frequency, multiplier),
+                context.beat_present,
+                output,
+            )
         }
     }
 }
@@ -183,12 +882,21 @@ mod app {
 
     #[shared]
     struct Shared {
-        usb_terminal: SerialTerminal,
+        usb_terminal: Option<SerialTerminal>,
         network: NetworkUsers<Settings, Telemetry, 3>,
 
         settings: Settings,
         telemetry: TelemetryBuffer,
         signal_generator: [SignalGenerator; 2],
+        cpu_temp_sensor: stabilizer::hardware::cpu_temp_sensor::CpuTempSensor,
+        pounder: Option<hardware::setup::PounderDevices>,
+        servo_config: ServoConfig,
+        watchdog: Watchdog,
+        status_leds: FrontPanelLeds,
+        dac_manual: [ManualOutput; 2],
+        adc_calibration: [AdcCalibration; 2],
+        uptime: Uptime,
+        lock_tare: bool,
     }
 
     #[local]
@@ -201,20 +909,47 @@ mod app {
         beat_timer: crate::hardware::pounder::timestamp::InputCaptureTimer,
         iir_state: [[iir::Vec5<f32>; IIR_CASCADE_LENGTH]; 2],
         generator: FrameGenerator,
-        cpu_temp_sensor: stabilizer::hardware::cpu_temp_sensor::CpuTempSensor,
+        adc_generator: AdcStreamGenerator,
+        batch_sequence: u32,
+        lock: LockController,
+        eeprom_i2c: hal::i2c::I2c<hal::stm32::I2C2>,
+        delay: AsmDelay,
+        reference_clock: ReferenceClock,
+    }
+
+    /// Get the current [SystemTimer] ticks.
+    ///
+    /// # Note
+    /// The system timer is the monotonic truncated to 32 bits. All users of the system timer,
+    /// including the uptime, must use this so that they agree on its wraps.
+    fn system_ticks() -> u32 {
+        monotonics::now().ticks() as u32
     }
 
     #[init]
     fn init(c: init::Context) -> (Shared, Local, init::Monotonics) {
-        let clock = SystemTimer::new(|| monotonics::now().ticks() as u32);
+        let clock = SystemTimer::new(system_ticks);
 
         // Configure the microcontroller
-        let (mut stabilizer, beat_timer) = hardware::setup::setup(
+        let (mut stabilizer, pounder, beat_timer) = hardware::setup::setup(
             c.core,
             c.device,
             clock,
-            BATCH_SIZE,
-            SAMPLE_TICKS,
+            hardware::setup::SetupConfig {
+                usb_enabled: USB_ENABLED,
+                ..hardware::setup::SetupConfig::new(BATCH_SIZE, SAMPLE_TICKS)
+            },
+        );
+
+        if let Err(condition) = stabilizer.wait_ready(READY_TIMEOUT_MS) {
+            log::error!("Hardware did not settle: {:?}", condition);
+        }
+
+        log::info!("Servo configuration: {:?}", stabilizer.servo_config);
+        log::info!(
+            "Sampling at {} Hz in batches of {}",
+            stabilizer.sample_rate_hz(),
+            stabilizer.batch_size()
         );
 
         let mut network = NetworkUsers::new(
@@ -227,10 +962,12 @@ mod app {
         );
 
         let generator = network.configure_streaming(StreamFormat::AdcDacData);
+        let adc_generator =
+            network.configure_adc_streaming((1. / SAMPLE_PERIOD) as u32);
 
         let settings = Settings::default();
 
-        let shared = Shared {
+        let mut shared = Shared {
             usb_terminal: stabilizer.usb_serial,
             network,
             settings,
@@ -247,6 +984,18 @@ mod app {
                         .unwrap(),
                 ),
             ],
+            cpu_temp_sensor: stabilizer.temperature_sensor,
+            pounder,
+            servo_config: stabilizer.servo_config,
+            watchdog: Watchdog::new(stabilizer.iwdg, SERVO_WATCHDOG_TIMEOUT_MS),
+            status_leds: stabilizer.status_leds,
+            dac_manual: [
+                stabilizer.dacs.0.manual_output(),
+                stabilizer.dacs.1.manual_output(),
+            ],
+            adc_calibration: stabilizer.adc_calibration,
+            uptime: Uptime::new(),
+            lock_tare: false,
         };
 
         let mut local = Local {
@@ -258,7 +1007,20 @@ mod app {
             beat_timer: beat_timer,
             iir_state: [[[0.; 5]; IIR_CASCADE_LENGTH]; 2],
             generator,
-            cpu_temp_sensor: stabilizer.temperature_sensor,
+            adc_generator,
+            batch_sequence: 0,
+            lock: LockController::new(
+                LOCK_CONFIG,
+                PiController::new(
+                    stabilizer.servo_config.kp,
+                    stabilizer.servo_config.ki,
+                    stabilizer.servo_config.setpoint,
+                    LOCK_SERVO_LIMITS,
+                ),
+            ),
+            eeprom_i2c: stabilizer.eeprom_i2c,
+            delay: stabilizer.delay,
+            reference_clock: stabilizer.reference_clock,
         };
 
         // Enable ADC/DAC events
@@ -273,9 +1035,23 @@ mod app {
         // Spawn a settings update for default settings.
         settings_update::spawn().unwrap();
         telemetry::spawn().unwrap();
+        cpu_telemetry::spawn().unwrap();
         ethernet_link::spawn().unwrap();
-        usb::spawn().unwrap();
+        leds::spawn().unwrap();
+        if USB_ENABLED {
+            usb::spawn().unwrap();
+        }
+        if shared.pounder.is_some() {
+            lock::spawn().unwrap();
+            attenuator_scan::spawn().unwrap();
+        }
         start::spawn_after(100.millis()).unwrap();
+        watchdog::spawn_after((SERVO_WATCHDOG_TIMEOUT_MS as u64).millis())
+            .unwrap();
+
+        // The IWDG was started while the shared resources were constructed. Restart its timeout
+        // so the first check has the full margin.
+        shared.watchdog.feed();
 
         (shared, local, init::Monotonics(stabilizer.systick))
     }
@@ -302,15 +1078,21 @@ mod app {
     ///
     /// Because the ADC and DAC operate at the same rate, these two constraints actually implement
     /// the same time bounds, meeting one also means the other is also met.
-    #[task(binds=DMA1_STR4, local=[digital_inputs, adcs, dacs, beat_timer, iir_state, generator], shared=[settings, signal_generator, telemetry], priority=3)]
+    #[task(binds=DMA1_STR4, local=[digital_inputs, adcs, dacs, beat_timer, iir_state, generator, adc_generator, batch_sequence], shared=[settings, signal_generator, telemetry, watchdog, dac_manual], priority=3)]
     #[link_section = ".itcm.process"]
     fn process(c: process::Context) {
         let process::SharedResources {
             settings,
             telemetry,
             signal_generator,
+            mut watchdog,
+            mut dac_manual,
         } = c.shared;
 
+        watchdog.lock(|watchdog| watchdog.kick());
+        let manual_codes =
+            dac_manual.lock(|manual| manual.map(|output| output.code()));
+
         let process::LocalResources {
             digital_inputs,
             adcs: (adc0, adc1),
@@ -318,6 +1100,8 @@ mod app {
             beat_timer,
             iir_state,
             generator,
+            adc_generator,
+            batch_sequence,
         } = c.local;
 
         (settings, telemetry, signal_generator).lock(
@@ -329,79 +1113,105 @@ mod app {
                 let hold = settings.force_hold
                     || (digital_inputs[1] && settings.allow_hold);
 
-                (adc0, adc1, dac0, dac1).lock(|adc0, adc1, dac0, dac1| {
-                    let adc_samples = [adc0, adc1];
-                    let dac_samples = [dac0, dac1];
-
-                    // Preserve instruction and data ordering w.r.t. DMA flag access.
-                    fence(Ordering::SeqCst);
-
-                    let timestamp_diff = beat_timer.latest_timestamp_diff();
-                    // Set all values in adc_samples to new_value
-                    for channel in 0..adc_samples.len() {
-                        for sample in adc_samples[channel].iter_mut() {
-                            *sample = timestamp_diff*2;
+                // A batch with a DMA error is dropped. The error is counted by the failing ADC or
+                // DAC.
+                hardware::with_batches(
+                    (adc0, adc1),
+                    (dac0, dac1),
+                    |adc0, adc1, dac0, dac1| {
+                        let adc_samples = [adc0, adc1];
+                        let dac_samples = [dac0, dac1];
+
+                        // Preserve instruction and data ordering w.r.t. DMA flag access.
+                        fence(Ordering::SeqCst);
+
+                        // Stream the raw ADC samples before they are replaced below.
+                        adc_generator.add(
+                            *batch_sequence,
+                            &adc_samples[0][..],
+                            &adc_samples[1][..],
+                        );
+                        *batch_sequence = batch_sequence.wrapping_add(1);
+
+                        let timestamp_diff = beat_timer.latest_timestamp_diff();
+                        telemetry.beat_frequency =
+                            beat_timer.diff_to_frequency(timestamp_diff);
+                        telemetry.capture_statistics = beat_timer.statistics();
+
+                        // Set all values in adc_samples to new_value
+                        for channel in 0..adc_samples.len() {
+                            for sample in adc_samples[channel].iter_mut() {
+                                *sample = timestamp_diff * 2;
+                            }
                         }
-                    }
-
-                    for channel in 0..adc_samples.len() {
-                        adc_samples[channel]
-                            .iter()
-                            .zip(dac_samples[channel].iter_mut())
-                            .zip(&mut signal_generator[channel])
-                            .map(|((ai, di), signal)| {
-                                let x = f32::from((*ai as i16) - 10000);
-                                let y = settings.iir_ch[channel]
-                                    .iter()
-                                    .zip(iir_state[channel].iter_mut())
-                                    .fold(x, |yi, (ch, state)| {
-                                        ch.update(state, yi, hold)
-                                    });
-
-                                // Note(unsafe): The filter limits must ensure that the value is in range.
-                                // The truncation introduces 1/2 LSB distortion.
-                                let y: i16 = unsafe { y.to_int_unchecked() };
-
-                                let y = y.saturating_add(signal);
-
-                                // Convert to DAC code
-                                *di = DacCode::from(y).0;
-                            })
-                            .last();
-                    }
 
-                    // Stream the data.
-                    const N: usize = BATCH_SIZE * core::mem::size_of::<i16>();
-                    generator.add(|buf| {
-                        for (data, buf) in adc_samples
-                            .iter()
-                            .chain(dac_samples.iter())
-                            .zip(buf.chunks_exact_mut(N))
-                        {
-                            let data = unsafe {
-                                core::slice::from_raw_parts(
-                                    data.as_ptr() as *const MaybeUninit<u8>,
-                                    N,
-                                )
-                            };
-                            buf.copy_from_slice(data)
+                        for channel in 0..adc_samples.len() {
+                            adc_samples[channel]
+                                .iter()
+                                .zip(dac_samples[channel].iter_mut())
+                                .zip(&mut signal_generator[channel])
+                                .map(|((ai, di), signal)| {
+                                    let x = f32::from((*ai as i16) - 10000);
+                                    let y = settings.iir_ch[channel]
+                                        .iter()
+                                        .zip(iir_state[channel].iter_mut())
+                                        .fold(x, |yi, (ch, state)| {
+                                            ch.update(state, yi, hold)
+                                        });
+
+                                    // Note(unsafe): The filter limits must ensure that the value is in range.
+                                    // The truncation introduces 1/2 LSB distortion.
+                                    let y: i16 =
+                                        unsafe { y.to_int_unchecked() };
+
+                                    let y = y.saturating_add(signal);
+
+                                    // Convert to DAC code
+                                    *di = DacCode::from(y).0;
+                                })
+                                .last();
+
+                            if let Some(code) = manual_codes[channel] {
+                                dac_samples[channel].fill(code);
+                            }
                         }
-                        N * 4
-                    });
-                    // Update telemetry measurements.
-                    telemetry.adcs = [
-                        AdcCode(adc_samples[0][0]),
-                        AdcCode(adc_samples[1][0]),
-                    ];
-
-                    telemetry.dacs = [
-                        DacCode(dac_samples[0][0]),
-                        DacCode(dac_samples[1][0]),
-                    ];
-
-                    // Preserve instruction and data ordering w.r.t. DMA flag access.
-                    fence(Ordering::SeqCst);
-                });
+
+                        // Stream the data.
+                        const N: usize =
+                            BATCH_SIZE * core::mem::size_of::<i16>();
+                        generator.set_decimation(settings.stream_decimation);
+                        generator.add(|buf| {
+                            for (data, buf) in adc_samples
+                                .iter()
+                                .chain(dac_samples.iter())
+                                .zip(buf.chunks_exact_mut(N))
+                            {
+                                let data = unsafe {
+                                    core::slice::from_raw_parts(
+                                        data.as_ptr() as *const MaybeUninit<u8>,
+                                        N,
+                                    )
+                                };
+                                buf.copy_from_slice(data)
+                            }
+                            N * 4
+                        });
+                        // Update telemetry measurements.
+                        telemetry.adcs = [
+                            AdcCode(adc_samples[0][0]),
+                            AdcCode(adc_samples[1][0]),
+                        ];
+
+                        telemetry.dacs = [
+                            DacCode(dac_samples[0][0]),
+                            DacCode(dac_samples[1][0]),
+                        ];
+
+                        // Preserve instruction and data ordering w.r.t. DMA flag access.
+                        fence(Ordering::SeqCst);
+                    },
+                )
+                .ok();
             },
         );
     }
@@ -416,10 +1226,11 @@ mod app {
                 NetworkState::Updated => {}
                 NetworkState::NoChange => {
                     // We can't sleep if USB is not in suspend.
-                    if c.shared
-                        .usb_terminal
-                        .lock(|terminal| terminal.usb_is_suspended())
-                    {
+                    if c.shared.usb_terminal.lock(|terminal| {
+                        terminal.as_ref().map_or(true, |terminal| {
+                            terminal.usb_is_suspended()
+                        })
+                    }) {
                         cortex_m::asm::wfi();
                     }
                 }
@@ -453,46 +1264,281 @@ mod app {
 
         let target = settings.stream_target.into();
         c.shared.network.lock(|net| net.direct_stream(target));
+
+        let target = settings.adc_stream_target.into();
+        c.shared
+            .network
+            .lock(|net| net.adc_stream.set_remote(target));
+
+        let target = settings.cpu_telemetry_target.into();
+        c.shared
+            .network
+            .lock(|net| net.cpu_telemetry.set_remote(target));
     }
 
-    #[task(priority = 1, shared=[network, settings, telemetry], local=[cpu_temp_sensor])]
+    #[task(priority = 1, shared=[network, settings, telemetry, cpu_temp_sensor, adc_calibration, uptime])]
     fn telemetry(mut c: telemetry::Context) {
-        let telemetry: TelemetryBuffer =
+        let mut telemetry: TelemetryBuffer =
             c.shared.telemetry.lock(|telemetry| *telemetry);
 
+        telemetry.uptime = c.shared.uptime.lock(|uptime| {
+            uptime.update(system_ticks());
+            uptime.seconds()
+        });
+
         let (gains, telemetry_period) = c
             .shared
             .settings
             .lock(|settings| (settings.afe, settings.telemetry_period));
 
-        c.shared.network.lock(|net| {
-            net.telemetry.publish(&telemetry.finalize(
-                gains[0],
-                gains[1],
-                c.local.cpu_temp_sensor.get_temperature().unwrap(),
-            ))
-        });
+        // The ADC may be in use by another driver. The temperature is then reported as NaN.
+        let cpu_temp = c
+            .shared
+            .cpu_temp_sensor
+            .lock(|sensor| sensor.get_temperature())
+            .unwrap_or_else(|err| {
+                log::warn!("Failed to sample CPU temperature: {:?}", err);
+                f32::NAN
+            });
+
+        let adc_calibration =
+            c.shared.adc_calibration.lock(|calibration| *calibration);
+
+        let telemetry =
+            telemetry.finalize(gains[0], gains[1], &adc_calibration, cpu_temp);
+        c.shared
+            .network
+            .lock(|net| net.telemetry.publish(&telemetry));
 
         // Schedule the telemetry task in the future.
         telemetry::Monotonic::spawn_after((telemetry_period as u64).secs())
             .unwrap();
     }
 
-    #[task(priority = 1, shared=[usb_terminal])]
-    fn usb(mut c: usb::Context) {
+    #[task(priority = 1, shared=[network, settings, cpu_temp_sensor, uptime])]
+    fn cpu_telemetry(mut c: cpu_telemetry::Context) {
+        let period = c
+            .shared
+            .settings
+            .lock(|settings| settings.cpu_telemetry_period);
+
+        // The ADC may be in use by another driver. Skip this cycle instead of failing.
+        match c
+            .shared
+            .cpu_temp_sensor
+            .lock(|sensor| sensor.get_temperature())
+        {
+            Ok(cpu_temp_c) => {
+                let uptime_s = c.shared.uptime.lock(|uptime| {
+                    uptime.update(system_ticks());
+                    uptime.seconds()
+                });
+                let telemetry = CpuTelemetry {
+                    cpu_temp_c,
+                    uptime_s: uptime_s as u32,
+                };
+                c.shared
+                    .network
+                    .lock(|net| net.cpu_telemetry.publish(&telemetry));
+            }
+            Err(err) => {
+                log::warn!("Failed to sample CPU temperature: {:?}", err)
+            }
+        }
+
+        cpu_telemetry::Monotonic::spawn_after((period as u64).secs()).unwrap();
+    }
+
+    #[task(priority = 1, local=[lock], shared=[settings, servo_config, telemetry, pounder, lock_tare])]
+    fn lock(mut c: lock::Context) {
+        let lock = c.local.lock;
+
+        // A tare requested while the lock is disabled is discarded.
+        let tare = c.shared.lock_tare.lock(core::mem::take);
+
+        if c.shared.settings.lock(|settings| settings.lock) {
+            let config = c.shared.servo_config.lock(|config| *config);
+            lock.servo.kp = config.kp;
+            lock.servo.ki = config.ki;
+            lock.servo.setpoint = config.setpoint;
+
+            let beat = c
+                .shared
+                .telemetry
+                .lock(|telemetry| telemetry.beat_frequency);
+            if tare && beat.is_finite() {
+                // Clearing the integrator keeps the correction from jumping at the new setpoint.
+                lock.servo.tare(beat);
+                c.shared.servo_config.lock(|config| config.setpoint = beat);
+            }
+
+            let state = lock.state();
+            if lock.update(beat) != state {
+                log::info!("Lock state: {:?}", lock.state());
+            }
+
+            let frequency = lock.frequency();
+            c.shared.pounder.lock(|pounder| {
+                if let Some(pounder) = pounder {
+                    if let Err(err) = pounder.pounder.set_dds_frequency(
+                        &mut pounder.dds_output,
+                        LOCK_CHANNEL,
+                        frequency,
+                    ) {
+                        log::error!(
+                            "Failed to update the lock frequency: {err}"
+                        );
+                    }
+                }
+            });
+        } else if lock.state() != LockState::Search {
+            log::info!("Lock disabled");
+            lock.restart();
+        }
+
+        lock::spawn_after(LOCK_PERIOD_MS.millis()).unwrap();
+    }
+
+    #[task(priority = 1, local=[eeprom_i2c, delay, reference_clock], shared=[usb_terminal, telemetry, pounder, servo_config, cpu_temp_sensor, network, dac_manual, adc_calibration, lock_tare])]
+    fn usb(c: usb::Context) {
+        let usb::SharedResources {
+            usb_terminal,
+            mut telemetry,
+            pounder,
+            servo_config,
+            cpu_temp_sensor,
+            mut network,
+            mut dac_manual,
+            mut adc_calibration,
+            mut lock_tare,
+        } = c.shared;
+        let usb::LocalResources {
+            eeprom_i2c,
+            delay,
+            reference_clock,
+        } = c.local;
+
         // Handle the USB serial terminal.
-        c.shared.usb_terminal.lock(|usb| usb.process());
+        (usb_terminal, pounder, servo_config, cpu_temp_sensor).lock(
+            |usb, pounder, servo_config, cpu_temp_sensor| {
+                let Some(usb) = usb.as_mut() else {
+                    return;
+                };
+                usb.process(|command, output| {
+                    let beat_frequency =
+                        telemetry.lock(|telemetry| telemetry.beat_frequency);
+                    let link_up = network.lock(|net| net.processor.link_up());
+                    // The overrides and calibrations are only modified here, so they are copied to
+                    // avoid blocking the processing while the command executes.
+                    let mut manual = dac_manual.lock(|manual| *manual);
+                    let mut adc = adc_calibration.lock(|adc| *adc);
+                    let mut tare = false;
+                    let mut beat_present = || {
+                        cortex_m::asm::delay(BEAT_DETECT_CYCLES);
+                        telemetry.lock(|telemetry| {
+                            telemetry.beat_frequency.is_finite()
+                        })
+                    };
+                    let mut context = TerminalContext {
+                        pounder: pounder.as_mut(),
+                        servo_config: &mut *servo_config,
+                        eeprom_i2c: &mut *eeprom_i2c,
+                        delay: &mut *delay,
+                        cpu_temp_sensor: &mut *cpu_temp_sensor,
+                        beat_frequency,
+                        beat_present: &mut beat_present,
+                        reference_clock: *reference_clock,
+                        link_up,
+                        dac_manual: &mut manual,
+                        adc_calibration: &mut adc,
+                        lock_tare: &mut tare,
+                    };
+                    if handle_command(command, &mut context, output).is_err() {
+                        log::warn!("Terminal response formatting failed");
+                    }
+                    dac_manual.lock(|current| *current = manual);
+                    adc_calibration.lock(|current| *current = adc);
+                    if tare {
+                        lock_tare.lock(|current| *current = true);
+                    }
+                })
+            },
+        );
 
         // Schedule to run this task every 10 milliseconds.
         usb::spawn_after(10u64.millis()).unwrap();
     }
 
-    #[task(priority = 1, shared=[network])]
+    #[task(priority = 1, shared=[watchdog, pounder, status_leds])]
+    fn watchdog(mut c: watchdog::Context) {
+        let (event, timeout_ms) = c
+            .shared
+            .watchdog
+            .lock(|watchdog| (watchdog.check(), watchdog.timeout_ms()));
+
+        match event {
+            Some(WatchdogEvent::Stalled) => {
+                log::error!("Servo stalled, attenuating RF outputs");
+                c.shared.status_leds.lock(|leds| {
+                    leds.set_pattern(Led::Error, SERVO_STALLED_PATTERN)
+                });
+                c.shared.pounder.lock(|pounder| {
+                    if let Some(pounder) = pounder {
+                        if let Err(err) = pounder.pounder.write_all(31.5) {
+                            log::error!(
+                                "Failed to attenuate RF outputs: {err}"
+                            );
+                        }
+                    }
+                });
+            }
+            Some(WatchdogEvent::Recovered) => {
+                log::warn!("Servo resumed");
+                c.shared
+                    .status_leds
+                    .lock(|leds| leds.set_pattern(Led::Error, Pattern::Off));
+            }
+            None => {}
+        }
+
+        watchdog::spawn_after((timeout_ms as u64).millis()).unwrap();
+    }
+
+    #[task(priority = 1, shared=[pounder])]
+    fn attenuator_scan(mut c: attenuator_scan::Context) {
+        c.shared.pounder.lock(|pounder| {
+            if let Some(pounder) = pounder {
+                if let Err(err) = pounder.pounder.update_attenuator_scan() {
+                    log::warn!("Failed to scan the attenuators: {err}");
+                }
+            }
+        });
+
+        attenuator_scan::spawn_after(ATTENUATOR_SCAN_PERIOD_MS.millis())
+            .unwrap();
+    }
+
+    #[task(priority = 1, shared=[network, status_leds])]
     fn ethernet_link(mut c: ethernet_link::Context) {
-        c.shared.network.lock(|net| net.processor.handle_link());
+        match c.shared.network.lock(|net| net.processor.handle_link()) {
+            LinkChange::Up => {
+                c.shared.status_leds.lock(|leds| leds.set_network(true))
+            }
+            LinkChange::Down => {
+                c.shared.status_leds.lock(|leds| leds.set_network(false))
+            }
+            LinkChange::NoChange => {}
+        }
         ethernet_link::Monotonic::spawn_after(1.secs()).unwrap();
     }
 
+    #[task(priority = 1, shared=[status_leds])]
+    fn leds(mut c: leds::Context) {
+        let now = system_ticks();
+        c.shared.status_leds.lock(|leds| leds.update(now));
+        leds::spawn_after(LED_UPDATE_PERIOD_MS.millis()).unwrap();
+    }
+
     #[task(binds = ETH, priority = 1)]
     fn eth(_: eth::Context) {
         unsafe { hal::ethernet::interrupt_handler() }
@@ -500,12 +1546,12 @@ mod app {
 
     #[task(binds = SPI2, priority = 4)]
     fn spi2(_: spi2::Context) {
-        panic!("ADC0 SPI error");
+        Adc0Input::handle_spi_error();
     }
 
     #[task(binds = SPI3, priority = 4)]
     fn spi3(_: spi3::Context) {
-        panic!("ADC1 SPI error");
+        Adc1Input::handle_spi_error();
     }
 
     #[task(binds = SPI4, priority = 4)]
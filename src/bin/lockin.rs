@@ -30,26 +30,27 @@
 
 use core::{
     convert::TryFrom,
+    fmt::Write,
     mem::MaybeUninit,
     sync::atomic::{fence, Ordering},
 };
 
 use fugit::ExtU64;
-use mutex_trait::prelude::*;
 
 use idsp::{Accu, Chain, Complex, ComplexExt, Filter, Lockin, Lowpass, RPLL};
 
 use stabilizer::{
     hardware::{
         self,
-        adc::{Adc0Input, Adc1Input, AdcCode},
+        adc::{Adc0Input, Adc1Input, AdcCalibration, AdcCode},
         afe::Gain,
         dac::{Dac0Output, Dac1Output, DacCode},
         hal,
         input_stamper::InputStamper,
-        serial_terminal::SerialTerminal,
+        serial_terminal::{self, Command, SerialTerminal},
         signal_generator,
         timers::SamplingTimer,
+        uptime::Uptime,
         DigitalInput0, DigitalInput1, SystemTimer, Systick, AFE0, AFE1,
     },
     net::{
@@ -72,6 +73,12 @@ const BATCH_SIZE: usize = 1 << BATCH_SIZE_LOG2;
 const SAMPLE_TICKS_LOG2: u32 = 7;
 const SAMPLE_TICKS: u32 = 1 << SAMPLE_TICKS_LOG2;
 
+// The time in milliseconds to wait for the analog supplies and clocks to settle after setup.
+const READY_TIMEOUT_MS: u32 = 100;
+
+// Whether to provide the USB serial terminal. Headless deployments can disable it.
+const USB_ENABLED: bool = true;
+
 #[derive(Copy, Clone, Debug, Serialize, Deserialize)]
 enum Conf {
     /// Output the lockin magnitude.
@@ -222,10 +229,11 @@ mod app {
 
     #[shared]
     struct Shared {
-        usb_terminal: SerialTerminal,
+        usb_terminal: Option<SerialTerminal>,
         network: NetworkUsers<Settings, Telemetry, 2>,
         settings: Settings,
         telemetry: TelemetryBuffer,
+        cpu_temp_sensor: stabilizer::hardware::cpu_temp_sensor::CpuTempSensor,
     }
 
     #[local]
@@ -240,7 +248,8 @@ mod app {
         lockin: Lockin<Chain<2, Lowpass<2>>>,
         signal_generator: signal_generator::SignalGenerator,
         generator: FrameGenerator,
-        cpu_temp_sensor: stabilizer::hardware::cpu_temp_sensor::CpuTempSensor,
+        uptime: Uptime,
+        adc_calibration: [AdcCalibration; 2],
     }
 
     #[init]
@@ -248,14 +257,20 @@ mod app {
         let clock = SystemTimer::new(|| monotonics::now().ticks() as u32);
 
         // Configure the microcontroller
-        let (mut stabilizer, _pounder) = hardware::setup::setup(
+        let (mut stabilizer, _pounder, _beat_timer) = hardware::setup::setup(
             c.core,
             c.device,
             clock,
-            BATCH_SIZE,
-            SAMPLE_TICKS,
+            hardware::setup::SetupConfig {
+                usb_enabled: USB_ENABLED,
+                ..hardware::setup::SetupConfig::new(BATCH_SIZE, SAMPLE_TICKS)
+            },
         );
 
+        if let Err(condition) = stabilizer.wait_ready(READY_TIMEOUT_MS) {
+            log::error!("Hardware did not settle: {:?}", condition);
+        }
+
         let mut network = NetworkUsers::new(
             stabilizer.net.stack,
             stabilizer.net.phy,
@@ -272,6 +287,7 @@ mod app {
             usb_terminal: stabilizer.usb_serial,
             telemetry: TelemetryBuffer::default(),
             settings: Settings::default(),
+            cpu_temp_sensor: stabilizer.temperature_sensor,
         };
 
         let signal_config = signal_generator::Config {
@@ -298,7 +314,8 @@ mod app {
             ),
 
             generator,
-            cpu_temp_sensor: stabilizer.temperature_sensor,
+            uptime: Uptime::new(),
+            adc_calibration: stabilizer.adc_calibration,
         };
 
         // Enable ADC/DAC events
@@ -378,79 +395,93 @@ mod app {
                 reference_phase.wrapping_mul(settings.lockin_harmonic),
             );
 
-            (adc0, adc1, dac0, dac1).lock(|adc0, adc1, dac0, dac1| {
-                let adc_samples = [adc0, adc1];
-                let mut dac_samples = [dac0, dac1];
-
-                // Preserve instruction and data ordering w.r.t. DMA flag access.
-                fence(Ordering::SeqCst);
-
-                let output: Complex<i32> = adc_samples[0]
-                    .iter()
-                    // Zip in the LO phase.
-                    .zip(Accu::new(sample_phase, sample_frequency))
-                    // Convert to signed, MSB align the ADC sample, update the Lockin (demodulate, filter)
-                    .map(|(&sample, phase)| {
-                        let s = (sample as i16 as i32) << 16;
-                        lockin.update(s, phase, &settings.lockin_k)
-                    })
-                    // Decimate
-                    .last()
-                    .unwrap()
-                    * 2; // Full scale assuming the 2f component is gone.
-
-                // Convert to DAC data.
-                for (channel, samples) in dac_samples.iter_mut().enumerate() {
-                    for sample in samples.iter_mut() {
-                        let value = match settings.output_conf[channel] {
-                            Conf::Magnitude => output.abs_sqr() as i32 >> 16,
-                            Conf::Phase => output.arg() >> 16,
-                            Conf::LogPower => output.log2() << 8,
-                            Conf::ReferenceFrequency => {
-                                reference_frequency >> 16
-                            }
-                            Conf::InPhase => output.re >> 16,
-                            Conf::Quadrature => output.im >> 16,
-
-                            Conf::Modulation => {
-                                signal_generator.next().unwrap() as i32
-                            }
-                        };
-
-                        *sample = DacCode::from(value as i16).0;
-                    }
-                }
+            // A batch with a DMA error is dropped. The error is counted by the failing ADC or
+            // DAC.
+            hardware::with_batches(
+                (adc0, adc1),
+                (dac0, dac1),
+                |adc0, adc1, dac0, dac1| {
+                    let adc_samples = [adc0, adc1];
+                    let mut dac_samples = [dac0, dac1];
 
-                // Stream the data.
-                const N: usize = BATCH_SIZE * core::mem::size_of::<i16>()
-                    / core::mem::size_of::<MaybeUninit<u8>>();
-                generator.add(|buf| {
-                    for (data, buf) in adc_samples
+                    // Preserve instruction and data ordering w.r.t. DMA flag access.
+                    fence(Ordering::SeqCst);
+
+                    let output: Complex<i32> = adc_samples[0]
                         .iter()
-                        .chain(dac_samples.iter())
-                        .zip(buf.chunks_exact_mut(N))
+                        // Zip in the LO phase.
+                        .zip(Accu::new(sample_phase, sample_frequency))
+                        // Convert to signed, MSB align the ADC sample, update the Lockin (demodulate, filter)
+                        .map(|(&sample, phase)| {
+                            let s = (sample as i16 as i32) << 16;
+                            lockin.update(s, phase, &settings.lockin_k)
+                        })
+                        // Decimate
+                        .last()
+                        .unwrap()
+                        * 2; // Full scale assuming the 2f component is gone.
+
+                    // Convert to DAC data.
+                    for (channel, samples) in dac_samples.iter_mut().enumerate()
                     {
-                        let data = unsafe {
-                            core::slice::from_raw_parts(
-                                data.as_ptr() as *const MaybeUninit<u8>,
-                                N,
-                            )
-                        };
-                        buf.copy_from_slice(data)
+                        for sample in samples.iter_mut() {
+                            let value = match settings.output_conf[channel] {
+                                Conf::Magnitude => {
+                                    output.abs_sqr() as i32 >> 16
+                                }
+                                Conf::Phase => output.arg() >> 16,
+                                Conf::LogPower => output.log2() << 8,
+                                Conf::ReferenceFrequency => {
+                                    reference_frequency >> 16
+                                }
+                                Conf::InPhase => output.re >> 16,
+                                Conf::Quadrature => output.im >> 16,
+
+                                Conf::Modulation => {
+                                    signal_generator.next().unwrap() as i32
+                                }
+                            };
+
+                            *sample = DacCode::from(value as i16).0;
+                        }
                     }
-                    N * 4
-                });
-
-                // Update telemetry measurements.
-                telemetry.adcs =
-                    [AdcCode(adc_samples[0][0]), AdcCode(adc_samples[1][0])];
 
-                telemetry.dacs =
-                    [DacCode(dac_samples[0][0]), DacCode(dac_samples[1][0])];
-
-                // Preserve instruction and data ordering w.r.t. DMA flag access.
-                fence(Ordering::SeqCst);
-            });
+                    // Stream the data.
+                    const N: usize = BATCH_SIZE * core::mem::size_of::<i16>()
+                        / core::mem::size_of::<MaybeUninit<u8>>();
+                    generator.add(|buf| {
+                        for (data, buf) in adc_samples
+                            .iter()
+                            .chain(dac_samples.iter())
+                            .zip(buf.chunks_exact_mut(N))
+                        {
+                            let data = unsafe {
+                                core::slice::from_raw_parts(
+                                    data.as_ptr() as *const MaybeUninit<u8>,
+                                    N,
+                                )
+                            };
+                            buf.copy_from_slice(data)
+                        }
+                        N * 4
+                    });
+
+                    // Update telemetry measurements.
+                    telemetry.adcs = [
+                        AdcCode(adc_samples[0][0]),
+                        AdcCode(adc_samples[1][0]),
+                    ];
+
+                    telemetry.dacs = [
+                        DacCode(dac_samples[0][0]),
+                        DacCode(dac_samples[1][0]),
+                    ];
+
+                    // Preserve instruction and data ordering w.r.t. DMA flag access.
+                    fence(Ordering::SeqCst);
+                },
+            )
+            .ok();
         });
     }
 
@@ -464,10 +495,11 @@ mod app {
                 NetworkState::Updated => {}
                 NetworkState::NoChange => {
                     // We can't sleep if USB is not in suspend.
-                    if c.shared
-                        .usb_terminal
-                        .lock(|terminal| terminal.usb_is_suspended())
-                    {
+                    if c.shared.usb_terminal.lock(|terminal| {
+                        terminal.as_ref().map_or(true, |terminal| {
+                            terminal.usb_is_suspended()
+                        })
+                    }) {
                         cortex_m::asm::wfi();
                     }
                 }
@@ -487,7 +519,7 @@ mod app {
         c.shared.network.lock(|net| net.direct_stream(target));
     }
 
-    #[task(priority = 1, local=[digital_inputs, cpu_temp_sensor], shared=[network, settings, telemetry])]
+    #[task(priority = 1, local=[digital_inputs, uptime, adc_calibration], shared=[network, settings, telemetry, cpu_temp_sensor])]
     fn telemetry(mut c: telemetry::Context) {
         let mut telemetry: TelemetryBuffer =
             c.shared.telemetry.lock(|telemetry| *telemetry);
@@ -497,28 +529,104 @@ mod app {
             c.local.digital_inputs.1.is_high(),
         ];
 
+        c.local.uptime.update(monotonics::now().ticks() as u32);
+        telemetry.uptime = c.local.uptime.seconds();
+
         let (gains, telemetry_period) = c
             .shared
             .settings
             .lock(|settings| (settings.afe, settings.telemetry_period));
 
-        c.shared.network.lock(|net| {
-            net.telemetry.publish(&telemetry.finalize(
-                gains[0],
-                gains[1],
-                c.local.cpu_temp_sensor.get_temperature().unwrap(),
-            ))
-        });
+        let cpu_temp = c
+            .shared
+            .cpu_temp_sensor
+            .lock(|sensor| sensor.get_temperature().unwrap());
+
+        let telemetry = telemetry.finalize(
+            gains[0],
+            gains[1],
+            c.local.adc_calibration,
+            cpu_temp,
+        );
+        c.shared
+            .network
+            .lock(|net| net.telemetry.publish(&telemetry));
 
         // Schedule the telemetry task in the future.
         telemetry::Monotonic::spawn_after((telemetry_period as u64).secs())
             .unwrap();
     }
 
-    #[task(priority = 1, shared=[usb_terminal])]
-    fn usb(mut c: usb::Context) {
+    #[task(priority = 1, shared=[usb_terminal, cpu_temp_sensor])]
+    fn usb(c: usb::Context) {
+        let usb::SharedResources {
+            mut usb_terminal,
+            mut cpu_temp_sensor,
+        } = c.shared;
+
         // Handle the USB serial terminal.
-        c.shared.usb_terminal.lock(|usb| usb.process());
+        usb_terminal.lock(|usb| {
+            let Some(usb) = usb.as_mut() else {
+                return;
+            };
+            usb.process(|command, output| match command {
+                Command::DdsFrequency(..)
+                | Command::DdsRead(..)
+                | Command::DdsRegisterWrite(..)
+                | Command::DdsRegisterRead(..)
+                | Command::Status
+                | Command::ClockOnboard
+                | Command::ClockExternal(..) => {
+                    writeln!(output, "error: pounder not present").unwrap()
+                }
+                Command::Get(..)
+                | Command::Set(..)
+                | Command::Save
+                | Command::Load
+                | Command::List => {
+                    writeln!(output, "error: settings are not supported")
+                        .unwrap()
+                }
+                Command::SelfTest => {
+                    writeln!(output, "error: self-test is not supported")
+                        .unwrap()
+                }
+                Command::DacSet(..) => writeln!(
+                    output,
+                    "error: manual DAC output is not supported"
+                )
+                .unwrap(),
+                Command::CalDac(..)
+                | Command::CalAdc(..)
+                | Command::CalPower(..)
+                | Command::CalFlatness(..)
+                | Command::CalFlatnessClear
+                | Command::CalSave => {
+                    writeln!(output, "error: calibration is not supported")
+                        .unwrap()
+                }
+                Command::LockTare => {
+                    writeln!(output, "error: lock tare is not supported")
+                        .unwrap()
+                }
+                Command::LogLevel(level) => {
+                    log::set_max_level(level);
+                    writeln!(output, "log level: {level}").unwrap()
+                }
+                Command::LogLevelQuery => {
+                    writeln!(output, "log level: {}", log::max_level()).unwrap()
+                }
+                Command::LogDump => {
+                    serial_terminal::write_log_dump(output).unwrap()
+                }
+                Command::Temperature => serial_terminal::write_temperature(
+                    output,
+                    "cpu",
+                    cpu_temp_sensor.lock(|sensor| sensor.get_temperature()),
+                )
+                .unwrap(),
+            })
+        });
 
         // Schedule to run this task every 10 milliseconds.
         usb::spawn_after(10u64.millis()).unwrap();
@@ -48,6 +48,35 @@
 //! batch sizes generally provide for lower overhead and more processing time per sample, but come
 //! at the expense of increased input -> output latency.
 //!
+//! ## Batch Sequencing
+//!
+//! Every batch is assigned a monotonic sequence number, starting at zero for the first batch after
+//! the sampling timer is enabled. Because all batches are acquired at a fixed rate, the sampling
+//! timer tick at the start of a batch is the sequence number multiplied by the batch duration in
+//! timer ticks. This allows ADC data to be aligned with other data acquired relative to the
+//! sampling timer, such as beat note timestamps. If a batch is lost to a DMA overrun, it still
+//! consumes its sequence number so that the gap remains visible to consumers. The number of lost
+//! batches is available from `lost_batches()`, which is distinct from the SPI RX FIFO overruns
+//! counted by `overrun_count()`.
+//!
+//! ## Clip Detection
+//!
+//! Every acquired batch is checked for samples at or near the full-scale codes, which indicate
+//! that the input saturates the ADC. Clipped samples are counted and the input is flagged as
+//! clipping while the latest batch contains any, see [ClipDetector]. The margin from full scale
+//! within which a sample counts as clipped is configurable.
+//!
+//! ## Zero-copy Access
+//!
+//! Consumers that only read the samples can borrow the completed half of the double buffer with
+//! `acquire_batch()` instead of copying it. The half is lent as a [LentBatch], which borrows the
+//! input, so no further batch can be acquired while it is held, and which hands the half back to
+//! the DMA when dropped. The DMA fills the other half in the meantime and switches back to the
+//! lent half once the next batch completes, i.e. after one batch period. A batch held longer is
+//! overwritten while it may still be read. This is detected with [LentBatch::is_intact()], which
+//! consumers check after reading the samples, and counted as a torn batch when the batch is
+//! dropped, see `torn_batches()`.
+//!
 //!
 //! # Note
 //!
@@ -67,8 +96,9 @@
 //! buffer mode DMA disable/enable and buffer update sequence is slow.
 use stm32h7xx_hal as hal;
 
-use mutex_trait::Mutex;
+use core::sync::atomic::{AtomicU32, Ordering};
 
+use super::afe;
 use super::design_parameters::{SampleBuffer, MAX_SAMPLE_BUFFER_SIZE};
 use super::timers;
 
@@ -82,6 +112,188 @@ use hal::{
     spi::{HalDisabledSpi, HalEnabledSpi, HalSpi},
 };
 
+/// Batch sequence bookkeeping of an ADC input.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct BatchCounter {
+    next: u64,
+    lost: u32,
+    torn: u32,
+}
+
+impl BatchCounter {
+    /// Record the completion of a batch.
+    ///
+    /// # Returns
+    /// The sequence number assigned to the batch.
+    pub fn complete(&mut self) -> u64 {
+        let sequence = self.next;
+        self.next += 1;
+        sequence
+    }
+
+    /// Record a batch that was lost to an overrun.
+    ///
+    /// # Note
+    /// The lost batch consumes its sequence number, so the next completed batch reflects the
+    /// gap.
+    pub fn overrun(&mut self) {
+        self.next += 1;
+        self.lost = self.lost.wrapping_add(1);
+    }
+
+    /// Get the sequence number of the most recently completed or lost batch.
+    ///
+    /// # Returns
+    /// The sequence number, or `None` if no batch has been acquired yet.
+    pub fn latest(&self) -> Option<u64> {
+        self.next.checked_sub(1)
+    }
+
+    /// Get the number of batches lost to overruns.
+    pub fn lost_batches(&self) -> u32 {
+        self.lost
+    }
+
+    /// Record a lent batch that was overwritten by the DMA before it was released.
+    pub fn torn(&mut self) {
+        self.torn = self.torn.wrapping_add(1);
+    }
+
+    /// Get the number of lent batches that were overwritten before they were released.
+    pub fn torn_batches(&self) -> u32 {
+        self.torn
+    }
+}
+
+/// A completed batch lent from the DMA double buffer of an ADC input.
+///
+/// # Note
+/// The batch is handed back to the DMA when this is dropped. If the DMA completed the next batch
+/// in the meantime, it has switched back to the lent half of the double buffer and is
+/// overwriting the samples. The batch is then counted as torn.
+pub struct LentBatch<'a, F: Fn() -> bool> {
+    samples: &'a [u16],
+    batches: &'a mut BatchCounter,
+    next_complete: F,
+}
+
+impl<'a, F: Fn() -> bool> LentBatch<'a, F> {
+    /// Lend a batch.
+    ///
+    /// # Args
+    /// * `samples` - The completed half of the double buffer.
+    /// * `batches` - The batch bookkeeping of the input.
+    /// * `next_complete` - A function checking whether the DMA completed the next batch.
+    fn new(
+        samples: &'a [u16],
+        batches: &'a mut BatchCounter,
+        next_complete: F,
+    ) -> Self {
+        Self {
+            samples,
+            batches,
+            next_complete,
+        }
+    }
+
+    /// Check whether the samples were not overwritten by the DMA yet.
+    ///
+    /// # Note
+    /// Samples read before this returns true are valid.
+    pub fn is_intact(&self) -> bool {
+        !(self.next_complete)()
+    }
+}
+
+impl<F: Fn() -> bool> core::ops::Deref for LentBatch<'_, F> {
+    type Target = [u16];
+
+    fn deref(&self) -> &[u16] {
+        self.samples
+    }
+}
+
+impl<F: Fn() -> bool> Drop for LentBatch<'_, F> {
+    fn drop(&mut self) {
+        if !self.is_intact() {
+            self.batches.torn();
+        }
+    }
+}
+
+/// The default distance in codes from the full-scale codes within which a sample is clipped.
+pub const DEFAULT_CLIP_MARGIN: u16 = 16;
+
+/// Detection of ADC samples at or near full scale.
+#[derive(Copy, Clone, Debug)]
+pub struct ClipDetector {
+    margin: u16,
+    count: u32,
+    clipping: bool,
+}
+
+impl Default for ClipDetector {
+    fn default() -> Self {
+        Self::new(DEFAULT_CLIP_MARGIN)
+    }
+}
+
+impl ClipDetector {
+    /// Construct the clip detector.
+    ///
+    /// # Args
+    /// * `margin` - The distance in codes from the full-scale codes within which a sample is
+    ///   considered clipped. A margin of zero only detects the full-scale codes themselves.
+    pub fn new(margin: u16) -> Self {
+        Self {
+            margin,
+            count: 0,
+            clipping: false,
+        }
+    }
+
+    /// Check a batch of samples for clipping.
+    ///
+    /// # Args
+    /// * `batch` - The ADC-formatted samples of the batch.
+    ///
+    /// # Returns
+    /// The number of clipped samples in the batch.
+    pub fn process(&mut self, batch: &[u16]) -> u32 {
+        let min = i16::MIN as i32 + self.margin as i32;
+        let max = i16::MAX as i32 - self.margin as i32;
+        let clipped = batch
+            .iter()
+            .map(|&code| code as i16 as i32)
+            .filter(|&code| code <= min || code >= max)
+            .count() as u32;
+
+        self.count = self.count.wrapping_add(clipped);
+        self.clipping = clipped > 0;
+        clipped
+    }
+
+    /// Set the distance in codes from the full-scale codes within which a sample is clipped.
+    pub fn set_margin(&mut self, margin: u16) {
+        self.margin = margin;
+    }
+
+    /// Get the distance in codes from the full-scale codes within which a sample is clipped.
+    pub fn margin(&self) -> u16 {
+        self.margin
+    }
+
+    /// Get the number of clipped samples since boot. It wraps on overflow.
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    /// Check whether the latest batch contained clipped samples.
+    pub fn clipping(&self) -> bool {
+        self.clipping
+    }
+}
+
 /// A type representing an ADC sample.
 #[derive(Copy, Clone)]
 pub struct AdcCode(pub u16);
@@ -89,7 +301,7 @@ pub struct AdcCode(pub u16);
 impl AdcCode {
     // The ADC has a differential input with a range of +/- 4.096 V and 16-bit resolution.
     // The gain into the two inputs is 1/5.
-    const FULL_SCALE: f32 = 5.0 / 2.0 * 4.096;
+    pub const FULL_SCALE: f32 = 5.0 / 2.0 * 4.096;
     const VOLT_PER_LSB: f32 = -Self::FULL_SCALE / i16::MIN as f32;
     const LSB_PER_VOLT: f32 = 1. / Self::VOLT_PER_LSB;
 }
@@ -145,6 +357,76 @@ impl TryFrom<f32> for AdcCode {
     }
 }
 
+/// A linear calibration of an ADC input.
+///
+/// # Note
+/// The calibration models the actual input voltage as `gain * nominal + offset`, where `nominal`
+/// is the input voltage of an ideal ADC and AFE for a given code.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct AdcCalibration {
+    /// The ratio of the actual to the nominal input voltage.
+    pub gain: f32,
+
+    /// The input voltage in volts at a nominal input of zero.
+    pub offset: f32,
+}
+
+impl Default for AdcCalibration {
+    /// The identity calibration of an ideal ADC.
+    fn default() -> Self {
+        Self {
+            gain: 1.,
+            offset: 0.,
+        }
+    }
+}
+
+impl AdcCalibration {
+    /// Convert an ADC code into a calibrated input voltage.
+    ///
+    /// # Args
+    /// * `code` - The raw ADC code.
+    /// * `afe_gain` - The gain of the analog front end of the input.
+    pub fn codes_to_volts(&self, code: u16, afe_gain: afe::Gain) -> f32 {
+        self.gain * codes_to_volts(code, afe_gain) + self.offset
+    }
+}
+
+/// Convert an ADC code into the nominal input voltage.
+///
+/// # Note
+/// The input is bipolar. The code is two's complement, i.e. 0x8000 is the negative and 0x7FFF the
+/// positive full-scale input.
+///
+/// # Args
+/// * `code` - The raw ADC code.
+/// * `afe_gain` - The gain of the analog front end of the input.
+///
+/// # Returns
+/// The voltage at the input of the analog front end.
+pub fn codes_to_volts(code: u16, afe_gain: afe::Gain) -> f32 {
+    f32::from(AdcCode(code)) / afe_gain.as_multiplier()
+}
+
+/// Convert a batch of ADC codes into calibrated input voltages.
+///
+/// # Args
+/// * `codes` - The raw ADC codes.
+/// * `afe_gain` - The gain of the analog front end of the input.
+/// * `calibration` - The calibration of the input.
+/// * `volts` - The buffer to write the input voltages into. Only as many codes as fit are
+///   converted.
+pub fn codes_to_volts_batch(
+    codes: &[u16],
+    afe_gain: afe::Gain,
+    calibration: &AdcCalibration,
+    volts: &mut [f32],
+) {
+    for (volts, &code) in volts.iter_mut().zip(codes) {
+        *volts = calibration.codes_to_volts(code, afe_gain);
+    }
+}
+
 // The following data is written by the timer ADC sample trigger into the SPI CR1 to start the
 // transfer. Data in AXI SRAM is not initialized on boot, so the contents are random. This value is
 // initialized during setup.
@@ -258,6 +540,9 @@ macro_rules! adc_input {
                     &'static mut [u32; 1],
                     hal::dma::DBTransfer,
                 >,
+                batches: BatchCounter,
+                batch_ticks: u64,
+                clip: ClipDetector,
             }
 
             impl $name {
@@ -272,6 +557,9 @@ macro_rules! adc_input {
                 /// * `trigger_channel` - The ADC sampling timer output compare channel for read triggers.
                 /// * `clear_channel` - The shadow sampling timer output compare channel used for
                 ///   clearing the SPI EOT flag.
+                /// * `batch_size` - The number of samples per batch.
+                /// * `sample_ticks` - The number of sampling timer ticks between each sample.
+                #[allow(clippy::too_many_arguments)]
                 pub fn new(
                     spi: hal::spi::Spi<hal::stm32::$spi, hal::spi::Enabled, u16>,
                     trigger_stream: hal::dma::dma::$trigger_stream<
@@ -282,6 +570,7 @@ macro_rules! adc_input {
                     trigger_channel: timers::tim2::$trigger_channel,
                     clear_channel: timers::tim3::$clear_channel,
                     batch_size: usize,
+                    sample_ticks: u32,
                 ) -> Self {
                     // The flag clear DMA transfer always clears the EOT flag in the SPI
                     // peripheral. It has the highest priority to ensure it is completed before the
@@ -368,8 +657,8 @@ macro_rules! adc_input {
                     // A SPI peripheral error interrupt is used to determine if the RX FIFO
                     // overflows. This indicates that samples were dropped due to excessive
                     // processing time in the main application (e.g. a second DMA transfer completes
-                    // before the first was done with processing). This is used as a flow control
-                    // indicator to guarantee that no ADC samples are lost.
+                    // before the first was done with processing). Overruns are counted, see
+                    // `handle_spi_error()`.
                     let mut spi = spi.disable();
                     spi.listen(hal::spi::Event::Error);
 
@@ -390,6 +679,9 @@ macro_rules! adc_input {
                         transfer: data_transfer,
                         trigger_transfer,
                         clear_transfer,
+                        batches: BatchCounter::default(),
+                        batch_ticks: batch_size as u64 * sample_ticks as u64,
+                        clip: ClipDetector::default(),
                     }
                 }
 
@@ -409,7 +701,8 @@ macro_rules! adc_input {
 
                 /// Wait for the transfer of the currently active buffer to complete,
                 /// then call a function on the now inactive buffer and acknowledge the
-                /// transfer complete flag.
+                /// transfer complete flag. The batch is checked for clipping before the
+                /// function is called.
                 ///
                 /// NOTE(unsafe): Memory safety and access ordering is not guaranteed
                 /// (see the HAL DMA docs).
@@ -417,16 +710,134 @@ macro_rules! adc_input {
                 where
                     F: FnOnce(&mut &'static mut [u16]) -> R,
                 {
-                    unsafe { self.transfer.next_dbm_transfer_with(|buf, _current| f(buf)) }
+                    let clip = &mut self.clip;
+                    let result = unsafe {
+                        self.transfer.next_dbm_transfer_with(|buf, _current| {
+                            clip.process(buf);
+                            f(buf)
+                        })
+                    };
+
+                    if result.is_ok() {
+                        self.batches.complete();
+                    } else {
+                        self.batches.overrun();
+                    }
+
+                    result
+                }
+
+                /// Wait for the transfer of the currently active buffer to complete and lend
+                /// the now inactive buffer without copying it. The batch is checked for clipping
+                /// before it is lent.
+                ///
+                /// # Note
+                /// The batch must be released within one batch period, see [LentBatch].
+                ///
+                /// # Returns
+                /// The completed batch.
+                pub fn acquire_batch(
+                    &mut self,
+                ) -> Result<LentBatch<'_, impl Fn() -> bool + '_>, DMAError> {
+                    let clip = &mut self.clip;
+                    let result = unsafe {
+                        self.transfer.next_dbm_transfer_with(|buf, _current| {
+                            clip.process(buf);
+                            (buf.as_ptr(), buf.len())
+                        })
+                    };
+
+                    let (ptr, len) = match result {
+                        Ok(buffer) => buffer,
+                        Err(err) => {
+                            self.batches.overrun();
+                            return Err(err);
+                        }
+                    };
+                    self.batches.complete();
+
+                    // Note(unsafe): The buffer is the inactive half of a static DMA buffer of
+                    // this input. The DMA only writes to it again once the next transfer
+                    // completes, which the lent batch checks for.
+                    let samples = unsafe { core::slice::from_raw_parts(ptr, len) };
+                    let transfer = &self.transfer;
+                    Ok(LentBatch::new(samples, &mut self.batches, move || {
+                        transfer.get_transfer_complete_flag()
+                    }))
+                }
+
+                /// Get the number of lent batches that were overwritten before they were
+                /// released.
+                pub fn torn_batches(&self) -> u32 {
+                    self.batches.torn_batches()
+                }
+
+                /// Get the sequence number of the most recently acquired batch.
+                ///
+                /// # Returns
+                /// The sequence number, or `None` if no batch has been acquired yet.
+                pub fn sequence_number(&self) -> Option<u64> {
+                    self.batches.latest()
+                }
+
+                /// Get the sampling timer tick at the start of the most recently acquired batch.
+                ///
+                /// # Returns
+                /// The number of sampling timer ticks since sampling started, or `None` if no
+                /// batch has been acquired yet.
+                pub fn batch_timestamp(&self) -> Option<u64> {
+                    self.batches.latest().map(|sequence| sequence * self.batch_ticks)
+                }
+
+                /// Get the number of batches lost to DMA overruns.
+                pub fn lost_batches(&self) -> u32 {
+                    self.batches.lost_batches()
+                }
+
+                /// Get the number of clipped samples since boot.
+                pub fn clip_count(&self) -> u32 {
+                    self.clip.count()
+                }
+
+                /// Check whether the most recently acquired batch contained clipped samples.
+                pub fn clipping(&self) -> bool {
+                    self.clip.clipping()
                 }
-            }
 
-            // This is not actually a Mutex. It only re-uses the semantics and macros of mutex-trait
-            // to reduce rightward drift when jointly calling `with_buffer(f)` on multiple DAC/ADCs.
-            impl Mutex for $name {
-                type Data = &'static mut [u16];
-                fn lock<R>(&mut self, f: impl FnOnce(&mut Self::Data) -> R) -> R {
-                    self.with_buffer(f).unwrap()
+                /// Set the distance in codes from full scale within which a sample is clipped.
+                pub fn set_clip_margin(&mut self, margin: u16) {
+                    self.clip.set_margin(margin);
+                }
+
+                /// Handle an SPI error interrupt of the ADC.
+                ///
+                /// # Note
+                /// An RX FIFO overrun indicates that samples were dropped because the data DMA
+                /// stream did not keep up. Overruns are counted and cleared so that sampling
+                /// continues. Any other SPI error is unrecoverable and panics.
+                pub fn handle_spi_error() {
+                    // Note(unsafe): The SPI is owned by the DMA transfer. Only the status and flag
+                    // clear registers are accessed here.
+                    let regs = unsafe { &*hal::stm32::$spi::ptr() };
+                    if regs.sr.read().ovr().bit_is_clear() {
+                        panic!("{} SPI error", stringify!($name));
+                    }
+
+                    regs.ifcr.write(|w| w.ovrc().set_bit());
+                    Self::overrun_counter().fetch_add(1, Ordering::Relaxed);
+                }
+
+                /// Get the number of SPI RX FIFO overruns since boot.
+                ///
+                /// # Note
+                /// The count is monotonic and is not cleared when read. It wraps on overflow.
+                pub fn overrun_count() -> u32 {
+                    Self::overrun_counter().load(Ordering::Relaxed)
+                }
+
+                fn overrun_counter() -> &'static AtomicU32 {
+                    static OVERRUNS: AtomicU32 = AtomicU32::new(0);
+                    &OVERRUNS
                 }
             }
         }
@@ -441,3 +852,113 @@ adc_input!(
     Adc1Input, 1, Stream3, Stream4, Stream5, SPI3, Channel2, Tim2Ch2, Channel2,
     Tim3Ch2
 );
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn batch_sequence_starts_at_zero() {
+        let mut batches = BatchCounter::default();
+        assert_eq!(batches.latest(), None);
+
+        assert_eq!(batches.complete(), 0);
+        assert_eq!(batches.complete(), 1);
+        assert_eq!(batches.latest(), Some(1));
+        assert_eq!(batches.lost_batches(), 0);
+    }
+
+    #[test]
+    fn batch_overrun_leaves_gap() {
+        let mut batches = BatchCounter::default();
+        assert_eq!(batches.complete(), 0);
+
+        batches.overrun();
+        assert_eq!(batches.latest(), Some(1));
+        assert_eq!(batches.lost_batches(), 1);
+
+        // The lost batch consumed its sequence number.
+        assert_eq!(batches.complete(), 2);
+        assert_eq!(batches.latest(), Some(2));
+    }
+
+    #[test]
+    fn lent_batch_is_handed_back() {
+        let buffers = [[1u16, 2, 3], [4, 5, 6]];
+        let next_complete = core::cell::Cell::new(false);
+        let mut batches = BatchCounter::default();
+
+        // Simulate the DMA alternating between the halves of the double buffer.
+        for sequence in 0..4 {
+            assert_eq!(batches.complete(), sequence);
+            let batch = LentBatch::new(
+                &buffers[sequence as usize % 2],
+                &mut batches,
+                || next_complete.get(),
+            );
+            assert_eq!(*batch, buffers[sequence as usize % 2]);
+            assert!(batch.is_intact());
+        }
+        assert_eq!(batches.torn_batches(), 0);
+
+        // The next batch completes while the batch is still lent.
+        batches.complete();
+        let batch =
+            LentBatch::new(&buffers[0], &mut batches, || next_complete.get());
+        next_complete.set(true);
+        assert!(!batch.is_intact());
+        drop(batch);
+        assert_eq!(batches.torn_batches(), 1);
+        assert_eq!(batches.lost_batches(), 0);
+        assert_eq!(batches.latest(), Some(4));
+    }
+
+    #[test]
+    fn calibrated_codes_to_volts() {
+        let calibration = AdcCalibration {
+            gain: 1.01,
+            offset: -0.002,
+        };
+        let max = AdcCode::FULL_SCALE * (i16::MAX as f32 / -(i16::MIN as f32));
+
+        for (afe_gain, multiplier) in
+            [(afe::Gain::G1, 1.), (afe::Gain::G10, 10.)]
+        {
+            for (code, nominal) in
+                [(0x8000, -AdcCode::FULL_SCALE), (0x0000, 0.), (0x7FFF, max)]
+            {
+                let expected = 1.01 * nominal / multiplier - 0.002;
+                let volts = calibration.codes_to_volts(code, afe_gain);
+                assert!(
+                    (volts - expected).abs() < 1e-5,
+                    "{code:#x} at {afe_gain:?}: {volts}"
+                );
+                assert_eq!(
+                    AdcCalibration::default().codes_to_volts(code, afe_gain),
+                    nominal / multiplier
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn clipped_samples_are_detected() {
+        let mut clip = ClipDetector::new(16);
+
+        // Codes are two's complement: 0x7FFF is positive and 0x8000 negative full scale.
+        let batch = [
+            0x0000, 0x7FFF, 0x7FEF, 0x7FEE, 0x8000, 0x8010, 0x8011, 0xFFFF,
+        ];
+        assert_eq!(clip.process(&batch), 4);
+        assert!(clip.clipping());
+        assert_eq!(clip.count(), 4);
+
+        assert_eq!(clip.process(&[0x1234, 0xC000]), 0);
+        assert!(!clip.clipping());
+        assert_eq!(clip.count(), 4);
+
+        clip.set_margin(0);
+        assert_eq!(clip.process(&batch), 2);
+        assert_eq!(clip.count(), 6);
+    }
+}
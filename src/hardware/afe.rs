@@ -3,7 +3,11 @@ use serde::{Deserialize, Serialize};
 use core::convert::TryFrom;
 use num_enum::TryFromPrimitive;
 
-#[derive(Copy, Clone, Debug, Serialize, Deserialize, TryFromPrimitive)]
+use super::adc::AdcCode;
+
+#[derive(
+    Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize, TryFromPrimitive,
+)]
 #[repr(u8)]
 pub enum Gain {
     G1 = 0b00,
@@ -16,6 +20,7 @@ pub enum Gain {
 pub struct ProgrammableGainAmplifier<A0, A1> {
     a0: A0,
     a1: A1,
+    gain: Gain,
 }
 
 impl Gain {
@@ -28,6 +33,147 @@ impl Gain {
             Gain::G10 => 10.0,
         }
     }
+
+    /// Get the AFE gain in dB.
+    pub fn as_db(self) -> f32 {
+        match self {
+            Gain::G1 => 0.0,
+            Gain::G2 => 6.0206,
+            Gain::G5 => 13.9794,
+            Gain::G10 => 20.0,
+        }
+    }
+
+    // The next higher gain, if any.
+    fn higher(self) -> Option<Gain> {
+        match self {
+            Gain::G1 => Some(Gain::G2),
+            Gain::G2 => Some(Gain::G5),
+            Gain::G5 => Some(Gain::G10),
+            Gain::G10 => None,
+        }
+    }
+
+    // The next lower gain, if any.
+    fn lower(self) -> Option<Gain> {
+        match self {
+            Gain::G1 => None,
+            Gain::G2 => Some(Gain::G1),
+            Gain::G5 => Some(Gain::G2),
+            Gain::G10 => Some(Gain::G5),
+        }
+    }
+}
+
+/// Automatic gain selection of an analog front-end with hysteresis.
+///
+/// # Note
+/// The input amplitude is compared against thresholds relative to the ADC full-scale range. The
+/// gain is lowered by one step if the amplitude at the current gain exceeds `down_threshold`. It
+/// is raised by one step if the amplitude at the next higher gain stays below `up_threshold`.
+/// Because `up_threshold` is lower than `down_threshold`, a switch never immediately warrants the
+/// opposite switch. Additionally, a switch is only made once it has been warranted for `dwell`
+/// consecutive updates.
+#[derive(Copy, Clone, Debug)]
+pub struct AutoGain {
+    /// The fraction of the ADC full-scale range below which the gain is raised.
+    pub up_threshold: f32,
+
+    /// The fraction of the ADC full-scale range above which the gain is lowered.
+    pub down_threshold: f32,
+
+    /// The number of consecutive updates a switch must be warranted for.
+    pub dwell: u32,
+
+    pending: Option<Gain>,
+    count: u32,
+}
+
+impl AutoGain {
+    /// Construct the automatic gain selection.
+    ///
+    /// # Args
+    /// * `up_threshold` - The fraction of the ADC full-scale range below which the gain is raised.
+    /// * `down_threshold` - The fraction of the ADC full-scale range above which the gain is
+    ///   lowered. Must be larger than `up_threshold`.
+    /// * `dwell` - The number of consecutive updates a switch must be warranted for.
+    pub fn new(up_threshold: f32, down_threshold: f32, dwell: u32) -> Self {
+        debug_assert!(up_threshold < down_threshold);
+        Self {
+            up_threshold,
+            down_threshold,
+            dwell,
+            pending: None,
+            count: 0,
+        }
+    }
+
+    /// Select the gain for a new amplitude measurement.
+    ///
+    /// # Args
+    /// * `gain` - The current gain.
+    /// * `amplitude` - The peak amplitude at the AFE input in volts.
+    ///
+    /// # Returns
+    /// The gain to switch to, if a switch is due.
+    pub fn select(&mut self, gain: Gain, amplitude: f32) -> Option<Gain> {
+        let level =
+            |gain: Gain| amplitude * gain.as_multiplier() / AdcCode::FULL_SCALE;
+
+        let candidate = if level(gain) > self.down_threshold {
+            gain.lower()
+        } else {
+            gain.higher()
+                .filter(|&higher| level(higher) < self.up_threshold)
+        };
+
+        let Some(candidate) = candidate else {
+            self.pending = None;
+            self.count = 0;
+            return None;
+        };
+
+        if self.pending != Some(candidate) {
+            self.pending = Some(candidate);
+            self.count = 0;
+        }
+
+        self.count += 1;
+        if self.count < self.dwell {
+            return None;
+        }
+
+        self.pending = None;
+        self.count = 0;
+        Some(candidate)
+    }
+
+    /// Update the gain of a front-end for a new amplitude measurement.
+    ///
+    /// # Note
+    /// The gain is only set if a switch is due.
+    ///
+    /// # Args
+    /// * `afe` - The front-end to control.
+    /// * `amplitude` - The peak amplitude at the AFE input in volts.
+    ///
+    /// # Returns
+    /// The new gain, if it was switched.
+    pub fn update<A0, A1>(
+        &mut self,
+        afe: &mut ProgrammableGainAmplifier<A0, A1>,
+        amplitude: f32,
+    ) -> Option<Gain>
+    where
+        A0: embedded_hal::digital::v2::StatefulOutputPin,
+        A0::Error: core::fmt::Debug,
+        A1: embedded_hal::digital::v2::StatefulOutputPin,
+        A1::Error: core::fmt::Debug,
+    {
+        let gain = self.select(afe.gain(), amplitude)?;
+        afe.set_gain(gain);
+        Some(gain)
+    }
 }
 
 impl<A0, A1> ProgrammableGainAmplifier<A0, A1>
@@ -43,7 +189,11 @@ where
     /// * `a0` - An output connected to the A0 input of the amplifier.
     /// * `a1` - An output connected to the A1 input of the amplifier.
     pub fn new(a0: A0, a1: A1) -> Self {
-        let mut afe = Self { a0, a1 };
+        let mut afe = Self {
+            a0,
+            a1,
+            gain: Gain::G1,
+        };
 
         afe.set_gain(Gain::G1);
 
@@ -51,6 +201,19 @@ where
     }
 
     /// Set the gain of the front-end.
+    ///
+    /// # Note
+    /// The gain is encoded onto the amplifier inputs as follows:
+    ///
+    /// | Gain | A1 | A0 |
+    /// |------|----|----|
+    /// | G1   | 0  | 0  |
+    /// | G2   | 0  | 1  |
+    /// | G5   | 1  | 0  |
+    /// | G10  | 1  | 1  |
+    ///
+    /// # Args
+    /// * `gain` - The desired gain of the front-end.
     pub fn set_gain(&mut self, gain: Gain) {
         if (gain as u8 & 0b01) != 0 {
             self.a0.set_high().unwrap();
@@ -63,6 +226,22 @@ where
         } else {
             self.a1.set_low().unwrap();
         }
+
+        self.gain = gain;
+    }
+
+    /// Get the most recently configured gain of the front-end.
+    ///
+    /// # Note
+    /// Unlike [Self::get_gain], this does not read back the pin state but returns the gain
+    /// tracked by [Self::set_gain].
+    pub fn gain(&self) -> Gain {
+        self.gain
+    }
+
+    /// Get the most recently configured gain of the front-end in dB.
+    pub fn gain_db(&self) -> f32 {
+        self.gain.as_db()
     }
 
     /// Get the programmed gain of the analog front-end.
@@ -79,3 +258,123 @@ where
         Gain::try_from(code).unwrap()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::convert::Infallible;
+
+    /// An output pin that records its state.
+    #[derive(Default)]
+    struct MockPin {
+        high: bool,
+    }
+
+    impl embedded_hal::digital::v2::OutputPin for MockPin {
+        type Error = Infallible;
+
+        fn set_low(&mut self) -> Result<(), Self::Error> {
+            self.high = false;
+            Ok(())
+        }
+
+        fn set_high(&mut self) -> Result<(), Self::Error> {
+            self.high = true;
+            Ok(())
+        }
+    }
+
+    impl embedded_hal::digital::v2::StatefulOutputPin for MockPin {
+        fn is_set_high(&self) -> Result<bool, Self::Error> {
+            Ok(self.high)
+        }
+
+        fn is_set_low(&self) -> Result<bool, Self::Error> {
+            Ok(!self.high)
+        }
+    }
+
+    const GAINS: [Gain; 4] = [Gain::G1, Gain::G2, Gain::G5, Gain::G10];
+
+    #[test]
+    fn set_gain_pin_encoding() {
+        let mut afe = ProgrammableGainAmplifier::new(
+            MockPin::default(),
+            MockPin::default(),
+        );
+        assert_eq!(afe.get_gain(), Gain::G1);
+
+        for (gain, a1, a0) in [
+            (Gain::G1, false, false),
+            (Gain::G2, false, true),
+            (Gain::G5, true, false),
+            (Gain::G10, true, true),
+        ] {
+            afe.set_gain(gain);
+            assert_eq!((afe.a1.high, afe.a0.high), (a1, a0));
+            assert_eq!(afe.get_gain(), gain);
+            assert_eq!(afe.gain(), gain);
+            assert_eq!(afe.gain_db(), gain.as_db());
+        }
+    }
+
+    #[test]
+    fn gain_db_matches_multiplier() {
+        for gain in GAINS {
+            let db = 20. * libm::log10f(gain.as_multiplier());
+            assert!((gain.as_db() - db).abs() < 1e-3, "{gain:?}");
+        }
+    }
+
+    #[test]
+    fn gain_steps_are_ordered() {
+        for pair in GAINS.windows(2) {
+            assert_eq!(pair[0].higher(), Some(pair[1]));
+            assert_eq!(pair[1].lower(), Some(pair[0]));
+        }
+        assert_eq!(Gain::G1.lower(), None);
+        assert_eq!(Gain::G10.higher(), None);
+    }
+
+    #[test]
+    fn auto_gain_lowers_after_dwell() {
+        let mut auto = AutoGain::new(0.3, 0.9, 3);
+        let amplitude = 0.95 * AdcCode::FULL_SCALE / 10.;
+
+        assert_eq!(auto.select(Gain::G10, amplitude), None);
+        assert_eq!(auto.select(Gain::G10, amplitude), None);
+        assert_eq!(auto.select(Gain::G10, amplitude), Some(Gain::G5));
+    }
+
+    #[test]
+    fn auto_gain_raises_only_below_up_threshold() {
+        let mut auto = AutoGain::new(0.3, 0.9, 1);
+
+        // At G2 the level would be 0.4, which is above the up threshold.
+        let amplitude = 0.2 * AdcCode::FULL_SCALE;
+        assert_eq!(auto.select(Gain::G1, amplitude), None);
+
+        // At G2 the level would be 0.2, so the gain is raised.
+        let amplitude = 0.1 * AdcCode::FULL_SCALE;
+        assert_eq!(auto.select(Gain::G1, amplitude), Some(Gain::G2));
+    }
+
+    #[test]
+    fn auto_gain_saturates_at_limits() {
+        let mut auto = AutoGain::new(0.3, 0.9, 1);
+        assert_eq!(auto.select(Gain::G10, 0.), None);
+        assert_eq!(auto.select(Gain::G1, AdcCode::FULL_SCALE), None);
+    }
+
+    #[test]
+    fn auto_gain_interrupted_dwell_restarts() {
+        let mut auto = AutoGain::new(0.3, 0.9, 2);
+        let high = AdcCode::FULL_SCALE / 2.;
+        let nominal = 0.5 * AdcCode::FULL_SCALE / 2.;
+
+        assert_eq!(auto.select(Gain::G2, high), None);
+        assert_eq!(auto.select(Gain::G2, nominal), None);
+        assert_eq!(auto.select(Gain::G2, high), None);
+        assert_eq!(auto.select(Gain::G2, high), Some(Gain::G1));
+    }
+}
@@ -3,6 +3,10 @@
 //! # Description
 //! This file provides an API for measuring the internal STM32 temperature sensor. This temperature
 //! sensor measures the silicon junction temperature (Tj) and is connected via an internal ADC.
+//!
+//! By default, the factory-programmed TS_CAL values in system memory are used to convert ADC codes
+//! to temperature. Because the sensor has a large part-to-part offset, an additional one-point
+//! correction can be applied as a temperature offset derived from a known ambient reading.
 use stm32h7xx_hal::{
     self as hal,
     signature::{TS_CAL_110, TS_CAL_30},
@@ -17,15 +21,31 @@ struct Calibration {
 }
 
 impl Calibration {
-    /// Construct the calibration utility.
-    pub fn new() -> Self {
-        let ts_cal2 = TS_CAL_110::read();
+    /// Construct the calibration utility from the factory-programmed TS_CAL values.
+    pub fn factory() -> Self {
+        Self::with_slope(Self::factory_slope())
+    }
+
+    /// Construct the calibration utility with a custom slope.
+    ///
+    /// # Note
+    /// The calibration is anchored at the factory-programmed 30 degree Celsius point (TS_CAL1).
+    ///
+    /// # Args
+    /// * `slope` - The sensor slope in degrees Celsius per ADC code at a 3.3V reference.
+    pub fn with_slope(slope: f32) -> Self {
         let ts_cal1 = TS_CAL_30::read();
-        let slope = (110. - 30.) / (ts_cal2 as f32 - ts_cal1 as f32);
         let offset = 30. - slope * ts_cal1 as f32;
         Self { slope, offset }
     }
 
+    /// Get the sensor slope derived from the factory-programmed TS_CAL values.
+    pub fn factory_slope() -> f32 {
+        let ts_cal2 = TS_CAL_110::read();
+        let ts_cal1 = TS_CAL_30::read();
+        (110. - 30.) / (ts_cal2 as f32 - ts_cal1 as f32)
+    }
+
     /// Convert a raw ADC sample to a temperature in degrees Celsius.
     pub fn sample_to_temperature(&self, sample: u32) -> f32 {
         // We use a 2.048V reference, but calibration data was taken at 3.3V.
@@ -35,15 +55,35 @@ impl Calibration {
     }
 }
 
+/// A channel providing raw temperature sensor samples.
+pub trait SensorChannel {
+    /// Read the raw ADC code of the temperature sensor.
+    fn read_raw(&mut self) -> Result<u32, AdcError>;
+}
+
+impl SensorChannel
+    for AdcChannel<'static, hal::stm32::ADC3, hal::adc::Temperature>
+{
+    fn read_raw(&mut self) -> Result<u32, AdcError> {
+        AdcChannel::read_raw(self)
+    }
+}
+
 /// A driver to access the CPU temeprature sensor.
-pub struct CpuTempSensor {
-    sensor: AdcChannel<'static, hal::stm32::ADC3, hal::adc::Temperature>,
+pub struct CpuTempSensor<
+    S = AdcChannel<'static, hal::stm32::ADC3, hal::adc::Temperature>,
+> {
+    sensor: S,
     calibration: Calibration,
+    offset_c: f32,
 }
 
 impl CpuTempSensor {
     /// Construct the temperature sensor.
     ///
+    /// # Note
+    /// The factory-programmed TS_CAL values are used for calibration and no offset is applied.
+    ///
     /// # Args
     /// * `sensor` - The ADC channel of the integrated temperature sensor.
     pub fn new(
@@ -51,14 +91,84 @@ impl CpuTempSensor {
     ) -> Self {
         Self {
             sensor,
-            calibration: Calibration::new(),
+            calibration: Calibration::factory(),
+            offset_c: 0.,
+        }
+    }
+
+    /// Construct the temperature sensor with a custom calibration.
+    ///
+    /// # Args
+    /// * `sensor` - The ADC channel of the integrated temperature sensor.
+    /// * `slope` - The sensor slope in degrees Celsius per ADC code at a 3.3V reference. Use
+    ///   [CpuTempSensor::factory_slope] for the factory-programmed value.
+    /// * `offset_c` - The offset in degrees Celsius added to every temperature reading.
+    pub fn with_calibration(
+        sensor: AdcChannel<'static, hal::stm32::ADC3, hal::adc::Temperature>,
+        slope: f32,
+        offset_c: f32,
+    ) -> Self {
+        Self {
+            sensor,
+            calibration: Calibration::with_slope(slope),
+            offset_c,
         }
     }
 
+    /// Get the sensor slope derived from the factory-programmed TS_CAL values in system memory.
+    ///
+    /// # Returns
+    /// The slope in degrees Celsius per ADC code at a 3.3V reference.
+    pub fn factory_slope() -> f32 {
+        Calibration::factory_slope()
+    }
+}
+
+impl<S: SensorChannel> CpuTempSensor<S> {
+    /// Set the one-point calibration offset.
+    ///
+    /// # Args
+    /// * `offset_c` - The offset in degrees Celsius added to every temperature reading. This is
+    ///   typically the difference between a known ambient temperature and the uncorrected reading.
+    pub fn set_offset(&mut self, offset_c: f32) {
+        self.offset_c = offset_c;
+    }
+
     /// Get the temperature of the CPU in degrees Celsius.
     pub fn get_temperature(&mut self) -> Result<f32, AdcError> {
-        self.sensor
-            .read_raw()
-            .map(|raw| self.calibration.sample_to_temperature(raw))
+        self.sensor.read_raw().map(|raw| {
+            self.calibration.sample_to_temperature(raw) + self.offset_c
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct MockSensor(u32);
+
+    impl SensorChannel for MockSensor {
+        fn read_raw(&mut self) -> Result<u32, AdcError> {
+            Ok(self.0)
+        }
+    }
+
+    #[test]
+    fn offset_shifts_temperature() {
+        let mut sensor = CpuTempSensor {
+            sensor: MockSensor(1000),
+            calibration: Calibration {
+                slope: 0.1,
+                offset: -50.,
+            },
+            offset_c: 0.,
+        };
+
+        let uncorrected = sensor.get_temperature().unwrap();
+        sensor.set_offset(2.5);
+        assert_eq!(sensor.get_temperature().unwrap(), uncorrected + 2.5);
+        sensor.set_offset(-1.25);
+        assert_eq!(sensor.get_temperature().unwrap(), uncorrected - 1.25);
     }
 }
@@ -45,6 +45,21 @@
 //! DMA channels to arbitrate which transfer occurs first.
 //!
 //!
+//! ## Calibration
+//!
+//! The DAC and output amplifier chain has gain and offset errors. Each DAC output holds a
+//! [DacCalibration] that maps a desired output voltage to the DAC code generating it. Codes
+//! outside of the DAC range saturate at the rails.
+//!
+//!
+//! ## Output control
+//!
+//! The DAC `CLR` and `LDAC` inputs are retained in a [DacControl]. By default, `LDAC` is held low,
+//! so each code is transferred to the output as soon as it is received. Holding `LDAC` high
+//! instead keeps the outputs at their current value while new codes are received, until they are
+//! loaded. Pulsing `CLR` resets both outputs to midscale, which is 0 V.
+//!
+//!
 //! # Limitations
 //!
 //! While double-buffered mode is used for DMA to avoid lost DAC-update events, there is no check
@@ -52,12 +67,12 @@
 //! served promptly after the transfer completes.
 use stm32h7xx_hal as hal;
 
-use mutex_trait::Mutex;
-
 use super::design_parameters::{SampleBuffer, MAX_SAMPLE_BUFFER_SIZE};
 use super::timers;
 
 use core::convert::TryFrom;
+use core::sync::atomic::{AtomicU32, Ordering};
+use embedded_hal::digital::v2::OutputPin;
 
 use hal::{
     dma::{
@@ -127,6 +142,150 @@ impl From<u16> for DacCode {
     }
 }
 
+/// A two-point linear calibration of a DAC output.
+///
+/// # Note
+/// The calibration models the actual output voltage as `gain * nominal + offset`, where `nominal`
+/// is the output voltage of an ideal DAC for a given code.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct DacCalibration {
+    /// The ratio of the actual to the nominal output voltage.
+    pub gain: f32,
+
+    /// The output voltage error in volts at a nominal output of zero.
+    pub offset: f32,
+}
+
+impl Default for DacCalibration {
+    /// The identity calibration of an ideal DAC.
+    fn default() -> Self {
+        Self {
+            gain: 1.,
+            offset: 0.,
+        }
+    }
+}
+
+impl DacCalibration {
+    /// Compute the calibration from two output voltage measurements.
+    ///
+    /// # Args
+    /// * `point0` - The (nominal, measured) output voltages of the first point.
+    /// * `point1` - The (nominal, measured) output voltages of the second point. The nominal
+    ///   voltage must differ from the one of `point0`.
+    pub fn from_points(point0: (f32, f32), point1: (f32, f32)) -> Self {
+        let gain = (point1.1 - point0.1) / (point1.0 - point0.0);
+        Self {
+            gain,
+            offset: point0.1 - gain * point0.0,
+        }
+    }
+
+    /// Convert an output voltage into a DAC output code.
+    ///
+    /// # Args
+    /// * `volts` - The desired output voltage.
+    ///
+    /// # Returns
+    /// The raw DAC output code. Voltages outside of the output range saturate at the rails.
+    pub fn volts_to_code(&self, volts: f32) -> u16 {
+        let code = (volts - self.offset) / self.gain * DacCode::LSB_PER_VOLT;
+        // Note: The float to integer cast saturates at the bounds of the target type and maps NaN
+        // to zero.
+        DacCode::from(code as i16).0
+    }
+
+    /// Convert a DAC output code into the output voltage.
+    ///
+    /// # Args
+    /// * `code` - The raw DAC output code.
+    pub fn code_to_volts(&self, code: u16) -> f32 {
+        self.gain * f32::from(DacCode(code)) + self.offset
+    }
+}
+
+/// A static output voltage that overrides the servo on a DAC output, e.g. for bring-up.
+///
+/// # Note
+/// The output code is computed with the calibration and clamped to the soft limits of the DAC
+/// output at the time the override was constructed, see [Dac0Output::manual_output()].
+#[derive(Copy, Clone, Debug)]
+pub struct ManualOutput {
+    calibration: DacCalibration,
+    limits: (u16, u16),
+    code: Option<u16>,
+}
+
+impl ManualOutput {
+    /// Override the output with a static voltage.
+    ///
+    /// # Args
+    /// * `volts` - The desired output voltage.
+    ///
+    /// # Returns
+    /// The output voltage after clamping to the soft limits.
+    pub fn set(&mut self, volts: f32) -> f32 {
+        let (min, max) = self.limits;
+        let code = self.calibration.volts_to_code(volts).clamp(min, max);
+        self.code = Some(code);
+        self.calibration.code_to_volts(code)
+    }
+
+    /// Return control of the output to the servo.
+    ///
+    /// # Returns
+    /// True if the output was overridden.
+    pub fn release(&mut self) -> bool {
+        self.code.take().is_some()
+    }
+
+    /// Get the overriding output code, or `None` if the servo controls the output.
+    pub fn code(&self) -> Option<u16> {
+        self.code
+    }
+
+    /// Set the calibration used to convert voltages to output codes.
+    ///
+    /// # Note
+    /// An active override keeps its output code until it is set again.
+    pub fn set_calibration(&mut self, calibration: DacCalibration) {
+        self.calibration = calibration;
+    }
+
+    /// Get the calibration used to convert voltages to output codes.
+    pub fn calibration(&self) -> DacCalibration {
+        self.calibration
+    }
+}
+
+/// Errors encountered while configuring the soft limits of a DAC output.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LimitsError {
+    /// The lowest allowed code is higher than the highest allowed code.
+    Inverted,
+}
+
+/// Clamp output codes to limits.
+///
+/// # Args
+/// * `codes` - The output codes to clamp.
+/// * `limits` - The lowest and highest allowed output codes.
+/// * `limit_hits` - The count of clamped codes. It is incremented by the number of codes that
+///   were clamped and wraps on overflow.
+fn clamp_codes(codes: &mut [u16], limits: (u16, u16), limit_hits: &mut u32) {
+    let (min, max) = limits;
+    if limits == (u16::MIN, u16::MAX) {
+        return;
+    }
+
+    for code in codes.iter_mut() {
+        if *code < min || *code > max {
+            *code = (*code).clamp(min, max);
+            *limit_hits = limit_hits.wrapping_add(1);
+        }
+    }
+}
+
 macro_rules! dac_output {
     ($name:ident, $index:literal, $data_stream:ident,
      $spi:ident, $trigger_channel:ident, $dma_req:ident) => {
@@ -181,6 +340,9 @@ macro_rules! dac_output {
                 &'static mut [u16],
                 hal::dma::DBTransfer,
             >,
+            calibration: DacCalibration,
+            limits: (u16, u16),
+            limit_hits: u32,
         }
 
         impl $name {
@@ -234,35 +396,124 @@ macro_rules! dac_output {
                         trigger_config,
                     );
 
-                Self { transfer }
+                Self {
+                    transfer,
+                    calibration: DacCalibration::default(),
+                    limits: (u16::MIN, u16::MAX),
+                    limit_hits: 0,
+                }
             }
 
             pub fn start(&mut self) {
                 self.transfer.start(|spi| spi.start_dma());
             }
 
+            /// Set the calibration of the DAC output.
+            ///
+            /// # Args
+            /// * `calibration` - The calibration used to convert voltages to output codes.
+            pub fn set_calibration(&mut self, calibration: DacCalibration) {
+                self.calibration = calibration;
+            }
+
+            /// Get the calibration of the DAC output.
+            pub fn calibration(&self) -> DacCalibration {
+                self.calibration
+            }
+
+            /// Set the soft limits of the output codes.
+            ///
+            /// # Note
+            /// Every code written through [Self::with_buffer()] is clamped to the limits. The
+            /// codes are offset binary, so the limits are monotonic in the output voltage. By
+            /// default, the limits span the full DAC range.
+            ///
+            /// # Args
+            /// * `min_code` - The lowest allowed output code.
+            /// * `max_code` - The highest allowed output code. Must not be lower than `min_code`.
+            ///
+            /// # Returns
+            /// [LimitsError::Inverted] if `min_code` is higher than `max_code`. The limits are
+            /// then left unchanged.
+            pub fn set_limits(
+                &mut self,
+                min_code: u16,
+                max_code: u16,
+            ) -> Result<(), LimitsError> {
+                if min_code > max_code {
+                    return Err(LimitsError::Inverted);
+                }
+
+                self.limits = (min_code, max_code);
+                Ok(())
+            }
+
+            /// Get the soft limits of the output codes as (min, max).
+            pub fn limits(&self) -> (u16, u16) {
+                self.limits
+            }
+
+            /// Construct a manual override of the output with the current calibration and limits.
+            /// The override is initially released.
+            pub fn manual_output(&self) -> ManualOutput {
+                ManualOutput {
+                    calibration: self.calibration,
+                    limits: self.limits,
+                    code: None,
+                }
+            }
+
+            /// Get the number of output codes that were clamped to the limits.
+            ///
+            /// # Note
+            /// The count is monotonic and is not cleared when read. It wraps on overflow.
+            pub fn limit_hit_count(&self) -> u32 {
+                self.limit_hits
+            }
+
             /// Wait for the transfer of the currently active buffer to complete,
             /// then call a function on the now inactive buffer and acknowledge the
             /// transfer complete flag.
             ///
+            /// # Note
+            /// The codes in the buffer are clamped to the limits after the function returns.
+            ///
             /// NOTE(unsafe): Memory safety and access ordering is not guaranteed
             /// (see the HAL DMA docs).
             pub fn with_buffer<F, R>(&mut self, f: F) -> Result<R, DMAError>
             where
                 F: FnOnce(&mut &'static mut [u16]) -> R,
             {
-                unsafe {
-                    self.transfer.next_dbm_transfer_with(|buf, _current| f(buf))
+                let limits = self.limits;
+                let limit_hits = &mut self.limit_hits;
+                let result = unsafe {
+                    self.transfer.next_dbm_transfer_with(|buf, _current| {
+                        let result = f(buf);
+                        clamp_codes(buf, limits, limit_hits);
+                        result
+                    })
+                };
+
+                if result.is_err() {
+                    Self::underrun_counter().fetch_add(1, Ordering::Relaxed);
                 }
+
+                result
             }
-        }
 
-        // This is not actually a Mutex. It only re-uses the semantics and macros of mutex-trait
-        // to reduce rightward drift when jointly calling `with_buffer(f)` on multiple DAC/ADCs.
-        impl Mutex for $name {
-            type Data = &'static mut [u16];
-            fn lock<R>(&mut self, f: impl FnOnce(&mut Self::Data) -> R) -> R {
-                self.with_buffer(f).unwrap()
+            /// Get the number of DMA underruns since boot.
+            ///
+            /// # Note
+            /// An underrun occurs when the next buffer was not provided before the DMA transfer
+            /// of the current buffer completed, such that stale codes are generated on the output.
+            /// The count is monotonic and is not cleared when read. It wraps on overflow.
+            pub fn underrun_count() -> u32 {
+                Self::underrun_counter().load(Ordering::Relaxed)
+            }
+
+            fn underrun_counter() -> &'static AtomicU32 {
+                static UNDERRUNS: AtomicU32 = AtomicU32::new(0);
+                &UNDERRUNS
             }
         }
     };
@@ -270,3 +521,300 @@ macro_rules! dac_output {
 
 dac_output!(Dac0Output, 0, Stream6, SPI4, Channel3, Tim2Ch3);
 dac_output!(Dac1Output, 1, Stream7, SPI5, Channel4, Tim2Ch4);
+
+/// Pulse the active-low clear input, see [DacControl::clear()].
+///
+/// # Args
+/// * `clr_n` - The active-low clear input.
+/// * `stretch` - Stretches the pulse to the minimum width.
+fn pulse_clear<P>(clr_n: &mut P, stretch: impl FnOnce())
+where
+    P: OutputPin,
+    P::Error: core::fmt::Debug,
+{
+    clr_n.set_low().unwrap();
+    stretch();
+    clr_n.set_high().unwrap();
+}
+
+/// Drive both active-low load inputs, see [DacControl::hold()].
+///
+/// # Args
+/// * `ldac_n` - The active-low load inputs of DAC0 and DAC1.
+/// * `hold` - If true, the inputs are deasserted so the outputs are held.
+fn drive_load<P0, P1>(ldac_n: &mut (P0, P1), hold: bool)
+where
+    P0: OutputPin,
+    P1: OutputPin,
+    P0::Error: core::fmt::Debug,
+    P1::Error: core::fmt::Debug,
+{
+    if hold {
+        ldac_n.0.set_high().unwrap();
+        ldac_n.1.set_high().unwrap();
+    } else {
+        ldac_n.0.set_low().unwrap();
+        ldac_n.1.set_low().unwrap();
+    }
+}
+
+/// Pulse both active-low load inputs if the outputs are held, see [DacControl::load()].
+///
+/// # Args
+/// * `ldac_n` - The active-low load inputs of DAC0 and DAC1.
+/// * `held` - Whether the outputs are held.
+/// * `stretch` - Stretches the pulse to the minimum width.
+fn pulse_load<P0, P1>(ldac_n: &mut (P0, P1), held: bool, stretch: impl FnOnce())
+where
+    P0: OutputPin,
+    P1: OutputPin,
+    P0::Error: core::fmt::Debug,
+    P1::Error: core::fmt::Debug,
+{
+    if held {
+        drive_load(ldac_n, false);
+        stretch();
+        drive_load(ldac_n, true);
+    }
+}
+
+/// The shared clear and load control inputs of the DACs.
+pub struct DacControl {
+    clr_n: hal::gpio::gpioe::PE12<hal::gpio::Output<hal::gpio::PushPull>>,
+    ldac_n: (
+        hal::gpio::gpioe::PE11<hal::gpio::Output<hal::gpio::PushPull>>,
+        hal::gpio::gpioe::PE15<hal::gpio::Output<hal::gpio::PushPull>>,
+    ),
+    held: bool,
+}
+
+impl DacControl {
+    /// Construct the DAC control. The outputs are cleared and updated on every received code.
+    ///
+    /// # Args
+    /// * `clr_n` - The active-low clear input of both DACs.
+    /// * `ldac0_n` - The active-low load input of DAC0.
+    /// * `ldac1_n` - The active-low load input of DAC1.
+    pub fn new(
+        clr_n: hal::gpio::gpioe::PE12<hal::gpio::Output<hal::gpio::PushPull>>,
+        ldac0_n: hal::gpio::gpioe::PE11<hal::gpio::Output<hal::gpio::PushPull>>,
+        ldac1_n: hal::gpio::gpioe::PE15<hal::gpio::Output<hal::gpio::PushPull>>,
+    ) -> Self {
+        let mut control = Self {
+            clr_n,
+            ldac_n: (ldac0_n, ldac1_n),
+            held: true,
+        };
+
+        control.hold(false);
+        control.clear();
+        control
+    }
+
+    /// Clear both DAC outputs to midscale (0 V).
+    ///
+    /// # Note
+    /// The DAC requires a minimum `CLR` low pulse width of 20 ns. The pulse is stretched with a
+    /// busy-wait of 20 CPU cycles, which is at least 50 ns at the maximum core clock of 400 MHz.
+    /// Codes written afterwards update the outputs as usual.
+    pub fn clear(&mut self) {
+        pulse_clear(&mut self.clr_n, || cortex_m::asm::delay(20));
+    }
+
+    /// Check whether the clear input is released, so that the DAC outputs follow received codes.
+    pub fn cleared(&self) -> bool {
+        self.clr_n.is_set_high()
+    }
+
+    /// Hold or release the DAC outputs.
+    ///
+    /// # Args
+    /// * `hold` - If true, the outputs keep their current value while new codes are received
+    ///   until [DacControl::load()] is called. Otherwise, each received code is output
+    ///   immediately.
+    pub fn hold(&mut self, hold: bool) {
+        drive_load(&mut self.ldac_n, hold);
+        self.held = hold;
+    }
+
+    /// Load the most recently received codes to the held outputs.
+    ///
+    /// # Note
+    /// The outputs remain held afterwards. If the outputs are not held, this has no effect.
+    pub fn load(&mut self) {
+        pulse_load(&mut self.ldac_n, self.held, || cortex_m::asm::delay(20));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A change of a control input level, or the pulse stretching busy-wait.
+    #[derive(Debug, PartialEq)]
+    enum Access {
+        Pin(&'static str, bool),
+        Stretch,
+    }
+
+    type Accesses = core::cell::RefCell<heapless::Vec<Access, 16>>;
+
+    /// A GPIO that records its output level.
+    struct MockPin<'a> {
+        accesses: &'a Accesses,
+        name: &'static str,
+    }
+
+    impl OutputPin for MockPin<'_> {
+        type Error = core::convert::Infallible;
+
+        fn set_low(&mut self) -> Result<(), Self::Error> {
+            let access = Access::Pin(self.name, false);
+            self.accesses.borrow_mut().push(access).unwrap();
+            Ok(())
+        }
+
+        fn set_high(&mut self) -> Result<(), Self::Error> {
+            let access = Access::Pin(self.name, true);
+            self.accesses.borrow_mut().push(access).unwrap();
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn control_pulse_sequencing() {
+        let accesses = Accesses::default();
+        let stretch = || accesses.borrow_mut().push(Access::Stretch).unwrap();
+        let pin = |name| MockPin {
+            accesses: &accesses,
+            name,
+        };
+        let mut clr_n = pin("clr");
+        let mut ldac_n = (pin("ldac0"), pin("ldac1"));
+
+        pulse_clear(&mut clr_n, stretch);
+        assert_eq!(
+            accesses.take().as_slice(),
+            [
+                Access::Pin("clr", false),
+                Access::Stretch,
+                Access::Pin("clr", true)
+            ]
+        );
+
+        // Loading has no effect while the outputs follow the codes.
+        drive_load(&mut ldac_n, false);
+        pulse_load(&mut ldac_n, false, stretch);
+        assert_eq!(
+            accesses.take().as_slice(),
+            [Access::Pin("ldac0", false), Access::Pin("ldac1", false)]
+        );
+
+        // Held outputs are updated by a stretched pulse and stay held.
+        drive_load(&mut ldac_n, true);
+        pulse_load(&mut ldac_n, true, stretch);
+        assert_eq!(
+            accesses.take().as_slice(),
+            [
+                Access::Pin("ldac0", true),
+                Access::Pin("ldac1", true),
+                Access::Pin("ldac0", false),
+                Access::Pin("ldac1", false),
+                Access::Stretch,
+                Access::Pin("ldac0", true),
+                Access::Pin("ldac1", true),
+            ]
+        );
+    }
+
+    #[test]
+    fn calibration_from_two_points() {
+        // The output is 2% high with an offset of 10 mV.
+        let calibration = DacCalibration::from_points((-5., -5.09), (5., 5.11));
+        assert!((calibration.gain - 1.02).abs() < 1e-6);
+        assert!((calibration.offset - 0.01).abs() < 1e-6);
+
+        for volts in [-8., -1., 0., 0.5, 8.] {
+            let actual =
+                calibration.code_to_volts(calibration.volts_to_code(volts));
+            assert!(
+                (actual - volts).abs() <= DacCode::VOLT_PER_LSB * 1.02,
+                "{volts}: {actual}"
+            );
+        }
+
+        let identity = DacCalibration::default();
+        assert_eq!(identity.volts_to_code(0.), 0x8000);
+        assert_eq!(identity.code_to_volts(0x8000), 0.);
+    }
+
+    #[test]
+    fn calibration_saturates_at_rails() {
+        let calibration = DacCalibration {
+            gain: 1.02,
+            offset: 0.01,
+        };
+
+        assert_eq!(
+            calibration.volts_to_code(DacCode::FULL_SCALE * 2.),
+            u16::MAX
+        );
+        assert_eq!(calibration.volts_to_code(1e9), u16::MAX);
+        assert_eq!(calibration.volts_to_code(-DacCode::FULL_SCALE * 2.), 0);
+        assert_eq!(calibration.volts_to_code(-1e9), 0);
+    }
+
+    #[test]
+    fn codes_are_clamped_and_counted() {
+        let mut codes = [0, 100, 1000, 2000, u16::MAX];
+        let mut limit_hits = 0;
+        clamp_codes(&mut codes, (100, 1000), &mut limit_hits);
+        assert_eq!(codes, [100, 100, 1000, 1000, 1000]);
+        assert_eq!(limit_hits, 3);
+
+        clamp_codes(&mut [50], (100, 1000), &mut limit_hits);
+        assert_eq!(limit_hits, 4);
+
+        // The count wraps on overflow.
+        let mut limit_hits = u32::MAX;
+        clamp_codes(&mut [0, 0], (1, 1), &mut limit_hits);
+        assert_eq!(limit_hits, 1);
+    }
+
+    #[test]
+    fn full_range_limits_do_not_clamp() {
+        let mut codes = [0, 0x8000, u16::MAX];
+        let mut limit_hits = 0;
+        clamp_codes(&mut codes, (u16::MIN, u16::MAX), &mut limit_hits);
+        assert_eq!(codes, [0, 0x8000, u16::MAX]);
+        assert_eq!(limit_hits, 0);
+    }
+
+    #[test]
+    fn manual_output_clamps_to_limits() {
+        let calibration = DacCalibration::default();
+        let (min, max) = (
+            calibration.volts_to_code(-1.),
+            calibration.volts_to_code(2.),
+        );
+        let mut output = ManualOutput {
+            calibration,
+            limits: (min, max),
+            code: None,
+        };
+
+        let volts = output.set(0.5);
+        assert!((volts - 0.5).abs() <= DacCode::VOLT_PER_LSB);
+        assert_eq!(output.code(), Some(calibration.volts_to_code(0.5)));
+
+        // Out of range voltages are clamped and the clamped voltage is reported.
+        assert_eq!(output.set(5.), calibration.code_to_volts(max));
+        assert_eq!(output.code(), Some(max));
+        assert_eq!(output.set(-20.), calibration.code_to_volts(min));
+        assert_eq!(output.code(), Some(min));
+
+        assert!(output.release());
+        assert_eq!(output.code(), None);
+        assert!(!output.release());
+    }
+}
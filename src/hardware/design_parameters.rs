@@ -1,3 +1,4 @@
+use core::ops::RangeInclusive;
 use stm32h7xx_hal::time::MegaHertz;
 
 /// The system clock, used in various timer calculations
@@ -14,6 +15,9 @@ pub const ADC_DAC_SCK_MAX: MegaHertz = MegaHertz::MHz(50);
 pub const TIMER_FREQUENCY: MegaHertz = MegaHertz::MHz(100);
 pub const TIMER_PERIOD: f32 = 1. / (TIMER_FREQUENCY.to_Hz() as f32);
 
+/// The nominal frequency of the external reference clock supplied to the reference timer.
+pub const EXTERNAL_REFERENCE_FREQUENCY: MegaHertz = MegaHertz::MHz(10);
+
 /// The QSPI frequency for communicating with the pounder DDS.
 pub const POUNDER_QSPI_FREQUENCY: MegaHertz = MegaHertz::MHz(50);
 
@@ -24,10 +28,11 @@ pub const POUNDER_QSPI_FREQUENCY: MegaHertz = MegaHertz::MHz(50);
 // of 1280 ns. We use 1300 ns to be safe.
 pub const POUNDER_IO_UPDATE_DELAY: f32 = 1_300e-9;
 
-/// The duration to assert IO_Update for the pounder DDS.
+/// The default duration to assert IO_Update for the pounder DDS.
 // IO_Update should be latched for 4 SYNC_CLK cycles after the QSPI profile write. With pounder
-// SYNC_CLK running at 100MHz (1/4 of the pounder reference clock of 500MHz), this corresponds to
-// 32ns. To accomodate rounding errors, we use 50ns instead.
+// SYNC_CLK running at 125MHz (1/4 of the pounder system clock of 500MHz), this corresponds to
+// 32ns. To accomodate rounding errors, we use 50ns instead. The minimum scales inversely with the
+// system clock, see `dds_output::min_io_update_duration()`.
 pub const POUNDER_IO_UPDATE_DURATION: f32 = 50e-9;
 
 /// The DDS reference clock frequency in MHz.
@@ -41,10 +46,38 @@ pub const DDS_MULTIPLIER: u8 = 5;
 pub const DDS_SYSTEM_CLK: MegaHertz =
     MegaHertz::MHz(DDS_REF_CLK.to_MHz() * DDS_MULTIPLIER as u32);
 
+/// The allowed frequencies of an external DDS reference clock in Hz, by PLL multiplier.
+///
+/// The external reference clock input of Pounder drives the AD9959 REF_CLK input. With the PLL
+/// bypassed (multiplier 1), the AD9959 accepts 1 MHz to 500 MHz. With the PLL enabled (multiplier
+/// 4-20), it accepts 10 MHz to 125 MHz.
+pub const DDS_EXT_REF_CLK_LIMITS: [(RangeInclusive<u8>, RangeInclusive<f32>);
+    2] = [(1..=1, 1e6..=500e6), (4..=20, 10e6..=125e6)];
+
+/// The allowed DDS system clock frequencies in Hz if the PLL is enabled.
+///
+/// These are the AD9959 VCO ranges with the VCO gain control low and high respectively.
+pub const DDS_PLL_SYSTEM_CLK_LIMITS: [RangeInclusive<f32>; 2] =
+    [100e6..=160e6, 255e6..=500e6];
+
 /// The divider from the DDS system clock to the SYNC_CLK output (sync-clk is always 1/4 of sysclk).
 #[allow(dead_code)]
 pub const DDS_SYNC_CLK_DIV: u8 = 4;
 
+/// The time for the AFE supply to settle after AFE_PWR_ON is asserted, in seconds.
+///
+/// The AFE rails are switched on by a load switch feeding the bulk decoupling of the front-end.
+/// There is no power-good feedback to the MCU, so a fixed settle time is used that leaves a wide
+/// margin over the soft-start of the switch and the charging of the decoupling capacitors.
+pub const AFE_POWER_SETTLE_TIME: f32 = 10e-3;
+
+/// The time for the DDS reference clock multiplier PLL to lock after it is configured, in
+/// seconds.
+///
+/// The AD9959 does not expose a lock indicator, so a fixed time is used that covers the PLL lock
+/// time specified in the datasheet with margin.
+pub const DDS_PLL_LOCK_TIME: f32 = 1e-3;
+
 /// The maximum ADC/DAC sample processing buffer size.
 pub const MAX_SAMPLE_BUFFER_SIZE: usize = 32;
 
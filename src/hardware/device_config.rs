@@ -0,0 +1,384 @@
+//! Device configuration snapshots
+//!
+//! # Design
+//! A [DeviceConfig] captures the runtime configuration of all subsystems that determine the
+//! output of the device: the servo configuration, the AFE gains and, if Pounder is present, the
+//! DDS reference clock and the frequency, phase, amplitude and attenuation of each channel. It is
+//! serializable with serde, e.g. into JSON with `serde-json-core`, so experiments can be
+//! reproduced by restoring a captured configuration.
+//!
+//! The devices are owned by different tasks, so they are passed by reference through [Devices].
+//!
+//! ## Restore Order
+//! [DeviceConfig::apply()] never lets the RF output power overshoot the restored configuration.
+//! The Pounder outputs are parked first (muted and fully attenuated). The reference clock is then
+//! selected before the channels are tuned, as the tuning words depend on the system clock. The
+//! attenuations are restored last, once the DDS channels carry the restored amplitudes.
+use embedded_hal::digital::v2::OutputPin;
+use serde::{Deserialize, Serialize};
+
+use super::{
+    afe::Gain,
+    eeprom::ServoConfig,
+    pounder::{
+        self,
+        attenuators::AttenuatorInterface,
+        dds_output::{DdsOutput, IoUpdateTimer, ProfileStream},
+        Channel, ChannelState, ClockInterface, DdsChannelState, DdsClockConfig,
+        Error,
+    },
+    setup, AFE0, AFE1,
+};
+
+/// The configuration of the Pounder RF outputs.
+#[derive(Serialize, Deserialize, Copy, Clone, Debug)]
+pub struct PounderConfig {
+    /// The DDS reference clock configuration.
+    pub clock: DdsClockConfig,
+
+    /// The state of each channel, indexed by [Channel].
+    pub channels: [ChannelState; 4],
+}
+
+/// The runtime configuration of the whole device.
+#[derive(Serialize, Deserialize, Copy, Clone, Debug)]
+pub struct DeviceConfig {
+    /// The servo configuration.
+    pub servo: ServoConfig,
+
+    /// The gain of each AFE.
+    pub afe_gains: [Gain; 2],
+
+    /// The Pounder configuration, or `None` if Pounder is not present.
+    pub pounder: Option<PounderConfig>,
+}
+
+/// References to the devices covered by a [DeviceConfig].
+pub struct Devices<'a> {
+    /// The active servo configuration.
+    pub servo: &'a mut ServoConfig,
+
+    /// The AFEs of ADC0 and ADC1.
+    pub afes: &'a mut (AFE0, AFE1),
+
+    /// The Pounder devices, if Pounder is present.
+    pub pounder: Option<&'a mut setup::PounderDevices>,
+}
+
+impl DeviceConfig {
+    /// Capture the current configuration of the devices.
+    ///
+    /// # Note
+    /// The attenuations are read back from the attenuators, which requires mutable access to
+    /// the devices. The DDS state is taken from the values last programmed.
+    ///
+    /// # Args
+    /// * `devices` - The devices to capture the configuration of.
+    pub fn capture(devices: &mut Devices) -> Result<Self, Error> {
+        let pounder = match devices.pounder.as_deref_mut() {
+            Some(pounder) => Some(capture_pounder(
+                &mut pounder.pounder,
+                &pounder.dds_output,
+            )?),
+            None => None,
+        };
+
+        Ok(Self {
+            servo: *devices.servo,
+            afe_gains: [devices.afes.0.gain(), devices.afes.1.gain()],
+            pounder,
+        })
+    }
+
+    /// Restore the configuration of the devices.
+    ///
+    /// # Note
+    /// See the module documentation for the order of operations. If an operation fails, the
+    /// remaining operations are skipped. The Pounder outputs are then left in a safe state, but
+    /// possibly parked. A Pounder configuration can not be applied if Pounder is absent, in which
+    /// case [Error::InvalidState] is returned before any device is modified.
+    ///
+    /// # Args
+    /// * `devices` - The devices to configure.
+    /// * `reference_present` - Detects the external DDS reference clock, see
+    ///   `ClockInterface::select_external_clock()`.
+    pub fn apply(
+        &self,
+        devices: &mut Devices,
+        reference_present: impl FnOnce() -> bool,
+    ) -> Result<(), Error> {
+        if self.pounder.is_some() && devices.pounder.is_none() {
+            return Err(Error::InvalidState);
+        }
+
+        devices.afes.0.set_gain(self.afe_gains[0]);
+        devices.afes.1.set_gain(self.afe_gains[1]);
+
+        if let (Some(config), Some(pounder)) =
+            (&self.pounder, devices.pounder.as_deref_mut())
+        {
+            let dds_present = pounder.pounder.dds_present();
+            apply_pounder(
+                config,
+                &mut pounder.pounder,
+                &mut pounder.dds_output,
+                dds_present,
+                reference_present,
+            )?;
+        }
+
+        *devices.servo = self.servo;
+        Ok(())
+    }
+}
+
+/// Capture the configuration of the Pounder RF outputs.
+///
+/// # Args
+/// * `rf` - The attenuators and reference clock selection of Pounder.
+/// * `dds` - The DDS output generating the channels.
+fn capture_pounder<R, Q, T, P>(
+    rf: &mut R,
+    dds: &DdsOutput<Q, T, P>,
+) -> Result<PounderConfig, Error>
+where
+    R: AttenuatorInterface + ClockInterface,
+    Q: ProfileStream,
+    T: IoUpdateTimer,
+    P: OutputPin,
+    P::Error: core::fmt::Debug,
+{
+    let attenuations = rf.attenuations()?;
+
+    let mut channels = [ChannelState {
+        parameters: DdsChannelState {
+            phase_offset: 0.,
+            frequency: 0.,
+            amplitude: 0.,
+            enabled: false,
+        },
+        attenuation: 0.,
+    }; 4];
+    for channel in enum_iterator::all::<Channel>() {
+        channels[channel as usize] = ChannelState {
+            parameters: DdsChannelState {
+                phase_offset: dds.phase(channel),
+                frequency: dds.frequency(channel),
+                amplitude: dds.amplitude(channel),
+                enabled: dds.channel_enabled(channel),
+            },
+            attenuation: attenuations[channel as usize],
+        };
+    }
+
+    Ok(PounderConfig {
+        clock: rf.clock_config(),
+        channels,
+    })
+}
+
+/// Restore the configuration of the Pounder RF outputs.
+///
+/// # Args
+/// * `config` - The configuration to restore.
+/// * `rf` - The attenuators and reference clock selection of Pounder.
+/// * `dds` - The DDS output generating the channels.
+/// * `dds_present` - Whether the DDS passed its self test. The channels are not tuned otherwise,
+///   see `PounderDevices::set_dds_channel()`.
+/// * `reference_present` - Detects the external DDS reference clock.
+fn apply_pounder<R, Q, T, P>(
+    config: &PounderConfig,
+    rf: &mut R,
+    dds: &mut DdsOutput<Q, T, P>,
+    dds_present: bool,
+    reference_present: impl FnOnce() -> bool,
+) -> Result<(), Error>
+where
+    R: AttenuatorInterface + ClockInterface,
+    Q: ProfileStream,
+    T: IoUpdateTimer,
+    P: OutputPin,
+    P::Error: core::fmt::Debug,
+{
+    pounder::park_outputs(rf, dds)?;
+
+    if config.clock.external_clock {
+        rf.select_external_clock(
+            dds,
+            config.clock.reference_clock,
+            config.clock.multiplier,
+            reference_present,
+        )?;
+    } else {
+        rf.select_onboard_clock(dds)?;
+    }
+
+    if !dds_present {
+        return Err(Error::InvalidState);
+    }
+
+    for channel in enum_iterator::all::<Channel>() {
+        let parameters = config.channels[channel as usize].parameters;
+        dds.set_channel(
+            channel,
+            parameters.frequency,
+            parameters.phase_offset,
+            parameters.amplitude,
+        )?;
+        dds.enable_channel(channel, parameters.enabled);
+    }
+
+    rf.set_all_attenuations(
+        &config.channels.map(|channel| channel.attenuation),
+    )?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hardware::pounder::{
+        dds_output::tests::{dds_output, Access, Accesses},
+        ClockState,
+    };
+
+    /// Attenuator shift registers and a reference clock selection that record the accesses
+    /// along with the DDS accesses.
+    struct MockRf<'a> {
+        accesses: &'a Accesses,
+        shift: [u8; 4],
+        clock: ClockState,
+    }
+
+    impl<'a> MockRf<'a> {
+        fn new(accesses: &'a Accesses) -> Self {
+            Self {
+                accesses,
+                shift: [0; 4],
+                clock: ClockState::default(),
+            }
+        }
+
+        fn log(&self, access: Access) {
+            self.accesses.borrow_mut().push(access).unwrap();
+        }
+    }
+
+    impl AttenuatorInterface for MockRf<'_> {
+        fn reset_attenuators(&mut self) -> Result<(), Error> {
+            unimplemented!()
+        }
+
+        fn latch_attenuator(&mut self, channel: Channel) -> Result<(), Error> {
+            self.log(Access::LatchAttenuator(channel as usize));
+            Ok(())
+        }
+
+        fn transfer_attenuators(
+            &mut self,
+            channels: &mut [u8; 4],
+        ) -> Result<(), Error> {
+            self.log(Access::TransferAttenuators(*channels));
+            core::mem::swap(&mut self.shift, channels);
+            Ok(())
+        }
+    }
+
+    impl ClockInterface for MockRf<'_> {
+        fn select_reference_input(
+            &mut self,
+            external: bool,
+        ) -> Result<(), Error> {
+            self.log(Access::ReferenceInput(external));
+            Ok(())
+        }
+
+        fn clock_state(&self) -> &ClockState {
+            &self.clock
+        }
+
+        fn clock_state_mut(&mut self) -> &mut ClockState {
+            &mut self.clock
+        }
+    }
+
+    fn serialize(config: &DeviceConfig, buffer: &mut [u8]) -> usize {
+        serde_json_core::to_slice(config, buffer).unwrap()
+    }
+
+    #[test]
+    fn pounder_config_round_trip() {
+        let accesses = Accesses::default();
+        let mut dds = dds_output(&accesses);
+        let mut rf = MockRf::new(&accesses);
+        rf.select_external_clock(&mut dds, 20e6, 20, || true)
+            .unwrap();
+        dds.set_channel(Channel::Out0, 80e6, 0.25, 0.5).unwrap();
+        dds.set_channel(Channel::Out1, 10e6, 0., 1.).unwrap();
+        dds.enable_channel(Channel::In1, false);
+        rf.set_all_attenuations(&[1., 2.5, 10., 31.5]).unwrap();
+
+        let config = DeviceConfig {
+            servo: ServoConfig::default(),
+            afe_gains: [Gain::G2, Gain::G10],
+            pounder: Some(capture_pounder(&mut rf, &dds).unwrap()),
+        };
+        let mut captured = [0; 1024];
+        let len = serialize(&config, &mut captured);
+        let captured = &captured[..len];
+        let (restored, _): (DeviceConfig, _) =
+            serde_json_core::from_slice(captured).unwrap();
+
+        let accesses = Accesses::default();
+        let mut dds = dds_output(&accesses);
+        let mut rf = MockRf::new(&accesses);
+        apply_pounder(
+            restored.pounder.as_ref().unwrap(),
+            &mut rf,
+            &mut dds,
+            true,
+            || true,
+        )
+        .unwrap();
+
+        let config = DeviceConfig {
+            pounder: Some(capture_pounder(&mut rf, &dds).unwrap()),
+            ..restored
+        };
+        let mut recaptured = [0; 1024];
+        let len = serialize(&config, &mut recaptured);
+        assert_eq!(&recaptured[..len], captured);
+
+        // The outputs are fully attenuated before the channels are tuned to the restored
+        // amplitudes, and the reference clock is selected before the tuning words are computed.
+        let accesses = accesses.borrow();
+        let parked = accesses
+            .iter()
+            .position(|access| matches!(access, Access::TransferAttenuators(_)))
+            .unwrap();
+        assert!(accesses[..parked].iter().all(|access| matches!(
+            access,
+            Access::Profile(_) | Access::IoUpdate
+        )));
+        let clock = accesses
+            .iter()
+            .position(|access| *access == Access::ReferenceInput(true))
+            .unwrap();
+        let restored = accesses
+            .iter()
+            .rposition(|access| {
+                matches!(access, Access::TransferAttenuators(_))
+            })
+            .unwrap();
+        assert!(parked < clock);
+        let tuning = accesses[clock..restored]
+            .iter()
+            .filter(|access| matches!(access, Access::Profile(_)));
+        // The system clock and, for each channel, its tuning and its channel function.
+        assert_eq!(tuning.count(), 9);
+        assert!(accesses[restored + 1..]
+            .iter()
+            .all(|access| matches!(access, Access::LatchAttenuator(_))));
+    }
+}
@@ -0,0 +1,288 @@
+//! Stabilizer EEM GPIO edge capture
+//!
+//! # Design
+//! If Pounder is not present, the EEM connector provides two digital inputs (LVDS4 and LVDS5) and
+//! two digital outputs (LVDS6 and LVDS7). The inputs can be used as external triggers, e.g. to
+//! start or stop data acquisition on an external pulse.
+//!
+//! The inputs are routed to the EXTI controller. LVDS4 (PD1) maps to EXTI line 1 and LVDS5 (PD2)
+//! maps to EXTI line 2, which are serviced by the `EXTI1` and `EXTI2` interrupts respectively.
+//! The application is required to bind these interrupts and call
+//! [EemGpioDevices::handle_interrupt()] from them.
+//!
+//! When an enabled edge occurs, an [EdgeEvent] is latched together with the sampling timer tick
+//! at which the interrupt was serviced. Only the first event is latched. Subsequent events are
+//! ignored until the latched event is taken with [EemGpioDevices::take_edge_event()]. Taking the
+//! event clears the latch. Because both the interrupt handler and the consumer require mutable
+//! access, they are serialized by the resource lock of the application, which makes taking and
+//! clearing the latch atomic with respect to the interrupt.
+use super::{
+    hal::{
+        self,
+        gpio::{Edge, ExtiPin},
+    },
+    EemDigitalInput0, EemDigitalInput1, EemDigitalOutput0, EemDigitalOutput1,
+};
+use embedded_hal::digital::v2::OutputPin;
+
+/// The EEM GPIO inputs supporting edge capture.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum EemPin {
+    /// LVDS4 on PD1 (EXTI line 1).
+    Lvds4,
+    /// LVDS5 on PD2 (EXTI line 2).
+    Lvds5,
+}
+
+/// The EEM GPIO outputs.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum EemOutput {
+    /// LVDS6 on PD3.
+    Lvds6,
+    /// LVDS7 on PD4.
+    Lvds7,
+}
+
+/// A latched edge on an EEM GPIO input.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct EdgeEvent {
+    /// The input the edge occurred on.
+    pub pin: EemPin,
+
+    /// The direction of the edge. This is either [Edge::Rising] or [Edge::Falling].
+    pub edge: Edge,
+
+    /// The sampling timer counter value when the edge was serviced.
+    pub timestamp: u32,
+}
+
+/// The GPIO pins available on the EEM connector, if Pounder is not present.
+pub struct EemGpioDevices {
+    pub lvds4: EemDigitalInput0,
+    pub lvds5: EemDigitalInput1,
+    pub lvds6: EemDigitalOutput0,
+    pub lvds7: EemDigitalOutput1,
+    exti: hal::stm32::EXTI,
+    event: Option<EdgeEvent>,
+    output_levels: [bool; 2],
+}
+
+impl EemGpioDevices {
+    /// Construct the EEM GPIO devices.
+    ///
+    /// # Note
+    /// The inputs must already be configured as EXTI interrupt sources. The outputs are driven
+    /// low.
+    ///
+    /// # Args
+    /// * `lvds4` - The LVDS4 input.
+    /// * `lvds5` - The LVDS5 input.
+    /// * `lvds6` - The LVDS6 output.
+    /// * `lvds7` - The LVDS7 output.
+    /// * `exti` - The EXTI peripheral used to configure edge detection.
+    pub fn new(
+        lvds4: EemDigitalInput0,
+        lvds5: EemDigitalInput1,
+        lvds6: EemDigitalOutput0,
+        lvds7: EemDigitalOutput1,
+        exti: hal::stm32::EXTI,
+    ) -> Self {
+        let mut devices = Self {
+            lvds4,
+            lvds5,
+            lvds6,
+            lvds7,
+            exti,
+            event: None,
+            output_levels: [false; 2],
+        };
+
+        devices.set_output(EemOutput::Lvds6, false);
+        devices.set_output(EemOutput::Lvds7, false);
+
+        devices
+    }
+
+    /// Drive an output.
+    ///
+    /// # Args
+    /// * `pin` - The output to drive.
+    /// * `level` - True to drive the output high, false to drive it low.
+    pub fn set_output(&mut self, pin: EemOutput, level: bool) {
+        drive_output(
+            (&mut self.lvds6, &mut self.lvds7),
+            &mut self.output_levels,
+            pin,
+            level,
+        );
+    }
+
+    /// Get the level an output was last driven to.
+    ///
+    /// # Note
+    /// The level is tracked when it is set with [EemGpioDevices::set_output()]. Driving the
+    /// pins directly bypasses the tracking.
+    ///
+    /// # Args
+    /// * `pin` - The output to get the level of.
+    ///
+    /// # Returns
+    /// True if the output is driven high.
+    pub fn output_state(&self, pin: EemOutput) -> bool {
+        self.output_levels[pin as usize]
+    }
+
+    /// Enable edge capture on an input.
+    ///
+    /// # Args
+    /// * `pin` - The input to capture edges on.
+    /// * `edge` - The edges to capture.
+    pub fn on_edge(&mut self, pin: EemPin, edge: Edge) {
+        match pin {
+            EemPin::Lvds4 => {
+                self.lvds4.trigger_on_edge(&mut self.exti, edge);
+                self.lvds4.enable_interrupt(&mut self.exti);
+            }
+            EemPin::Lvds5 => {
+                self.lvds5.trigger_on_edge(&mut self.exti, edge);
+                self.lvds5.enable_interrupt(&mut self.exti);
+            }
+        }
+    }
+
+    /// Disable edge capture on an input.
+    ///
+    /// # Args
+    /// * `pin` - The input to stop capturing edges on.
+    pub fn disable_edge(&mut self, pin: EemPin) {
+        match pin {
+            EemPin::Lvds4 => self.lvds4.disable_interrupt(&mut self.exti),
+            EemPin::Lvds5 => self.lvds5.disable_interrupt(&mut self.exti),
+        }
+    }
+
+    /// Handle a pending EXTI interrupt of the inputs.
+    ///
+    /// # Note
+    /// This must be called from the `EXTI1` and `EXTI2` interrupt handlers. The pending bit is
+    /// always cleared, but an event is only latched if no other event is latched.
+    pub fn handle_interrupt(&mut self) {
+        let timestamp = sampling_timer_ticks();
+
+        if self.lvds4.check_interrupt() {
+            self.lvds4.clear_interrupt_pending_bit();
+            let edge = edge_direction(self.lvds4.is_high());
+            self.latch(EemPin::Lvds4, edge, timestamp);
+        }
+
+        if self.lvds5.check_interrupt() {
+            self.lvds5.clear_interrupt_pending_bit();
+            let edge = edge_direction(self.lvds5.is_high());
+            self.latch(EemPin::Lvds5, edge, timestamp);
+        }
+    }
+
+    /// Take the latched edge event, clearing the latch.
+    ///
+    /// # Returns
+    /// The first edge event since the latch was last cleared, if any.
+    pub fn take_edge_event(&mut self) -> Option<EdgeEvent> {
+        self.event.take()
+    }
+
+    fn latch(&mut self, pin: EemPin, edge: Edge, timestamp: u32) {
+        if self.event.is_none() {
+            self.event.replace(EdgeEvent {
+                pin,
+                edge,
+                timestamp,
+            });
+        }
+    }
+}
+
+/// Drive an output and track its level, see [EemGpioDevices::set_output()].
+///
+/// # Args
+/// * `outputs` - The LVDS6 and LVDS7 outputs.
+/// * `levels` - The tracked levels of the outputs.
+/// * `pin` - The output to drive.
+/// * `level` - True to drive the output high, false to drive it low.
+fn drive_output<P6, P7>(
+    outputs: (&mut P6, &mut P7),
+    levels: &mut [bool; 2],
+    pin: EemOutput,
+    level: bool,
+) where
+    P6: OutputPin,
+    P7: OutputPin,
+    P6::Error: core::fmt::Debug,
+    P7::Error: core::fmt::Debug,
+{
+    match pin {
+        EemOutput::Lvds6 => outputs.0.set_state(level.into()).unwrap(),
+        EemOutput::Lvds7 => outputs.1.set_state(level.into()).unwrap(),
+    }
+    levels[pin as usize] = level;
+}
+
+// The edge direction is derived from the input level when the interrupt is serviced. For pulses
+// shorter than the interrupt latency, this may report the opposite edge.
+fn edge_direction(is_high: bool) -> Edge {
+    if is_high {
+        Edge::Rising
+    } else {
+        Edge::Falling
+    }
+}
+
+fn sampling_timer_ticks() -> u32 {
+    // Note(unsafe): The sampling timer (TIM2) counter is only read, which is atomic and has no
+    // side effects on the timer owned elsewhere.
+    unsafe { (*hal::stm32::TIM2::ptr()).cnt.read().bits() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A GPIO that records its output level.
+    #[derive(Default)]
+    struct MockPin {
+        levels: heapless::Vec<bool, 8>,
+    }
+
+    impl OutputPin for MockPin {
+        type Error = core::convert::Infallible;
+
+        fn set_low(&mut self) -> Result<(), Self::Error> {
+            self.levels.push(false).unwrap();
+            Ok(())
+        }
+
+        fn set_high(&mut self) -> Result<(), Self::Error> {
+            self.levels.push(true).unwrap();
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn output_state_tracks_commands() {
+        let (mut lvds6, mut lvds7) = (MockPin::default(), MockPin::default());
+        let mut levels = [false; 2];
+
+        for (pin, level) in [
+            (EemOutput::Lvds6, true),
+            (EemOutput::Lvds7, true),
+            (EemOutput::Lvds6, false),
+            (EemOutput::Lvds6, false),
+        ] {
+            drive_output((&mut lvds6, &mut lvds7), &mut levels, pin, level);
+            assert_eq!(levels[pin as usize], level);
+        }
+
+        assert_eq!(lvds6.levels.as_slice(), [true, false, false]);
+        assert_eq!(lvds7.levels.as_slice(), [true]);
+        assert_eq!(levels, [false, true]);
+    }
+}
@@ -1,4 +1,24 @@
-use embedded_hal::blocking::{delay::DelayMs, i2c::WriteRead};
+//! Stabilizer EEPROM
+//!
+//! # Design
+//! The EEPROM on I2C2 holds the factory-programmed EUI48 in the last bytes of its address space.
+//! The lower half persists the servo configuration, the DAC calibrations and the Pounder output
+//! power and flatness calibrations. Each record is stored as a block protected by a CRC, so blank
+//! or corrupted records are rejected on read.
+use embedded_hal::blocking::{
+    delay::DelayMs,
+    i2c::{Write, WriteRead},
+};
+use serde::{Deserialize, Serialize};
+use smoltcp_nal::smoltcp::wire::EthernetAddress;
+
+use super::{
+    adc::AdcCalibration,
+    dac::DacCalibration,
+    pounder::{
+        FlatnessCalibration, FlatnessPoint, PowerCalibration, FLATNESS_POINTS,
+    },
+};
 
 // The EEPROM is a variant without address bits, so the 3 LSB of this word are "dont-cares".
 const I2C_ADDR: u8 = 0x50;
@@ -6,6 +26,140 @@ const I2C_ADDR: u8 = 0x50;
 // The MAC address is stored in the last 6 bytes of the 256 byte address space.
 const MAC_POINTER: u8 = 0xFA;
 
+// The servo configuration is stored at the start of the writable lower half of the address space.
+const SERVO_CONFIG_POINTER: u8 = 0x00;
+
+// The servo configuration consists of three f32 values followed by a CRC byte.
+const SERVO_CONFIG_SIZE: usize = 3 * 4 + 1;
+
+// The DAC calibrations are stored at the next page boundary after the servo configuration.
+const DAC_CALIBRATION_POINTER: u8 = 0x10;
+
+// The DAC calibrations consist of a gain and offset f32 per DAC followed by a CRC byte.
+const DAC_CALIBRATION_SIZE: usize = 2 * 2 * 4 + 1;
+
+// The power calibration is stored at the next page boundary after the DAC calibrations.
+const POWER_CALIBRATION_POINTER: u8 = 0x28;
+
+// The power calibration consists of a full-scale power f32 per channel followed by a CRC byte.
+const POWER_CALIBRATION_SIZE: usize = 4 * 4 + 1;
+
+// The ADC calibrations are stored at the next page boundary after the power calibration.
+const ADC_CALIBRATION_POINTER: u8 = 0x40;
+
+// The ADC calibrations consist of a gain and offset f32 per ADC followed by a CRC byte.
+const ADC_CALIBRATION_SIZE: usize = 2 * 2 * 4 + 1;
+
+// The flatness calibration is stored at the next page boundary after the ADC calibrations.
+const FLATNESS_CALIBRATION_POINTER: u8 = 0x58;
+
+// The flatness calibration consists of the number of points, a frequency and correction per
+// point and a CRC byte. The frequency is stored as a u16 in units of 10 kHz and the
+// correction as an i16 in units of 0.01 dB.
+const FLATNESS_CALIBRATION_SIZE: usize = 1 + FLATNESS_POINTS * 4 + 1;
+
+// The frequency unit of the flatness calibration in Hz.
+const FLATNESS_FREQUENCY_UNIT: f32 = 10e3;
+
+// The correction unit of the flatness calibration in dB.
+const FLATNESS_CORRECTION_UNIT: f32 = 0.01;
+
+// The size of the largest block in bytes.
+const MAX_BLOCK_SIZE: usize = FLATNESS_CALIBRATION_SIZE;
+
+// The EEPROM page size in bytes. Writes must not cross a page boundary.
+const PAGE_SIZE: usize = 8;
+
+// The maximum duration of an internal write cycle in milliseconds.
+const WRITE_CYCLE_TIME_MS: u8 = 5;
+
+/// Errors reading the EEPROM or indicating implausible EEPROM contents.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum EepromError {
+    /// The MAC address is all 0xFF, which is the contents of a blank EEPROM.
+    BlankMac,
+
+    /// The MAC address is all zero.
+    ZeroMac,
+
+    /// The MAC address has the multicast bit set, so it can not identify a single device.
+    MulticastMac,
+
+    /// The EEPROM did not respond.
+    Unreachable,
+}
+
+/// The servo configuration persisted in EEPROM.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ServoConfig {
+    /// The servo setpoint in Hz.
+    pub setpoint: f32,
+
+    /// The proportional gain.
+    pub kp: f32,
+
+    /// The integral gain.
+    pub ki: f32,
+}
+
+impl Default for ServoConfig {
+    /// The safe default configuration with the servo disabled (zero gains).
+    fn default() -> Self {
+        Self {
+            setpoint: 0.,
+            kp: 0.,
+            ki: 0.,
+        }
+    }
+}
+
+impl ServoConfig {
+    /// Serialize the configuration into its EEPROM block.
+    ///
+    /// # Note
+    /// The three values are stored in little-endian order. The CRC byte is left to
+    /// [write_block()].
+    fn pack(&self) -> [u8; SERVO_CONFIG_SIZE] {
+        let mut block = [0u8; SERVO_CONFIG_SIZE];
+        block[0..4].copy_from_slice(&self.setpoint.to_le_bytes());
+        block[4..8].copy_from_slice(&self.kp.to_le_bytes());
+        block[8..12].copy_from_slice(&self.ki.to_le_bytes());
+        block
+    }
+
+    /// Deserialize the configuration from the data of its EEPROM block.
+    fn unpack(data: &[u8]) -> Self {
+        Self {
+            setpoint: f32_at(data, 0),
+            kp: f32_at(data, 4),
+            ki: f32_at(data, 8),
+        }
+    }
+}
+
+/// Compute the CRC-8 of a block of data.
+///
+/// # Note
+/// The CRC uses the polynomial 0x07 with an initial value of 0xFF. The initial value ensures that
+/// neither a blank (all 0xFF) nor a zeroed EEPROM region is accepted as a valid block.
+fn crc8(data: &[u8]) -> u8 {
+    data.iter().fold(0xFF, |crc, &byte| {
+        (0..8).fold(crc ^ byte, |crc, _| {
+            if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x07
+            } else {
+                crc << 1
+            }
+        })
+    })
+}
+
+/// Read a little-endian f32 from block data.
+fn f32_at(data: &[u8], offset: usize) -> f32 {
+    // Note(unwrap): The slice is guaranteed to be 4 bytes long.
+    f32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
 pub fn read_eui48<T>(i2c: &mut T, delay: &mut impl DelayMs<u8>) -> [u8; 6]
 where
     T: WriteRead,
@@ -40,3 +194,611 @@ where
 
     panic!("Failed to read MAC address");
 }
+
+/// Read and validate the MAC address from EEPROM.
+///
+/// # Note
+/// The factory-programmed EUI48 is not protected by a CRC. The address is validated to be a
+/// plausible unicast address instead.
+///
+/// # Args
+/// * `i2c` - The I2C bus connected to the EEPROM.
+/// * `delay` - A delay to wait between read attempts.
+///
+/// # Returns
+/// The MAC address, or the reason it was rejected.
+pub fn read_validated_eui48<T>(
+    i2c: &mut T,
+    delay: &mut impl DelayMs<u8>,
+) -> Result<EthernetAddress, EepromError>
+where
+    T: WriteRead,
+{
+    validate_eui48(read_eui48(i2c, delay))
+}
+
+/// Check that the MAC address can be read from EEPROM and is plausible.
+///
+/// # Note
+/// Unlike [read_eui48()], the address is read only once. This neither waits for the I2C bus to
+/// come up nor panics if the EEPROM does not respond.
+///
+/// # Args
+/// * `i2c` - The I2C bus connected to the EEPROM.
+///
+/// # Returns
+/// The MAC address, or the reason it could not be read or was rejected.
+pub fn check_eui48<T>(i2c: &mut T) -> Result<EthernetAddress, EepromError>
+where
+    T: WriteRead,
+{
+    let mut buffer = [0u8; 6];
+    i2c.write_read(I2C_ADDR, &[MAC_POINTER], &mut buffer)
+        .map_err(|_| EepromError::Unreachable)?;
+    validate_eui48(buffer)
+}
+
+fn validate_eui48(octets: [u8; 6]) -> Result<EthernetAddress, EepromError> {
+    if octets == [0xFF; 6] {
+        Err(EepromError::BlankMac)
+    } else if octets == [0; 6] {
+        Err(EepromError::ZeroMac)
+    } else if octets[0] & 0x01 != 0 {
+        Err(EepromError::MulticastMac)
+    } else {
+        Ok(EthernetAddress(octets))
+    }
+}
+
+/// Read the servo configuration from EEPROM.
+///
+/// # Args
+/// * `i2c` - The I2C bus connected to the EEPROM.
+///
+/// # Returns
+/// The stored configuration, or `None` if the read failed or the CRC does not match.
+pub fn read_servo_config<T>(i2c: &mut T) -> Option<ServoConfig>
+where
+    T: WriteRead,
+{
+    let mut block = [0u8; SERVO_CONFIG_SIZE];
+    let data = read_block(i2c, SERVO_CONFIG_POINTER, &mut block)?;
+    Some(ServoConfig::unpack(data))
+}
+
+/// Write the servo configuration to EEPROM.
+///
+/// # Note
+/// As with all blocks, the configuration is only written if it differs from the stored one, see
+/// [write_block()].
+///
+/// # Args
+/// * `i2c` - The I2C bus connected to the EEPROM.
+/// * `delay` - A delay to wait for the completion of each page write.
+/// * `config` - The configuration to store.
+pub fn write_servo_config<T, E>(
+    i2c: &mut T,
+    delay: &mut impl DelayMs<u8>,
+    config: &ServoConfig,
+) -> Result<(), E>
+where
+    T: WriteRead<Error = E> + Write<Error = E>,
+{
+    write_block(i2c, delay, SERVO_CONFIG_POINTER, &mut config.pack())
+}
+
+/// Read the calibrations of DAC0 and DAC1 from EEPROM.
+///
+/// # Args
+/// * `i2c` - The I2C bus connected to the EEPROM.
+///
+/// # Returns
+/// The stored calibrations, or `None` if the read failed or the CRC does not match.
+pub fn read_dac_calibration<T>(i2c: &mut T) -> Option<[DacCalibration; 2]>
+where
+    T: WriteRead,
+{
+    let mut block = [0u8; DAC_CALIBRATION_SIZE];
+    let data = read_block(i2c, DAC_CALIBRATION_POINTER, &mut block)?;
+
+    Some([
+        DacCalibration {
+            gain: f32_at(data, 0),
+            offset: f32_at(data, 4),
+        },
+        DacCalibration {
+            gain: f32_at(data, 8),
+            offset: f32_at(data, 12),
+        },
+    ])
+}
+
+/// Write the calibrations of DAC0 and DAC1 to EEPROM.
+///
+/// # Note
+/// As with all blocks, the calibrations are only written if they differ from the stored ones.
+///
+/// # Args
+/// * `i2c` - The I2C bus connected to the EEPROM.
+/// * `delay` - A delay to wait for the completion of each page write.
+/// * `calibration` - The calibrations of DAC0 and DAC1.
+pub fn write_dac_calibration<T, E>(
+    i2c: &mut T,
+    delay: &mut impl DelayMs<u8>,
+    calibration: &[DacCalibration; 2],
+) -> Result<(), E>
+where
+    T: WriteRead<Error = E> + Write<Error = E>,
+{
+    let mut block = [0u8; DAC_CALIBRATION_SIZE];
+    for (chunk, calibration) in block.chunks_exact_mut(8).zip(calibration) {
+        chunk[0..4].copy_from_slice(&calibration.gain.to_le_bytes());
+        chunk[4..8].copy_from_slice(&calibration.offset.to_le_bytes());
+    }
+
+    write_block(i2c, delay, DAC_CALIBRATION_POINTER, &mut block)
+}
+
+/// Read the Pounder output power calibration from EEPROM.
+///
+/// # Args
+/// * `i2c` - The I2C bus connected to the EEPROM.
+///
+/// # Returns
+/// The stored calibration, or `None` if the read failed or the CRC does not match.
+pub fn read_power_calibration<T>(i2c: &mut T) -> Option<PowerCalibration>
+where
+    T: WriteRead,
+{
+    let mut block = [0u8; POWER_CALIBRATION_SIZE];
+    let data = read_block(i2c, POWER_CALIBRATION_POINTER, &mut block)?;
+
+    let mut calibration = PowerCalibration::default();
+    for (index, full_scale) in calibration.full_scale_dbm.iter_mut().enumerate()
+    {
+        *full_scale = f32_at(data, 4 * index);
+    }
+
+    Some(calibration)
+}
+
+/// Write the Pounder output power calibration to EEPROM.
+///
+/// # Note
+/// As with all blocks, the calibration is only written if it differs from the stored one.
+///
+/// # Args
+/// * `i2c` - The I2C bus connected to the EEPROM.
+/// * `delay` - A delay to wait for the completion of each page write.
+/// * `calibration` - The output power calibration.
+pub fn write_power_calibration<T, E>(
+    i2c: &mut T,
+    delay: &mut impl DelayMs<u8>,
+    calibration: &PowerCalibration,
+) -> Result<(), E>
+where
+    T: WriteRead<Error = E> + Write<Error = E>,
+{
+    let mut block = [0u8; POWER_CALIBRATION_SIZE];
+    for (chunk, full_scale) in
+        block.chunks_exact_mut(4).zip(&calibration.full_scale_dbm)
+    {
+        chunk.copy_from_slice(&full_scale.to_le_bytes());
+    }
+
+    write_block(i2c, delay, POWER_CALIBRATION_POINTER, &mut block)
+}
+
+/// Read the Pounder output flatness calibration from EEPROM.
+///
+/// # Args
+/// * `i2c` - The I2C bus connected to the EEPROM.
+///
+/// # Returns
+/// The stored calibration, or `None` if the read failed, the CRC does not match or the
+/// points are invalid.
+pub fn read_flatness_calibration<T>(i2c: &mut T) -> Option<FlatnessCalibration>
+where
+    T: WriteRead,
+{
+    let mut block = [0u8; FLATNESS_CALIBRATION_SIZE];
+    let data = read_block(i2c, FLATNESS_CALIBRATION_POINTER, &mut block)?;
+
+    let len = data[0] as usize;
+    if len > FLATNESS_POINTS {
+        return None;
+    }
+
+    let mut points = [FlatnessPoint {
+        frequency: 0.,
+        correction_db: 0.,
+    }; FLATNESS_POINTS];
+    for (point, chunk) in points.iter_mut().zip(data[1..].chunks_exact(4)) {
+        let frequency = u16::from_le_bytes([chunk[0], chunk[1]]);
+        let correction = i16::from_le_bytes([chunk[2], chunk[3]]);
+        *point = FlatnessPoint {
+            frequency: frequency as f32 * FLATNESS_FREQUENCY_UNIT,
+            correction_db: correction as f32 * FLATNESS_CORRECTION_UNIT,
+        };
+    }
+
+    FlatnessCalibration::new(&points[..len]).ok()
+}
+
+/// Write the Pounder output flatness calibration to EEPROM.
+///
+/// # Note
+/// The frequencies are rounded to 10 kHz and the corrections to 0.01 dB. As with all blocks, the
+/// calibration is only written if it differs from the stored one.
+///
+/// # Args
+/// * `i2c` - The I2C bus connected to the EEPROM.
+/// * `delay` - A delay to wait for the completion of each page write.
+/// * `calibration` - The output flatness calibration.
+pub fn write_flatness_calibration<T, E>(
+    i2c: &mut T,
+    delay: &mut impl DelayMs<u8>,
+    calibration: &FlatnessCalibration,
+) -> Result<(), E>
+where
+    T: WriteRead<Error = E> + Write<Error = E>,
+{
+    let mut block = [0u8; FLATNESS_CALIBRATION_SIZE];
+    block[0] = calibration.points().len() as u8;
+    for (chunk, point) in
+        block[1..].chunks_exact_mut(4).zip(calibration.points())
+    {
+        let frequency =
+            libm::roundf(point.frequency / FLATNESS_FREQUENCY_UNIT) as u16;
+        let correction =
+            libm::roundf(point.correction_db / FLATNESS_CORRECTION_UNIT) as i16;
+        chunk[..2].copy_from_slice(&frequency.to_le_bytes());
+        chunk[2..].copy_from_slice(&correction.to_le_bytes());
+    }
+
+    write_block(i2c, delay, FLATNESS_CALIBRATION_POINTER, &mut block)
+}
+
+/// Read the calibrations of ADC0 and ADC1 from EEPROM.
+///
+/// # Args
+/// * `i2c` - The I2C bus connected to the EEPROM.
+///
+/// # Returns
+/// The stored calibrations, or `None` if the read failed or the CRC does not match.
+pub fn read_adc_calibration<T>(i2c: &mut T) -> Option<[AdcCalibration; 2]>
+where
+    T: WriteRead,
+{
+    let mut block = [0u8; ADC_CALIBRATION_SIZE];
+    let data = read_block(i2c, ADC_CALIBRATION_POINTER, &mut block)?;
+
+    Some([
+        AdcCalibration {
+            gain: f32_at(data, 0),
+            offset: f32_at(data, 4),
+        },
+        AdcCalibration {
+            gain: f32_at(data, 8),
+            offset: f32_at(data, 12),
+        },
+    ])
+}
+
+/// Write the calibrations of ADC0 and ADC1 to EEPROM.
+///
+/// # Note
+/// As with all blocks, the calibrations are only written if they differ from the stored ones.
+///
+/// # Args
+/// * `i2c` - The I2C bus connected to the EEPROM.
+/// * `delay` - A delay to wait for the completion of each page write.
+/// * `calibration` - The calibrations of ADC0 and ADC1.
+pub fn write_adc_calibration<T, E>(
+    i2c: &mut T,
+    delay: &mut impl DelayMs<u8>,
+    calibration: &[AdcCalibration; 2],
+) -> Result<(), E>
+where
+    T: WriteRead<Error = E> + Write<Error = E>,
+{
+    let mut block = [0u8; ADC_CALIBRATION_SIZE];
+    for (chunk, calibration) in block.chunks_exact_mut(8).zip(calibration) {
+        chunk[0..4].copy_from_slice(&calibration.gain.to_le_bytes());
+        chunk[4..8].copy_from_slice(&calibration.offset.to_le_bytes());
+    }
+
+    write_block(i2c, delay, ADC_CALIBRATION_POINTER, &mut block)
+}
+
+/// Read a block of data protected by a CRC from EEPROM.
+///
+/// # Args
+/// * `i2c` - The I2C bus connected to the EEPROM.
+/// * `pointer` - The start address of the block.
+/// * `block` - The buffer to read the block into. Its last byte receives the CRC.
+///
+/// # Returns
+/// The data of the block without the CRC, or `None` if the read failed or the CRC does not match.
+fn read_block<'a, T>(
+    i2c: &mut T,
+    pointer: u8,
+    block: &'a mut [u8],
+) -> Option<&'a [u8]>
+where
+    T: WriteRead,
+{
+    i2c.write_read(I2C_ADDR, &[pointer], block).ok()?;
+
+    let (data, crc) = block.split_at(block.len() - 1);
+    (crc8(data) == crc[0]).then_some(data)
+}
+
+/// Write a block of data protected by a CRC to EEPROM.
+///
+/// # Note
+/// To limit EEPROM wear, the block is only written if it differs from the stored one. This blocks
+/// for the duration of the internal write cycles.
+///
+/// # Args
+/// * `i2c` - The I2C bus connected to the EEPROM.
+/// * `delay` - A delay to wait for the completion of each page write.
+/// * `pointer` - The start address of the block. Must be aligned to a page boundary.
+/// * `block` - The block to write, at most [MAX_BLOCK_SIZE] bytes. Its last byte is overwritten
+///   with the CRC of the data.
+fn write_block<T, E>(
+    i2c: &mut T,
+    delay: &mut impl DelayMs<u8>,
+    pointer: u8,
+    block: &mut [u8],
+) -> Result<(), E>
+where
+    T: WriteRead<Error = E> + Write<Error = E>,
+{
+    let (data, crc) = block.split_at_mut(block.len() - 1);
+    crc[0] = crc8(data);
+
+    let mut stored = [0u8; MAX_BLOCK_SIZE];
+    let stored = &mut stored[..block.len()];
+    if i2c.write_read(I2C_ADDR, &[pointer], stored).is_ok() && stored == block {
+        return Ok(());
+    }
+
+    write_pages(i2c, delay, pointer, block)
+}
+
+/// Write data to EEPROM page by page.
+///
+/// # Args
+/// * `i2c` - The I2C bus connected to the EEPROM.
+/// * `delay` - A delay to wait for the completion of each page write.
+/// * `pointer` - The start address of the data. Must be aligned to a page boundary.
+/// * `data` - The data to write.
+fn write_pages<T, E>(
+    i2c: &mut T,
+    delay: &mut impl DelayMs<u8>,
+    pointer: u8,
+    data: &[u8],
+) -> Result<(), E>
+where
+    T: Write<Error = E>,
+{
+    for (page, chunk) in data.chunks(PAGE_SIZE).enumerate() {
+        let mut buffer = [0u8; PAGE_SIZE + 1];
+        buffer[0] = pointer + (page * PAGE_SIZE) as u8;
+        buffer[1..=chunk.len()].copy_from_slice(chunk);
+        i2c.write(I2C_ADDR, &buffer[..=chunk.len()])?;
+
+        delay.delay_ms(WRITE_CYCLE_TIME_MS);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::convert::Infallible;
+
+    /// An EEPROM backed by memory that counts the page writes.
+    struct MockEeprom {
+        memory: [u8; 256],
+        writes: usize,
+    }
+
+    impl MockEeprom {
+        fn blank() -> Self {
+            Self {
+                memory: [0xFF; 256],
+                writes: 0,
+            }
+        }
+    }
+
+    impl WriteRead for MockEeprom {
+        type Error = Infallible;
+
+        fn write_read(
+            &mut self,
+            address: u8,
+            bytes: &[u8],
+            buffer: &mut [u8],
+        ) -> Result<(), Self::Error> {
+            assert_eq!(address, I2C_ADDR);
+            let start = bytes[0] as usize;
+            buffer.copy_from_slice(&self.memory[start..start + buffer.len()]);
+            Ok(())
+        }
+    }
+
+    impl Write for MockEeprom {
+        type Error = Infallible;
+
+        fn write(
+            &mut self,
+            address: u8,
+            bytes: &[u8],
+        ) -> Result<(), Self::Error> {
+            assert_eq!(address, I2C_ADDR);
+            assert!(bytes.len() <= PAGE_SIZE + 1);
+            let start = bytes[0] as usize;
+            self.memory[start..start + bytes.len() - 1]
+                .copy_from_slice(&bytes[1..]);
+            self.writes += 1;
+            Ok(())
+        }
+    }
+
+    struct NoDelay;
+
+    impl DelayMs<u8> for NoDelay {
+        fn delay_ms(&mut self, _ms: u8) {}
+    }
+
+    #[test]
+    fn layout_does_not_overlap() {
+        let regions = [
+            (SERVO_CONFIG_POINTER, SERVO_CONFIG_SIZE),
+            (DAC_CALIBRATION_POINTER, DAC_CALIBRATION_SIZE),
+            (POWER_CALIBRATION_POINTER, POWER_CALIBRATION_SIZE),
+            (ADC_CALIBRATION_POINTER, ADC_CALIBRATION_SIZE),
+            (FLATNESS_CALIBRATION_POINTER, FLATNESS_CALIBRATION_SIZE),
+            (MAC_POINTER, 6),
+        ];
+
+        for pair in regions.windows(2) {
+            let ((start, size), (next, _)) = (pair[0], pair[1]);
+            assert!(start as usize + size <= next as usize);
+        }
+
+        let (mac, size) = regions[regions.len() - 1];
+        assert_eq!(mac as usize + size, 256);
+
+        // The upper half of the address space holding the EUI48 is write-protected.
+        for &(pointer, size) in &regions[..regions.len() - 1] {
+            assert_eq!(pointer as usize % PAGE_SIZE, 0);
+            assert!(size <= MAX_BLOCK_SIZE);
+            assert!(
+                pointer as usize + size <= 0x80,
+                "block at {pointer:#x} is write-protected"
+            );
+        }
+    }
+
+    #[test]
+    fn blank_and_zeroed_blocks_are_rejected() {
+        let mut eeprom = MockEeprom::blank();
+        assert_eq!(read_servo_config(&mut eeprom), None);
+        assert_eq!(read_dac_calibration(&mut eeprom), None);
+        assert_eq!(read_power_calibration(&mut eeprom), None);
+        assert_eq!(read_adc_calibration(&mut eeprom), None);
+        assert_eq!(read_flatness_calibration(&mut eeprom), None);
+
+        eeprom.memory = [0; 256];
+        assert_eq!(read_servo_config(&mut eeprom), None);
+        assert_eq!(read_dac_calibration(&mut eeprom), None);
+        assert_eq!(read_power_calibration(&mut eeprom), None);
+        assert_eq!(read_adc_calibration(&mut eeprom), None);
+        assert_eq!(read_flatness_calibration(&mut eeprom), None);
+    }
+
+    #[test]
+    fn corrupted_block_is_rejected() {
+        let mut eeprom = MockEeprom::blank();
+        let config = ServoConfig {
+            setpoint: 1e6,
+            kp: 0.5,
+            ki: 0.01,
+        };
+        write_servo_config(&mut eeprom, &mut NoDelay, &config).unwrap();
+        assert_eq!(read_servo_config(&mut eeprom), Some(config));
+
+        eeprom.memory[SERVO_CONFIG_POINTER as usize + 5] ^= 0x10;
+        assert_eq!(read_servo_config(&mut eeprom), None);
+    }
+
+    #[test]
+    fn calibrations_round_trip() {
+        let mut eeprom = MockEeprom::blank();
+
+        let dac = [
+            DacCalibration {
+                gain: 1.01,
+                offset: -0.002,
+            },
+            DacCalibration {
+                gain: 0.99,
+                offset: 0.003,
+            },
+        ];
+        write_dac_calibration(&mut eeprom, &mut NoDelay, &dac).unwrap();
+
+        let adc = [
+            AdcCalibration {
+                gain: 0.98,
+                offset: 0.001,
+            },
+            AdcCalibration {
+                gain: 1.02,
+                offset: -0.004,
+            },
+        ];
+        write_adc_calibration(&mut eeprom, &mut NoDelay, &adc).unwrap();
+
+        let power = PowerCalibration {
+            full_scale_dbm: [1., 2.5, -3., 4.25],
+        };
+        write_power_calibration(&mut eeprom, &mut NoDelay, &power).unwrap();
+
+        let flatness = FlatnessCalibration::new(&[
+            FlatnessPoint {
+                frequency: 10e6,
+                correction_db: 0.,
+            },
+            FlatnessPoint {
+                frequency: 200e6,
+                correction_db: -1.5,
+            },
+        ])
+        .unwrap();
+        write_flatness_calibration(&mut eeprom, &mut NoDelay, &flatness)
+            .unwrap();
+
+        // The blocks do not overwrite each other.
+        assert_eq!(read_dac_calibration(&mut eeprom), Some(dac));
+        assert_eq!(read_adc_calibration(&mut eeprom), Some(adc));
+        assert_eq!(read_power_calibration(&mut eeprom), Some(power));
+        assert_eq!(read_flatness_calibration(&mut eeprom), Some(flatness));
+        assert_eq!(read_servo_config(&mut eeprom), None);
+    }
+
+    #[test]
+    fn unchanged_block_is_not_written() {
+        let mut eeprom = MockEeprom::blank();
+        let config = ServoConfig::default();
+
+        write_servo_config(&mut eeprom, &mut NoDelay, &config).unwrap();
+        let writes = eeprom.writes;
+        assert_eq!(writes, 2);
+
+        write_servo_config(&mut eeprom, &mut NoDelay, &config).unwrap();
+        assert_eq!(eeprom.writes, writes);
+    }
+
+    #[test]
+    fn eui48_is_validated() {
+        let mac = [0x80, 0x1F, 0x12, 0x34, 0x56, 0x78];
+        assert_eq!(validate_eui48(mac), Ok(EthernetAddress(mac)));
+        assert_eq!(validate_eui48([0xFF; 6]), Err(EepromError::BlankMac));
+        assert_eq!(validate_eui48([0; 6]), Err(EepromError::ZeroMac));
+        assert_eq!(
+            validate_eui48([0x01, 0x00, 0x5E, 0x00, 0x00, 0x01]),
+            Err(EepromError::MulticastMac)
+        );
+
+        let mut eeprom = MockEeprom::blank();
+        assert_eq!(check_eui48(&mut eeprom), Err(EepromError::BlankMac));
+        eeprom.memory[MAC_POINTER as usize..].copy_from_slice(&mac);
+        assert_eq!(check_eui48(&mut eeprom), Ok(EthernetAddress(mac)));
+    }
+}
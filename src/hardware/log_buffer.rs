@@ -0,0 +1,232 @@
+//! Ring-buffered logging
+//!
+//! # Design
+//! RTT logs are only visible with a debug probe attached. The [BufferedLogger] therefore also
+//! keeps the most recent log lines in a fixed-size ring buffer, [LOG_BUFFER], from which they can
+//! be retrieved remotely, e.g. with the `log dump` serial command. When the buffer is full, the
+//! oldest lines are overwritten.
+//!
+//! # Note
+//! Like the RTT channel, the buffer is guarded by a lock that is only ever tried, never waited
+//! for. Logging is thus safe from any interrupt priority and never blocks. If a higher priority
+//! task logs while the buffer is locked by a preempted task, its line is only logged to RTT.
+use core::{
+    cell::UnsafeCell,
+    fmt::Write,
+    sync::atomic::{AtomicBool, Ordering},
+};
+use heapless::HistoryBuffer;
+use log::Log;
+
+/// The size of the log ring buffer in bytes.
+pub const LOG_BUFFER_SIZE: usize = 1024;
+
+/// The ring buffer holding the most recent log lines.
+pub static LOG_BUFFER: LogBuffer = LogBuffer::new();
+
+/// A ring buffer of log lines.
+pub struct LogBuffer {
+    locked: AtomicBool,
+    buffer: UnsafeCell<HistoryBuffer<u8, LOG_BUFFER_SIZE>>,
+}
+
+// Note(unsafe): The buffer is only accessed while holding the lock.
+unsafe impl Sync for LogBuffer {}
+
+/// A copy of the log buffer contents.
+pub struct Snapshot {
+    data: heapless::Vec<u8, LOG_BUFFER_SIZE>,
+    wrapped: bool,
+}
+
+impl LogBuffer {
+    /// Construct an empty log buffer.
+    pub const fn new() -> Self {
+        Self {
+            locked: AtomicBool::new(false),
+            buffer: UnsafeCell::new(HistoryBuffer::new()),
+        }
+    }
+
+    /// Run a closure with exclusive access to the buffer.
+    ///
+    /// # Returns
+    /// The result of the closure, or `None` if the buffer is locked.
+    fn try_with<R>(
+        &self,
+        f: impl FnOnce(&mut HistoryBuffer<u8, LOG_BUFFER_SIZE>) -> R,
+    ) -> Option<R> {
+        self.locked
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .ok()?;
+
+        // Note(unsafe): The lock was acquired above, so the buffer is not aliased.
+        let result = f(unsafe { &mut *self.buffer.get() });
+        self.locked.store(false, Ordering::Release);
+        Some(result)
+    }
+
+    /// Append a log record as a line.
+    ///
+    /// # Returns
+    /// True if the line was stored, false if the buffer was locked.
+    pub fn push(&self, record: &log::Record) -> bool {
+        self.try_with(|buffer| {
+            // Note(unwrap): Writing into the history buffer never fails.
+            writeln!(Writer(buffer), "{} - {}", record.level(), record.args())
+                .unwrap();
+        })
+        .is_some()
+    }
+
+    /// Copy the contents of the buffer.
+    ///
+    /// # Returns
+    /// The buffered lines, or `None` if the buffer is locked by a log call.
+    pub fn snapshot(&self) -> Option<Snapshot> {
+        self.try_with(|buffer| Snapshot {
+            data: buffer.oldest_ordered().copied().collect(),
+            wrapped: buffer.len() == LOG_BUFFER_SIZE,
+        })
+    }
+}
+
+impl Default for LogBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Snapshot {
+    /// Get the buffered lines, oldest first.
+    ///
+    /// # Note
+    /// Once the buffer has wrapped, the oldest line is dropped, as it may be partially
+    /// overwritten.
+    pub fn lines(&self) -> impl Iterator<Item = &str> {
+        let start = if self.wrapped {
+            self.data
+                .iter()
+                .position(|&byte| byte == b'\n')
+                .map_or(self.data.len(), |newline| newline + 1)
+        } else {
+            0
+        };
+
+        self.data[start..]
+            .split(|&byte| byte == b'\n')
+            .filter(|line| !line.is_empty())
+            .map(|line| core::str::from_utf8(line).unwrap_or("<invalid>"))
+    }
+}
+
+/// Adapts a history buffer for formatted writes.
+struct Writer<'a>(&'a mut HistoryBuffer<u8, LOG_BUFFER_SIZE>);
+
+impl Write for Writer<'_> {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        self.0.extend_from_slice(s.as_bytes());
+        Ok(())
+    }
+}
+
+/// A logger that writes to RTT and into a [LogBuffer].
+pub struct BufferedLogger {
+    rtt: rtt_logger::RTTLogger,
+    buffer: &'static LogBuffer,
+}
+
+impl BufferedLogger {
+    /// Construct the logger.
+    ///
+    /// # Args
+    /// * `level` - The most verbose level that is logged.
+    /// * `buffer` - The ring buffer to keep the recent lines in.
+    pub const fn new(
+        level: log::LevelFilter,
+        buffer: &'static LogBuffer,
+    ) -> Self {
+        Self {
+            rtt: rtt_logger::RTTLogger::new(level),
+            buffer,
+        }
+    }
+}
+
+impl Log for BufferedLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        self.rtt.enabled(metadata)
+    }
+
+    fn log(&self, record: &log::Record) {
+        if self.enabled(record.metadata()) {
+            self.rtt.log(record);
+            self.buffer.push(record);
+        }
+    }
+
+    fn flush(&self) {
+        self.rtt.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn push(buffer: &LogBuffer, index: usize) -> bool {
+        buffer.push(
+            &log::Record::builder()
+                .level(log::Level::Info)
+                .args(format_args!("line {index:02}"))
+                .build(),
+        )
+    }
+
+    fn index(line: &str) -> usize {
+        line.strip_prefix("INFO - line ").unwrap().parse().unwrap()
+    }
+
+    #[test]
+    fn lines_are_dumped_oldest_first() {
+        let buffer = LogBuffer::new();
+        for i in 0..3 {
+            assert!(push(&buffer, i));
+        }
+
+        let snapshot = buffer.snapshot().unwrap();
+        assert!(snapshot.lines().eq([
+            "INFO - line 00",
+            "INFO - line 01",
+            "INFO - line 02",
+        ]));
+    }
+
+    #[test]
+    fn wrapped_buffer_drops_the_oldest_lines() {
+        let buffer = LogBuffer::new();
+        for i in 0..100 {
+            push(&buffer, i);
+        }
+
+        // The lines take 15 bytes each, so the buffer holds the last 68 lines and 4 bytes of
+        // line 31, which is dropped.
+        let snapshot = buffer.snapshot().unwrap();
+        assert!(snapshot.lines().map(index).eq(32..100));
+    }
+
+    #[test]
+    fn locked_buffer_skips_lines() {
+        let buffer = LogBuffer::new();
+        push(&buffer, 0);
+
+        buffer.try_with(|_| {
+            assert!(!push(&buffer, 1));
+            assert!(buffer.snapshot().is_none());
+        });
+
+        assert!(push(&buffer, 2));
+        let snapshot = buffer.snapshot().unwrap();
+        assert!(snapshot.lines().map(index).eq([0, 2]));
+    }
+}
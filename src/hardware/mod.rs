@@ -9,14 +9,21 @@ pub mod cpu_temp_sensor;
 pub mod dac;
 pub mod delay;
 pub mod design_parameters;
+pub mod device_config;
+pub mod eem_gpio;
+pub mod eeprom;
+pub mod log_buffer;
 pub mod pounder;
+pub mod self_test;
 pub mod serial_terminal;
 pub mod setup;
 pub mod shared_adc;
 pub mod signal_generator;
+pub mod stack;
+pub mod status_leds;
 pub mod timers;
-
-mod eeprom;
+pub mod uptime;
+pub mod watchdog;
 
 // Type alias for the analog front-end (AFE) for ADC0.
 pub type AFE0 = afe::ProgrammableGainAmplifier<
@@ -50,11 +57,44 @@ pub type EemDigitalOutput0 = hal::gpio::gpiod::PD3<hal::gpio::Output>;
 // Type alias for LVDS7 (digital output).
 pub type EemDigitalOutput1 = hal::gpio::gpiod::PD4<hal::gpio::Output>;
 
+// The ethernet descriptor ring sizes can be overridden at build time using the
+// `ETH_TX_DESRING_CNT` and `ETH_RX_DESRING_CNT` environment variables. Each descriptor holds a
+// full ethernet frame buffer in SRAM3. Fewer descriptors reduce SRAM3 usage, but also reduce the
+// number of frames that can be queued for transmission or awaiting reception, which limits
+// throughput (e.g. for data streaming) and increases the likelihood of dropped frames under load.
+
 // Number of TX descriptors in the ethernet descriptor ring.
-const TX_DESRING_CNT: usize = 4;
+const TX_DESRING_CNT: usize =
+    parse_desring_count(option_env!("ETH_TX_DESRING_CNT"), 4);
 
 // Number of RX descriptors in the ethernet descriptor ring.
-const RX_DESRING_CNT: usize = 4;
+const RX_DESRING_CNT: usize =
+    parse_desring_count(option_env!("ETH_RX_DESRING_CNT"), 4);
+
+/// Parse a descriptor ring size at compile time.
+///
+/// # Args
+/// * `value` - The decimal descriptor count, if provided.
+/// * `default` - The descriptor count to use if no value is provided.
+const fn parse_desring_count(value: Option<&str>, default: usize) -> usize {
+    let Some(value) = value else {
+        return default;
+    };
+
+    let digits = value.as_bytes();
+    assert!(!digits.is_empty(), "Empty descriptor ring size");
+
+    let mut count = 0;
+    let mut i = 0;
+    while i < digits.len() {
+        assert!(digits[i].is_ascii_digit(), "Invalid descriptor ring size");
+        count = count * 10 + (digits[i] - b'0') as usize;
+        i += 1;
+    }
+
+    assert!(count > 0, "Descriptor ring size must be non-zero");
+    count
+}
 
 pub type NetworkStack = smoltcp_nal::NetworkStack<
     'static,
@@ -79,6 +119,44 @@ pub type I2c1 = hal::i2c::I2c<hal::stm32::I2C1>;
 pub type I2c1Proxy =
     shared_bus::I2cProxy<'static, shared_bus::AtomicCheckMutex<I2c1>>;
 
+/// Call a function on the completed batches of both ADC inputs and DAC outputs.
+///
+/// # Note
+/// The buffers are acquired in the order ADC0, ADC1, DAC0, DAC1, each with `with_buffer()`. If
+/// any of the transfers fails, the error is returned. It is counted by the failing input or
+/// output, see [adc::Adc0Input::lost_batches()] and [dac::Dac0Output::underrun_count()].
+///
+/// # Args
+/// * `adcs` - The ADC inputs.
+/// * `dacs` - The DAC outputs.
+/// * `f` - The function to call with the ADC0, ADC1, DAC0 and DAC1 buffers.
+///
+/// # Returns
+/// The result of the function, or the first DMA error.
+pub fn with_batches<R>(
+    adcs: (&mut adc::Adc0Input, &mut adc::Adc1Input),
+    dacs: (&mut dac::Dac0Output, &mut dac::Dac1Output),
+    f: impl FnOnce(
+        &mut &'static mut [u16],
+        &mut &'static mut [u16],
+        &mut &'static mut [u16],
+        &mut &'static mut [u16],
+    ) -> R,
+) -> Result<R, hal::dma::DMAError> {
+    let (adc0, adc1) = adcs;
+    let (dac0, dac1) = dacs;
+
+    adc0.with_buffer(|adc0_buf| {
+        adc1.with_buffer(|adc1_buf| {
+            dac0.with_buffer(|dac0_buf| {
+                dac1.with_buffer(|dac1_buf| {
+                    f(adc0_buf, adc1_buf, dac0_buf, dac1_buf)
+                })
+            })?
+        })?
+    })?
+}
+
 #[inline(never)]
 #[panic_handler]
 fn panic(info: &core::panic::PanicInfo) -> ! {
@@ -123,3 +201,28 @@ unsafe fn HardFault(ef: &cortex_m_rt::ExceptionFrame) -> ! {
 unsafe fn DefaultHandler(irqn: i16) {
     panic!("Unhandled exception (IRQn = {})", irqn);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn desring_count_parses_decimal() {
+        assert_eq!(parse_desring_count(None, 4), 4);
+        assert_eq!(parse_desring_count(Some("2"), 4), 2);
+        assert_eq!(parse_desring_count(Some("16"), 4), 16);
+        assert_eq!(parse_desring_count(Some("007"), 4), 7);
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid descriptor ring size")]
+    fn desring_count_rejects_non_digits() {
+        parse_desring_count(Some("4k"), 4);
+    }
+
+    #[test]
+    #[should_panic(expected = "Descriptor ring size must be non-zero")]
+    fn desring_count_rejects_zero() {
+        parse_desring_count(Some("0"), 4);
+    }
+}
@@ -0,0 +1,431 @@
+//! Non-blocking monitoring of the Pounder attenuators
+//!
+//! # Design
+//! Reading the attenuator shift registers with [super::attenuators::AttenuatorInterface] is a
+//! blocking SPI transfer. For continuous monitoring, the attenuator codes can instead be scanned
+//! by DMA on SPI1: a scan shifts the staged codes back into the attenuators while shifting the
+//! current contents out, so the staging registers remain in sync with the output registers.
+//!
+//! The received codes are collected into a double buffer. While the DMA fills one half, the other
+//! half holds the most recently completed scan, which is copied into the snapshot once the scan
+//! completes. Scans are started from a periodic, low priority context by [AttenuatorScan::update],
+//! so the real-time processing never waits for the attenuators.
+//!
+//! SPI1 is shared with the blocking attenuator access. It is only switched into DMA mode for the
+//! duration of a scan and must be returned to blocking operation, see [AttenuatorScan::poll],
+//! before any blocking transfer.
+//!
+//! # Note
+//! If a scan fails because of an SPI error or because it does not complete in time, the DMA
+//! streams are abandoned and the scan is flagged as failed. The snapshot is then only updated by
+//! blocking reads.
+use super::Error;
+use stm32h7xx_hal as hal;
+
+use hal::prelude::_stm32h7xx_hal_spi_HalSpi;
+
+use hal::dma::{
+    config::Priority,
+    dma::{DMAReq, DmaConfig},
+    traits::TargetAddress,
+    MemoryToPeripheral, PeripheralToMemory, Transfer,
+};
+
+/// The number of updates a scan may remain incomplete before it is considered failed.
+const MAX_STALLED_UPDATES: u32 = 4;
+
+/// The attenuator SPI interface.
+type AttenuatorSpi = hal::spi::Spi<hal::stm32::SPI1, hal::spi::Enabled, u8>;
+
+// The codes shifted into the attenuators during a scan. One buffer is armed in the DMA stream
+// while the other is staged with updated codes. Data in AXI SRAM is not initialized on boot, so
+// the buffers are initialized during construction.
+#[link_section = ".axisram.buffers"]
+static mut SCAN_TX_BUF: [[u8; 4]; 2] = [[0; 4]; 2];
+
+// The codes shifted out of the attenuators during a scan, in a ping-pong configuration.
+#[link_section = ".axisram.buffers"]
+static mut SCAN_RX_BUF: [[u8; 4]; 2] = [[0; 4]; 2];
+
+/// Indicates a DMA transfer into the SPI1 TX FIFO.
+pub struct AttenuatorTxData {}
+
+// Note(unsafe): This structure is only instantiated once by the attenuator scan, which is the only
+// user of the SPI1 TX DMA request.
+unsafe impl TargetAddress<MemoryToPeripheral> for AttenuatorTxData {
+    type MemSize = u8;
+
+    const REQUEST_LINE: Option<u8> = Some(DMAReq::Spi1TxDma as u8);
+
+    fn address(&self) -> usize {
+        // Note(unsafe): The SPI is owned by the pounder devices. Only the address of the data
+        // register is taken here.
+        let regs = unsafe { &*hal::stm32::SPI1::ptr() };
+        &regs.txdr as *const _ as usize
+    }
+}
+
+/// Indicates a DMA transfer out of the SPI1 RX FIFO.
+pub struct AttenuatorRxData {}
+
+// Note(unsafe): This structure is only instantiated once by the attenuator scan, which is the only
+// user of the SPI1 RX DMA request.
+unsafe impl TargetAddress<PeripheralToMemory> for AttenuatorRxData {
+    type MemSize = u8;
+
+    const REQUEST_LINE: Option<u8> = Some(DMAReq::Spi1RxDma as u8);
+
+    fn address(&self) -> usize {
+        // Note(unsafe): The SPI is owned by the pounder devices. Only the address of the data
+        // register is taken here.
+        let regs = unsafe { &*hal::stm32::SPI1::ptr() };
+        &regs.rxdr as *const _ as usize
+    }
+}
+
+/// A DMA-driven scan of the attenuator shift registers.
+pub struct AttenuatorScan {
+    rx: Transfer<
+        hal::dma::dma::Stream0<hal::stm32::DMA2>,
+        AttenuatorRxData,
+        PeripheralToMemory,
+        &'static mut [u8; 4],
+        hal::dma::DBTransfer,
+    >,
+    tx: Transfer<
+        hal::dma::dma::Stream1<hal::stm32::DMA2>,
+        AttenuatorTxData,
+        MemoryToPeripheral,
+        &'static mut [u8; 4],
+        hal::dma::DBTransfer,
+    >,
+    rx_spare: Option<&'static mut [u8; 4]>,
+    tx_spare: Option<&'static mut [u8; 4]>,
+    state: ScanState,
+}
+
+/// The progress of the scans, independent of the DMA streams.
+#[derive(Debug, Default)]
+struct ScanState {
+    snapshot: Option<[u8; 4]>,
+    in_flight: bool,
+    stalled_updates: u32,
+    failed: bool,
+}
+
+impl ScanState {
+    /// Record the start of a scan.
+    fn start(&mut self) {
+        self.in_flight = true;
+        self.stalled_updates = 0;
+    }
+
+    /// Hand off the half of the double buffer filled by a completed scan.
+    ///
+    /// # Args
+    /// * `completed` - The codes received by the scan.
+    fn complete(&mut self, completed: &[u8; 4]) {
+        self.in_flight = false;
+        self.snapshot = Some(*completed);
+    }
+
+    /// Record an update during which the scan did not complete.
+    ///
+    /// # Returns
+    /// True if the scan is considered failed.
+    fn stall(&mut self) -> bool {
+        self.stalled_updates += 1;
+        self.stalled_updates > MAX_STALLED_UPDATES
+    }
+
+    /// Record that the scan was abandoned.
+    fn abort(&mut self) {
+        self.in_flight = false;
+        self.failed = true;
+    }
+}
+
+impl AttenuatorScan {
+    /// Construct the attenuator scan.
+    ///
+    /// # Note
+    /// Both DMA streams are armed immediately. They only transfer data once a scan enables the
+    /// DMA requests of the SPI.
+    ///
+    /// # Args
+    /// * `rx_stream` - The DMA stream reading the codes out of the SPI RX FIFO.
+    /// * `tx_stream` - The DMA stream writing the codes into the SPI TX FIFO.
+    /// * `codes` - The codes currently staged in the attenuator shift registers.
+    pub fn new(
+        rx_stream: hal::dma::dma::Stream0<hal::stm32::DMA2>,
+        tx_stream: hal::dma::dma::Stream1<hal::stm32::DMA2>,
+        codes: [u8; 4],
+    ) -> Self {
+        // Note(unsafe): The scan buffers are only used by the attenuator scan, which is only
+        // constructed once as it owns the DMA streams.
+        let (tx_buf, rx_buf) = unsafe {
+            SCAN_TX_BUF = [codes; 2];
+            (
+                &mut *core::ptr::addr_of_mut!(SCAN_TX_BUF),
+                &mut *core::ptr::addr_of_mut!(SCAN_RX_BUF),
+            )
+        };
+        let [tx_armed, tx_spare] = tx_buf;
+        let [rx_armed, rx_spare] = rx_buf;
+
+        let config = DmaConfig::default()
+            .memory_increment(true)
+            .priority(Priority::Low);
+
+        let mut rx: Transfer<_, _, PeripheralToMemory, _, _> = Transfer::init(
+            rx_stream,
+            AttenuatorRxData {},
+            rx_armed,
+            None,
+            config,
+        );
+        let mut tx: Transfer<_, _, MemoryToPeripheral, _, _> = Transfer::init(
+            tx_stream,
+            AttenuatorTxData {},
+            tx_armed,
+            None,
+            config,
+        );
+
+        rx.start(|_| {});
+        tx.start(|_| {});
+
+        Self {
+            rx,
+            tx,
+            rx_spare: Some(rx_spare),
+            tx_spare: Some(tx_spare),
+            state: ScanState::default(),
+        }
+    }
+
+    /// Advance the scan.
+    ///
+    /// # Note
+    /// This is intended to be called periodically from a low priority context. It hands off a
+    /// completed scan to the snapshot and starts the next scan. A scan that does not complete
+    /// within a few updates is considered failed.
+    ///
+    /// # Args
+    /// * `spi` - The attenuator SPI interface.
+    pub fn update(&mut self, spi: &mut AttenuatorSpi) -> Result<(), Error> {
+        if self.state.failed {
+            return Err(Error::Spi);
+        }
+
+        match self.poll(spi) {
+            Ok(()) => {
+                self.start(spi);
+                Ok(())
+            }
+            Err(nb::Error::WouldBlock) => {
+                if self.state.stall() {
+                    log::warn!("Attenuator scan stalled");
+                    self.abort(spi);
+                    return Err(Error::Timeout);
+                }
+                Ok(())
+            }
+            Err(nb::Error::Other(err)) => Err(err),
+        }
+    }
+
+    /// Check for completion of a scan in progress.
+    ///
+    /// # Note
+    /// Once this returns `Ok`, the SPI is in blocking operation. On an SPI error, the scan is
+    /// aborted and flagged as failed.
+    ///
+    /// # Args
+    /// * `spi` - The attenuator SPI interface.
+    pub fn poll(&mut self, spi: &mut AttenuatorSpi) -> nb::Result<(), Error> {
+        if !self.state.in_flight {
+            return Ok(());
+        }
+
+        let sr = spi.inner().sr.read();
+        if sr.ovr().bit_is_set()
+            || sr.udr().bit_is_set()
+            || sr.modf().bit_is_set()
+        {
+            log::warn!("Attenuator scan SPI error");
+            self.abort(spi);
+            return Err(nb::Error::Other(Error::Spi));
+        }
+
+        if !self.rx.get_transfer_complete_flag() {
+            return Err(nb::Error::WouldBlock);
+        }
+
+        self.rx.clear_transfer_complete_interrupt();
+        self.tx.clear_transfer_complete_interrupt();
+        Self::release_spi(spi);
+
+        // Hand off the completed half of the double buffer and re-arm the streams for the next
+        // scan. The transmitted codes are unchanged, as both TX buffers hold the staged codes.
+        // Note(unwrap): The spare buffers are only taken temporarily while the streams are
+        // re-armed.
+        let (completed, _, _) = self
+            .rx
+            .next_transfer(self.rx_spare.take().unwrap())
+            .map_err(|_| nb::Error::Other(Error::InvalidState))?;
+        self.state.complete(completed);
+        self.rx_spare = Some(completed);
+
+        let (sent, _, _) = self
+            .tx
+            .next_transfer(self.tx_spare.take().unwrap())
+            .map_err(|_| nb::Error::Other(Error::InvalidState))?;
+        self.tx_spare = Some(sent);
+
+        Ok(())
+    }
+
+    /// Update the codes staged in the attenuator shift registers.
+    ///
+    /// # Note
+    /// This must be called after every blocking transfer, as subsequent scans shift these codes
+    /// back into the attenuators. The scan must not be in progress, see [Self::poll()].
+    ///
+    /// # Args
+    /// * `codes` - The codes shifted into the attenuators by the blocking transfer.
+    pub fn set_codes(&mut self, codes: [u8; 4]) {
+        if self.state.failed {
+            return;
+        }
+
+        // Note(unwrap): The spare buffers are only taken temporarily while the streams are
+        // re-armed.
+        let spare = self.tx_spare.take().unwrap();
+        *spare = codes;
+        match self.tx.next_transfer(spare) {
+            Ok((previous, _, _)) => {
+                *previous = codes;
+                self.tx_spare = Some(previous);
+            }
+            Err(_) => self.state.failed = true,
+        }
+    }
+
+    /// Store codes read by a blocking transfer as the snapshot.
+    ///
+    /// # Args
+    /// * `codes` - The codes shifted out of the attenuators.
+    pub fn set_snapshot(&mut self, codes: [u8; 4]) {
+        self.state.snapshot = Some(codes);
+    }
+
+    /// Get the codes of the most recent scan without blocking.
+    ///
+    /// # Returns
+    /// The raw attenuator codes, or `None` if no scan has completed yet.
+    pub fn snapshot(&self) -> Option<[u8; 4]> {
+        self.state.snapshot
+    }
+
+    /// Check whether the scan failed and snapshots are only updated by blocking reads.
+    pub fn failed(&self) -> bool {
+        self.state.failed
+    }
+
+    /// Start a scan of the attenuators.
+    fn start(&mut self, spi: &mut AttenuatorSpi) {
+        let regs = spi.inner();
+
+        // The DMA requests and the transfer size can only be changed while the SPI is disabled.
+        // The RX requests are enabled before the TX requests, so that no received byte is missed.
+        regs.cr1.modify(|_, w| w.spe().clear_bit());
+        regs.cfg1.modify(|_, w| w.rxdmaen().set_bit());
+        regs.cfg1.modify(|_, w| w.txdmaen().set_bit());
+        regs.cr2.modify(|_, w| w.tsize().bits(4));
+        regs.cr1.modify(|_, w| w.spe().set_bit());
+        regs.cr1.modify(|_, w| w.cstart().set_bit());
+
+        self.state.start();
+    }
+
+    /// Abandon the DMA scan and fall back to blocking reads.
+    pub fn abort(&mut self, spi: &mut AttenuatorSpi) {
+        Self::release_spi(spi);
+        self.state.abort();
+    }
+
+    /// Return the SPI to blocking operation.
+    fn release_spi(spi: &mut AttenuatorSpi) {
+        let regs = spi.inner();
+        regs.cr1.modify(|_, w| w.spe().clear_bit());
+        regs.cfg1
+            .modify(|_, w| w.rxdmaen().clear_bit().txdmaen().clear_bit());
+        regs.cr2.modify(|_, w| w.tsize().bits(0));
+        regs.ifcr.write(|w| {
+            w.eotc()
+                .set_bit()
+                .txtfc()
+                .set_bit()
+                .ovrc()
+                .set_bit()
+                .udrc()
+                .set_bit()
+                .modfc()
+                .set_bit()
+        });
+        regs.cr1.modify(|_, w| w.spe().set_bit());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn completed_scans_are_handed_off() {
+        let mut state = ScanState::default();
+        let mut buffers = [[0u8; 4]; 2];
+        assert_eq!(state.snapshot, None);
+
+        // Simulate the DMA filling alternating halves of the double buffer. The half that is
+        // filled next must not affect the snapshot.
+        for (scan, codes) in [[1, 2, 3, 4], [5, 6, 7, 8], [9, 10, 11, 12]]
+            .iter()
+            .enumerate()
+        {
+            state.start();
+            assert!(state.in_flight);
+
+            let filled = scan % 2;
+            buffers[filled] = *codes;
+            state.complete(&buffers[filled]);
+            assert!(!state.in_flight);
+
+            buffers[1 - filled] = [0xFF; 4];
+            assert_eq!(state.snapshot, Some(*codes));
+        }
+    }
+
+    #[test]
+    fn stalled_scan_fails() {
+        let mut state = ScanState::default();
+        state.start();
+        for _ in 0..MAX_STALLED_UPDATES {
+            assert!(!state.stall());
+        }
+        assert!(state.stall());
+
+        state.abort();
+        assert!(state.failed);
+        assert!(!state.in_flight);
+
+        // A new scan restarts the stall count.
+        let mut state = ScanState::default();
+        state.start();
+        state.stall();
+        state.complete(&[0; 4]);
+        state.start();
+        assert_eq!(state.stalled_updates, 0);
+    }
+}
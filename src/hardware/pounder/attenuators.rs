@@ -1,4 +1,147 @@
 use super::{Channel, Error};
+use embedded_hal::{blocking::delay::DelayMs, spi::FullDuplex};
+use log::warn;
+
+/// The maximum attenuation of the attenuators in dB.
+pub const MAX_ATTENUATION: f32 = 31.5;
+
+/// The resolution of the attenuators in dB.
+pub const ATTENUATION_STEP: f32 = 0.5;
+
+/// Encode an attenuation into the value of an attenuator shift register.
+///
+/// Args:
+/// * `attenuation` - The desired attenuation in dB. This has a resolution of 0.5dB.
+///
+/// Returns:
+/// The register value and the quantized attenuation in dB.
+pub(super) fn encode(attenuation: f32) -> Result<(u8, f32), Error> {
+    if !(0.0..=MAX_ATTENUATION).contains(&attenuation) {
+        return Err(Error::Bounds);
+    }
+
+    // The attenuator uses a code where the LSB is 0.5 dB. The lowest 2 bits of the 8-bit shift
+    // register on the attenuator are ignored, so the code is shifted into the upper 6 bits of the
+    // register value. Note that the attenuator treats inputs as active-low, so the code is
+    // inverted before writing.
+    let attenuation_code = (attenuation * 2.0) as u8;
+    Ok((!(attenuation_code << 2), attenuation_code as f32 / 2.0))
+}
+
+/// Decode the value of an attenuator shift register into an attenuation.
+///
+/// Args:
+/// * `code` - The register value as shifted out of the attenuator.
+///
+/// Returns:
+/// The attenuation in dB.
+pub(super) fn decode(code: u8) -> f32 {
+    // The attenuation code is stored in the upper 6 bits of the register, where each LSB
+    // represents 0.5 dB. The attenuator stores the code as active-low, so inverting the result
+    // (before the shift) has the affect of transforming the bits of interest (and the
+    // dont-care bits) into an active-high state and then masking off the don't care bits. If
+    // the shift occurs before the inversion, the upper 2 bits (which would then be don't
+    // care) would contain erroneous data.
+    let attenuation_code = (!code) >> 2;
+
+    // Convert the desired channel code into dB of attenuation.
+    attenuation_code as f32 / 2.0
+}
+
+/// A request for a transfer of the attenuator shift registers.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TransferRequest {
+    /// Read the codes of all attenuators and write them back, see [AttenuatorInterface].
+    Read,
+
+    /// Shift the given codes into the attenuators.
+    Write([u8; 4]),
+}
+
+/// A non-blocking transfer of the attenuator shift registers.
+///
+/// Note:
+/// The codes are shifted one byte at a time through a full-duplex SPI interface. The transfer
+/// is advanced by polling it until it completes, so the caller never waits for the SPI.
+#[derive(Copy, Clone, Debug)]
+pub struct AttenuatorTransfer {
+    request: TransferRequest,
+    tx: [u8; 4],
+    rx: [u8; 4],
+    index: usize,
+    sent: bool,
+    written_back: bool,
+}
+
+impl AttenuatorTransfer {
+    /// Construct a transfer.
+    ///
+    /// Args:
+    /// * `request` - The transfer to perform.
+    pub fn new(request: TransferRequest) -> Self {
+        let tx = match request {
+            TransferRequest::Read => [0; 4],
+            TransferRequest::Write(codes) => codes,
+        };
+
+        Self {
+            request,
+            tx,
+            rx: [0; 4],
+            index: 0,
+            sent: false,
+            written_back: false,
+        }
+    }
+
+    /// Get the request performed by the transfer.
+    pub fn request(&self) -> TransferRequest {
+        self.request
+    }
+
+    /// Get the codes left in the attenuator shift registers once the transfer completes.
+    pub fn staged(&self) -> [u8; 4] {
+        self.tx
+    }
+
+    /// Advance the transfer.
+    ///
+    /// Args:
+    /// * `spi` - The attenuator SPI interface.
+    ///
+    /// Returns:
+    /// For a read, the codes of the attenuators. For a write, the codes shifted out of the
+    /// attenuators. [nb::Error::WouldBlock] is returned until the transfer completes.
+    pub fn poll<SPI: FullDuplex<u8>>(
+        &mut self,
+        spi: &mut SPI,
+    ) -> nb::Result<[u8; 4], Error> {
+        loop {
+            while self.index < self.tx.len() {
+                if !self.sent {
+                    spi.send(self.tx[self.index])
+                        .map_err(|err| err.map(|_| Error::Spi))?;
+                    self.sent = true;
+                }
+                self.rx[self.index] =
+                    spi.read().map_err(|err| err.map(|_| Error::Spi))?;
+                self.sent = false;
+                self.index += 1;
+            }
+
+            match self.request {
+                // Reading is destructive, so the codes are written back in a second pass.
+                TransferRequest::Read if !self.written_back => {
+                    self.tx = self.rx;
+                    self.index = 0;
+                    self.written_back = true;
+                }
+                TransferRequest::Read => return Ok(self.tx),
+                TransferRequest::Write(_) => return Ok(self.rx),
+            }
+        }
+    }
+}
 
 /// Provide an interface for managing digital attenuators on Pounder hardware.
 ///
@@ -19,29 +162,19 @@ pub trait AttenuatorInterface {
         channel: Channel,
         attenuation: f32,
     ) -> Result<f32, Error> {
-        if !(0.0..=31.5).contains(&attenuation) {
-            return Err(Error::Bounds);
-        }
-
-        // Calculate the attenuation code to program into the attenuator. The attenuator uses a
-        // code where the LSB is 0.5 dB.
-        let attenuation_code = (attenuation * 2.0) as u8;
+        let (code, quantized) = encode(attenuation)?;
 
         // Read all the channels, modify the channel of interest, and write all the channels back.
         // This ensures the staging register and the output register are always in sync.
         let mut channels = [0_u8; 4];
         self.transfer_attenuators(&mut channels)?;
-
-        // The lowest 2 bits of the 8-bit shift register on the attenuator are ignored. Shift the
-        // attenuator code into the upper 6 bits of the register value. Note that the attenuator
-        // treats inputs as active-low, so the code is inverted before writing.
-        channels[channel as usize] = !(attenuation_code << 2);
+        channels[channel as usize] = code;
         self.transfer_attenuators(&mut channels)?;
 
         // Finally, latch the output of the updated channel to force it into an active state.
         self.latch_attenuator(channel)?;
 
-        Ok(attenuation_code as f32 / 2.0)
+        Ok(quantized)
     }
 
     /// Get the attenuation of a channel.
@@ -60,16 +193,127 @@ pub trait AttenuatorInterface {
         self.transfer_attenuators(&mut channels)?;
         self.transfer_attenuators(&mut channels)?;
 
-        // The attenuation code is stored in the upper 6 bits of the register, where each LSB
-        // represents 0.5 dB. The attenuator stores the code as active-low, so inverting the result
-        // (before the shift) has the affect of transforming the bits of interest (and the
-        // dont-care bits) into an active-high state and then masking off the don't care bits. If
-        // the shift occurs before the inversion, the upper 2 bits (which would then be don't
-        // care) would contain erroneous data.
-        let attenuation_code = (!channels[channel as usize]) >> 2;
+        Ok(decode(channels[channel as usize]))
+    }
+
+    /// Get the attenuation of all channels.
+    ///
+    /// Note:
+    /// All channels are read and written back in the same transfers a single channel read
+    /// requires, so this is preferred over reading each channel individually.
+    ///
+    /// Returns:
+    /// The programmed attenuation of each channel in dB, indexed by [Channel].
+    fn attenuations(&mut self) -> Result<[f32; 4], Error> {
+        let mut channels = [0_u8; 4];
+
+        // Reading is destructive, see `get_attenuation()`.
+        self.transfer_attenuators(&mut channels)?;
+        self.transfer_attenuators(&mut channels)?;
+
+        Ok(channels.map(decode))
+    }
+
+    /// Set the attenuation of all channels.
+    ///
+    /// Args:
+    /// * `attenuation` - The desired attenuation of the channels in dB. This has a resolution of
+    ///   0.5dB.
+    ///
+    /// Returns:
+    /// The programmed attenuation of the channels in dB.
+    fn write_all(&mut self, attenuation: f32) -> Result<f32, Error> {
+        let attenuations = self.set_all_attenuations(&[attenuation; 4])?;
+        Ok(attenuations[0])
+    }
+
+    /// Set the attenuation of each channel individually.
+    ///
+    /// Note:
+    /// All channels are written in a single transfer, so the staging and output registers stay in
+    /// sync without a read-back. Attenuations out of bounds are clamped to the attenuator range
+    /// individually. If any attenuation is NaN, no channel is modified.
+    ///
+    /// Args:
+    /// * `attenuations` - The desired attenuation of each channel in dB, indexed by [Channel].
+    ///   This has a resolution of 0.5dB.
+    ///
+    /// Returns:
+    /// The programmed attenuation of each channel in dB.
+    fn set_all_attenuations(
+        &mut self,
+        attenuations: &[f32; 4],
+    ) -> Result<[f32; 4], Error> {
+        let mut channels = [0_u8; 4];
+        let mut programmed = [0.0; 4];
+        for channel in enum_iterator::all::<Channel>() {
+            let requested = attenuations[channel as usize];
+            if requested.is_nan() {
+                return Err(Error::Bounds);
+            }
+
+            let attenuation = requested.clamp(0.0, MAX_ATTENUATION);
+            if attenuation != requested {
+                warn!("{channel:?} attenuation {requested} clamped to {attenuation}");
+            }
+
+            // Note(unwrap): The attenuation was clamped to the bounds above.
+            let (code, quantized) = encode(attenuation).unwrap();
+            channels[channel as usize] = code;
+            programmed[channel as usize] = quantized;
+        }
+
+        self.transfer_attenuators(&mut channels)?;
+
+        for channel in enum_iterator::all::<Channel>() {
+            self.latch_attenuator(channel)?;
+        }
+
+        Ok(programmed)
+    }
 
-        // Convert the desired channel code into dB of attenuation.
-        Ok(attenuation_code as f32 / 2.0)
+    /// Ramp the attenuation of a single channel to a target in fixed steps.
+    ///
+    /// Note:
+    /// Ramping avoids transients of an abrupt attenuation change in a locked RF chain. The ramp
+    /// starts at the current attenuation of the channel. The last step is shortened to end at the
+    /// target. Each step is latched and followed by the step delay, except for the last one.
+    ///
+    /// Args:
+    /// * `channel` - The pounder channel to ramp the attenuation of.
+    /// * `target` - The target attenuation in dB. It is clamped to the attenuator range.
+    /// * `step` - The positive attenuation change per step in dB. Steps finer than the attenuator
+    ///   resolution of [ATTENUATION_STEP] are widened to it.
+    /// * `delay` - A delay to wait between steps.
+    /// * `step_ms` - The duration of each step in milliseconds.
+    ///
+    /// Returns:
+    /// The programmed attenuation of the channel in dB.
+    fn ramp_attenuation(
+        &mut self,
+        channel: Channel,
+        target: f32,
+        step: f32,
+        delay: &mut impl DelayMs<u8>,
+        step_ms: u8,
+    ) -> Result<f32, Error> {
+        if step.is_nan() || step <= 0.0 {
+            return Err(Error::Bounds);
+        }
+
+        let start = self.get_attenuation(channel)?;
+        let target = target.clamp(0.0, MAX_ATTENUATION);
+        let mut attenuation = start;
+
+        let steps = ramp_steps(start, target, step);
+        for (index, point) in steps.enumerate() {
+            if index > 0 {
+                delay.delay_ms(step_ms);
+            }
+            attenuation = self.set_attenuation(channel, point)?;
+        }
+
+        Ok(attenuation)
     }
 
     fn reset_attenuators(&mut self) -> Result<(), Error>;
@@ -81,3 +325,241 @@ pub trait AttenuatorInterface {
         channels: &mut [u8; 4],
     ) -> Result<(), Error>;
 }
+
+/// Generate the points of an attenuation ramp, excluding the start and including the target.
+///
+/// Args:
+/// * `start` - The attenuation at the start of the ramp in dB.
+/// * `target` - The attenuation at the end of the ramp in dB.
+/// * `step` - The positive attenuation change per step in dB. Steps finer than the attenuator
+///   resolution are widened to [ATTENUATION_STEP], as they would program the same code repeatedly.
+fn ramp_steps(start: f32, target: f32, step: f32) -> impl Iterator<Item = f32> {
+    let step = step.max(ATTENUATION_STEP);
+    let (distance, direction) = if target >= start {
+        (target - start, 1.0)
+    } else {
+        (start - target, -1.0)
+    };
+    let count = (distance / step) as u32;
+
+    // A remaining partial step is taken as an additional, shortened step to the target.
+    let partial = distance > count as f32 * step;
+    let count = count + partial as u32;
+
+    (1..=count).map(move |index| {
+        if index == count {
+            target
+        } else {
+            start + direction * index as f32 * step
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A model of the daisy-chained attenuator shift and output registers.
+    struct MockAttenuators {
+        shift: [u8; 4],
+        output: [u8; 4],
+    }
+
+    impl MockAttenuators {
+        fn new() -> Self {
+            let (code, _) = encode(MAX_ATTENUATION).unwrap();
+            Self {
+                shift: [code; 4],
+                output: [code; 4],
+            }
+        }
+    }
+
+    impl AttenuatorInterface for MockAttenuators {
+        fn reset_attenuators(&mut self) -> Result<(), Error> {
+            *self = Self::new();
+            Ok(())
+        }
+
+        fn latch_attenuator(&mut self, channel: Channel) -> Result<(), Error> {
+            self.output[channel as usize] = self.shift[channel as usize];
+            Ok(())
+        }
+
+        fn transfer_attenuators(
+            &mut self,
+            channels: &mut [u8; 4],
+        ) -> Result<(), Error> {
+            core::mem::swap(&mut self.shift, channels);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn encode_round_trip() {
+        for attenuation in [0.0, 0.5, 10.0, 31.5] {
+            let (code, quantized) = encode(attenuation).unwrap();
+            assert_eq!(quantized, attenuation);
+            assert_eq!(decode(code), attenuation);
+        }
+        assert_eq!(encode(10.3).unwrap().1, 10.0);
+        assert!(matches!(encode(-0.5), Err(Error::Bounds)));
+        assert!(matches!(encode(32.0), Err(Error::Bounds)));
+        assert!(matches!(encode(f32::NAN), Err(Error::Bounds)));
+    }
+
+    #[test]
+    fn set_attenuation_latches_one_channel() {
+        let mut attenuators = MockAttenuators::new();
+        assert_eq!(
+            attenuators.set_attenuation(Channel::In1, 6.5).unwrap(),
+            6.5
+        );
+        assert_eq!(decode(attenuators.output[Channel::In1 as usize]), 6.5);
+        assert_eq!(decode(attenuators.output[Channel::In0 as usize]), 31.5);
+        assert!(matches!(
+            attenuators.set_attenuation(Channel::In1, 40.0),
+            Err(Error::Bounds)
+        ));
+    }
+
+    #[test]
+    fn set_all_attenuations_clamps_each_channel() {
+        let mut attenuators = MockAttenuators::new();
+        let programmed = attenuators
+            .set_all_attenuations(&[-1.0, 10.0, 40.0, 20.5])
+            .unwrap();
+        assert_eq!(programmed, [0.0, 10.0, 31.5, 20.5]);
+        assert_eq!(attenuators.output.map(decode), programmed);
+
+        assert!(matches!(
+            attenuators.set_all_attenuations(&[0.0, f32::NAN, 0.0, 0.0]),
+            Err(Error::Bounds)
+        ));
+        assert_eq!(attenuators.output.map(decode), programmed);
+    }
+
+    #[test]
+    fn ramp_steps_end_at_the_target() {
+        assert!(ramp_steps(10.0, 12.0, 1.0).eq([11.0, 12.0]));
+        assert!(ramp_steps(12.0, 9.5, 1.0).eq([11.0, 10.0, 9.5]));
+        assert_eq!(ramp_steps(5.0, 5.0, 1.0).count(), 0);
+    }
+
+    #[test]
+    fn ramp_steps_are_at_least_the_resolution() {
+        assert!(ramp_steps(0.0, 2.0, 0.1).eq([0.5, 1.0, 1.5, 2.0]));
+        assert!(ramp_steps(1.0, 0.0, 0.0).eq([0.5, 0.0]));
+    }
+
+    /// A model of the attenuator shift registers behind a SPI that is busy for a number of polls
+    /// before every byte.
+    struct MockSpi {
+        shift: [u8; 4],
+        received: Option<u8>,
+        busy: u32,
+        wait: u32,
+        fail: bool,
+    }
+
+    impl MockSpi {
+        fn new(shift: [u8; 4], busy: u32) -> Self {
+            Self {
+                shift,
+                received: None,
+                busy,
+                wait: busy,
+                fail: false,
+            }
+        }
+
+        fn ready(&mut self) -> nb::Result<(), ()> {
+            if self.fail {
+                return Err(nb::Error::Other(()));
+            }
+            if self.wait > 0 {
+                self.wait -= 1;
+                return Err(nb::Error::WouldBlock);
+            }
+            self.wait = self.busy;
+            Ok(())
+        }
+    }
+
+    impl FullDuplex<u8> for MockSpi {
+        type Error = ();
+
+        fn send(&mut self, byte: u8) -> nb::Result<(), ()> {
+            assert!(self.received.is_none());
+            self.ready()?;
+            self.received = Some(self.shift[0]);
+            self.shift.rotate_left(1);
+            self.shift[3] = byte;
+            Ok(())
+        }
+
+        fn read(&mut self) -> nb::Result<u8, ()> {
+            self.ready()?;
+            Ok(self.received.take().unwrap())
+        }
+    }
+
+    /// Poll a transfer to completion.
+    ///
+    /// Returns:
+    /// The result of the transfer and the number of polls that would have blocked.
+    fn complete(
+        transfer: &mut AttenuatorTransfer,
+        spi: &mut MockSpi,
+    ) -> (Result<[u8; 4], Error>, u32) {
+        let mut blocked = 0;
+        loop {
+            match transfer.poll(spi) {
+                Err(nb::Error::WouldBlock) => blocked += 1,
+                Err(nb::Error::Other(err)) => return (Err(err), blocked),
+                Ok(codes) => return (Ok(codes), blocked),
+            }
+        }
+    }
+
+    #[test]
+    fn transfer_blocks_until_written() {
+        let mut spi = MockSpi::new([1, 2, 3, 4], 2);
+        let mut transfer =
+            AttenuatorTransfer::new(TransferRequest::Write([5, 6, 7, 8]));
+
+        let (codes, blocked) = complete(&mut transfer, &mut spi);
+        assert_eq!(codes.unwrap(), [1, 2, 3, 4]);
+        // Both the send and the read of each byte block twice.
+        assert_eq!(blocked, 4 * 2 * 2);
+        assert_eq!(spi.shift, [5, 6, 7, 8]);
+        assert_eq!(transfer.staged(), [5, 6, 7, 8]);
+    }
+
+    #[test]
+    fn transfer_reads_and_writes_back() {
+        let mut spi = MockSpi::new([1, 2, 3, 4], 1);
+        let mut transfer = AttenuatorTransfer::new(TransferRequest::Read);
+
+        let (codes, blocked) = complete(&mut transfer, &mut spi);
+        assert_eq!(codes.unwrap(), [1, 2, 3, 4]);
+        assert_eq!(blocked, 2 * 4 * 2);
+        assert_eq!(spi.shift, [1, 2, 3, 4]);
+        assert_eq!(transfer.staged(), [1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn transfer_reports_spi_errors() {
+        let mut spi = MockSpi::new([1, 2, 3, 4], 1);
+        let mut transfer =
+            AttenuatorTransfer::new(TransferRequest::Write([5, 6, 7, 8]));
+
+        assert!(matches!(
+            transfer.poll(&mut spi),
+            Err(nb::Error::WouldBlock)
+        ));
+        spi.fail = true;
+        let (codes, _) = complete(&mut transfer, &mut spi);
+        assert!(matches!(codes, Err(Error::Spi)));
+    }
+}
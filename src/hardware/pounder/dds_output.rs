@@ -29,6 +29,39 @@
 //! software can schedule the DDS updates and then continue data processing. DDS updates then take
 //! place in the future when the IO-update is toggled by hardware.
 //!
+//! The IO-update pulse must be held for at least 4 SYNC_CLK cycles (16 system clock cycles) to
+//! be registered by the DDS, so the minimum pulse width depends on the system clock, see
+//! [min_io_update_duration]. The pulse width and polarity can be adjusted for board revisions
+//! with [DdsOutput::set_io_update_pulse_width] and [DdsOutput::set_io_update_polarity]. If the
+//! system clock is lowered, the pulse is widened to the new minimum.
+//!
+//!
+//! # Sweeps
+//!
+//! A single channel can be swept linearly in frequency with [DdsOutput::start_sweep]. The sweep is
+//! advanced by calling [DdsOutput::update_sweep] once per processing cycle, and each sweep point
+//! is held for a configurable number of these calls (the dwell). Every sweep step is a regular
+//! profile write followed by an IO-update, so at most one step can be taken per processing cycle.
+//! The maximum step rate is thus the batch rate (the sample rate divided by the batch size), and
+//! it is further bounded by the IO-update delay, which must elapse within each batch.
+//!
+//! # Profiles
+//!
+//! For fast frequency hopping, the frequencies of a channel can be preloaded into profiles with
+//! [DdsOutput::preload_profiles]. The channel is then switched between them with
+//! [DdsOutput::select_profile] by driving the profile pins of the DDS, without any register
+//! write or IO-update. The DDS is left in its default two-level modulation, where the profile
+//! pin P0 to P3 of each DDS channel selects between its frequency tuning word CFTW0 (profile 0)
+//! and the channel word CW1 (profile 1). Only the frequency is modulated, the phase offset and
+//! amplitude are shared by both profiles.
+//!
+//! This requires the profile pins of the AD9959 to be connected to GPIOs, which are provided
+//! with [DdsOutput::set_profile_pins]. They are not configured during setup.
+//!
+//! # Streaming
+//!
+//! Pre-computed profiles can be streamed at a timer-gated rate from a queue with
+//! [DdsOutput::stream_profile], see [super::profile_stream].
 //!
 //! # Limitations
 //!
@@ -52,20 +85,175 @@
 //! compile-time-known register update sequence needed for the application, the serialization
 //! process can be done once and then register values can be written into a pre-computed serialized
 //! buffer to avoid the software overhead of much of the serialization process.
+use embedded_hal::{blocking::delay::DelayUs, digital::v2::OutputPin};
 use log::warn;
 use stm32h7xx_hal as hal;
 
-use super::{hrtimer::HighResTimerE, QspiInterface};
-use ad9959::{Channel, Mode, ProfileSerializer};
+use super::{
+    hrtimer::{HighResTimerE, Polarity, IO_UPDATE_CHANNEL},
+    profile_stream::ProfileConsumer,
+    Error, InitStage, QspiInterface,
+};
+use crate::hardware::design_parameters::{
+    DDS_SYSTEM_CLK, POUNDER_IO_UPDATE_DELAY, POUNDER_IO_UPDATE_DURATION,
+};
+use ad9959::{Channel, Interface, Mode, ProfileSerializer, Register, Tuning};
+
+/// The power-on default of the channel function register. It is not modified during
+/// initialization.
+const CFR_DEFAULT: [u8; 3] = [0x00, 0x03, 0x02];
+
+/// The DAC power-down bit in the least significant byte of the channel function register.
+const CFR_DAC_POWER_DOWN: u8 = 1 << 6;
+
+/// The modulation type (AFP select) bits in the most significant byte of the channel function
+/// register.
+const CFR_AFP_SELECT: u8 = 0b11 << 6;
+
+/// The AFP select setting for frequency modulation.
+const CFR_FREQUENCY_MODULATION: u8 = 0b10 << 6;
+
+/// The number of frequency profiles of each channel.
+pub const PROFILES_PER_CHANNEL: usize = 2;
+
+/// The GPIOs driving the profile pins P0 to P3 of the DDS.
+pub type ProfilePins = [hal::gpio::ErasedPin<hal::gpio::Output>; 4];
+
+/// The fraction of the system clock above which an output frequency is considered prone to
+/// aliasing. Image products of the DAC fold close to the output above it.
+pub const ALIAS_WARNING_FRACTION: f32 = 0.4;
+
+/// A warning about an output frequency that was programmed, but may degrade the output.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FrequencyWarning {
+    /// The frequency is above [ALIAS_WARNING_FRACTION] of the system clock, but below Nyquist.
+    NearNyquist,
+}
+
+impl core::fmt::Display for FrequencyWarning {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            FrequencyWarning::NearNyquist => {
+                write!(f, "frequency close to Nyquist, images may alias")
+            }
+        }
+    }
+}
+
+/// The programmed state of a DDS channel after quantization.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct DdsActual {
+    /// The output frequency in Hz.
+    pub frequency: f32,
+
+    /// The phase offset in turns.
+    pub phase: f32,
+
+    /// The amplitude relative to full-scale.
+    pub amplitude: f32,
+
+    /// A warning if the frequency is prone to aliasing.
+    pub warning: Option<FrequencyWarning>,
+}
+
+/// The number of system clock cycles IO_Update must be held for: 4 SYNC_CLK cycles, where
+/// SYNC_CLK runs at a quarter of the system clock.
+const IO_UPDATE_SYSTEM_CLOCK_CYCLES: f32 = 16.;
+
+/// The maximum IO_Update pulse width in seconds. This keeps the pulse within the range of the
+/// HRTimer.
+const MAX_IO_UPDATE_DURATION: f32 = 100e-6;
+
+/// Get the minimum IO_Update pulse width that the DDS registers.
+///
+/// # Args
+/// * `system_clock` - The DDS system clock frequency in Hz.
+///
+/// # Returns
+/// The minimum pulse width in seconds.
+pub fn min_io_update_duration(system_clock: f32) -> f32 {
+    IO_UPDATE_SYSTEM_CLOCK_CYCLES / system_clock
+}
+
+/// The serial interface to the DDS that carries the profile stream.
+pub trait ProfileStream: Interface<Error = Error> {
+    /// Start the profile stream. Register accesses are not possible while it runs.
+    fn start_stream(&mut self) -> Result<(), Error>;
+
+    /// Stop the profile stream. This does nothing if the stream is not running.
+    fn stop_stream(&mut self);
+
+    /// Append a serialized profile to the running stream.
+    fn write_profile(&mut self, profile: &[u32]);
+}
+
+/// The timer generating the IO_Update pulses of the profile stream.
+pub trait IoUpdateTimer {
+    /// Configure the pulse.
+    ///
+    /// # Args
+    /// * `delay` - The time from the trigger to the start of the pulse in seconds.
+    /// * `duration` - The width of the pulse in seconds.
+    fn configure_pulse(&mut self, delay: f32, duration: f32);
+
+    /// Set the polarity of the pulse.
+    fn set_polarity(&mut self, polarity: Polarity);
+
+    /// Trigger a pulse.
+    fn trigger(&mut self);
+}
+
+impl IoUpdateTimer for HighResTimerE {
+    fn configure_pulse(&mut self, delay: f32, duration: f32) {
+        self.configure_single_shot(IO_UPDATE_CHANNEL, delay, duration);
+    }
+
+    fn set_polarity(&mut self, polarity: Polarity) {
+        self.set_polarity(IO_UPDATE_CHANNEL, polarity);
+    }
+
+    fn trigger(&mut self) {
+        self.trigger();
+    }
+}
 
 /// The DDS profile update stream.
-pub struct DdsOutput {
-    _qspi: QspiInterface,
-    io_update_trigger: HighResTimerE,
+pub struct DdsOutput<
+    Q = QspiInterface,
+    T = HighResTimerE,
+    P = hal::gpio::ErasedPin<hal::gpio::Output>,
+> {
+    qspi: Q,
+    io_update_trigger: T,
+    reset_pin: P,
+    io_update_duration: f32,
     mode: Mode,
+    frequency_tuning_words: [u32; 4],
+    phase_offsets: [u16; 4],
+    amplitude_controls: [u16; 4],
+    channel_functions: [[u8; 3]; 4],
+    sweep: Option<Sweep>,
+    refresh: Option<Refresh>,
+    skip_frequency_refresh: [bool; 4],
+    tuning: Tuning,
+    profile_pins: Option<[P; 4]>,
 }
 
-impl DdsOutput {
+/// The state of the periodic channel refresh.
+struct Refresh {
+    interval: u32,
+    ticks: u32,
+    // A bit mask of the channels left to refresh in the current pass.
+    pending: u8,
+}
+
+impl<Q, T, P> DdsOutput<Q, T, P>
+where
+    Q: ProfileStream,
+    T: IoUpdateTimer,
+    P: OutputPin,
+    P::Error: core::fmt::Debug,
+{
     /// Construct a new DDS output stream.
     ///
     /// # Note
@@ -76,23 +264,749 @@ impl DdsOutput {
     /// # Args
     /// * `qspi` - The QSPI interface to the run the stream on.
     /// * `io_update_trigger` - The HighResTimerE used to generate IO_Update pulses.
+    /// * `reset_pin` - The GPIO driving the master reset of the DDS.
     /// * `config` - The frozen DDS configuration.
     pub fn new(
-        mut qspi: QspiInterface,
-        io_update_trigger: HighResTimerE,
+        mut qspi: Q,
+        io_update_trigger: T,
+        reset_pin: P,
         mode: Mode,
     ) -> Self {
         qspi.start_stream().unwrap();
         Self {
             mode,
-            _qspi: qspi,
+            qspi,
             io_update_trigger,
+            reset_pin,
+            io_update_duration: POUNDER_IO_UPDATE_DURATION,
+            frequency_tuning_words: [0; 4],
+            phase_offsets: [0; 4],
+            amplitude_controls: [0; 4],
+            channel_functions: [CFR_DEFAULT; 4],
+            sweep: None,
+            refresh: None,
+            skip_frequency_refresh: [false; 4],
+            tuning: Tuning::new(DDS_SYSTEM_CLK.to_Hz() as f32),
+            profile_pins: None,
+        }
+    }
+
+    /// Write raw bytes to a DDS register.
+    ///
+    /// # Note
+    /// This bypasses the profile stream and the cached channel state. The QSPI interface does not
+    /// support direct register access while the stream is running, so the stream is stopped for
+    /// the access and restarted afterwards, also if the access fails. Profiles already written to
+    /// the stream are sent before the access. The written value only takes effect at the next
+    /// IO_Update, i.e. with the next profile.
+    ///
+    /// # Args
+    /// * `addr` - The register address. The read bit (0x80) must not be set.
+    /// * `data` - The bytes to write.
+    pub fn write_register(
+        &mut self,
+        addr: u8,
+        data: &[u8],
+    ) -> Result<(), Error> {
+        with_stream_stopped(&mut self.qspi, |qspi| qspi.write(addr, data))
+    }
+
+    /// Read raw bytes from a DDS register.
+    ///
+    /// # Note
+    /// See [DdsOutput::write_register()].
+    ///
+    /// # Args
+    /// * `addr` - The register address. The read bit (0x80) must not be set.
+    /// * `dest` - The buffer to read into.
+    pub fn read_register(
+        &mut self,
+        addr: u8,
+        dest: &mut [u8],
+    ) -> Result<(), Error> {
+        with_stream_stopped(&mut self.qspi, |qspi| qspi.read(addr, dest))
+    }
+
+    /// Set the output frequency of a single pounder DDS channel.
+    ///
+    /// # Args
+    /// * `channel` - The pounder channel to configure.
+    /// * `frequency` - The desired output frequency in Hz. Must be below the Nyquist frequency.
+    ///
+    /// # Returns
+    /// The actual programmed frequency of the channel in Hz and a warning if the frequency is
+    /// prone to aliasing.
+    pub fn set_frequency(
+        &mut self,
+        channel: super::Channel,
+        frequency: f32,
+    ) -> Result<(f32, Option<FrequencyWarning>), Error> {
+        self.check_clock()?;
+        let warning = check_frequency(&self.tuning, frequency)?;
+
+        let ftw = self.tuning.ftw(frequency);
+
+        self.builder()
+            .update_channels(channel.into(), Some(ftw), None, None)
+            .write();
+        self.frequency_tuning_words[channel as usize] = ftw;
+
+        Ok((self.frequency(channel), warning))
+    }
+
+    /// Set the frequency, phase offset and amplitude of a single pounder DDS channel at once.
+    ///
+    /// # Note
+    /// All three registers are written in a single profile followed by a single IO_Update pulse,
+    /// so they take effect simultaneously and the channel never outputs a mix of old and new
+    /// settings. If any value is out of bounds, nothing is written.
+    ///
+    /// # Args
+    /// * `channel` - The pounder channel to configure.
+    /// * `frequency` - The desired output frequency in Hz. Must be below the Nyquist frequency.
+    /// * `turns` - The desired phase offset in turns.
+    /// * `scale` - The desired amplitude relative to full-scale, from 0 to 1.
+    ///
+    /// # Returns
+    /// The actual programmed state of the channel.
+    pub fn set_channel(
+        &mut self,
+        channel: super::Channel,
+        frequency: f32,
+        turns: f32,
+        scale: f32,
+    ) -> Result<DdsActual, Error> {
+        self.check_clock()?;
+        let warning = check_frequency(&self.tuning, frequency)?;
+        if !(0.0..=1.0).contains(&scale) {
+            return Err(Error::Bounds);
         }
+
+        let ftw = self.tuning.ftw(frequency);
+        let pow = self.tuning.pow(turns);
+        let acr = self.tuning.acr(scale);
+
+        self.builder()
+            .update_channels(
+                channel.into(),
+                Some(ftw),
+                Some(pow),
+                Some(acr as u32),
+            )
+            .write();
+
+        let index = channel as usize;
+        self.frequency_tuning_words[index] = ftw;
+        self.phase_offsets[index] = pow;
+        self.amplitude_controls[index] = acr;
+
+        Ok(DdsActual {
+            frequency: self.frequency(channel),
+            phase: self.phase(channel),
+            amplitude: self.amplitude(channel),
+            warning,
+        })
+    }
+
+    /// Get the most recently programmed output frequency of a pounder DDS channel.
+    ///
+    /// # Note
+    /// The DDS cannot be read back while streaming, so this reports the last frequency written
+    /// through [DdsOutput::set_frequency].
+    ///
+    /// # Args
+    /// * `channel` - The pounder channel to query.
+    ///
+    /// # Returns
+    /// The frequency of the channel in Hz.
+    pub fn frequency(&self, channel: super::Channel) -> f32 {
+        let ftw = self.frequency_tuning_words[channel as usize];
+        self.tuning.frequency(ftw)
+    }
+
+    /// Set the phase offset of a single pounder DDS channel.
+    ///
+    /// # Args
+    /// * `channel` - The pounder channel to configure.
+    /// * `turns` - The desired phase offset in turns.
+    ///
+    /// # Returns
+    /// The actual programmed phase offset of the channel in turns.
+    pub fn set_phase(&mut self, channel: super::Channel, turns: f32) -> f32 {
+        let pow = self.tuning.pow(turns);
+
+        self.builder()
+            .update_channels(channel.into(), None, Some(pow), None)
+            .write();
+        self.phase_offsets[channel as usize] = pow;
+
+        self.phase(channel)
+    }
+
+    /// Get the most recently programmed phase offset of a pounder DDS channel.
+    ///
+    /// # Args
+    /// * `channel` - The pounder channel to query.
+    ///
+    /// # Returns
+    /// The phase offset of the channel in turns.
+    pub fn phase(&self, channel: super::Channel) -> f32 {
+        self.tuning.phase(self.phase_offsets[channel as usize])
+    }
+
+    /// Set the output amplitude of a single pounder DDS channel.
+    ///
+    /// # Note
+    /// The amplitude is scaled digitally in the DDS with a resolution of 10 bits. A scale of zero
+    /// mutes the channel. For coarse changes of the output power, use the attenuators instead.
+    ///
+    /// # Args
+    /// * `channel` - The pounder channel to configure.
+    /// * `scale` - The desired amplitude relative to full-scale, from 0 to 1.
+    ///
+    /// # Returns
+    /// The actual programmed amplitude of the channel relative to full-scale.
+    pub fn set_amplitude(
+        &mut self,
+        channel: super::Channel,
+        scale: f32,
+    ) -> Result<f32, Error> {
+        if !(0.0..=1.0).contains(&scale) {
+            return Err(Error::Bounds);
+        }
+
+        let acr = self.tuning.acr(scale);
+
+        self.builder()
+            .update_channels(channel.into(), None, None, Some(acr as u32))
+            .write();
+        self.amplitude_controls[channel as usize] = acr;
+
+        Ok(self.amplitude(channel))
+    }
+
+    /// Get the most recently programmed output amplitude of a pounder DDS channel.
+    ///
+    /// # Args
+    /// * `channel` - The pounder channel to query.
+    ///
+    /// # Returns
+    /// The amplitude of the channel relative to full-scale.
+    pub fn amplitude(&self, channel: super::Channel) -> f32 {
+        self.tuning
+            .amplitude(self.amplitude_controls[channel as usize])
+    }
+
+    /// Enable or disable the output of a single pounder DDS channel.
+    ///
+    /// # Note
+    /// The channel enable bits of the channel select register only select which channels a
+    /// register write addresses, they do not gate the outputs. A disabled channel therefore sets
+    /// the DAC power-down bit in its channel function register, so it stops emitting. Only the
+    /// channel function register of the selected channel is written, so the other channels are
+    /// not disturbed.
+    ///
+    /// # Args
+    /// * `channel` - The pounder channel to configure.
+    /// * `enable` - Whether the channel output is enabled.
+    pub fn enable_channel(&mut self, channel: super::Channel, enable: bool) {
+        let cfr = &mut self.channel_functions[channel as usize];
+        if enable {
+            cfr[2] &= !CFR_DAC_POWER_DOWN;
+        } else {
+            cfr[2] |= CFR_DAC_POWER_DOWN;
+        }
+
+        let mut serializer = ProfileSerializer::new(self.mode);
+        serializer.update_channel_function(channel.into(), *cfr);
+        self.write(serializer.finalize());
+    }
+
+    /// Check whether the output of a pounder DDS channel is enabled.
+    ///
+    /// # Args
+    /// * `channel` - The pounder channel to query.
+    pub fn channel_enabled(&self, channel: super::Channel) -> bool {
+        self.channel_functions[channel as usize][2] & CFR_DAC_POWER_DOWN == 0
+    }
+
+    /// Provide the GPIOs driving the profile pins of the DDS.
+    ///
+    /// # Note
+    /// All pins are driven low, which selects profile 0 of every channel.
+    ///
+    /// # Args
+    /// * `pins` - The GPIOs connected to the profile pins P0 to P3.
+    pub fn set_profile_pins(&mut self, mut pins: [P; 4]) {
+        for pin in pins.iter_mut() {
+            pin.set_low().unwrap();
+        }
+        self.profile_pins.replace(pins);
+    }
+
+    /// Preload the frequency profiles of a single pounder DDS channel.
+    ///
+    /// # Note
+    /// This enables frequency modulation of the channel, see the module documentation. Profile 0
+    /// is the regular frequency of the channel, so it is also changed by
+    /// [DdsOutput::set_frequency], and [DdsOutput::frequency] reports it regardless of the
+    /// selected profile. If any frequency is out of bounds, nothing is written.
+    ///
+    /// # Args
+    /// * `channel` - The pounder channel to configure.
+    /// * `frequencies` - The frequency of each profile in Hz. Must be below the Nyquist frequency.
+    ///
+    /// # Returns
+    /// The actual programmed frequency of each profile in Hz.
+    pub fn preload_profiles(
+        &mut self,
+        channel: super::Channel,
+        frequencies: [f32; PROFILES_PER_CHANNEL],
+    ) -> Result<[f32; PROFILES_PER_CHANNEL], Error> {
+        self.check_clock()?;
+        if !frequencies
+            .iter()
+            .all(|frequency| (0.0..self.tuning.nyquist()).contains(frequency))
+        {
+            return Err(Error::Bounds);
+        }
+
+        let words = frequencies.map(|frequency| self.tuning.ftw(frequency));
+        let index = channel as usize;
+        let mut cfr = self.channel_functions[index];
+        cfr[0] = (cfr[0] & !CFR_AFP_SELECT) | CFR_FREQUENCY_MODULATION;
+
+        let mut serializer = ProfileSerializer::new(self.mode);
+        serializer.update_channels(channel.into(), Some(words[0]), None, None);
+        for (profile, word) in words.iter().enumerate().skip(1) {
+            // Note(unwrap): There are fewer profiles than channel word registers.
+            serializer
+                .update_channel_word(channel.into(), profile, *word)
+                .unwrap();
+        }
+        serializer.update_channel_function(channel.into(), cfr);
+        self.write(serializer.finalize());
+
+        self.frequency_tuning_words[index] = words[0];
+        self.channel_functions[index] = cfr;
+
+        Ok(words.map(|word| self.tuning.frequency(word)))
+    }
+
+    /// Select the active frequency profile of a single pounder DDS channel.
+    ///
+    /// # Note
+    /// Only the profile pin of the channel is driven. No register is written, so the profile
+    /// takes effect within a few DDS clock cycles and does not depend on an IO-update.
+    ///
+    /// # Args
+    /// * `channel` - The pounder channel to configure.
+    /// * `profile` - The profile to select, see [PROFILES_PER_CHANNEL].
+    pub fn select_profile(
+        &mut self,
+        channel: super::Channel,
+        profile: usize,
+    ) -> Result<(), Error> {
+        if profile >= PROFILES_PER_CHANNEL {
+            return Err(Error::Bounds);
+        }
+
+        // The profiles must be preloaded first.
+        if self.channel_functions[channel as usize][0] & CFR_AFP_SELECT
+            != CFR_FREQUENCY_MODULATION
+        {
+            return Err(Error::InvalidState);
+        }
+
+        let pins = self.profile_pins.as_mut().ok_or(Error::InvalidState)?;
+        let pin = &mut pins[profile_pin(channel)];
+        if profile == 0 {
+            pin.set_low().unwrap();
+        } else {
+            pin.set_high().unwrap();
+        }
+        Ok(())
+    }
+
+    /// Disable the frequency profiles of a single pounder DDS channel.
+    ///
+    /// # Note
+    /// The channel returns to the frequency of profile 0.
+    ///
+    /// # Args
+    /// * `channel` - The pounder channel to configure.
+    pub fn clear_profiles(&mut self, channel: super::Channel) {
+        let cfr = &mut self.channel_functions[channel as usize];
+        cfr[0] &= !CFR_AFP_SELECT;
+
+        let mut serializer = ProfileSerializer::new(self.mode);
+        serializer.update_channel_function(channel.into(), *cfr);
+        self.write(serializer.finalize());
+
+        if let Some(pins) = self.profile_pins.as_mut() {
+            pins[profile_pin(channel)].set_low().unwrap();
+        }
+    }
+
+    /// Reconfigure the DDS system clock for a new reference clock.
+    ///
+    /// # Note
+    /// The frequency tuning words of the channels are kept, so their output frequencies scale
+    /// with the system clock. Subsequent frequency updates use the new system clock. If the
+    /// IO_Update pulse is too short for the new system clock, it is widened to the minimum.
+    ///
+    /// # Args
+    /// * `reference_clock` - The reference clock frequency in Hz.
+    /// * `multiplier` - The PLL multiplier of the system clock. Must be 1 (PLL bypassed) or 4-20.
+    ///
+    /// # Returns
+    /// The new system clock frequency in Hz.
+    pub fn set_system_clock(
+        &mut self,
+        reference_clock: f32,
+        multiplier: u8,
+    ) -> Result<f32, Error> {
+        let mut serializer = ProfileSerializer::new(self.mode);
+        let system_clock = serializer
+            .configure_system_clock(reference_clock, multiplier)
+            .or(Err(Error::Bounds))?;
+
+        // The wider pulse is also registered at the previous system clock, so it is configured
+        // before the IO_Update latching the new clock.
+        let min_duration = min_io_update_duration(system_clock);
+        if self.io_update_duration < min_duration {
+            warn!(
+                "Widening IO_Update pulse to {} ns for the system clock",
+                min_duration * 1e9
+            );
+            self.configure_io_update(min_duration);
+        }
+        self.write(serializer.finalize());
+
+        // A sweep validated against the previous system clock may no longer be in bounds.
+        self.sweep.take();
+        self.tuning = Tuning::new(system_clock);
+        Ok(system_clock)
+    }
+
+    /// Reset the DDS and initialize it again.
+    ///
+    /// # Note
+    /// The master reset returns all DDS registers to their power-on defaults, including the
+    /// single-bit serial mode. The stream is stopped to switch the DDS back to the serial mode
+    /// of the stream, as in `ad9959::Ad9959::new()`, and restarted afterwards, also if the DDS
+    /// fails to switch to the serial mode of the stream. The system clock
+    /// is then configured again and the most recently programmed frequency, phase, amplitude
+    /// and channel function of all channels are restored. Preloaded profiles and a running sweep
+    /// are cleared.
+    ///
+    /// If the reset fails, the system clock is left unconfigured, so frequency updates fail with
+    /// [Error::ClockNotConfigured] until the system clock is configured again, see
+    /// [DdsOutput::set_system_clock()].
+    ///
+    /// # Args
+    /// * `delay` - A delay to time the reset and IO_Update pulses.
+    /// * `reference_clock` - The reference clock frequency in Hz.
+    /// * `multiplier` - The PLL multiplier of the system clock. Must be 1 (PLL bypassed) or 4-20.
+    ///
+    /// # Returns
+    /// The system clock frequency in Hz.
+    pub fn reset(
+        &mut self,
+        delay: &mut impl DelayUs<u32>,
+        reference_clock: f32,
+        multiplier: u8,
+    ) -> Result<f32, Error> {
+        let pulse_end = POUNDER_IO_UPDATE_DELAY + self.io_update_duration;
+        let mode = self.mode;
+        let reset_pin = &mut self.reset_pin;
+        let io_update_trigger = &mut self.io_update_trigger;
+
+        // The master reset returns the DDS to its default system clock, which the cached tuning
+        // does not describe until the system clock is configured again.
+        self.tuning = Tuning::new(0.);
+
+        with_stream_stopped(&mut self.qspi, |qspi| {
+            // Delay for at least 1 SYNC_CLK period for the reset to occur, see
+            // `ad9959::Ad9959::new()`.
+            reset_pin.set_high().unwrap();
+            delay.delay_us(5);
+            reset_pin.set_low().unwrap();
+
+            restore_serial_mode(
+                qspi,
+                delay,
+                mode,
+                || io_update_trigger.trigger(),
+                libm::ceilf(pulse_end * 1e6) as u32 + 1,
+            )
+        })?;
+
+        let system_clock =
+            self.set_system_clock(reference_clock, multiplier)?;
+
+        // The channel words holding the profiles were reset.
+        for channel in enum_iterator::all::<super::Channel>() {
+            self.channel_functions[channel as usize][0] &= !CFR_AFP_SELECT;
+            if let Some(pins) = self.profile_pins.as_mut() {
+                pins[profile_pin(channel)].set_low().unwrap();
+            }
+        }
+
+        for channel in enum_iterator::all::<super::Channel>() {
+            let index = channel as usize;
+            let mut serializer = ProfileSerializer::new(self.mode);
+            serializer.update_channels(
+                channel.into(),
+                Some(self.frequency_tuning_words[index]),
+                Some(self.phase_offsets[index]),
+                Some(self.amplitude_controls[index] as u32),
+            );
+            serializer.update_channel_function(
+                channel.into(),
+                self.channel_functions[index],
+            );
+            self.write(serializer.finalize());
+        }
+
+        Ok(system_clock)
+    }
+
+    /// Get the DDS system clock frequency in Hz.
+    pub fn system_clock(&self) -> f32 {
+        self.tuning.system_clock()
+    }
+
+    /// Set the width of the IO_Update pulse.
+    ///
+    /// # Note
+    /// The HRTimer is reconfigured, which cuts short a pulse pending from a preceding profile
+    /// write. This should thus not be called within the IO_Update delay of a write.
+    ///
+    /// # Args
+    /// * `us` - The pulse width in microseconds. It must be at least the minimum width at the
+    ///   current system clock, see [min_io_update_duration], and at most 100 us.
+    pub fn set_io_update_pulse_width(&mut self, us: f32) -> Result<(), Error> {
+        self.check_clock()?;
+        let duration = us * 1e-6;
+        let min_duration = min_io_update_duration(self.tuning.system_clock());
+        if !(min_duration..=MAX_IO_UPDATE_DURATION).contains(&duration) {
+            return Err(Error::Bounds);
+        }
+
+        self.configure_io_update(duration);
+        Ok(())
+    }
+
+    /// Get the width of the IO_Update pulse in microseconds.
+    pub fn io_update_pulse_width(&self) -> f32 {
+        self.io_update_duration * 1e6
+    }
+
+    /// Set the polarity of the IO_Update output.
+    ///
+    /// # Note
+    /// The AD9959 latches on a rising IO_Update edge, so [Polarity::ActiveLow] is only required
+    /// for board revisions with an inverting buffer. The polarity only applies to the pulses of
+    /// the profile stream, as the output is driven as a GPIO during DDS initialization.
+    ///
+    /// # Args
+    /// * `polarity` - The polarity of the IO_Update output.
+    pub fn set_io_update_polarity(&mut self, polarity: Polarity) {
+        self.io_update_trigger.set_polarity(polarity);
+    }
+
+    /// Configure the IO_Update pulse with the given width in seconds.
+    fn configure_io_update(&mut self, duration: f32) {
+        self.io_update_trigger
+            .configure_pulse(POUNDER_IO_UPDATE_DELAY, duration);
+        self.io_update_duration = duration;
+    }
+
+    /// Check that the system clock is configured, as frequency tuning words depend on it.
+    ///
+    /// # Returns
+    /// [Error::ClockNotConfigured] if the stored system clock is zero or not finite.
+    fn check_clock(&self) -> Result<(), Error> {
+        let system_clock = self.tuning.system_clock();
+        if system_clock.is_finite() && system_clock > 0. {
+            Ok(())
+        } else {
+            Err(Error::ClockNotConfigured)
+        }
+    }
+
+    /// Start a linear frequency sweep of a single pounder DDS channel.
+    ///
+    /// # Note
+    /// The channel is set to the start frequency immediately. The sweep is then advanced by
+    /// [DdsOutput::update_sweep]. Once the stop frequency has been held for its dwell, the sweep
+    /// restarts at the start frequency. Any sweep in progress is replaced.
+    ///
+    /// # Args
+    /// * `channel` - The pounder channel to sweep.
+    /// * `start` - The start frequency in Hz.
+    /// * `stop` - The stop frequency in Hz. Must be larger than `start`.
+    /// * `step` - The frequency step in Hz. If it does not divide the span evenly, the last step
+    ///   is shortened to end at `stop`.
+    /// * `dwell_ticks` - The number of [DdsOutput::update_sweep] calls to hold each point for.
+    pub fn start_sweep(
+        &mut self,
+        channel: super::Channel,
+        start: f32,
+        stop: f32,
+        step: f32,
+        dwell_ticks: u32,
+    ) -> Result<(), Error> {
+        self.check_clock()?;
+        let sweep = Sweep::new(
+            channel,
+            start,
+            stop,
+            step,
+            dwell_ticks,
+            self.tuning.system_clock(),
+        )?;
+        self.set_frequency(channel, sweep.point(0))?;
+        self.sweep.replace(sweep);
+        Ok(())
+    }
+
+    /// Stop the sweep in progress.
+    ///
+    /// # Note
+    /// The swept channel keeps its current frequency.
+    pub fn stop_sweep(&mut self) {
+        self.sweep.take();
+    }
+
+    /// Advance the sweep in progress.
+    ///
+    /// # Note
+    /// This should be called once per processing cycle. It does nothing if no sweep is active.
+    pub fn update_sweep(&mut self) {
+        let Some(sweep) = self.sweep.as_mut() else {
+            return;
+        };
+
+        if let Some(frequency) = sweep.advance() {
+            let channel = sweep.channel;
+            // Note(unwrap): All sweep points are validated to be in bounds on construction.
+            self.set_frequency(channel, frequency).unwrap();
+        }
+    }
+
+    /// Write the next pre-computed profile of a stream.
+    ///
+    /// # Note
+    /// This is intended to be called at a fixed rate from a timer-gated context. Like
+    /// [DdsOutput::write_register()], streamed profiles bypass the cached channel state, so
+    /// [DdsOutput::frequency] and the refresh do not reflect them. If no profile is pending, the
+    /// DDS holds its last value and an underrun is flagged while streaming.
+    ///
+    /// # Args
+    /// * `stream` - The consumer of the profile stream.
+    ///
+    /// # Returns
+    /// True if a profile was written.
+    pub fn stream_profile(&mut self, stream: &mut ProfileConsumer) -> bool {
+        match stream.pop() {
+            Some(profile) => {
+                self.write(&profile);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Configure the periodic refresh of the enabled channels.
+    ///
+    /// # Note
+    /// A refresh re-writes the frequency, phase, amplitude and channel function of a channel from
+    /// the most recently programmed values, so registers corrupted by a transfer glitch are
+    /// restored. Re-writing unchanged values does not disturb the output.
+    ///
+    /// # Args
+    /// * `interval` - The number of [DdsOutput::update_refresh] calls between refresh passes, or
+    ///   `None` to disable the refresh.
+    pub fn set_refresh(&mut self, interval: Option<u32>) {
+        self.refresh = interval.map(|interval| Refresh {
+            interval,
+            ticks: 0,
+            pending: 0,
+        });
+    }
+
+    /// Exclude the frequency of a channel from the refresh.
+    ///
+    /// # Note
+    /// This should be used while the frequency of the channel is owned by a servo that writes it
+    /// through custom profiles, as the cached frequency is stale in that case.
+    ///
+    /// # Args
+    /// * `channel` - The pounder channel to configure.
+    /// * `skip` - Whether the frequency is skipped by the refresh.
+    pub fn skip_frequency_refresh(
+        &mut self,
+        channel: super::Channel,
+        skip: bool,
+    ) {
+        self.skip_frequency_refresh[channel as usize] = skip;
+    }
+
+    /// Advance the periodic refresh.
+    ///
+    /// # Note
+    /// This should be called once per processing cycle. It does nothing if the refresh is
+    /// disabled. To keep each profile short, a pass refreshes one channel per call, so all
+    /// enabled channels are refreshed within three calls after the interval has elapsed.
+    pub fn update_refresh(&mut self) {
+        let Some(refresh) = self.refresh.as_mut() else {
+            return;
+        };
+
+        refresh.ticks += 1;
+        if refresh.ticks >= refresh.interval {
+            refresh.ticks = 0;
+            refresh.pending = 0b1111;
+        }
+
+        let pending = refresh.pending;
+        let Some(channel) = enum_iterator::all::<super::Channel>()
+            .find(|&channel| pending & (1 << channel as u8) != 0)
+        else {
+            return;
+        };
+        refresh.pending &= !(1 << channel as u8);
+
+        if self.channel_enabled(channel) {
+            self.refresh_channel(channel);
+        }
+    }
+
+    /// Re-write the most recently programmed state of a channel.
+    fn refresh_channel(&mut self, channel: super::Channel) {
+        let index = channel as usize;
+        let ftw = (!self.skip_frequency_refresh[index])
+            .then_some(self.frequency_tuning_words[index]);
+
+        let mut serializer = ProfileSerializer::new(self.mode);
+        serializer.update_channels(
+            channel.into(),
+            ftw,
+            Some(self.phase_offsets[index]),
+            Some(self.amplitude_controls[index] as u32),
+        );
+        serializer.update_channel_function(
+            channel.into(),
+            self.channel_functions[index],
+        );
+        self.write(serializer.finalize());
     }
 
     /// Get a builder for serializing a Pounder DDS profile.
     #[allow(dead_code)]
-    pub fn builder(&mut self) -> ProfileBuilder {
+    pub fn builder(&mut self) -> ProfileBuilder<'_, Q, T, P> {
         let mode = self.mode;
         ProfileBuilder {
             dds_output: self,
@@ -110,32 +1024,224 @@ impl DdsOutput {
     /// # Args
     /// * `profile` - The serialized DDS profile to write.
     pub fn write(&mut self, profile: &[u32]) {
-        // Note(unsafe): We own the QSPI interface, so it is safe to access the registers in a raw
-        // fashion.
-        let regs = unsafe { &*hal::stm32::QUADSPI::ptr() };
+        self.qspi.write_profile(profile);
 
-        // Warn if the fifo is still at least half full.
-        if regs.sr.read().flevel().bits() >= 16 {
-            warn!("QSPI stalling")
+        // Trigger the IO_update signal generating timer to asynchronous create the IO_Update pulse.
+        self.io_update_trigger.trigger();
+    }
+}
+
+/// Check whether an output frequency can be synthesized.
+///
+/// # Args
+/// * `tuning` - The tuning of the DDS.
+/// * `frequency` - The desired output frequency in Hz.
+///
+/// # Returns
+/// A warning if the frequency is prone to aliasing, or [Error::Bounds] if the frequency is
+/// negative or not below the Nyquist frequency.
+fn check_frequency(
+    tuning: &Tuning,
+    frequency: f32,
+) -> Result<Option<FrequencyWarning>, Error> {
+    if !(0.0..tuning.nyquist()).contains(&frequency) {
+        return Err(Error::Bounds);
+    }
+
+    Ok((frequency > ALIAS_WARNING_FRACTION * tuning.system_clock())
+        .then_some(FrequencyWarning::NearNyquist))
+}
+
+/// Access the DDS registers with the profile stream stopped.
+///
+/// # Note
+/// The stream is restarted after the access, also if the access fails.
+///
+/// # Args
+/// * `stream` - The serial interface of the DDS.
+/// * `access` - The register access.
+///
+/// # Returns
+/// The result of the access, or the error of restarting the stream.
+fn with_stream_stopped<S: ProfileStream, R>(
+    stream: &mut S,
+    access: impl FnOnce(&mut S) -> Result<R, Error>,
+) -> Result<R, Error> {
+    stream.stop_stream();
+    let result = access(stream);
+    stream.start_stream()?;
+    result
+}
+
+/// Switch the DDS from the single-bit serial mode to a different serial mode after a master reset.
+///
+/// # Note
+/// The mode is latched with an IO_Update pulse and read back, as in `ad9959::Ad9959::new()`.
+///
+/// # Args
+/// * `interface` - The serial interface of the DDS. It must not be streaming.
+/// * `delay` - A delay to wait for the IO_Update pulse and the mode change.
+/// * `mode` - The serial mode to switch to.
+/// * `io_update` - A function triggering the IO_Update pulse.
+/// * `io_update_end_us` - The time from triggering the IO_Update pulse to its end in
+///   microseconds.
+///
+/// # Returns
+/// [Error::Init] if the readback of the mode does not match.
+fn restore_serial_mode<I: Interface<Error = Error>>(
+    interface: &mut I,
+    delay: &mut impl DelayUs<u32>,
+    mode: Mode,
+    io_update: impl FnOnce(),
+    io_update_end_us: u32,
+) -> Result<(), Error> {
+    let csr = [Channel::ALL.bits() | mode as u8];
+    interface.configure_mode(Mode::SingleBitTwoWire)?;
+    interface.write(Register::CSR as u8, &csr)?;
+
+    // Latch the serial mode and wait for the IO_Update pulse to end.
+    io_update();
+    delay.delay_us(io_update_end_us);
+
+    // Wait at least 1 SYNC_CLK period for the serial mode to become active before the
+    // readback, as in `ad9959::Ad9959::new()`.
+    interface.configure_mode(mode)?;
+    delay.delay_us(5);
+    let mut readback = [0];
+    interface.read(Register::CSR as u8, &mut readback)?;
+    if readback != csr {
+        return Err(Error::Init(InitStage::Dds));
+    }
+
+    Ok(())
+}
+
+/// Get the index of the profile pin of a pounder channel.
+///
+/// # Note
+/// In two-level modulation, profile pin Pn controls DDS channel n.
+fn profile_pin(channel: super::Channel) -> usize {
+    let dds_channel: Channel = channel.into();
+    (dds_channel.bits().trailing_zeros() - Channel::ONE.bits().trailing_zeros())
+        as usize
+}
+
+/// A linear frequency sweep of a single DDS channel.
+#[derive(Copy, Clone, Debug)]
+pub struct Sweep {
+    channel: super::Channel,
+    start: f32,
+    stop: f32,
+    step: f32,
+    dwell_ticks: u32,
+    num_points: u32,
+    index: u32,
+    ticks: u32,
+}
+
+impl Sweep {
+    /// Construct a sweep.
+    ///
+    /// # Args
+    /// * `channel` - The pounder channel to sweep.
+    /// * `start` - The start frequency in Hz.
+    /// * `stop` - The stop frequency in Hz. Must be larger than `start`.
+    /// * `step` - The positive frequency step in Hz.
+    /// * `dwell_ticks` - The non-zero number of updates to hold each point for.
+    /// * `system_clock` - The DDS system clock frequency in Hz. All points must be below the
+    ///   Nyquist frequency.
+    pub fn new(
+        channel: super::Channel,
+        start: f32,
+        stop: f32,
+        step: f32,
+        dwell_ticks: u32,
+        system_clock: f32,
+    ) -> Result<Self, Error> {
+        let nyquist = system_clock / 2.;
+        if !(0.0..nyquist).contains(&start)
+            || !(start..nyquist).contains(&stop)
+            || start == stop
+            || step.is_nan()
+            || step <= 0.
+            || dwell_ticks == 0
+        {
+            return Err(Error::Bounds);
         }
 
-        for word in profile.iter() {
-            // Note(unsafe): any bit pattern is valid for a TX FIFO write.
-            regs.dr.write(|w| unsafe { w.bits(*word) });
+        // Round the number of steps up so that the last point is always `stop`.
+        let mut steps = ((stop - start) / step) as u32;
+        if start + steps as f32 * step < stop {
+            steps += 1;
         }
 
-        // Trigger the IO_update signal generating timer to asynchronous create the IO_Update pulse.
-        self.io_update_trigger.trigger();
+        Ok(Self {
+            channel,
+            start,
+            stop,
+            step,
+            dwell_ticks,
+            num_points: steps + 1,
+            index: 0,
+            ticks: 0,
+        })
+    }
+
+    /// Get the number of points of the sweep.
+    pub fn num_points(&self) -> u32 {
+        self.num_points
+    }
+
+    /// Get the frequency of a sweep point.
+    ///
+    /// # Args
+    /// * `index` - The index of the point.
+    ///
+    /// # Returns
+    /// The frequency of the point in Hz.
+    pub fn point(&self, index: u32) -> f32 {
+        (self.start + index as f32 * self.step).min(self.stop)
+    }
+
+    /// Get an iterator over the frequencies of all sweep points in order.
+    pub fn points(&self) -> impl Iterator<Item = f32> + '_ {
+        (0..self.num_points).map(|index| self.point(index))
+    }
+
+    /// Advance the sweep by one update.
+    ///
+    /// # Returns
+    /// The frequency of the next point if the dwell of the current point has elapsed.
+    fn advance(&mut self) -> Option<f32> {
+        self.ticks += 1;
+        if self.ticks < self.dwell_ticks {
+            return None;
+        }
+
+        self.ticks = 0;
+        self.index = (self.index + 1) % self.num_points;
+        Some(self.point(self.index))
     }
 }
 
 /// A temporary builder for serializing and writing profiles.
-pub struct ProfileBuilder<'a> {
-    dds_output: &'a mut DdsOutput,
+pub struct ProfileBuilder<
+    'a,
+    Q = QspiInterface,
+    T = HighResTimerE,
+    P = hal::gpio::ErasedPin<hal::gpio::Output>,
+> {
+    dds_output: &'a mut DdsOutput<Q, T, P>,
     serializer: ProfileSerializer,
 }
 
-impl<'a> ProfileBuilder<'a> {
+impl<'a, Q, T, P> ProfileBuilder<'a, Q, T, P>
+where
+    Q: ProfileStream,
+    T: IoUpdateTimer,
+    P: OutputPin,
+    P::Error: core::fmt::Debug,
+{
     /// Update a number of channels with the provided configuration
     ///
     /// # Args
@@ -164,3 +1270,526 @@ impl<'a> ProfileBuilder<'a> {
         self.dds_output.write(self.serializer.finalize());
     }
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::hardware::pounder;
+
+    const SYSTEM_CLOCK: f32 = 500e6;
+
+    fn sweep(start: f32, stop: f32, step: f32) -> Result<Sweep, Error> {
+        Sweep::new(pounder::Channel::Out0, start, stop, step, 2, SYSTEM_CLOCK)
+    }
+
+    /// An access to the DDS, its IO_Update timer, its pins, the attenuators or the reference
+    /// clock selection.
+    #[derive(Debug, PartialEq)]
+    pub(crate) enum Access {
+        Mode(u8),
+        Write(u8, u8),
+        Read(u8),
+        IoUpdate,
+        Delay(u32),
+        StopStream,
+        StartStream,
+        Profile(heapless::Vec<u32, 8>),
+        Pulse(f32, f32),
+        Polarity(Polarity),
+        Pin(usize, bool),
+        TransferAttenuators([u8; 4]),
+        LatchAttenuator(usize),
+        ReferenceInput(bool),
+    }
+
+    pub(crate) type Accesses = core::cell::RefCell<heapless::Vec<Access, 64>>;
+
+    /// A DDS serial interface and delay that record the accesses.
+    pub(crate) struct MockDds<'a> {
+        accesses: &'a Accesses,
+        pub(crate) csr: u8,
+        pub(crate) fail_addr: Option<u8>,
+        streaming: bool,
+    }
+
+    impl<'a> MockDds<'a> {
+        fn new(accesses: &'a Accesses) -> Self {
+            Self {
+                accesses,
+                csr: 0,
+                fail_addr: None,
+                streaming: false,
+            }
+        }
+
+        pub(crate) fn log(&self, access: Access) {
+            self.accesses.borrow_mut().push(access).unwrap();
+        }
+    }
+
+    impl Interface for MockDds<'_> {
+        type Error = Error;
+
+        fn configure_mode(&mut self, mode: Mode) -> Result<(), Error> {
+            self.log(Access::Mode(mode as u8));
+            Ok(())
+        }
+
+        fn write(&mut self, addr: u8, data: &[u8]) -> Result<(), Error> {
+            // As for the QSPI interface, registers are not accessible while streaming.
+            if self.streaming {
+                return Err(Error::InvalidState);
+            }
+            self.log(Access::Write(addr, data[0]));
+            if self.fail_addr == Some(addr) {
+                return Err(Error::Bounds);
+            }
+            Ok(())
+        }
+
+        fn read(&mut self, addr: u8, dest: &mut [u8]) -> Result<(), Error> {
+            if self.streaming {
+                return Err(Error::InvalidState);
+            }
+            self.log(Access::Read(addr));
+            dest[0] = self.csr;
+            Ok(())
+        }
+    }
+
+    impl ProfileStream for MockDds<'_> {
+        fn start_stream(&mut self) -> Result<(), Error> {
+            self.log(Access::StartStream);
+            self.streaming = true;
+            Ok(())
+        }
+
+        fn stop_stream(&mut self) {
+            self.log(Access::StopStream);
+            self.streaming = false;
+        }
+
+        fn write_profile(&mut self, profile: &[u32]) {
+            assert!(self.streaming);
+            self.log(Access::Profile(
+                heapless::Vec::from_slice(profile).unwrap(),
+            ));
+        }
+    }
+
+    impl DelayUs<u32> for MockDds<'_> {
+        fn delay_us(&mut self, us: u32) {
+            self.log(Access::Delay(us));
+        }
+    }
+
+    /// An IO_Update timer that records the pulses.
+    pub(crate) struct MockIoUpdate<'a> {
+        accesses: &'a Accesses,
+    }
+
+    impl IoUpdateTimer for MockIoUpdate<'_> {
+        fn configure_pulse(&mut self, delay: f32, duration: f32) {
+            let access = Access::Pulse(delay, duration);
+            self.accesses.borrow_mut().push(access).unwrap();
+        }
+
+        fn set_polarity(&mut self, polarity: Polarity) {
+            let access = Access::Polarity(polarity);
+            self.accesses.borrow_mut().push(access).unwrap();
+        }
+
+        fn trigger(&mut self) {
+            self.accesses.borrow_mut().push(Access::IoUpdate).unwrap();
+        }
+    }
+
+    /// The index of the reset pin, following the profile pins.
+    pub(crate) const RESET_PIN: usize = 4;
+
+    /// A GPIO that records its output level.
+    pub(crate) struct MockPin<'a> {
+        accesses: &'a Accesses,
+        index: usize,
+    }
+
+    impl OutputPin for MockPin<'_> {
+        type Error = core::convert::Infallible;
+
+        fn set_low(&mut self) -> Result<(), Self::Error> {
+            let access = Access::Pin(self.index, false);
+            self.accesses.borrow_mut().push(access).unwrap();
+            Ok(())
+        }
+
+        fn set_high(&mut self) -> Result<(), Self::Error> {
+            let access = Access::Pin(self.index, true);
+            self.accesses.borrow_mut().push(access).unwrap();
+            Ok(())
+        }
+    }
+
+    pub(crate) type MockOutput<'a> =
+        DdsOutput<MockDds<'a>, MockIoUpdate<'a>, MockPin<'a>>;
+
+    /// Construct a streaming DDS output over mocks, with an empty access log.
+    pub(crate) fn dds_output(accesses: &Accesses) -> MockOutput<'_> {
+        let dds = DdsOutput::new(
+            MockDds::new(accesses),
+            MockIoUpdate { accesses },
+            MockPin {
+                accesses,
+                index: RESET_PIN,
+            },
+            Mode::FourBitSerial,
+        );
+        accesses.borrow_mut().clear();
+        dds
+    }
+
+    #[test]
+    fn register_access_stops_stream() {
+        let accesses = Accesses::default();
+        let mut dds = dds_output(&accesses);
+
+        dds.write_register(Register::CFTW0 as u8, &[1, 2, 3, 4])
+            .unwrap();
+        let mut data = [0];
+        dds.read_register(Register::CSR as u8, &mut data).unwrap();
+
+        assert_eq!(
+            accesses.borrow().as_slice(),
+            [
+                Access::StopStream,
+                Access::Write(Register::CFTW0 as u8, 1),
+                Access::StartStream,
+                Access::StopStream,
+                Access::Read(Register::CSR as u8),
+                Access::StartStream,
+            ]
+        );
+    }
+
+    #[test]
+    fn register_access_restarts_stream_on_error() {
+        let accesses = Accesses::default();
+        let mut dds = dds_output(&accesses);
+        dds.qspi.fail_addr = Some(Register::CFTW0 as u8);
+
+        assert!(matches!(
+            dds.write_register(Register::CFTW0 as u8, &[1]),
+            Err(Error::Bounds)
+        ));
+        assert_eq!(
+            accesses.borrow().as_slice(),
+            [
+                Access::StopStream,
+                Access::Write(Register::CFTW0 as u8, 1),
+                Access::StartStream,
+            ]
+        );
+
+        // Profiles can be streamed again.
+        dds.set_phase(pounder::Channel::Out0, 0.);
+    }
+
+    #[test]
+    fn failed_reset_unsets_clock() {
+        let accesses = Accesses::default();
+        let mut dds = dds_output(&accesses);
+        let mut delay = MockDds::new(&accesses);
+        dds.set_frequency(pounder::Channel::Out0, 10e6).unwrap();
+
+        // The DDS does not read back the serial mode of the stream.
+        assert!(matches!(
+            dds.reset(&mut delay, 100e6, 5),
+            Err(Error::Init(InitStage::Dds))
+        ));
+        assert!(matches!(
+            dds.set_frequency(pounder::Channel::Out0, 10e6),
+            Err(Error::ClockNotConfigured)
+        ));
+        assert!(matches!(
+            dds.start_sweep(pounder::Channel::Out0, 1e6, 2e6, 1e5, 1),
+            Err(Error::ClockNotConfigured)
+        ));
+
+        assert_eq!(dds.set_system_clock(100e6, 5).unwrap(), 500e6);
+        dds.set_frequency(pounder::Channel::Out0, 10e6).unwrap();
+
+        // A successful reset configures the system clock again.
+        dds.qspi.csr = Channel::ALL.bits() | Mode::FourBitSerial as u8;
+        accesses.borrow_mut().clear();
+        assert_eq!(dds.reset(&mut delay, 20e6, 20).unwrap(), 400e6);
+        dds.set_frequency(pounder::Channel::Out0, 10e6).unwrap();
+    }
+
+    #[test]
+    fn channel_update_strobes_once() {
+        let accesses = Accesses::default();
+        let mut dds = dds_output(&accesses);
+
+        dds.set_channel(pounder::Channel::Out0, 10e6, 0.25, 0.5)
+            .unwrap();
+
+        // Frequency, phase and amplitude are written in one profile and latched together.
+        let mut serializer = ProfileSerializer::new(Mode::FourBitSerial);
+        serializer.update_channels(
+            Channel::ONE,
+            Some(dds.tuning.ftw(10e6)),
+            Some(dds.tuning.pow(0.25)),
+            Some(dds.tuning.acr(0.5) as u32),
+        );
+        assert_eq!(
+            accesses.borrow().as_slice(),
+            [
+                Access::Profile(
+                    heapless::Vec::from_slice(serializer.finalize()).unwrap()
+                ),
+                Access::IoUpdate,
+            ]
+        );
+
+        // Nothing is written if any value is out of bounds.
+        accesses.borrow_mut().clear();
+        assert!(matches!(
+            dds.set_channel(pounder::Channel::Out0, 10e6, 0.25, 1.5),
+            Err(Error::Bounds)
+        ));
+        assert!(accesses.borrow().is_empty());
+    }
+
+    #[test]
+    fn profile_selection_drives_only_pins() {
+        let accesses = Accesses::default();
+        let mut dds = dds_output(&accesses);
+        dds.set_profile_pins(core::array::from_fn(|index| MockPin {
+            accesses: &accesses,
+            index,
+        }));
+        dds.preload_profiles(pounder::Channel::Out1, [10e6, 20e6])
+            .unwrap();
+        accesses.borrow_mut().clear();
+
+        dds.select_profile(pounder::Channel::Out1, 1).unwrap();
+        dds.select_profile(pounder::Channel::Out1, 0).unwrap();
+
+        let pin = profile_pin(pounder::Channel::Out1);
+        assert_eq!(
+            accesses.borrow().as_slice(),
+            [Access::Pin(pin, true), Access::Pin(pin, false)]
+        );
+
+        // Channels without preloaded profiles are rejected.
+        accesses.borrow_mut().clear();
+        assert!(matches!(
+            dds.select_profile(pounder::Channel::Out0, 1),
+            Err(Error::InvalidState)
+        ));
+        assert!(accesses.borrow().is_empty());
+    }
+
+    #[test]
+    fn io_update_pulse_width_configures_timer() {
+        let accesses = Accesses::default();
+        let mut dds = dds_output(&accesses);
+
+        dds.set_io_update_pulse_width(2.).unwrap();
+        assert_eq!(
+            accesses.borrow().as_slice(),
+            [Access::Pulse(POUNDER_IO_UPDATE_DELAY, 2e-6)]
+        );
+        assert_eq!(dds.io_update_pulse_width(), 2e-6 * 1e6);
+
+        // Out of bounds widths leave the timer untouched.
+        accesses.borrow_mut().clear();
+        for us in [0.001, 101., f32::NAN] {
+            assert!(matches!(
+                dds.set_io_update_pulse_width(us),
+                Err(Error::Bounds)
+            ));
+        }
+        assert!(accesses.borrow().is_empty());
+        assert_eq!(dds.io_update_pulse_width(), 2e-6 * 1e6);
+    }
+
+    #[test]
+    fn enabling_channel_preserves_other_channels() {
+        let accesses = Accesses::default();
+        let mut dds = dds_output(&accesses);
+        dds.enable_channel(pounder::Channel::Out0, false);
+        dds.enable_channel(pounder::Channel::In1, false);
+        accesses.borrow_mut().clear();
+
+        dds.enable_channel(pounder::Channel::Out1, true);
+
+        // Only the channel function register of channel three is addressed.
+        let mut serializer = ProfileSerializer::new(Mode::FourBitSerial);
+        serializer.update_channel_function(Channel::THREE, CFR_DEFAULT);
+        assert_eq!(
+            accesses.borrow().as_slice(),
+            [
+                Access::Profile(
+                    heapless::Vec::from_slice(serializer.finalize()).unwrap()
+                ),
+                Access::IoUpdate,
+            ]
+        );
+
+        assert!(dds.channel_enabled(pounder::Channel::In0));
+        assert!(!dds.channel_enabled(pounder::Channel::Out0));
+        assert!(dds.channel_enabled(pounder::Channel::Out1));
+        assert!(!dds.channel_enabled(pounder::Channel::In1));
+        assert_eq!(
+            dds.channel_functions[pounder::Channel::Out0 as usize][2],
+            CFR_DEFAULT[2] | CFR_DAC_POWER_DOWN
+        );
+        assert_eq!(
+            dds.channel_functions[pounder::Channel::In0 as usize],
+            CFR_DEFAULT
+        );
+    }
+
+    #[test]
+    fn serial_mode_restore_sequence() {
+        let accesses = Accesses::default();
+        let csr = Channel::ALL.bits() | Mode::FourBitSerial as u8;
+        let mut dds = MockDds {
+            accesses: &accesses,
+            csr,
+            fail_addr: None,
+            streaming: false,
+        };
+        let mut delay = MockDds {
+            accesses: &accesses,
+            csr,
+            fail_addr: None,
+            streaming: false,
+        };
+
+        // The IO_Update pulse may end more than 255 us after its trigger.
+        restore_serial_mode(
+            &mut dds,
+            &mut delay,
+            Mode::FourBitSerial,
+            || accesses.borrow_mut().push(Access::IoUpdate).unwrap(),
+            300,
+        )
+        .unwrap();
+
+        assert_eq!(
+            accesses.borrow().as_slice(),
+            [
+                Access::Mode(Mode::SingleBitTwoWire as u8),
+                Access::Write(Register::CSR as u8, csr),
+                Access::IoUpdate,
+                Access::Delay(300),
+                Access::Mode(Mode::FourBitSerial as u8),
+                Access::Delay(5),
+                Access::Read(Register::CSR as u8),
+            ]
+        );
+    }
+
+    #[test]
+    fn serial_mode_restore_checks_readback() {
+        let accesses = Accesses::default();
+        let mut dds = MockDds {
+            accesses: &accesses,
+            csr: Channel::ALL.bits() | Mode::SingleBitTwoWire as u8,
+            fail_addr: None,
+            streaming: false,
+        };
+        let mut delay = MockDds {
+            accesses: &accesses,
+            csr: 0,
+            fail_addr: None,
+            streaming: false,
+        };
+
+        assert!(matches!(
+            restore_serial_mode(
+                &mut dds,
+                &mut delay,
+                Mode::FourBitSerial,
+                || {},
+                2
+            ),
+            Err(Error::Init(InitStage::Dds))
+        ));
+    }
+
+    #[test]
+    fn frequency_check_warns_near_nyquist() {
+        let tuning = Tuning::new(SYSTEM_CLOCK);
+
+        assert_eq!(check_frequency(&tuning, 0.).unwrap(), None);
+        assert_eq!(check_frequency(&tuning, 100e6).unwrap(), None);
+        assert_eq!(
+            check_frequency(&tuning, ALIAS_WARNING_FRACTION * SYSTEM_CLOCK)
+                .unwrap(),
+            None
+        );
+
+        assert_eq!(
+            check_frequency(&tuning, 210e6).unwrap(),
+            Some(FrequencyWarning::NearNyquist)
+        );
+        assert_eq!(
+            check_frequency(&tuning, 249e6).unwrap(),
+            Some(FrequencyWarning::NearNyquist)
+        );
+
+        for frequency in [250e6, 300e6, -1., f32::NAN] {
+            assert!(matches!(
+                check_frequency(&tuning, frequency),
+                Err(Error::Bounds)
+            ));
+        }
+    }
+
+    #[test]
+    fn sweep_points_end_at_stop() {
+        let sweep = sweep(1e6, 2e6, 300e3).unwrap();
+        assert_eq!(sweep.num_points(), 5);
+        assert!(sweep
+            .points()
+            .eq([1e6, 1.3e6, 1.6e6, 1.9e6, 2e6].into_iter()));
+
+        let sweep = self::sweep(1e6, 2e6, 250e3).unwrap();
+        assert!(sweep
+            .points()
+            .eq([1e6, 1.25e6, 1.5e6, 1.75e6, 2e6].into_iter()));
+    }
+
+    #[test]
+    fn sweep_dwells_and_wraps() {
+        let mut sweep = sweep(1e6, 2e6, 500e3).unwrap();
+        let updates: [Option<f32>; 6] =
+            core::array::from_fn(|_| sweep.advance());
+        assert_eq!(
+            updates,
+            [None, Some(1.5e6), None, Some(2e6), None, Some(1e6)]
+        );
+    }
+
+    #[test]
+    fn sweep_rejects_invalid_parameters() {
+        for (start, stop, step) in [
+            (1e6, 2e6, 0.),
+            (1e6, 2e6, -1e3),
+            (1e6, 2e6, f32::NAN),
+            (f32::NAN, 2e6, 1e3),
+            (2e6, 1e6, 1e3),
+            (1e6, 1e6, 1e3),
+            (1e6, SYSTEM_CLOCK / 2., 1e3),
+        ] {
+            assert!(matches!(sweep(start, stop, step), Err(Error::Bounds)));
+        }
+
+        assert!(matches!(
+            Sweep::new(pounder::Channel::Out0, 1e6, 2e6, 1e3, 0, SYSTEM_CLOCK),
+            Err(Error::Bounds)
+        ));
+    }
+}
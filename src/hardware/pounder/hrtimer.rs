@@ -11,6 +11,69 @@ pub enum Channel {
     Two,
 }
 
+/// The output channel driving the DDS IO_Update on PG7.
+pub const IO_UPDATE_CHANNEL: Channel = Channel::Two;
+
+/// The polarity of a HRTimer output.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Polarity {
+    /// The output idles low and pulses high.
+    ActiveHigh,
+
+    /// The output idles high and pulses low.
+    ActiveLow,
+}
+
+/// The timer settings generating a single pulse.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+struct SingleShot {
+    /// The clock divider setting. 1, 2 and 3 divide the kernel clock by 1, 2 and 4.
+    div: u8,
+    /// The count at which the output is deasserted.
+    period: u16,
+    /// The count at which the output is asserted.
+    compare: u16,
+}
+
+impl SingleShot {
+    /// Compute the timer settings of a single pulse.
+    ///
+    /// # Args
+    /// * `clk` - The timer kernel clock frequency in Hz.
+    /// * `delay` - The time at which the output should be asserted.
+    /// * `duration` - The duration that the output should be asserted for.
+    fn new(clk: f32, delay: f32, duration: f32) -> Self {
+        // Configure the desired timer for single shot mode with set and reset of the specified
+        // channel at the desired durations.
+        let end = ((delay + duration) * clk) as u32 + 1;
+
+        // Determine the clock divider, which may be 1, 2, or 4. We will choose a clock divider that
+        // allows us the highest resolution per tick, so lower dividers are favored.
+        let div: u8 = if end < 0xFFDF {
+            1
+        } else if (end / 2) < 0xFFDF {
+            2
+        } else if (end / 4) < 0xFFDF {
+            3
+        } else {
+            panic!("Unattainable timing parameters!");
+        };
+
+        // The period register must be greater than or equal to 3 cycles.
+        let period = (end / (1 << (div - 1)) as u32) as u16;
+        assert!(period > 2);
+
+        let compare = (delay * clk) as u16;
+        assert!(compare >= 3);
+
+        Self {
+            div,
+            period,
+            compare,
+        }
+    }
+}
+
 /// The high resolution timer. Currently, only Timer E is supported.
 pub struct HighResTimerE {
     master: hal::stm32::HRTIM_MASTER,
@@ -60,27 +123,13 @@ impl HighResTimerE {
         // Disable the timer before configuration.
         self.master.mcr.modify(|_, w| w.tecen().clear_bit());
 
-        // Configure the desired timer for single shot mode with set and reset of the specified
-        // channel at the desired durations. The HRTIM is on APB2 (D2 domain), and the kernel clock
-        // is the APB bus clock.
+        // The HRTIM is on APB2 (D2 domain), and the kernel clock is the APB bus clock.
         let clk = self.clocks.timy_ker_ck().to_Hz() as f32;
-        let end = ((delay + duration) * clk) as u32 + 1;
-
-        // Determine the clock divider, which may be 1, 2, or 4. We will choose a clock divider that
-        // allows us the highest resolution per tick, so lower dividers are favored.
-        let div: u8 = if end < 0xFFDF {
-            1
-        } else if (end / 2) < 0xFFDF {
-            2
-        } else if (end / 4) < 0xFFDF {
-            3
-        } else {
-            panic!("Unattainable timing parameters!");
-        };
-
-        // The period register must be greater than or equal to 3 cycles.
-        let period = (end / (1 << (div - 1)) as u32) as u16;
-        assert!(period > 2);
+        let SingleShot {
+            div,
+            period,
+            compare,
+        } = SingleShot::new(clk, delay, duration);
 
         // We now have the prescaler and the period registers. Configure the timer.
         // Note(unsafe): The prescaler is guaranteed to be greater than or equal to 4 (minimum
@@ -95,13 +144,11 @@ impl HighResTimerE {
         self.timer.perer.write(|w| unsafe { w.perx().bits(period) });
 
         // Configure the comparator 1 level.
-        let delay = (delay * clk) as u16;
         // Note(unsafe): The offset is always a 16-bit value, so is always valid for values >= 3, as
         // specified by the datasheet.
-        assert!(delay >= 3);
         self.timer
             .cmp1er
-            .write(|w| unsafe { w.cmp1x().bits(delay) });
+            .write(|w| unsafe { w.cmp1x().bits(compare) });
 
         // Configure the set/reset signals.
         // Set on compare with CMP1, reset upon reaching PER
@@ -122,9 +169,61 @@ impl HighResTimerE {
         self.master.mcr.modify(|_, w| w.tecen().set_bit());
     }
 
+    /// Configure the polarity of an output channel.
+    ///
+    /// # Note
+    /// The output idles at its inactive level, which follows the polarity. The polarity can only
+    /// be changed while the output is disabled, so the output is briefly disabled and any pulse in
+    /// progress is cut short.
+    ///
+    /// # Args
+    /// * `channel` - The timer output channel to configure.
+    /// * `polarity` - The output polarity.
+    pub fn set_polarity(&mut self, channel: Channel, polarity: Polarity) {
+        let inverted = polarity == Polarity::ActiveLow;
+        match channel {
+            Channel::One => {
+                self.common.disr.write(|w| w.te1odis().set_bit());
+                self.timer
+                    .outer
+                    .modify(|_, w| w.pol1().bit(inverted).idles1().clear_bit());
+                self.common.oenr.write(|w| w.te1oen().set_bit());
+            }
+            Channel::Two => {
+                self.common.disr.write(|w| w.te2odis().set_bit());
+                self.timer
+                    .outer
+                    .modify(|_, w| w.pol2().bit(inverted).idles2().clear_bit());
+                self.common.oenr.write(|w| w.te2oen().set_bit());
+            }
+        }
+    }
+
     /// Generate a single trigger of the timer to start the output pulse generation.
     pub fn trigger(&mut self) {
         // Generate a reset event to force the timer to start counting.
         self.common.cr2.write(|w| w.terst().set_bit());
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn single_shot_honors_pulse_width() {
+        let clk = 200e6;
+        let delay = 1.3e-6;
+
+        for duration in [20e-9, 50e-9, 1e-6, 100e-6] {
+            let pulse = SingleShot::new(clk, delay, duration);
+            assert_eq!(pulse.div, 1);
+            assert!(pulse.compare as f32 <= delay * clk);
+
+            // The pulse is never shortened and is widened by at most two ticks.
+            let width = (pulse.period - pulse.compare) as f32 / clk;
+            assert!(width >= duration);
+            assert!(width < duration + 2. / clk);
+        }
+    }
+}
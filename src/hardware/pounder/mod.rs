@@ -1,20 +1,210 @@
 use self::attenuators::AttenuatorInterface;
 
 use super::hal;
-use crate::hardware::{shared_adc::AdcChannel, I2c1Proxy};
-use embedded_hal::blocking::spi::Transfer;
+use crate::hardware::{
+    delay::AsmDelay, design_parameters, shared_adc::AdcChannel, I2c1Proxy,
+};
+use cortex_m::peripheral::DWT;
+use embedded_hal::{
+    blocking::{delay::DelayUs, i2c::WriteRead},
+    digital::v2::OutputPin,
+};
 use enum_iterator::Sequence;
 use serde::{Deserialize, Serialize};
 
+pub mod attenuator_scan;
 pub mod attenuators;
 pub mod dds_output;
 pub mod hrtimer;
+pub mod profile_stream;
 pub mod rf_power;
 
 #[cfg(not(feature = "pounder_v1_0"))]
 pub mod timestamp;
 
-#[derive(Debug, Copy, Clone, Sequence)]
+/// The I2C address of the MCP23017 GPIO expander on Pounder.
+const MCP23017_ADDRESS: u8 = 0x20;
+
+/// The default width of the attenuator reset pulse in microseconds.
+///
+/// The HMC542B attenuator datasheet specifies minimum control pulse widths in the order of tens
+/// of nanoseconds. The default is this minimum rounded up to the microsecond resolution of the
+/// pulse width.
+pub const DEFAULT_RESET_PULSE_US: u16 = 1;
+
+/// The default width of the attenuator latch enable pulse in microseconds.
+///
+/// This is the HMC542B minimum latch enable pulse width, rounded up to microseconds.
+pub const DEFAULT_LATCH_PULSE_US: u16 = 1;
+
+/// The default timeout of an attenuator SPI transfer in microseconds.
+///
+/// A transfer of the four attenuator registers takes less than 10 us at the 5 MHz SPI clock, so
+/// this leaves ample margin.
+pub const DEFAULT_SPI_TIMEOUT_US: u32 = 1_000;
+
+/// The default number of retries of a failed GPIO expander access.
+///
+/// A single NAK on the shared I2C bus, e.g. due to noise, is recovered by the first retry. A
+/// failure that persists over all retries is reported.
+pub const DEFAULT_I2C_RETRIES: u8 = 3;
+
+/// The default delay before the first retry of a failed GPIO expander access in microseconds.
+///
+/// This is longer than a GPIO expander transaction at the 400 kHz I2C clock, which lets the bus
+/// settle.
+pub const DEFAULT_I2C_BACKOFF_US: u16 = 100;
+
+/// The number of consecutive failed checks after which the external DDS reference clock is
+/// considered lost.
+pub const CLOCK_LOSS_CHECKS: u8 = 3;
+
+/// The smallest non-zero DDS amplitude relative to full-scale (one LSB of the 10-bit scale).
+const MIN_AMPLITUDE: f32 = 1. / 1024.;
+
+/// The calibration of the pounder RF output power.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct PowerCalibration {
+    /// The output power of each channel in dBm at full-scale DDS amplitude and without
+    /// attenuation, indexed by [Channel]. Defaults to an uncalibrated 0 dBm.
+    pub full_scale_dbm: [f32; 4],
+}
+
+/// The maximum number of points of the output flatness calibration.
+pub const FLATNESS_POINTS: usize = 8;
+
+/// A point of the output flatness calibration.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct FlatnessPoint {
+    /// The output frequency in Hz.
+    pub frequency: f32,
+
+    /// The output power at this frequency relative to the calibrated full-scale power in dB.
+    pub correction_db: f32,
+}
+
+/// The calibration of the output power versus the DDS output frequency.
+///
+/// Note:
+/// The DDS output power rolls off with frequency due to the sinc response of the DAC and the
+/// analog filters. The calibration is shared by all channels. Without points, the output power
+/// is assumed to be flat.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FlatnessCalibration {
+    points: heapless::Vec<FlatnessPoint, FLATNESS_POINTS>,
+}
+
+impl FlatnessCalibration {
+    /// Construct the calibration from a table.
+    ///
+    /// Args:
+    /// * `points` - The calibration points, in order of strictly increasing frequency.
+    ///
+    /// Returns:
+    /// The calibration, or [Error::Bounds] if there are too many points or they are not finite
+    /// and ordered.
+    pub fn new(points: &[FlatnessPoint]) -> Result<Self, Error> {
+        let finite = points.iter().all(|point| {
+            point.frequency.is_finite() && point.correction_db.is_finite()
+        });
+        let ordered = points
+            .windows(2)
+            .all(|pair| pair[0].frequency < pair[1].frequency);
+        if !finite || !ordered {
+            return Err(Error::Bounds);
+        }
+
+        Ok(Self {
+            points: heapless::Vec::from_slice(points).or(Err(Error::Bounds))?,
+        })
+    }
+
+    /// Get the calibration points.
+    pub fn points(&self) -> &[FlatnessPoint] {
+        &self.points
+    }
+
+    /// Add a calibration point, replacing any point at the same frequency.
+    ///
+    /// Args:
+    /// * `point` - The calibration point.
+    ///
+    /// Returns:
+    /// [Error::Bounds] if the point is not finite or the calibration is full.
+    pub fn insert(&mut self, point: FlatnessPoint) -> Result<(), Error> {
+        if !point.frequency.is_finite() || !point.correction_db.is_finite() {
+            return Err(Error::Bounds);
+        }
+
+        let index = self
+            .points
+            .iter()
+            .position(|existing| existing.frequency >= point.frequency)
+            .unwrap_or(self.points.len());
+        match self.points.get_mut(index) {
+            Some(existing) if existing.frequency == point.frequency => {
+                *existing = point
+            }
+            _ => self.points.insert(index, point).or(Err(Error::Bounds))?,
+        }
+
+        Ok(())
+    }
+
+    /// Get the power correction at an output frequency.
+    ///
+    /// Note:
+    /// The correction is interpolated linearly between the calibration points and clamped to the
+    /// correction of the first or last point outside of the calibrated range.
+    ///
+    /// Args:
+    /// * `frequency` - The output frequency in Hz.
+    ///
+    /// Returns:
+    /// The output power relative to the calibrated full-scale power in dB.
+    pub fn correction_db(&self, frequency: f32) -> f32 {
+        let (Some(first), Some(last)) =
+            (self.points.first(), self.points.last())
+        else {
+            return 0.;
+        };
+
+        if frequency <= first.frequency {
+            return first.correction_db;
+        }
+
+        self.points
+            .windows(2)
+            .find(|pair| frequency < pair[1].frequency)
+            .map_or(last.correction_db, |pair| {
+                let fraction = (frequency - pair[0].frequency)
+                    / (pair[1].frequency - pair[0].frequency);
+                pair[0].correction_db
+                    + fraction * (pair[1].correction_db - pair[0].correction_db)
+            })
+    }
+}
+
+/// Detect if Pounder is connected to Stabilizer.
+///
+/// # Note
+/// Pounder is detected by probing for its MCP23017 GPIO expander. If the expander acknowledges a
+/// read of its IODIRA register, Pounder is considered present. This does not require any of the
+/// other Pounder peripherals (QSPI, SPI) to be configured, so probing an absent Pounder fails
+/// without stalling.
+///
+/// # Args
+/// * `i2c` - The I2C bus connected to the Pounder EEM connector.
+///
+/// # Returns
+/// True if Pounder was detected.
+pub fn detect<I2C: WriteRead>(i2c: &mut I2C) -> bool {
+    let mut iodira = [0u8; 1];
+    i2c.write_read(MCP23017_ADDRESS, &[0x00], &mut iodira)
+        .is_ok()
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Sequence)]
 pub enum GpioPin {
     Led4Green,
     Led5Red,
@@ -51,8 +241,51 @@ impl From<GpioPin> for mcp230xx::Mcp23017 {
     }
 }
 
+/// The stages of the pounder initialization.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum InitStage {
+    /// Driving the GPIO expander port A outputs (LEDs) to their default level.
+    GpioA,
+
+    /// Driving the GPIO expander port B outputs (attenuator control) to their default level.
+    GpioB,
+
+    /// Configuring the GPIO expander pins as outputs.
+    PinMode,
+
+    /// Selecting and enabling the on-board reference oscillator.
+    ClockSelect,
+
+    /// Resetting the attenuators.
+    AttenuatorReset,
+
+    /// Resetting and configuring the DDS.
+    Dds,
+}
+
+impl GpioPin {
+    /// Get the initialization stage configuring the level of the pin.
+    fn init_stage(&self) -> InitStage {
+        match self {
+            GpioPin::OscEnN | GpioPin::ExtClkSel => InitStage::ClockSelect,
+            GpioPin::Led4Green
+            | GpioPin::Led5Red
+            | GpioPin::Led6Green
+            | GpioPin::Led7Red
+            | GpioPin::Led8Green
+            | GpioPin::Led9Red => InitStage::GpioA,
+            GpioPin::AttLe0
+            | GpioPin::AttLe1
+            | GpioPin::AttLe2
+            | GpioPin::AttLe3
+            | GpioPin::AttRstN => InitStage::GpioB,
+        }
+    }
+}
+
 #[derive(Debug, Copy, Clone)]
 pub enum Error {
+    Init(InitStage),
     Spi,
     I2c,
     Qspi(hal::xspi::QspiError),
@@ -61,6 +294,77 @@ pub enum Error {
     InvalidChannel,
     Adc,
     InvalidState,
+    Timeout,
+    ClockNotConfigured,
+    ClockAbsent,
+}
+
+impl core::fmt::Display for Error {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Error::Init(stage) => write!(f, "initialization failed: {stage:?}"),
+            Error::Spi => write!(f, "SPI transfer failed"),
+            Error::I2c => write!(f, "I2C transfer failed"),
+            Error::Qspi(e) => write!(f, "QSPI transfer failed: {e:?}"),
+            Error::Bounds => write!(f, "value out of bounds"),
+            Error::InvalidAddress => write!(f, "invalid DDS register address"),
+            Error::InvalidChannel => write!(f, "invalid channel"),
+            Error::Adc => write!(f, "ADC conversion failed"),
+            Error::InvalidState => write!(f, "invalid interface state"),
+            Error::Timeout => write!(f, "transfer timed out"),
+            Error::ClockNotConfigured => {
+                write!(f, "DDS system clock not configured")
+            }
+            Error::ClockAbsent => {
+                write!(f, "external reference clock not present")
+            }
+        }
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for Error {
+    fn format(&self, f: defmt::Formatter) {
+        match self {
+            Error::Init(stage) => match stage {
+                InitStage::GpioA => {
+                    defmt::write!(f, "initialization failed: GpioA")
+                }
+                InitStage::GpioB => {
+                    defmt::write!(f, "initialization failed: GpioB")
+                }
+                InitStage::PinMode => {
+                    defmt::write!(f, "initialization failed: PinMode")
+                }
+                InitStage::ClockSelect => {
+                    defmt::write!(f, "initialization failed: ClockSelect")
+                }
+                InitStage::AttenuatorReset => {
+                    defmt::write!(f, "initialization failed: AttenuatorReset")
+                }
+                InitStage::Dds => {
+                    defmt::write!(f, "initialization failed: Dds")
+                }
+            },
+            Error::Spi => defmt::write!(f, "SPI transfer failed"),
+            Error::I2c => defmt::write!(f, "I2C transfer failed"),
+            Error::Qspi(_) => defmt::write!(f, "QSPI transfer failed"),
+            Error::Bounds => defmt::write!(f, "value out of bounds"),
+            Error::InvalidAddress => {
+                defmt::write!(f, "invalid DDS register address")
+            }
+            Error::InvalidChannel => defmt::write!(f, "invalid channel"),
+            Error::Adc => defmt::write!(f, "ADC conversion failed"),
+            Error::InvalidState => defmt::write!(f, "invalid interface state"),
+            Error::Timeout => defmt::write!(f, "transfer timed out"),
+            Error::ClockNotConfigured => {
+                defmt::write!(f, "DDS system clock not configured")
+            }
+            Error::ClockAbsent => {
+                defmt::write!(f, "external reference clock not present")
+            }
+        }
+    }
 }
 
 impl From<hal::xspi::QspiError> for Error {
@@ -71,7 +375,7 @@ impl From<hal::xspi::QspiError> for Error {
 
 /// The numerical value (discriminant) of the Channel enum is the index in the attenuator shift
 /// register as well as the attenuator latch enable signal index on the GPIO extender.
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Sequence)]
 #[allow(dead_code)]
 pub enum Channel {
     In0 = 0,
@@ -125,6 +429,291 @@ pub struct DdsClockConfig {
     pub external_clock: bool,
 }
 
+/// Debounces periodic presence checks of the external DDS reference clock.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct ClockMonitor {
+    misses: u8,
+}
+
+impl ClockMonitor {
+    /// Record the result of a presence check.
+    ///
+    /// Args:
+    /// * `present` - Whether the reference was detected.
+    ///
+    /// Returns:
+    /// True if the reference is considered lost, i.e. it was not detected in the last
+    /// [CLOCK_LOSS_CHECKS] checks.
+    pub fn update(&mut self, present: bool) -> bool {
+        self.misses = if present {
+            0
+        } else {
+            self.misses.saturating_add(1)
+        };
+        self.misses >= CLOCK_LOSS_CHECKS
+    }
+
+    /// Forget all previous checks.
+    pub fn reset(&mut self) {
+        self.misses = 0;
+    }
+}
+
+/// The state of the DDS reference clock selection.
+#[derive(Copy, Clone, Debug)]
+pub struct ClockState {
+    config: DdsClockConfig,
+    monitor: ClockMonitor,
+    fault: bool,
+}
+
+impl Default for ClockState {
+    /// The on-board reference with the default DDS system clock.
+    fn default() -> Self {
+        Self {
+            config: DdsClockConfig {
+                multiplier: design_parameters::DDS_MULTIPLIER,
+                reference_clock: design_parameters::DDS_REF_CLK.to_Hz() as f32,
+                external_clock: false,
+            },
+            monitor: ClockMonitor::default(),
+            fault: false,
+        }
+    }
+}
+
+/// Provide an interface for selecting the DDS reference clock on Pounder hardware.
+pub trait ClockInterface {
+    /// Select external reference clock input.
+    fn set_ext_clk(&mut self, enabled: bool) -> Result<(), Error> {
+        self.select_reference_input(enabled)?;
+        self.clock_state_mut().config.external_clock = enabled;
+        Ok(())
+    }
+
+    /// Select the on-board reference oscillator and restore the default DDS system clock.
+    ///
+    /// Args:
+    /// * `dds` - The DDS output to reconfigure for the on-board reference.
+    ///
+    /// Returns:
+    /// The resulting DDS system clock frequency in Hz.
+    fn select_onboard_clock<Q, T, P>(
+        &mut self,
+        dds: &mut dds_output::DdsOutput<Q, T, P>,
+    ) -> Result<f32, Error>
+    where
+        Q: dds_output::ProfileStream,
+        T: dds_output::IoUpdateTimer,
+        P: OutputPin,
+        P::Error: core::fmt::Debug,
+    {
+        self.select_clock(
+            dds,
+            false,
+            || true,
+            design_parameters::DDS_REF_CLK.to_Hz() as f32,
+            design_parameters::DDS_MULTIPLIER,
+        )
+    }
+
+    /// Select the external reference clock input and derive the DDS system clock from it.
+    ///
+    /// Note:
+    /// The configuration is checked against [design_parameters::DDS_EXT_REF_CLK_LIMITS] and
+    /// [design_parameters::DDS_PLL_SYSTEM_CLK_LIMITS] before any hardware is touched. The
+    /// reference input is then selected and the presence of the reference is verified before the
+    /// DDS system clock is programmed for it. If the reference is absent, the previous reference
+    /// is restored and [Error::ClockAbsent] is returned. The frequency of the external reference
+    /// can not be verified, the caller is responsible for providing the correct frequency.
+    ///
+    /// Args:
+    /// * `dds` - The DDS output to reconfigure for the external reference.
+    /// * `frequency` - The frequency of the external reference clock in Hz.
+    /// * `multiplier` - The PLL multiplier of the system clock. Must be 1 (PLL bypassed) or 4-20.
+    /// * `reference_present` - Detects the external reference once its input is selected. The
+    ///   DDS reference has no dedicated detector, so this is provided by the caller.
+    ///
+    /// Returns:
+    /// The resulting DDS system clock frequency in Hz.
+    fn select_external_clock<Q, T, P>(
+        &mut self,
+        dds: &mut dds_output::DdsOutput<Q, T, P>,
+        frequency: f32,
+        multiplier: u8,
+        reference_present: impl FnOnce() -> bool,
+    ) -> Result<f32, Error>
+    where
+        Q: dds_output::ProfileStream,
+        T: dds_output::IoUpdateTimer,
+        P: OutputPin,
+        P::Error: core::fmt::Debug,
+    {
+        validate_external_clock(frequency, multiplier)?;
+        self.select_clock(dds, true, reference_present, frequency, multiplier)
+    }
+
+    /// Select a reference clock input and program the DDS system clock for it, see
+    /// [ClockInterface::select_external_clock()].
+    fn select_clock<Q, T, P>(
+        &mut self,
+        dds: &mut dds_output::DdsOutput<Q, T, P>,
+        external: bool,
+        reference_present: impl FnOnce() -> bool,
+        reference_clock: f32,
+        multiplier: u8,
+    ) -> Result<f32, Error>
+    where
+        Q: dds_output::ProfileStream,
+        T: dds_output::IoUpdateTimer,
+        P: OutputPin,
+        P::Error: core::fmt::Debug,
+    {
+        let previous = self.clock_state().config.external_clock;
+
+        // The reference is switched first, so the DDS PLL is only programmed for a reference that
+        // is known to be present.
+        self.set_ext_clk(external)?;
+        if !reference_present() {
+            self.set_ext_clk(previous)?;
+            return Err(Error::ClockAbsent);
+        }
+
+        // The DDS validates the configuration before writing it, so the previous reference is
+        // still configured if this fails.
+        let system_clock =
+            match dds.set_system_clock(reference_clock, multiplier) {
+                Ok(system_clock) => system_clock,
+                Err(err) => {
+                    self.set_ext_clk(previous)?;
+                    return Err(err);
+                }
+            };
+
+        let config = &mut self.clock_state_mut().config;
+        config.reference_clock = reference_clock;
+        config.multiplier = multiplier;
+        Ok(system_clock)
+    }
+
+    /// Get the current DDS reference clock configuration.
+    ///
+    /// Returns:
+    /// The clock configuration, indicating whether the external reference clock input is
+    /// selected and the reference frequency the DDS was configured for.
+    fn clock_config(&self) -> DdsClockConfig {
+        self.clock_state().config
+    }
+
+    /// Check the health of the external DDS reference clock.
+    ///
+    /// Note:
+    /// The AD9959 provides no readable PLL lock status and the DDS reference is not routed to a
+    /// timer input while Pounder is present, so the presence of the reference must be determined
+    /// by the caller, e.g. by an external detector or the validity of the beat note. This is
+    /// intended to be called periodically.
+    ///
+    /// If the reference is absent for [CLOCK_LOSS_CHECKS] consecutive checks while the external
+    /// clock is selected, the on-board clock is selected and a clock fault is flagged, see
+    /// [ClockInterface::clock_fault()]. The DDS channels are then retuned to the frequencies they
+    /// had before. A channel whose frequency can not be generated from the on-board clock is
+    /// muted. Checks have no effect while the on-board clock is selected.
+    ///
+    /// Args:
+    /// * `dds` - The DDS output generating the channels.
+    /// * `reference_present` - Whether the external reference was detected since the last check.
+    ///
+    /// Returns:
+    /// True if the reference was lost and the on-board clock was selected.
+    fn check_clock<Q, T, P>(
+        &mut self,
+        dds: &mut dds_output::DdsOutput<Q, T, P>,
+        reference_present: bool,
+    ) -> Result<bool, Error>
+    where
+        Q: dds_output::ProfileStream,
+        T: dds_output::IoUpdateTimer,
+        P: OutputPin,
+        P::Error: core::fmt::Debug,
+    {
+        let state = self.clock_state_mut();
+        if !state.config.external_clock {
+            state.monitor.reset();
+            return Ok(false);
+        }
+
+        if !state.monitor.update(reference_present) {
+            return Ok(false);
+        }
+
+        log::warn!("DDS reference clock lost, selecting on-board clock");
+        state.monitor.reset();
+        state.fault = true;
+
+        let mut frequencies = [0.; 4];
+        for channel in enum_iterator::all::<Channel>() {
+            frequencies[channel as usize] = dds.frequency(channel);
+        }
+
+        self.select_onboard_clock(dds)?;
+
+        for channel in enum_iterator::all::<Channel>() {
+            let frequency = frequencies[channel as usize];
+            if dds.set_frequency(channel, frequency).is_err() {
+                log::warn!("Muting {channel:?}, {frequency} Hz out of range");
+                dds.set_amplitude(channel, 0.)?;
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Check whether the external DDS reference clock was lost.
+    ///
+    /// Returns:
+    /// True if [ClockInterface::check_clock()] fell back to the on-board clock since the fault
+    /// was last cleared.
+    fn clock_fault(&self) -> bool {
+        self.clock_state().fault
+    }
+
+    /// Clear the clock fault, e.g. once the external reference was restored.
+    fn clear_clock_fault(&mut self) {
+        self.clock_state_mut().fault = false;
+    }
+
+    /// Drive the reference clock selection of the hardware.
+    ///
+    /// Args:
+    /// * `external` - Whether to select the external reference clock input instead of the
+    ///   on-board oscillator.
+    fn select_reference_input(&mut self, external: bool) -> Result<(), Error>;
+
+    fn clock_state(&self) -> &ClockState;
+
+    fn clock_state_mut(&mut self) -> &mut ClockState;
+}
+
+/// A snapshot of the Pounder RF state for status reporting.
+#[derive(Serialize, Copy, Clone, Debug)]
+pub struct PounderStatus {
+    /// The attenuation of each channel in dB, indexed by [Channel].
+    pub attenuation: [f32; 4],
+
+    /// The DDS output frequency of each channel in Hz, indexed by [Channel].
+    pub frequency: [f32; 4],
+
+    /// The DDS reference clock configuration.
+    pub clock: DdsClockConfig,
+
+    /// Whether the external reference clock was lost, see [ClockInterface::check_clock()].
+    pub clock_fault: bool,
+
+    /// The estimated output power of each channel in dBm, indexed by [Channel]. See
+    /// [PounderDevices::dds_power_dbm()].
+    pub power_dbm: [f32; 4],
+}
+
 impl From<Channel> for ad9959::Channel {
     /// Translate pounder channels to DDS output channels.
     fn from(other: Channel) -> Self {
@@ -138,6 +727,15 @@ impl From<Channel> for ad9959::Channel {
 }
 
 /// A structure for the QSPI interface for the DDS.
+///
+/// Note:
+/// The QSPI peripheral always transfers four bits per clock cycle. The interface mode mirrors the
+/// serial mode configured in the CSR of the AD9959 and only selects how data is encoded. In
+/// single-bit-two-wire mode, each payload bit is spread across a four-bit QSPI cycle, so a write
+/// takes four times as many QSPI cycles as in four-bit-serial mode. The AD9959 only changes its
+/// serial mode after a CSR write and an IO_Update, which this interface does not control. The DDS
+/// is thus switched to four-bit-serial mode once during initialization and all later writes use
+/// the fast encoding.
 pub struct QspiInterface {
     pub qspi: hal::xspi::Qspi<hal::stm32::QUADSPI>,
     mode: ad9959::Mode,
@@ -182,6 +780,61 @@ impl QspiInterface {
 
         Ok(())
     }
+
+    /// Stop the stream started by [QspiInterface::start_stream()].
+    ///
+    /// Note:
+    /// The data pending in the FIFO is sent before the transaction is aborted. Register accesses
+    /// are possible again afterwards. This does nothing if the stream is not running.
+    pub fn stop_stream(&mut self) {
+        if !self.streaming {
+            return;
+        }
+
+        // Note(unsafe): We own the QSPI interface and the stream does not use the HAL driver.
+        let qspi_regs = unsafe { &*hal::stm32::QUADSPI::ptr() };
+
+        // Wait for the FIFO to drain. The last word is then still being shifted out, which takes
+        // eight QSPI clock cycles in four-bit mode.
+        while qspi_regs.sr.read().flevel().bits() != 0 {}
+        cortex_m::asm::delay(
+            8 * design_parameters::SYSCLK.to_Hz()
+                / design_parameters::POUNDER_QSPI_FREQUENCY.to_Hz(),
+        );
+
+        qspi_regs.cr.modify(|_, w| w.abort().set_bit());
+        while qspi_regs.cr.read().abort().bit_is_set() {}
+        qspi_regs.fcr.modify(|_, w| w.ctcf().set_bit());
+
+        self.streaming = false;
+    }
+}
+
+impl dds_output::ProfileStream for QspiInterface {
+    fn start_stream(&mut self) -> Result<(), Error> {
+        self.start_stream()
+    }
+
+    fn stop_stream(&mut self) {
+        self.stop_stream()
+    }
+
+    /// Write a profile into the QSPI FIFO.
+    fn write_profile(&mut self, profile: &[u32]) {
+        // Note(unsafe): We own the QSPI interface, so it is safe to access the registers in a raw
+        // fashion.
+        let regs = unsafe { &*hal::stm32::QUADSPI::ptr() };
+
+        // Warn if the fifo is still at least half full.
+        if regs.sr.read().flevel().bits() >= 16 {
+            log::warn!("QSPI stalling")
+        }
+
+        for word in profile.iter() {
+            // Note(unsafe): any bit pattern is valid for a TX FIFO write.
+            regs.dr.write(|w| unsafe { w.bits(*word) });
+        }
+    }
 }
 
 impl ad9959::Interface for QspiInterface {
@@ -295,8 +948,9 @@ impl ad9959::Interface for QspiInterface {
             return Err(Error::InvalidAddress);
         }
 
-        // This implementation only supports operation (read) in four-bit-serial mode.
-        if self.mode != ad9959::Mode::FourBitSerial {
+        // This implementation only supports operation (read) in four-bit-serial mode. An
+        // indirect read would reconfigure the QSPI peripheral and break a running stream.
+        if self.mode != ad9959::Mode::FourBitSerial || self.streaming {
             return Err(Error::InvalidState);
         }
 
@@ -306,6 +960,63 @@ impl ad9959::Interface for QspiInterface {
     }
 }
 
+/// The retry policy of GPIO expander accesses.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct I2cRetry {
+    /// The number of retries after the first failed attempt.
+    pub retries: u8,
+
+    /// The delay before the first retry in microseconds. It doubles with every further retry.
+    pub backoff_us: u16,
+}
+
+impl Default for I2cRetry {
+    fn default() -> Self {
+        Self {
+            retries: DEFAULT_I2C_RETRIES,
+            backoff_us: DEFAULT_I2C_BACKOFF_US,
+        }
+    }
+}
+
+impl I2cRetry {
+    /// Perform an I2C operation, retrying it on failure.
+    ///
+    /// Args:
+    /// * `transient` - The count of transient failures, incremented if the operation succeeds
+    ///   after a retry.
+    /// * `delay` - A delay to wait for the backoff.
+    /// * `operation` - The I2C operation.
+    ///
+    /// Returns:
+    /// The result of the operation, or [Error::I2c] if all attempts failed.
+    fn run<T, E>(
+        &self,
+        transient: &mut u32,
+        delay: &mut impl DelayUs<u16>,
+        mut operation: impl FnMut() -> Result<T, E>,
+    ) -> Result<T, Error> {
+        let mut backoff_us = self.backoff_us;
+        for retry in 0..=self.retries {
+            if retry > 0 {
+                delay.delay_us(backoff_us);
+                backoff_us = backoff_us.saturating_mul(2);
+            }
+
+            if let Ok(value) = operation() {
+                if retry > 0 {
+                    log::debug!("I2C access succeeded after {retry} retries");
+                    *transient = transient.wrapping_add(1);
+                }
+                return Ok(value);
+            }
+        }
+
+        log::warn!("I2C access failed after {} retries", self.retries);
+        Err(Error::I2c)
+    }
+}
+
 /// A structure containing implementation for Pounder hardware.
 pub struct PounderDevices {
     mcp23017: mcp230xx::Mcp230xx<I2c1Proxy, mcp230xx::Mcp23017>,
@@ -331,6 +1042,41 @@ pub struct PounderDevices {
         hal::stm32::ADC3,
         hal::gpio::gpiof::PF4<hal::gpio::Analog>,
     >,
+    clock: ClockState,
+    reset_pulse_us: u16,
+    latch_pulse_us: u16,
+    delay: AsmDelay,
+    power_calibration: PowerCalibration,
+    flatness_calibration: FlatnessCalibration,
+    output_power_dbm: [Option<f32>; 4],
+    spi_timeout_us: u32,
+    dds_present: bool,
+    attenuator_scan: Option<attenuator_scan::AttenuatorScan>,
+    attenuator_transfer: Option<attenuators::AttenuatorTransfer>,
+    i2c_retry: I2cRetry,
+    transient_i2c_failures: u32,
+}
+
+/// Mute all DDS channels and then set all attenuators to maximum attenuation, see
+/// [PounderDevices::park()].
+///
+/// Args:
+/// * `attenuators` - The attenuators of the channels.
+/// * `dds` - The DDS output generating the channels.
+pub(crate) fn park_outputs<Q, T, P>(
+    attenuators: &mut impl AttenuatorInterface,
+    dds: &mut dds_output::DdsOutput<Q, T, P>,
+) -> Result<(), Error>
+where
+    Q: dds_output::ProfileStream,
+    T: dds_output::IoUpdateTimer,
+    P: OutputPin,
+    P::Error: core::fmt::Debug,
+{
+    let muted = enum_iterator::all::<Channel>()
+        .try_for_each(|channel| dds.set_amplitude(channel, 0.).map(|_| ()));
+    attenuators.write_all(attenuators::MAX_ATTENUATION)?;
+    muted
 }
 
 impl PounderDevices {
@@ -377,68 +1123,770 @@ impl PounderDevices {
             pwr1,
             aux_adc0,
             aux_adc1,
+            clock: ClockState::default(),
+            reset_pulse_us: DEFAULT_RESET_PULSE_US,
+            latch_pulse_us: DEFAULT_LATCH_PULSE_US,
+            delay: AsmDelay::new(design_parameters::SYSCLK.to_Hz()),
+            power_calibration: PowerCalibration::default(),
+            flatness_calibration: FlatnessCalibration::default(),
+            output_power_dbm: [None; 4],
+            spi_timeout_us: DEFAULT_SPI_TIMEOUT_US,
+            dds_present: false,
+            attenuator_scan: None,
+            attenuator_transfer: None,
+            i2c_retry: I2cRetry::default(),
+            transient_i2c_failures: 0,
         };
 
-        // Configure power-on-default state for pounder. All LEDs are off, on-board oscillator
-        // selected and enabled, attenuators out of reset. Note that testing indicates the
-        // output state needs to be set first to properly update the output registers.
-        for pin in enum_iterator::all::<GpioPin>() {
-            devices
-                .mcp23017
-                .set_gpio(pin.into(), mcp230xx::Level::Low)
-                .map_err(|_| Error::I2c)?;
-            devices
-                .mcp23017
-                .set_direction(pin.into(), mcp230xx::Direction::Output)
-                .map_err(|_| Error::I2c)?;
-        }
-        devices.reset_attenuators().unwrap();
+        devices.apply_defaults()?;
         Ok(devices)
     }
 
-    /// Sample one of the two auxiliary ADC channels associated with the respective RF input channel.
-    pub fn sample_aux_adc(&mut self, channel: Channel) -> Result<f32, Error> {
-        let adc_scale = match channel {
-            Channel::In0 => self.aux_adc0.read_normalized().unwrap(),
-            Channel::In1 => self.aux_adc1.read_normalized().unwrap(),
-            _ => return Err(Error::InvalidChannel),
-        };
-
-        // Convert analog percentage to voltage. Note that the ADC uses an external 2.048V analog
-        // reference.
-        Ok(adc_scale * 2.048)
-    }
-
-    /// Set the state (its electrical level) of the given GPIO pin on Pounder.
-    pub fn set_gpio_pin(
+    /// Reset the pounder mezzanine to its power-on default state and restore the clock source.
+    ///
+    /// Note:
+    /// All LEDs are turned off and the attenuators are reset. The previously selected reference
+    /// clock is then selected again and the DDS is reset and initialized for it, see
+    /// [dds_output::DdsOutput::reset()]. This is idempotent and may be called repeatedly.
+    ///
+    /// Args:
+    /// * `dds` - The DDS output to reset.
+    pub fn reset(
         &mut self,
-        pin: GpioPin,
-        level: mcp230xx::Level,
+        dds: &mut dds_output::DdsOutput,
     ) -> Result<(), Error> {
-        self.mcp23017
-            .set_gpio(pin.into(), level)
-            .map_err(|_| Error::I2c)
+        self.apply_defaults()?;
+        let config = self.clock.config;
+        self.set_ext_clk(config.external_clock)
+            .map_err(|_| Error::Init(InitStage::ClockSelect))?;
+        dds.reset(&mut self.delay, config.reference_clock, config.multiplier)?;
+        Ok(())
     }
 
-    /// Select external reference clock input.
-    pub fn set_ext_clk(&mut self, enabled: bool) -> Result<(), Error> {
-        let level = if enabled {
-            mcp230xx::Level::High
-        } else {
-            mcp230xx::Level::Low
-        };
-        // Active low
-        self.set_gpio_pin(GpioPin::OscEnN, level)?;
-        self.set_gpio_pin(GpioPin::ExtClkSel, level)
+    /// Put the RF outputs into a safe state.
+    ///
+    /// Note:
+    /// All DDS channels are muted first and all attenuators are then set to maximum attenuation,
+    /// so the output power never increases in between. The attenuators are set even if muting
+    /// fails. The time taken is bounded by the attenuator SPI timeout, so this may be called from
+    /// a watchdog or fault handler. The DDS amplitudes and attenuations must be restored
+    /// explicitly afterwards.
+    ///
+    /// Args:
+    /// * `dds` - The DDS output generating the channels.
+    pub fn park(
+        &mut self,
+        dds: &mut dds_output::DdsOutput,
+    ) -> Result<(), Error> {
+        park_outputs(self, dds)
     }
-}
+
+    /// Set the frequency, phase offset and amplitude of a DDS channel atomically.
+    ///
+    /// Note:
+    /// The settings are applied by a single IO_Update pulse, see
+    /// [dds_output::DdsOutput::set_channel()].
+    ///
+    /// Args:
+    /// * `dds` - The DDS output generating the channel.
+    /// * `channel` - The pounder channel to configure.
+    /// * `freq_hz` - The desired output frequency in Hz.
+    /// * `turns` - The desired phase offset in turns.
+    /// * `amp_scale` - The desired amplitude relative to full-scale, from 0 to 1.
+    ///
+    /// Returns:
+    /// The actual programmed state of the channel, or [Error::InvalidState] if the DDS failed its
+    /// self test during setup.
+    pub fn set_dds_channel(
+        &mut self,
+        dds: &mut dds_output::DdsOutput,
+        channel: Channel,
+        freq_hz: f32,
+        turns: f32,
+        amp_scale: f32,
+    ) -> Result<dds_output::DdsActual, Error> {
+        if !self.dds_present {
+            return Err(Error::InvalidState);
+        }
+
+        dds.set_channel(channel, freq_hz, turns, amp_scale)
+    }
+
+    /// Enable or disable the output of a DDS channel.
+    ///
+    /// Note:
+    /// A disabled channel powers down its DAC, see [dds_output::DdsOutput::enable_channel()].
+    /// The other channels are not disturbed.
+    ///
+    /// Args:
+    /// * `dds` - The DDS output generating the channel.
+    /// * `channel` - The pounder channel to configure.
+    /// * `enable` - Whether the channel output is enabled.
+    ///
+    /// Returns:
+    /// [Error::InvalidState] if the DDS failed its self test during setup.
+    pub fn enable_dds_channel(
+        &mut self,
+        dds: &mut dds_output::DdsOutput,
+        channel: Channel,
+        enable: bool,
+    ) -> Result<(), Error> {
+        if !self.dds_present {
+            return Err(Error::InvalidState);
+        }
+
+        dds.enable_channel(channel, enable);
+        Ok(())
+    }
+
+    fn apply_defaults(&mut self) -> Result<(), Error> {
+        // Configure power-on-default state for pounder. All LEDs are off, on-board oscillator
+        // selected and enabled, attenuators out of reset. Note that testing indicates the
+        // output state needs to be set first to properly update the output registers.
+        let retry = self.i2c_retry;
+        for pin in enum_iterator::all::<GpioPin>() {
+            let stage = pin.init_stage();
+            retry
+                .run(&mut self.transient_i2c_failures, &mut self.delay, || {
+                    self.mcp23017.set_gpio(pin.into(), mcp230xx::Level::Low)
+                })
+                .map_err(|_| Error::Init(stage))?;
+
+            // The clock selection is only complete once its pins are driven.
+            let stage = match stage {
+                InitStage::ClockSelect => stage,
+                _ => InitStage::PinMode,
+            };
+            retry
+                .run(&mut self.transient_i2c_failures, &mut self.delay, || {
+                    self.mcp23017
+                        .set_direction(pin.into(), mcp230xx::Direction::Output)
+                })
+                .map_err(|_| Error::Init(stage))?;
+        }
+        self.reset_attenuators()
+            .map_err(|_| Error::Init(InitStage::AttenuatorReset))
+    }
+
+    /// Read the temperature of the Pounder mezzanine.
+    ///
+    /// Returns:
+    /// The temperature measured by the on-board sensor in degrees Celsius.
+    pub fn temperature(&mut self) -> Result<f32, Error> {
+        self.lm75.read_temperature().map_err(|_| Error::I2c)
+    }
+
+    /// Sample one of the two auxiliary ADC channels associated with the respective RF input channel.
+    pub fn sample_aux_adc(&mut self, channel: Channel) -> Result<f32, Error> {
+        let adc_scale = match channel {
+            Channel::In0 => self.aux_adc0.read_normalized().unwrap(),
+            Channel::In1 => self.aux_adc1.read_normalized().unwrap(),
+            _ => return Err(Error::InvalidChannel),
+        };
+
+        // Convert analog percentage to voltage. Note that the ADC uses an external 2.048V analog
+        // reference.
+        Ok(adc_scale * 2.048)
+    }
+
+    /// Set the state (its electrical level) of the given GPIO pin on Pounder.
+    ///
+    /// Note:
+    /// A failed access is retried, see [PounderDevices::set_i2c_retry()].
+    pub fn set_gpio_pin(
+        &mut self,
+        pin: GpioPin,
+        level: mcp230xx::Level,
+    ) -> Result<(), Error> {
+        let mcp23017 = &mut self.mcp23017;
+        self.i2c_retry.run(
+            &mut self.transient_i2c_failures,
+            &mut self.delay,
+            || mcp23017.set_gpio(pin.into(), level),
+        )
+    }
+
+    /// Set the retry policy of GPIO expander accesses.
+    ///
+    /// Note:
+    /// Defaults to [DEFAULT_I2C_RETRIES] retries with a backoff of [DEFAULT_I2C_BACKOFF_US].
+    ///
+    /// Args:
+    /// * `retry` - The retry policy.
+    pub fn set_i2c_retry(&mut self, retry: I2cRetry) {
+        self.i2c_retry = retry;
+    }
+
+    /// Get the number of GPIO expander accesses that succeeded only after a retry.
+    ///
+    /// Note:
+    /// Persistent failures are not counted, they are reported as [Error::I2c].
+    pub fn transient_i2c_failures(&self) -> u32 {
+        self.transient_i2c_failures
+    }
+
+    /// Set the width of the attenuator reset pulse.
+    ///
+    /// Note:
+    /// The pulse width is a minimum. The pin is driven through the I2C GPIO expander, which adds
+    /// the duration of a bus transaction to the pulse. Defaults to [DEFAULT_RESET_PULSE_US].
+    ///
+    /// Args:
+    /// * `us` - The reset pulse width in microseconds.
+    pub fn set_reset_pulse_width(&mut self, us: u16) {
+        self.reset_pulse_us = us;
+    }
+
+    /// Set the width of the attenuator latch enable pulse.
+    ///
+    /// Note:
+    /// The pulse width is a minimum. The pin is driven through the I2C GPIO expander, which adds
+    /// the duration of a bus transaction to the pulse. Defaults to [DEFAULT_LATCH_PULSE_US].
+    ///
+    /// Args:
+    /// * `us` - The latch enable pulse width in microseconds.
+    pub fn set_latch_pulse_width(&mut self, us: u16) {
+        self.latch_pulse_us = us;
+    }
+
+    /// Check whether the DDS responded during bring-up.
+    ///
+    /// Note:
+    /// The DDS can only be read before it is frozen into the QSPI stream. Its presence is thus
+    /// determined once during setup by a self test, which writes the channel enables of the CSR
+    /// and reads them back.
+    ///
+    /// Returns:
+    /// True if the DDS self test succeeded.
+    pub fn dds_present(&self) -> bool {
+        self.dds_present
+    }
+
+    /// Record the result of the DDS self test during bring-up.
+    pub(crate) fn set_dds_present(&mut self, present: bool) {
+        self.dds_present = present;
+    }
+
+    /// Set the timeout of attenuator SPI transfers.
+    ///
+    /// Note:
+    /// Defaults to [DEFAULT_SPI_TIMEOUT_US].
+    ///
+    /// Args:
+    /// * `us` - The maximum duration of a transfer in microseconds.
+    pub fn set_spi_timeout(&mut self, us: u32) {
+        self.spi_timeout_us = us;
+    }
+
+    /// Enable monitoring of the attenuators by a DMA-driven scan.
+    ///
+    /// Note:
+    /// The staged attenuator codes are read once by a blocking transfer to seed the scan. Scans
+    /// are then performed by [PounderDevices::update_attenuator_scan()].
+    ///
+    /// Args:
+    /// * `rx_stream` - The DMA stream reading the attenuator codes.
+    /// * `tx_stream` - The DMA stream writing back the attenuator codes.
+    pub fn enable_attenuator_scan(
+        &mut self,
+        rx_stream: hal::dma::dma::Stream0<hal::stm32::DMA2>,
+        tx_stream: hal::dma::dma::Stream1<hal::stm32::DMA2>,
+    ) -> Result<(), Error> {
+        let codes = self.read_attenuator_codes()?;
+        let mut scan =
+            attenuator_scan::AttenuatorScan::new(rx_stream, tx_stream, codes);
+        scan.set_snapshot(codes);
+        self.attenuator_scan = Some(scan);
+        Ok(())
+    }
+
+    /// Advance the attenuator scan.
+    ///
+    /// Note:
+    /// This is intended to be called periodically from a low priority task. It never waits for
+    /// a DMA scan to complete. If the DMA scan failed, the snapshot is updated by a blocking read
+    /// instead, see [PounderDevices::attenuator_scan_failed()].
+    pub fn update_attenuator_scan(&mut self) -> Result<(), Error> {
+        let scan = self.attenuator_scan.as_mut().ok_or(Error::InvalidState)?;
+
+        // The SPI is in use by a non-blocking transfer.
+        if self.attenuator_transfer.is_some() {
+            return Ok(());
+        }
+
+        if !scan.failed() && scan.update(&mut self.attenuator_spi).is_ok() {
+            return Ok(());
+        }
+
+        let codes = self.read_attenuator_codes()?;
+        if let Some(scan) = self.attenuator_scan.as_mut() {
+            scan.set_snapshot(codes);
+        }
+        Ok(())
+    }
+
+    /// Get the attenuation of all channels from the latest attenuator scan without blocking.
+    ///
+    /// Returns:
+    /// The attenuation of each channel in dB, indexed by [Channel], or `None` if the scan is not
+    /// enabled.
+    pub fn attenuation_snapshot(&self) -> Option<[f32; 4]> {
+        let codes = self.attenuator_scan.as_ref()?.snapshot()?;
+        Some(codes.map(attenuators::decode))
+    }
+
+    /// Check whether the DMA attenuator scan failed and fell back to blocking reads.
+    pub fn attenuator_scan_failed(&self) -> bool {
+        self.attenuator_scan
+            .as_ref()
+            .map_or(false, |scan| scan.failed())
+    }
+
+    /// Set the attenuation of all channels without blocking on the SPI.
+    ///
+    /// Note:
+    /// The first call starts the transfer. It must be polled with the same attenuation until it
+    /// completes, otherwise [Error::InvalidState] is returned. The attenuators are latched once
+    /// the transfer completes, which accesses the GPIO expander by blocking I2C transfers.
+    ///
+    /// Args:
+    /// * `attenuation` - The desired attenuation of the channels in dB. This has a resolution of
+    ///   0.5dB.
+    ///
+    /// Returns:
+    /// The programmed attenuation of the channels in dB, or [nb::Error::WouldBlock] until the
+    /// transfer completes.
+    pub fn poll_write_all(
+        &mut self,
+        attenuation: f32,
+    ) -> nb::Result<f32, Error> {
+        let (code, quantized) = attenuators::encode(attenuation)?;
+        self.poll_attenuator_transfer(attenuators::TransferRequest::Write(
+            [code; 4],
+        ))?;
+
+        for channel in enum_iterator::all::<Channel>() {
+            self.latch_attenuator(channel)?;
+        }
+
+        Ok(quantized)
+    }
+
+    /// Get the attenuation of all channels without blocking on the SPI.
+    ///
+    /// Note:
+    /// The first call starts the transfer, which must be polled until it completes. A write
+    /// started by [PounderDevices::poll_write_all()] must complete first.
+    ///
+    /// Returns:
+    /// The programmed attenuation of each channel in dB, indexed by [Channel], or
+    /// [nb::Error::WouldBlock] until the transfer completes.
+    pub fn poll_read_all(&mut self) -> nb::Result<[f32; 4], Error> {
+        let codes =
+            self.poll_attenuator_transfer(attenuators::TransferRequest::Read)?;
+        Ok(codes.map(attenuators::decode))
+    }
+
+    /// Start or advance a non-blocking transfer of the attenuator shift registers.
+    ///
+    /// Note:
+    /// A DMA attenuator scan in progress is completed before the transfer starts.
+    ///
+    /// Args:
+    /// * `request` - The transfer to perform. It must match a transfer in progress.
+    ///
+    /// Returns:
+    /// The result of the transfer, see [attenuators::AttenuatorTransfer::poll()].
+    fn poll_attenuator_transfer(
+        &mut self,
+        request: attenuators::TransferRequest,
+    ) -> nb::Result<[u8; 4], Error> {
+        let spi = &mut self.attenuator_spi;
+
+        if self.attenuator_transfer.is_none() {
+            if let Some(scan) = self.attenuator_scan.as_mut() {
+                // A failed scan is aborted and has returned the SPI to blocking operation.
+                if let Err(nb::Error::WouldBlock) = scan.poll(spi) {
+                    return Err(nb::Error::WouldBlock);
+                }
+            }
+            self.attenuator_transfer =
+                Some(attenuators::AttenuatorTransfer::new(request));
+        }
+
+        // Note(unwrap): The transfer was started above if none was in progress.
+        let transfer = self.attenuator_transfer.as_mut().unwrap();
+        if transfer.request() != request {
+            return Err(nb::Error::Other(Error::InvalidState));
+        }
+
+        let result = transfer.poll(spi);
+        if let Err(nb::Error::WouldBlock) = result {
+            return result;
+        }
+
+        // Subsequent scans shift the codes back into the attenuators.
+        let staged = transfer.staged();
+        self.attenuator_transfer = None;
+        if let (Ok(_), Some(scan)) = (&result, self.attenuator_scan.as_mut()) {
+            scan.set_codes(staged);
+        }
+
+        result
+    }
+
+    /// Test the attenuator SPI chain by shifting a test pattern through the shift registers.
+    ///
+    /// Note:
+    /// The current codes are read, the inverted codes are shifted in and read back out, and the
+    /// original codes are restored. The attenuators are not latched, so the outputs are not
+    /// affected. If the test fails, the staged codes may differ from the outputs until the next
+    /// attenuation update.
+    ///
+    /// Returns:
+    /// Whether the pattern was shifted back out unmodified.
+    pub fn test_attenuators(&mut self) -> Result<bool, Error> {
+        let codes = self.read_attenuator_codes()?;
+        let pattern = codes.map(|code| !code);
+
+        let mut channels = pattern;
+        self.transfer_attenuators(&mut channels)?;
+        let shifted_in = channels == codes;
+
+        channels = codes;
+        self.transfer_attenuators(&mut channels)?;
+        Ok(shifted_in && channels == pattern)
+    }
+
+    /// Read the raw codes of the attenuator shift registers by blocking transfers.
+    fn read_attenuator_codes(&mut self) -> Result<[u8; 4], Error> {
+        let mut codes = [0; 4];
+
+        // Reading is destructive, see `AttenuatorInterface::get_attenuation()`.
+        self.transfer_attenuators(&mut codes)?;
+        self.transfer_attenuators(&mut codes)?;
+        Ok(codes)
+    }
+
+    /// Set the calibration of the output power.
+    ///
+    /// Args:
+    /// * `calibration` - The output power calibration, e.g. as read from EEPROM.
+    pub fn set_power_calibration(&mut self, calibration: PowerCalibration) {
+        self.power_calibration = calibration;
+    }
+
+    /// Get the calibration of the output power.
+    pub fn power_calibration(&self) -> PowerCalibration {
+        self.power_calibration
+    }
+
+    /// Set the calibration of the output power versus frequency.
+    ///
+    /// Note:
+    /// Output powers set by [PounderDevices::set_output_power_db()] are only corrected once they
+    /// are set again, e.g. by a frequency change.
+    ///
+    /// Args:
+    /// * `calibration` - The output flatness calibration, e.g. as read from EEPROM.
+    pub fn set_flatness_calibration(
+        &mut self,
+        calibration: FlatnessCalibration,
+    ) {
+        self.flatness_calibration = calibration;
+    }
+
+    /// Get the calibration of the output power versus frequency.
+    pub fn flatness_calibration(&self) -> &FlatnessCalibration {
+        &self.flatness_calibration
+    }
+
+    /// Set the output frequency of a DDS channel.
+    ///
+    /// Note:
+    /// If the output power of the channel was set by [PounderDevices::set_output_power_db()], it
+    /// is set again for the new frequency, so it stays flat according to the flatness
+    /// calibration.
+    ///
+    /// Args:
+    /// * `dds` - The DDS output generating the channel.
+    /// * `channel` - The pounder channel to configure.
+    /// * `freq_hz` - The desired output frequency in Hz.
+    ///
+    /// Returns:
+    /// The actual programmed frequency of the channel in Hz and a warning if the frequency is
+    /// prone to aliasing.
+    pub fn set_dds_frequency(
+        &mut self,
+        dds: &mut dds_output::DdsOutput,
+        channel: Channel,
+        freq_hz: f32,
+    ) -> Result<(f32, Option<dds_output::FrequencyWarning>), Error> {
+        let actual = dds.set_frequency(channel, freq_hz)?;
+
+        if let Some(power_dbm) = self.output_power_dbm[channel as usize] {
+            self.set_output_power_db(dds, channel, power_dbm)?;
+        }
+
+        Ok(actual)
+    }
+
+    /// Set the output power of a channel.
+    ///
+    /// Note:
+    /// The power is realized with the attenuator in coarse 0.5 dB steps and the DDS amplitude for
+    /// the remainder, which the DDS resolves much finer close to full-scale. The power is clamped
+    /// to the range achievable from the calibrated full-scale power, corrected for the current
+    /// output frequency of the channel by the flatness calibration.
+    ///
+    /// Args:
+    /// * `dds` - The DDS output generating the channel.
+    /// * `channel` - The pounder channel to configure.
+    /// * `power_dbm` - The desired output power in dBm.
+    ///
+    /// Returns:
+    /// The actual output power of the channel in dBm.
+    pub fn set_output_power_db(
+        &mut self,
+        dds: &mut dds_output::DdsOutput,
+        channel: Channel,
+        power_dbm: f32,
+    ) -> Result<f32, Error> {
+        if power_dbm.is_nan() {
+            return Err(Error::Bounds);
+        }
+
+        let full_scale = self.full_scale_dbm(dds, channel);
+        let (attenuation, amplitude) = allocate_power(full_scale - power_dbm);
+
+        let attenuation = self.set_attenuation(channel, attenuation)?;
+        let amplitude = dds.set_amplitude(channel, amplitude)?;
+        self.output_power_dbm[channel as usize] = Some(power_dbm);
+
+        Ok(power_dbm_at(full_scale, attenuation, amplitude))
+    }
+
+    /// Estimate the output power of a channel from its settings.
+    ///
+    /// Note:
+    /// This is computed from the DDS amplitude last programmed, the attenuation and the power
+    /// and flatness calibrations. It is not a measurement, so it is only as accurate as the
+    /// calibrations. A disabled channel or a zero amplitude is reported as negative infinity.
+    ///
+    /// Args:
+    /// * `dds` - The DDS output generating the channel.
+    /// * `channel` - The pounder channel to estimate the power of.
+    /// * `attenuation` - The attenuation of the channel in dB as read back from the attenuators,
+    ///   see [attenuators::AttenuatorInterface::attenuations()].
+    ///
+    /// Returns:
+    /// The estimated output power of the channel in dBm.
+    pub fn dds_power_dbm(
+        &self,
+        dds: &dds_output::DdsOutput,
+        channel: Channel,
+        attenuation: f32,
+    ) -> f32 {
+        if !dds.channel_enabled(channel) {
+            return f32::NEG_INFINITY;
+        }
+
+        power_dbm_at(
+            self.full_scale_dbm(dds, channel),
+            attenuation,
+            dds.amplitude(channel),
+        )
+    }
+
+    /// Get the calibrated full-scale output power of a channel at its current frequency.
+    ///
+    /// Args:
+    /// * `dds` - The DDS output generating the channel.
+    /// * `channel` - The pounder channel.
+    ///
+    /// Returns:
+    /// The output power in dBm at full-scale DDS amplitude and without attenuation.
+    fn full_scale_dbm(
+        &self,
+        dds: &dds_output::DdsOutput,
+        channel: Channel,
+    ) -> f32 {
+        self.power_calibration.full_scale_dbm[channel as usize]
+            + self
+                .flatness_calibration
+                .correction_db(dds.frequency(channel))
+    }
+}
+
+/// Compute the output power of a channel from its settings.
+///
+/// Args:
+/// * `full_scale_dbm` - The calibrated full-scale output power in dBm.
+/// * `attenuation` - The attenuation of the channel in dB.
+/// * `amplitude` - The DDS amplitude relative to full-scale.
+///
+/// Returns:
+/// The output power in dBm. A zero amplitude is negative infinity.
+fn power_dbm_at(full_scale_dbm: f32, attenuation: f32, amplitude: f32) -> f32 {
+    full_scale_dbm - attenuation + 20. * libm::log10f(amplitude)
+}
+
+/// Split a reduction of the output power into an attenuation and a DDS amplitude.
+///
+/// Args:
+/// * `reduction` - The desired reduction from full-scale power in dB. It is clamped to the
+///   achievable range.
+///
+/// Returns:
+/// The attenuation in dB and the DDS amplitude relative to full-scale.
+fn allocate_power(reduction: f32) -> (f32, f32) {
+    let max_reduction =
+        attenuators::MAX_ATTENUATION - 20. * libm::log10f(MIN_AMPLITUDE);
+    let reduction = reduction.clamp(0., max_reduction);
+
+    // Round the attenuation down to the attenuator resolution and leave the remainder to the DDS.
+    let attenuation =
+        ((reduction * 2.) as u8 as f32 / 2.).min(attenuators::MAX_ATTENUATION);
+    let amplitude = libm::powf(10., (attenuation - reduction) / 20.);
+
+    (attenuation, amplitude.clamp(MIN_AMPLITUDE, 1.))
+}
+
+/// Validate an external DDS reference clock configuration.
+///
+/// Args:
+/// * `frequency` - The frequency of the external reference clock in Hz.
+/// * `multiplier` - The PLL multiplier of the system clock.
+///
+/// Returns:
+/// The resulting DDS system clock frequency in Hz.
+fn validate_external_clock(
+    frequency: f32,
+    multiplier: u8,
+) -> Result<f32, Error> {
+    let (_, inputs) = design_parameters::DDS_EXT_REF_CLK_LIMITS
+        .iter()
+        .find(|(multipliers, _)| multipliers.contains(&multiplier))
+        .ok_or(Error::Bounds)?;
+    if !inputs.contains(&frequency) {
+        return Err(Error::Bounds);
+    }
+
+    let system_clock = frequency * multiplier as f32;
+    if multiplier != 1
+        && !design_parameters::DDS_PLL_SYSTEM_CLK_LIMITS
+            .iter()
+            .any(|limits| limits.contains(&system_clock))
+    {
+        return Err(Error::Bounds);
+    }
+
+    Ok(system_clock)
+}
+
+/// Poll a non-blocking operation until it completes or times out.
+///
+/// Args:
+/// * `start` - The cycle count at the start of the timeout.
+/// * `timeout_us` - The timeout in microseconds.
+/// * `cycle_count` - The cycle counter the time is measured with, usually the DWT cycle counter,
+///   which is enabled during setup.
+/// * `operation` - The operation to poll.
+///
+/// Returns:
+/// The result of the operation, or [Error::Timeout].
+fn poll_timeout<T>(
+    start: u32,
+    timeout_us: u32,
+    mut cycle_count: impl FnMut() -> u32,
+    mut operation: impl FnMut() -> nb::Result<T, Error>,
+) -> Result<T, Error> {
+    let cycles_per_us = design_parameters::SYSCLK.to_Hz() / 1_000_000;
+    let timeout = timeout_us.saturating_mul(cycles_per_us);
+    loop {
+        match operation() {
+            Ok(value) => return Ok(value),
+            Err(nb::Error::Other(err)) => return Err(err),
+            Err(nb::Error::WouldBlock) => {}
+        }
+
+        let elapsed = cycle_count().wrapping_sub(start);
+        if elapsed > timeout {
+            return Err(Error::Timeout);
+        }
+    }
+}
+
+/// GPIO expander pins that can be pulsed, see [pulse_low()].
+trait ExpanderPins {
+    /// Set the level of a GPIO expander pin.
+    fn set_pin(
+        &mut self,
+        pin: GpioPin,
+        level: mcp230xx::Level,
+    ) -> Result<(), Error>;
+
+    /// Wait for the given time.
+    fn delay_us(&mut self, us: u16);
+}
+
+impl ExpanderPins for PounderDevices {
+    fn set_pin(
+        &mut self,
+        pin: GpioPin,
+        level: mcp230xx::Level,
+    ) -> Result<(), Error> {
+        self.set_gpio_pin(pin, level)
+    }
+
+    fn delay_us(&mut self, us: u16) {
+        self.delay.delay_us(us);
+    }
+}
+
+/// Drive a GPIO expander pin low for a minimum time and then high again.
+///
+/// Args:
+/// * `pins` - The GPIO expander pins.
+/// * `pin` - The pin to pulse.
+/// * `width_us` - The minimum pulse width in microseconds.
+fn pulse_low(
+    pins: &mut impl ExpanderPins,
+    pin: GpioPin,
+    width_us: u16,
+) -> Result<(), Error> {
+    pins.set_pin(pin, mcp230xx::Level::Low)?;
+    pins.delay_us(width_us);
+    pins.set_pin(pin, mcp230xx::Level::High)
+}
+
+impl ClockInterface for PounderDevices {
+    fn select_reference_input(&mut self, external: bool) -> Result<(), Error> {
+        let level = if external {
+            mcp230xx::Level::High
+        } else {
+            mcp230xx::Level::Low
+        };
+        // Active low
+        self.set_gpio_pin(GpioPin::OscEnN, level)?;
+        self.set_gpio_pin(GpioPin::ExtClkSel, level)
+    }
+
+    fn clock_state(&self) -> &ClockState {
+        &self.clock
+    }
+
+    fn clock_state_mut(&mut self) -> &mut ClockState {
+        &mut self.clock
+    }
+}
 
 impl attenuators::AttenuatorInterface for PounderDevices {
     /// Reset all of the attenuators to a power-on default state.
     fn reset_attenuators(&mut self) -> Result<(), Error> {
         // Active low
-        self.set_gpio_pin(GpioPin::AttRstN, mcp230xx::Level::Low)?;
-        self.set_gpio_pin(GpioPin::AttRstN, mcp230xx::Level::High)
+        let width_us = self.reset_pulse_us;
+        pulse_low(self, GpioPin::AttRstN, width_us)
     }
 
     /// Latch a configuration into a digital attenuator.
@@ -448,12 +1896,18 @@ impl attenuators::AttenuatorInterface for PounderDevices {
     fn latch_attenuator(&mut self, channel: Channel) -> Result<(), Error> {
         // Rising edge sensitive
         // Be robust against initial state: drive low, then high (contrary to the datasheet figure).
-        self.set_gpio_pin(channel.into(), mcp230xx::Level::Low)?;
-        self.set_gpio_pin(channel.into(), mcp230xx::Level::High)
+        let width_us = self.latch_pulse_us;
+        pulse_low(self, channel.into(), width_us)
     }
 
     /// Read the raw attenuation codes stored in the attenuator shift registers.
     ///
+    /// Note:
+    /// If the transfer does not complete within the configured timeout (see
+    /// [PounderDevices::set_spi_timeout()]), [Error::Timeout] is returned. A DMA attenuator scan
+    /// in progress is completed first, or abandoned if it does not complete in time. A
+    /// non-blocking transfer in progress results in [Error::InvalidState].
+    ///
     /// Args:
     /// * `channels` - A 4 byte slice to be shifted into the
     ///     attenuators and to contain the data shifted out.
@@ -461,11 +1915,37 @@ impl attenuators::AttenuatorInterface for PounderDevices {
         &mut self,
         channels: &mut [u8; 4],
     ) -> Result<(), Error> {
-        self.attenuator_spi
-            .transfer(channels)
-            .map_err(|_| Error::Spi)?;
+        if self.attenuator_transfer.is_some() {
+            return Err(Error::InvalidState);
+        }
 
-        Ok(())
+        let start = DWT::cycle_count();
+        let timeout_us = self.spi_timeout_us;
+        let spi = &mut self.attenuator_spi;
+
+        if let Some(scan) = self.attenuator_scan.as_mut() {
+            if poll_timeout(start, timeout_us, DWT::cycle_count, || {
+                scan.poll(spi)
+            })
+            .is_err()
+            {
+                scan.abort(spi);
+            }
+        }
+
+        let request = attenuators::TransferRequest::Write(*channels);
+        match poll_timeout(start, timeout_us, DWT::cycle_count, || {
+            self.poll_attenuator_transfer(request)
+        }) {
+            Ok(codes) => {
+                *channels = codes;
+                Ok(())
+            }
+            Err(err) => {
+                self.attenuator_transfer = None;
+                Err(err)
+            }
+        }
     }
 }
 
@@ -489,3 +1969,552 @@ impl rf_power::PowerMeasurementInterface for PounderDevices {
         Ok(adc_scale * 2.048)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dds_output::tests::{dds_output, Access, Accesses};
+
+    fn point(frequency: f32, correction_db: f32) -> FlatnessPoint {
+        FlatnessPoint {
+            frequency,
+            correction_db,
+        }
+    }
+
+    #[test]
+    fn flatness_interpolates_and_clamps() {
+        let calibration =
+            FlatnessCalibration::new(&[point(10e6, 0.), point(110e6, -2.)])
+                .unwrap();
+
+        assert_eq!(calibration.correction_db(1e6), 0.);
+        assert_eq!(calibration.correction_db(10e6), 0.);
+        assert_eq!(calibration.correction_db(60e6), -1.);
+        assert_eq!(calibration.correction_db(110e6), -2.);
+        assert_eq!(calibration.correction_db(200e6), -2.);
+        assert_eq!(FlatnessCalibration::default().correction_db(60e6), 0.);
+    }
+
+    #[test]
+    fn flatness_rejects_unordered_points() {
+        assert!(matches!(
+            FlatnessCalibration::new(&[point(20e6, 0.), point(10e6, -1.)]),
+            Err(Error::Bounds)
+        ));
+        assert!(matches!(
+            FlatnessCalibration::new(&[point(10e6, f32::NAN)]),
+            Err(Error::Bounds)
+        ));
+    }
+
+    #[test]
+    fn flatness_insert_keeps_order() {
+        let mut calibration = FlatnessCalibration::default();
+        calibration.insert(point(50e6, -1.)).unwrap();
+        calibration.insert(point(10e6, 0.)).unwrap();
+        calibration.insert(point(100e6, -3.)).unwrap();
+        calibration.insert(point(50e6, -1.5)).unwrap();
+        assert_eq!(
+            calibration.points(),
+            [point(10e6, 0.), point(50e6, -1.5), point(100e6, -3.)]
+        );
+
+        assert!(matches!(
+            calibration.insert(point(1e6, f32::NAN)),
+            Err(Error::Bounds)
+        ));
+        for i in 3..FLATNESS_POINTS {
+            calibration
+                .insert(point(200e6 + i as f32 * 1e6, 0.))
+                .unwrap();
+        }
+        assert!(matches!(
+            calibration.insert(point(1e6, 0.)),
+            Err(Error::Bounds)
+        ));
+    }
+
+    /// Attenuators that record the transfers along with the DDS accesses.
+    struct MockAttenuators<'a> {
+        accesses: &'a Accesses,
+    }
+
+    impl AttenuatorInterface for MockAttenuators<'_> {
+        fn reset_attenuators(&mut self) -> Result<(), Error> {
+            unimplemented!()
+        }
+
+        fn latch_attenuator(&mut self, channel: Channel) -> Result<(), Error> {
+            let access = Access::LatchAttenuator(channel as usize);
+            self.accesses.borrow_mut().push(access).unwrap();
+            Ok(())
+        }
+
+        fn transfer_attenuators(
+            &mut self,
+            channels: &mut [u8; 4],
+        ) -> Result<(), Error> {
+            let access = Access::TransferAttenuators(*channels);
+            self.accesses.borrow_mut().push(access).unwrap();
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn park_mutes_before_attenuating() {
+        let accesses = Accesses::default();
+        let mut dds = dds_output(&accesses);
+        for channel in enum_iterator::all::<Channel>() {
+            dds.set_amplitude(channel, 1.).unwrap();
+        }
+        accesses.borrow_mut().clear();
+
+        let mut rf = MockAttenuators {
+            accesses: &accesses,
+        };
+        park_outputs(&mut rf, &mut dds).unwrap();
+
+        for channel in enum_iterator::all::<Channel>() {
+            assert_eq!(dds.amplitude(channel), 0.);
+        }
+
+        // Every channel is muted by its own profile before the attenuation is increased.
+        let accesses = accesses.borrow();
+        let (max, _) =
+            attenuators::encode(attenuators::MAX_ATTENUATION).unwrap();
+        let transfer = accesses
+            .iter()
+            .position(|access| *access == Access::TransferAttenuators([max; 4]))
+            .unwrap();
+        let (muting, attenuating) = accesses.split_at(transfer);
+        assert_eq!(muting.len(), 8);
+        for update in muting.chunks(2) {
+            assert!(matches!(update, [Access::Profile(_), Access::IoUpdate]));
+        }
+        assert_eq!(attenuating[1..], [0, 1, 2, 3].map(Access::LatchAttenuator));
+    }
+
+    /// A reference clock selection that records the selected inputs.
+    #[derive(Default)]
+    struct MockClock {
+        state: ClockState,
+        inputs: heapless::Vec<bool, 8>,
+    }
+
+    impl ClockInterface for MockClock {
+        fn select_reference_input(
+            &mut self,
+            external: bool,
+        ) -> Result<(), Error> {
+            self.inputs.push(external).unwrap();
+            Ok(())
+        }
+
+        fn clock_state(&self) -> &ClockState {
+            &self.state
+        }
+
+        fn clock_state_mut(&mut self) -> &mut ClockState {
+            &mut self.state
+        }
+    }
+
+    #[test]
+    fn clock_loss_falls_back_to_onboard_clock() {
+        let accesses = Accesses::default();
+        let mut dds = dds_output(&accesses);
+        let mut clock = MockClock::default();
+        assert_eq!(
+            clock
+                .select_external_clock(&mut dds, 20e6, 20, || true)
+                .unwrap(),
+            400e6
+        );
+        let (frequency, _) = dds.set_frequency(Channel::Out0, 80e6).unwrap();
+
+        // Missed checks are tolerated as long as the reference reappears.
+        for _ in 1..CLOCK_LOSS_CHECKS {
+            assert!(!clock.check_clock(&mut dds, false).unwrap());
+        }
+        assert!(!clock.check_clock(&mut dds, true).unwrap());
+        for _ in 1..CLOCK_LOSS_CHECKS {
+            assert!(!clock.check_clock(&mut dds, false).unwrap());
+        }
+        assert!(clock.clock_config().external_clock);
+        assert!(!clock.clock_fault());
+
+        accesses.borrow_mut().clear();
+        assert!(clock.check_clock(&mut dds, false).unwrap());
+        assert!(clock.clock_fault());
+        assert_eq!(clock.inputs, [true, false]);
+
+        let config = clock.clock_config();
+        assert!(!config.external_clock);
+        assert_eq!(
+            config.reference_clock,
+            design_parameters::DDS_REF_CLK.to_Hz() as f32
+        );
+        assert_eq!(config.multiplier, design_parameters::DDS_MULTIPLIER);
+
+        // The channel is retuned for the on-board system clock.
+        assert!((dds.frequency(Channel::Out0) - frequency).abs() < 1.);
+        assert!(accesses.borrow().contains(&Access::IoUpdate));
+
+        // The on-board clock is not monitored.
+        for _ in 0..CLOCK_LOSS_CHECKS {
+            assert!(!clock.check_clock(&mut dds, false).unwrap());
+        }
+        assert_eq!(clock.inputs, [true, false]);
+        assert!(clock.clock_fault());
+        clock.clear_clock_fault();
+        assert!(!clock.clock_fault());
+    }
+
+    /// A GPIO expander that NAKs a number of accesses before it responds.
+    struct MockI2c {
+        failures: u32,
+        accesses: u32,
+    }
+
+    impl WriteRead for MockI2c {
+        type Error = ();
+
+        fn write_read(
+            &mut self,
+            _address: u8,
+            _bytes: &[u8],
+            buffer: &mut [u8],
+        ) -> Result<(), ()> {
+            self.accesses += 1;
+            if self.accesses <= self.failures {
+                return Err(());
+            }
+            buffer.fill(0x5a);
+            Ok(())
+        }
+    }
+
+    /// A delay that records the requested delays instead of waiting.
+    #[derive(Default)]
+    struct MockDelay {
+        delays: heapless::Vec<u16, 8>,
+    }
+
+    impl DelayUs<u16> for MockDelay {
+        fn delay_us(&mut self, us: u16) {
+            self.delays.push(us).unwrap();
+        }
+    }
+
+    /// A change of a GPIO expander pin level, or a delay.
+    #[derive(Debug, PartialEq)]
+    enum PinAccess {
+        Pin(GpioPin, mcp230xx::Level),
+        Delay(u16),
+    }
+
+    /// GPIO expander pins that record the accesses instead of driving the pins.
+    #[derive(Default)]
+    struct MockPins {
+        accesses: heapless::Vec<PinAccess, 8>,
+        fail: bool,
+    }
+
+    impl ExpanderPins for MockPins {
+        fn set_pin(
+            &mut self,
+            pin: GpioPin,
+            level: mcp230xx::Level,
+        ) -> Result<(), Error> {
+            if self.fail {
+                return Err(Error::I2c);
+            }
+            self.accesses.push(PinAccess::Pin(pin, level)).unwrap();
+            Ok(())
+        }
+
+        fn delay_us(&mut self, us: u16) {
+            self.accesses.push(PinAccess::Delay(us)).unwrap();
+        }
+    }
+
+    #[test]
+    fn pulse_waits_configured_width() {
+        use mcp230xx::Level::{High, Low};
+
+        let mut pins = MockPins::default();
+        pulse_low(&mut pins, GpioPin::AttRstN, 250).unwrap();
+        pulse_low(&mut pins, Channel::Out1.into(), DEFAULT_LATCH_PULSE_US)
+            .unwrap();
+        assert_eq!(
+            pins.accesses,
+            [
+                PinAccess::Pin(GpioPin::AttRstN, Low),
+                PinAccess::Delay(250),
+                PinAccess::Pin(GpioPin::AttRstN, High),
+                PinAccess::Pin(GpioPin::AttLe3, Low),
+                PinAccess::Delay(DEFAULT_LATCH_PULSE_US),
+                PinAccess::Pin(GpioPin::AttLe3, High),
+            ]
+        );
+
+        // No pulse is timed if the pin can not be driven.
+        let mut pins = MockPins {
+            fail: true,
+            ..Default::default()
+        };
+        assert!(matches!(
+            pulse_low(&mut pins, GpioPin::AttRstN, 250),
+            Err(Error::I2c)
+        ));
+        assert!(pins.accesses.is_empty());
+    }
+
+    fn read(i2c: &mut MockI2c) -> Result<u8, ()> {
+        let mut buffer = [0];
+        i2c.write_read(MCP23017_ADDRESS, &[0x12], &mut buffer)?;
+        Ok(buffer[0])
+    }
+
+    #[test]
+    fn i2c_retry_recovers_from_transient_failures() {
+        let retry = I2cRetry {
+            retries: 3,
+            backoff_us: 10,
+        };
+        let mut i2c = MockI2c {
+            failures: 3,
+            accesses: 0,
+        };
+        let mut delay = MockDelay::default();
+        let mut transient = 0;
+
+        let value = retry.run(&mut transient, &mut delay, || read(&mut i2c));
+        assert_eq!(value.unwrap(), 0x5a);
+        assert_eq!(i2c.accesses, 4);
+        assert_eq!(delay.delays, [10, 20, 40]);
+        assert_eq!(transient, 1);
+
+        // An access that succeeds at once is not transient.
+        let value = retry.run(&mut transient, &mut delay, || read(&mut i2c));
+        assert_eq!(value.unwrap(), 0x5a);
+        assert_eq!(i2c.accesses, 5);
+        assert_eq!(transient, 1);
+    }
+
+    #[test]
+    fn i2c_retry_reports_persistent_failure() {
+        let retry = I2cRetry {
+            retries: 3,
+            backoff_us: 40_000,
+        };
+        let mut i2c = MockI2c {
+            failures: u32::MAX,
+            accesses: 0,
+        };
+        let mut delay = MockDelay::default();
+        let mut transient = 0;
+
+        let value = retry.run(&mut transient, &mut delay, || read(&mut i2c));
+        assert!(matches!(value, Err(Error::I2c)));
+        assert_eq!(i2c.accesses, 4);
+        // The backoff saturates instead of overflowing.
+        assert_eq!(delay.delays, [40_000, u16::MAX, u16::MAX]);
+        assert_eq!(transient, 0);
+    }
+
+    /// A cycle counter that advances by 1 us at every read.
+    fn cycle_counter(start: u32) -> impl FnMut() -> u32 {
+        let cycles_per_us = design_parameters::SYSCLK.to_Hz() / 1_000_000;
+        let mut cycles = start;
+        move || {
+            cycles = cycles.wrapping_add(cycles_per_us);
+            cycles
+        }
+    }
+
+    #[test]
+    fn poll_timeout_completes() {
+        let mut polls = 0;
+        let result = poll_timeout(0, 10, cycle_counter(0), || {
+            polls += 1;
+            if polls < 5 {
+                Err(nb::Error::WouldBlock)
+            } else {
+                Ok(polls)
+            }
+        });
+        assert_eq!(result.unwrap(), 5);
+    }
+
+    #[test]
+    fn poll_timeout_times_out() {
+        // The timeout is measured across a wrap of the cycle counter.
+        let start = u32::MAX - 100;
+        let mut polls = 0;
+        let result = poll_timeout(start, 10, cycle_counter(start), || {
+            polls += 1;
+            nb::Result::<(), Error>::Err(nb::Error::WouldBlock)
+        });
+        assert!(matches!(result, Err(Error::Timeout)));
+        assert_eq!(polls, 11);
+    }
+
+    #[test]
+    fn poll_timeout_propagates_errors() {
+        let result = poll_timeout(0, 10, cycle_counter(0), || {
+            nb::Result::<(), Error>::Err(nb::Error::Other(Error::InvalidState))
+        });
+        assert!(matches!(result, Err(Error::InvalidState)));
+
+        let result = poll_timeout(0, 10, cycle_counter(0), || {
+            nb::Result::<(), Error>::Err(nb::Error::Other(Error::Spi))
+        });
+        assert!(matches!(result, Err(Error::Spi)));
+    }
+
+    #[test]
+    fn power_allocation_realizes_reduction() {
+        let max_reduction =
+            attenuators::MAX_ATTENUATION - 20. * libm::log10f(MIN_AMPLITUDE);
+
+        let mut reduction = 0.;
+        while reduction <= max_reduction {
+            let (attenuation, amplitude) = allocate_power(reduction);
+
+            // The attenuation is a whole number of attenuator steps and the DDS covers the rest.
+            assert_eq!(attenuation * 2., (attenuation * 2.) as u8 as f32);
+            assert!((0.0..=attenuators::MAX_ATTENUATION).contains(&attenuation));
+            assert!((MIN_AMPLITUDE..=1.).contains(&amplitude));
+
+            let realized = attenuation - 20. * libm::log10f(amplitude);
+            assert!(
+                (realized - reduction).abs() < 1e-3,
+                "{reduction} dB realized as {realized} dB"
+            );
+
+            reduction += 0.1;
+        }
+    }
+
+    #[test]
+    fn power_allocation_clamps_reduction() {
+        assert_eq!(allocate_power(-3.), (0., 1.));
+        assert_eq!(allocate_power(f32::NEG_INFINITY), (0., 1.));
+
+        let (attenuation, amplitude) = allocate_power(200.);
+        assert_eq!(attenuation, attenuators::MAX_ATTENUATION);
+        assert!((amplitude - MIN_AMPLITUDE).abs() < 1e-6);
+    }
+
+    #[test]
+    fn power_from_amplitude_and_attenuation() {
+        assert_eq!(power_dbm_at(10., 0., 1.), 10.);
+        assert_eq!(power_dbm_at(10., 3.5, 1.), 6.5);
+        assert!((power_dbm_at(10., 0., 0.5) - 3.9794).abs() < 1e-3);
+        assert!((power_dbm_at(10., 20., 0.1) - -30.).abs() < 1e-3);
+        assert!(
+            (power_dbm_at(10., 31.5, MIN_AMPLITUDE) - -81.7062).abs() < 1e-3
+        );
+        assert_eq!(power_dbm_at(10., 0., 0.), f32::NEG_INFINITY);
+
+        // The estimate inverts the power allocation.
+        let (attenuation, amplitude) = allocate_power(17.3);
+        assert!(
+            (power_dbm_at(5., attenuation, amplitude) - -12.3).abs() < 1e-3
+        );
+    }
+
+    #[test]
+    fn external_clock_in_range() {
+        // Without the PLL, the reference is the system clock.
+        assert_eq!(validate_external_clock(1e6, 1).unwrap(), 1e6);
+        assert_eq!(validate_external_clock(500e6, 1).unwrap(), 500e6);
+
+        // With the PLL, the system clock has to fall into one of the VCO ranges.
+        assert_eq!(validate_external_clock(25e6, 4).unwrap(), 100e6);
+        assert_eq!(validate_external_clock(10e6, 16).unwrap(), 160e6);
+        assert_eq!(validate_external_clock(100e6, 5).unwrap(), 500e6);
+        assert_eq!(validate_external_clock(25e6, 20).unwrap(), 500e6);
+    }
+
+    #[test]
+    fn external_clock_too_low() {
+        for (frequency, multiplier) in [
+            (0.5e6, 1),
+            (9e6, 20),
+            // The system clock falls between the VCO ranges.
+            (10e6, 20),
+            // The system clock falls below the low VCO range.
+            (20e6, 4),
+            // The multiplier is below the PLL range.
+            (100e6, 3),
+            (100e6, 0),
+        ] {
+            assert!(
+                matches!(
+                    validate_external_clock(frequency, multiplier),
+                    Err(Error::Bounds)
+                ),
+                "{frequency} Hz x {multiplier} accepted"
+            );
+        }
+    }
+
+    #[test]
+    fn external_clock_too_high() {
+        for (frequency, multiplier) in [
+            (501e6, 1),
+            (126e6, 4),
+            // The system clock exceeds the high VCO range.
+            (125e6, 5),
+            // The multiplier exceeds the PLL range.
+            (10e6, 21),
+            (f32::INFINITY, 1),
+            (f32::NAN, 1),
+        ] {
+            assert!(
+                matches!(
+                    validate_external_clock(frequency, multiplier),
+                    Err(Error::Bounds)
+                ),
+                "{frequency} Hz x {multiplier} accepted"
+            );
+        }
+    }
+
+    #[test]
+    fn error_display_is_distinct() {
+        use core::fmt::Write;
+
+        let errors = [
+            Error::Init(InitStage::GpioA),
+            Error::Init(InitStage::Dds),
+            Error::Spi,
+            Error::I2c,
+            Error::Qspi(hal::xspi::QspiError::Busy),
+            Error::Bounds,
+            Error::InvalidAddress,
+            Error::InvalidChannel,
+            Error::Adc,
+            Error::InvalidState,
+            Error::Timeout,
+            Error::ClockNotConfigured,
+            Error::ClockAbsent,
+        ];
+
+        let mut messages: heapless::Vec<heapless::String<64>, 16> =
+            heapless::Vec::new();
+        for error in errors {
+            let mut message = heapless::String::new();
+            write!(message, "{error}").unwrap();
+            assert!(!message.is_empty(), "{error:?} has no message");
+            assert!(
+                !messages.contains(&message),
+                "{error:?} message is not distinct"
+            );
+            messages.push(message).unwrap();
+        }
+    }
+}
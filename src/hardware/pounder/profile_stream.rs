@@ -0,0 +1,223 @@
+//! Timer-gated streaming of pre-computed DDS profiles
+//!
+//! # Design
+//! For deterministic sweep timing, profiles are serialized ahead of time by a producer and
+//! enqueued into a single-producer single-consumer queue with [ProfileProducer::enqueue()]. The
+//! consumer is advanced at a fixed rate from a timer-gated context, e.g. the interrupt of a timer
+//! running at the update rate, by [super::dds_output::DdsOutput::stream_profile()]. Each advance
+//! writes at most one profile followed by an IO_Update pulse, so the update timing is set by the
+//! timer and does not depend on when the producer runs.
+//!
+//! ## Backpressure
+//! The producer can enqueue up to [PROFILE_QUEUE_SIZE] profiles ahead. Once the queue is full,
+//! [ProfileProducer::enqueue()] returns [nb::Error::WouldBlock] and the profile must be retried
+//! later. The fill level is available from [ProfileProducer::len()].
+//!
+//! ## Underruns
+//! If the queue is empty when the consumer is advanced while streaming, no profile is written and
+//! the DDS holds the last streamed value. The underrun is flagged once per gap, see
+//! [ProfileProducer::take_underrun()]. Streaming resumes with the next enqueued profile. The end
+//! of a finite stream, e.g. a single sweep, is also reported as an underrun.
+//!
+//! # Note
+//! The QUADSPI peripheral only has a request line into the MDMA controller, which is not
+//! supported by the HAL. The profiles are therefore copied into the QSPI FIFO by the consumer,
+//! which takes a few register writes per profile.
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use heapless::spsc::{Consumer, Producer, Queue};
+
+use super::Error;
+
+/// The number of profiles that can be enqueued ahead.
+pub const PROFILE_QUEUE_SIZE: usize = 64;
+
+/// The maximum size of a streamed profile in 32-bit words. It is limited by the part of the QSPI
+/// FIFO that is used per update.
+pub const MAX_PROFILE_WORDS: usize = 4;
+
+/// A serialized DDS profile.
+pub type Profile = heapless::Vec<u32, MAX_PROFILE_WORDS>;
+
+// The size of the queue. It holds one element less than its size.
+const QUEUE_SIZE: usize = PROFILE_QUEUE_SIZE + 1;
+
+/// The underrun state shared between the producer and the consumer.
+struct StreamStatus {
+    underrun: AtomicBool,
+    underruns: AtomicU32,
+}
+
+/// Construct the profile stream.
+///
+/// # Note
+/// The queue is statically allocated, so this can only be called once.
+///
+/// # Returns
+/// The producer and consumer of the stream.
+pub fn setup() -> (ProfileProducer, ProfileConsumer) {
+    // Note(unwrap): This function may only be called once.
+    let queue =
+        cortex_m::singleton!(: Queue<Profile, QUEUE_SIZE> = Queue::new())
+            .unwrap();
+    let status = cortex_m::singleton!(: StreamStatus = StreamStatus {
+        underrun: AtomicBool::new(false),
+        underruns: AtomicU32::new(0),
+    })
+    .unwrap();
+    split(queue, status)
+}
+
+/// Split the profile queue into the producer and consumer of the stream.
+fn split(
+    queue: &'static mut Queue<Profile, QUEUE_SIZE>,
+    status: &'static StreamStatus,
+) -> (ProfileProducer, ProfileConsumer) {
+    let (producer, consumer) = queue.split();
+
+    (
+        ProfileProducer {
+            queue: producer,
+            status,
+        },
+        ProfileConsumer {
+            queue: consumer,
+            status,
+            streaming: false,
+        },
+    )
+}
+
+/// The producer side of the profile stream.
+pub struct ProfileProducer {
+    queue: Producer<'static, Profile, QUEUE_SIZE>,
+    status: &'static StreamStatus,
+}
+
+impl ProfileProducer {
+    /// Enqueue a profile for streaming.
+    ///
+    /// # Args
+    /// * `profile` - The serialized profile, e.g. from `ProfileSerializer::finalize()`.
+    ///
+    /// # Returns
+    /// [nb::Error::WouldBlock] if the queue is full, or [Error::Bounds] if the profile exceeds
+    /// [MAX_PROFILE_WORDS].
+    pub fn enqueue(&mut self, profile: &[u32]) -> nb::Result<(), Error> {
+        let profile = Profile::from_slice(profile)
+            .or(Err(nb::Error::Other(Error::Bounds)))?;
+        self.queue.enqueue(profile).or(Err(nb::Error::WouldBlock))
+    }
+
+    /// Get the number of enqueued profiles that were not streamed yet.
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// Check whether no profiles are pending.
+    pub fn is_empty(&self) -> bool {
+        self.queue.len() == 0
+    }
+
+    /// Check whether the queue is full and profiles must not be enqueued.
+    pub fn is_full(&self) -> bool {
+        !self.queue.ready()
+    }
+
+    /// Check and clear the underrun flag.
+    ///
+    /// # Returns
+    /// True if the stream ran out of profiles since the previous call.
+    pub fn take_underrun(&mut self) -> bool {
+        self.status.underrun.swap(false, Ordering::Relaxed)
+    }
+
+    /// Get the number of underruns since setup. It wraps on overflow.
+    pub fn underrun_count(&self) -> u32 {
+        self.status.underruns.load(Ordering::Relaxed)
+    }
+}
+
+/// The consumer side of the profile stream.
+pub struct ProfileConsumer {
+    queue: Consumer<'static, Profile, QUEUE_SIZE>,
+    status: &'static StreamStatus,
+    streaming: bool,
+}
+
+impl ProfileConsumer {
+    /// Take the next profile to stream.
+    ///
+    /// # Note
+    /// If the queue runs empty while streaming, an underrun is flagged. Streaming resumes with
+    /// the next enqueued profile.
+    ///
+    /// # Returns
+    /// The next profile, or `None` if the queue is empty.
+    pub fn pop(&mut self) -> Option<Profile> {
+        let profile = self.queue.dequeue();
+        if profile.is_none() && self.streaming {
+            self.status.underrun.store(true, Ordering::Relaxed);
+            self.status.underruns.fetch_add(1, Ordering::Relaxed);
+        }
+        self.streaming = profile.is_some();
+        profile
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Note: The queue is static, so there is only one test of the stream.
+    #[test]
+    fn stream_backpressure_and_underruns() {
+        static STATUS: StreamStatus = StreamStatus {
+            underrun: AtomicBool::new(false),
+            underruns: AtomicU32::new(0),
+        };
+        static mut QUEUE: Queue<Profile, QUEUE_SIZE> = Queue::new();
+        // Safety: The queue is only used by this test.
+        let (mut producer, mut consumer) =
+            split(unsafe { &mut QUEUE }, &STATUS);
+        assert!(producer.is_empty());
+
+        // Nothing was streamed yet, so an empty queue is no underrun.
+        assert!(consumer.pop().is_none());
+        assert!(!producer.take_underrun());
+
+        // The queue fills up to its size and then pushes back.
+        for i in 0..PROFILE_QUEUE_SIZE as u32 {
+            producer.enqueue(&[i, i + 1]).unwrap();
+        }
+        assert!(producer.is_full());
+        assert_eq!(producer.len(), PROFILE_QUEUE_SIZE);
+        assert!(matches!(producer.enqueue(&[0]), Err(nb::Error::WouldBlock)));
+
+        // Oversized profiles are rejected regardless of the fill level.
+        assert!(matches!(
+            producer.enqueue(&[0; MAX_PROFILE_WORDS + 1]),
+            Err(nb::Error::Other(Error::Bounds))
+        ));
+
+        // Profiles are streamed in order.
+        for i in 0..PROFILE_QUEUE_SIZE as u32 {
+            assert_eq!(consumer.pop().unwrap(), [i, i + 1]);
+        }
+        assert!(producer.is_empty());
+        assert!(!producer.take_underrun());
+
+        // Running empty is flagged once per gap.
+        assert!(consumer.pop().is_none());
+        assert!(consumer.pop().is_none());
+        assert!(producer.take_underrun());
+        assert!(!producer.take_underrun());
+        assert_eq!(producer.underrun_count(), 1);
+
+        // Streaming resumes with the next profile and a new gap is counted again.
+        producer.enqueue(&[7]).unwrap();
+        assert_eq!(consumer.pop().unwrap(), [7]);
+        assert!(consumer.pop().is_none());
+        assert!(producer.take_underrun());
+        assert_eq!(producer.underrun_count(), 2);
+    }
+}
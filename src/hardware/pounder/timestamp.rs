@@ -1,20 +1,258 @@
 //! 
-use crate::hardware::timers;
+use super::Error;
+use crate::hardware::timers::{self, wrap};
 use stm32h7xx_hal as hal;
 
+/// The accumulated phase of the beat note.
+///
+/// # Note
+/// Captures of the beat timer are accumulated into a continuous phase in beat timer counts. The
+/// diff between two captures is interpreted as the nearest signed distance on the wrapping
+/// counter, so no counts are lost as long as consecutive captures are less than half the counter
+/// range apart. The phase saturates instead of overflowing.
+#[derive(Copy, Clone, Debug)]
+pub struct PhaseAccumulator {
+    previous: Option<u32>,
+    phase: i64,
+    counter_width: u32,
+}
+
+impl PhaseAccumulator {
+    /// Construct an accumulator with zero phase.
+    ///
+    /// # Args
+    /// * `counter_width` - The width of the counter in bits, from 1 to 32.
+    pub fn new(counter_width: u32) -> Self {
+        debug_assert!((1..=32).contains(&counter_width));
+        Self {
+            previous: None,
+            phase: 0,
+            counter_width,
+        }
+    }
+
+    /// Update the width of the counter.
+    ///
+    /// # Args
+    /// * `counter_width` - The width of the counter in bits, from 1 to 32.
+    pub fn set_counter_width(&mut self, counter_width: u32) {
+        debug_assert!((1..=32).contains(&counter_width));
+        self.counter_width = counter_width;
+    }
+
+    /// Accumulate a capture of the counter.
+    ///
+    /// # Note
+    /// The first capture only establishes the reference of the phase.
+    ///
+    /// # Args
+    /// * `capture` - The captured counter value.
+    ///
+    /// # Returns
+    /// The unwrapped phase in counts.
+    pub fn update(&mut self, capture: u32) -> i64 {
+        if let Some(previous) = self.previous {
+            let diff = wrap::diff_signed(
+                capture as u64,
+                previous as u64,
+                self.counter_width,
+            );
+            self.phase = self.phase.saturating_add(diff);
+        }
+        self.previous = Some(capture);
+        self.phase
+    }
+
+    /// Get the unwrapped phase in counts.
+    pub fn unwrapped_phase(&self) -> i64 {
+        self.phase
+    }
+
+    /// Get the phase error relative to a setpoint.
+    ///
+    /// # Args
+    /// * `setpoint` - The desired phase in counts.
+    ///
+    /// # Returns
+    /// The difference of the setpoint and the unwrapped phase in counts.
+    pub fn phase_error(&self, setpoint: i64) -> i64 {
+        setpoint.saturating_sub(self.phase)
+    }
+}
+
+/// The largest timestamp timer range in counts.
+const MAX_RANGE: u32 = 1 << u16::BITS;
+
+/// The smallest timestamp timer range in counts.
+const MIN_RANGE: u32 = 4;
+
+/// The number of diffs over which the diff statistics are computed.
+const STATISTICS_WINDOW: u32 = 1024;
+
+/// Statistics of the timestamp diffs over a window of captures.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct DiffStatistics {
+    count: u32,
+    min: u16,
+    max: u16,
+    sum: u64,
+    sum_squares: u64,
+}
+
+impl DiffStatistics {
+    /// Add a diff to the statistics.
+    fn add(&mut self, diff: u16) {
+        if self.count == 0 {
+            self.min = diff;
+            self.max = diff;
+        } else {
+            self.min = self.min.min(diff);
+            self.max = self.max.max(diff);
+        }
+        self.count += 1;
+        self.sum += diff as u64;
+        self.sum_squares += diff as u64 * diff as u64;
+    }
+
+    /// Get the number of diffs in the statistics.
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    /// Get the smallest diff, or `None` if there are no diffs.
+    pub fn min(&self) -> Option<u16> {
+        (self.count > 0).then_some(self.min)
+    }
+
+    /// Get the largest diff, or `None` if there are no diffs.
+    pub fn max(&self) -> Option<u16> {
+        (self.count > 0).then_some(self.max)
+    }
+
+    /// Get the mean diff in counts, or NaN if there are no diffs.
+    pub fn mean(&self) -> f32 {
+        self.sum as f32 / self.count as f32
+    }
+
+    /// Get the population variance of the diffs in counts squared, or NaN if there are no diffs.
+    ///
+    /// # Note
+    /// The variance is computed from the exact integer sums, so it does not suffer from
+    /// cancellation for large diffs. The sums can not overflow within [STATISTICS_WINDOW] diffs.
+    pub fn variance(&self) -> f32 {
+        let count = self.count as u64;
+        (count * self.sum_squares - self.sum * self.sum) as f32
+            / (count * count) as f32
+    }
+}
+
+/// The jitter statistics and error counts of the beat note captures.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct CaptureStatistics {
+    /// The statistics of the most recently completed window of [STATISTICS_WINDOW] diffs.
+    pub diffs: DiffStatistics,
+
+    /// The number of captures since boot that were overwritten before being read.
+    pub overcaptures: u32,
+}
+
+/// The beat timer settings chosen by [InputCaptureTimer::calibrate_period()].
+#[derive(Copy, Clone, Debug)]
+pub struct CaptureSettings {
+    /// The ETR prescaler of the beat timer.
+    pub prescaler: timers::Prescaler,
+
+    /// The period of the beat timer in ticks.
+    pub period: u16,
+
+    /// The expected number of beat timer counts between two captures.
+    pub expected_diff: f32,
+}
+
+impl CaptureSettings {
+    /// Compute the beat timer settings for an expected beat note frequency.
+    ///
+    /// # Note
+    /// The smallest prescaler is used for which the expected diff fits into the counter, as it
+    /// gives the best frequency resolution. The counter range is then chosen as the smallest
+    /// power of two of at least four times the expected diff. This places the expected diff
+    /// between an eighth and a quarter of the counter range, i.e. near the center of the range
+    /// of unambiguous diffs, leaving room for the beat to at least double before diffs become
+    /// ambiguous.
+    ///
+    /// # Args
+    /// * `expected_beat_hz` - The expected beat note frequency in Hz.
+    /// * `gate_period` - The time between two captures in seconds.
+    ///
+    /// # Returns
+    /// The settings, or [Error::Bounds] if the beat note is too slow to be counted at least once
+    /// per gate period or too fast to be counted unambiguously with the largest prescaler.
+    pub fn compute(
+        expected_beat_hz: f32,
+        gate_period: f32,
+    ) -> Result<Self, Error> {
+        let counts = expected_beat_hz * gate_period;
+        if !counts.is_finite() || counts < 1. {
+            return Err(Error::Bounds);
+        }
+
+        let prescaler = [
+            timers::Prescaler::Div1,
+            timers::Prescaler::Div2,
+            timers::Prescaler::Div4,
+            timers::Prescaler::Div8,
+        ]
+        .into_iter()
+        .find(|prescaler| {
+            counts / prescaler.ratio() as f32 <= (MAX_RANGE / 4) as f32
+        })
+        .ok_or(Error::Bounds)?;
+
+        let expected_diff = counts / prescaler.ratio() as f32;
+        let range = (libm::ceilf(4. * expected_diff) as u32)
+            .next_power_of_two()
+            .clamp(MIN_RANGE, MAX_RANGE);
+
+        Ok(Self {
+            prescaler,
+            period: (range - 1) as u16,
+            expected_diff,
+        })
+    }
+}
+
 pub struct InputCaptureTimer {
     timer: timers::BeatTimer,
     capture_channel: timers::tim8::Channel1InputCapture,
     previous_capture: u16,
     previous_diff: u16,
+    counter_width: u32,
+    phase: PhaseAccumulator,
+    beat_prescaler: timers::Prescaler,
+    capture_prescaler: timers::Prescaler,
+    reference_period: u16,
+    reference_frequency: f32,
+    window: DiffStatistics,
+    statistics: CaptureStatistics,
 }
 
 impl InputCaptureTimer {
+    /// Construct the beat note input capture timer.
+    ///
+    /// # Args
+    /// * `beat_timer` - The timer counting the beat note, clocked from its ETR input.
+    /// * `capture_channel` - The channel capturing the beat timer count.
+    /// * `reference_timer` - The timer whose update events gate the capture.
+    /// * `_clock_input` - The ETR input pin of the beat timer.
+    /// * `beat_prescaler` - The ETR prescaler configured on the beat timer.
+    /// * `reference_frequency` - The tick frequency of the reference timer in Hz.
     pub fn new(
         mut beat_timer: timers::BeatTimer,
         capture_channel: timers::tim8::Channel1,
         reference_timer: &mut timers::ReferenceTimer,
         _clock_input: hal::gpio::gpioa::PA0<hal::gpio::Alternate<3>>,
+        beat_prescaler: timers::Prescaler,
+        reference_frequency: f32,
     ) -> Self {
         // Trigger source should trigger on its overflow
         reference_timer.generate_trigger(timers::TriggerGenerator::Update);
@@ -26,14 +264,24 @@ impl InputCaptureTimer {
         let mut input_capture = capture_channel
             .into_input_capture(timers::tim8::CaptureSource1::Trc);
 
+        let capture_prescaler = timers::Prescaler::Div1;
+        input_capture.configure_prescaler(capture_prescaler);
 
-        input_capture.configure_prescaler(timers::Prescaler::Div1);
+        let reference_period = reference_timer.get_period();
 
         Self {
             timer: beat_timer,
             capture_channel: input_capture,
             previous_capture: 0,
             previous_diff: 0,
+            counter_width: u16::BITS,
+            phase: PhaseAccumulator::new(u16::BITS),
+            beat_prescaler,
+            capture_prescaler,
+            reference_period,
+            reference_frequency,
+            window: DiffStatistics::default(),
+            statistics: CaptureStatistics::default(),
         }
     }
 
@@ -44,24 +292,284 @@ impl InputCaptureTimer {
     }
 
     /// Update the period of the underlying timestamp timer.
+    ///
+    /// # Args
+    /// * `period` - The timer period in ticks. The number of counts per period (`period + 1`) must
+    ///   be a power of two, so that timestamp diffs can be unwrapped.
     pub fn update_period(&mut self, period: u16) {
+        let range = period as u32 + 1;
+        debug_assert!(range > 1 && range.is_power_of_two());
         self.timer.set_period_ticks(period);
+        self.counter_width = range.trailing_zeros();
+        self.phase.set_counter_width(self.counter_width);
     }
 
+    /// Configure the beat timer for an expected beat note frequency.
+    ///
+    /// # Note
+    /// The beat timer is clocked by the beat note through its ETR input and captured on every
+    /// update of the reference timer, so the number of counts between two captures is given by
+    /// the beat note frequency, the gate period and the ETR prescaler. See
+    /// [CaptureSettings::compute()] for how the prescaler and period are chosen.
+    ///
+    /// The diff of the next capture is not meaningful, as it spans the reconfiguration.
+    ///
+    /// # Args
+    /// * `expected_beat_hz` - The expected beat note frequency in Hz.
+    ///
+    /// # Returns
+    /// The applied settings, or [Error::Bounds] if the beat note can not be measured. In that
+    /// case the timer is left unchanged.
+    pub fn calibrate_period(
+        &mut self,
+        expected_beat_hz: f32,
+    ) -> Result<CaptureSettings, Error> {
+        let settings =
+            CaptureSettings::compute(expected_beat_hz, self.gate_period())?;
+
+        self.timer.set_external_clock(settings.prescaler);
+        self.beat_prescaler = settings.prescaler;
+        self.update_period(settings.period);
+
+        Ok(settings)
+    }
+
+    /// Get the time between two captures in seconds.
+    fn gate_period(&self) -> f32 {
+        ((self.reference_period as u32 + 1) * self.capture_prescaler.ratio())
+            as f32
+            / self.reference_frequency
+    }
+
+    /// Get the number of beat timer counts between the two latest captures.
+    ///
+    /// # Note
+    /// The diff wraps at the counter range. If there is no new capture, the previous diff is
+    /// returned. Every new capture is accumulated into the phase, see [Self::phase()].
+    ///
+    /// If the capture flag was still set when the next capture occurred, a capture was
+    /// overwritten and the diff spans more than one gate period. The overcapture is counted, see
+    /// [Self::statistics()], the previous diff is returned and the capture only serves as the
+    /// reference of the next diff. All other diffs are added to the statistics.
     pub fn latest_timestamp_diff(&mut self) -> u16 {
-        let diff =  match self.capture_channel.latest_capture() {
+        let diff = match self.capture_channel.latest_capture() {
             Ok(Some(value)) => {
-                let tmp = value - self.previous_capture; //this assumes that we are never missing a capture
-                self.previous_capture = value;
-                tmp
-            },
+                let diff = self.accumulate(value);
+                self.record_diff(diff);
+                diff
+            }
             Ok(None) => self.previous_diff,
-            Err(Some(_value)) => 1, //1 for testing if this ever happens
-            Err(None) => self.previous_diff, 
+            Err(Some(value)) => {
+                self.statistics.overcaptures =
+                    self.statistics.overcaptures.wrapping_add(1);
+                self.accumulate(value);
+                self.previous_diff
+            }
+            Err(None) => self.previous_diff,
         };
         self.previous_diff = diff;
 
         diff
     }
 
+    /// Accumulate a capture into the phase.
+    ///
+    /// # Returns
+    /// The number of beat timer counts since the previous capture.
+    fn accumulate(&mut self, capture: u16) -> u16 {
+        let diff = wrap::diff_wide(
+            capture as u64,
+            self.previous_capture as u64,
+            self.counter_width,
+        ) as u16;
+        self.previous_capture = capture;
+        self.phase.update(capture as u32);
+        diff
+    }
+
+    /// Add a diff to the current statistics window.
+    fn record_diff(&mut self, diff: u16) {
+        self.window.add(diff);
+        if self.window.count() >= STATISTICS_WINDOW {
+            self.statistics.diffs = self.window;
+            self.window = DiffStatistics::default();
+        }
+    }
+
+    /// Get the jitter statistics and error counts of the captures.
+    ///
+    /// # Note
+    /// The statistics are updated by [Self::latest_timestamp_diff()]. Copying them in the same
+    /// context yields a consistent snapshot.
+    pub fn statistics(&self) -> CaptureStatistics {
+        self.statistics
+    }
+
+    /// Get the accumulated beat note phase of all captures so far.
+    pub fn phase(&self) -> &PhaseAccumulator {
+        &self.phase
+    }
+
+    /// Update the period of the reference timer.
+    ///
+    /// # Note
+    /// This must be called whenever the period of the reference timer is reconfigured, as it
+    /// determines the gate period of the captures.
+    ///
+    /// # Args
+    /// * `reference_timer` - The reconfigured reference timer.
+    pub fn set_reference_period(
+        &mut self,
+        reference_timer: &timers::ReferenceTimer,
+    ) {
+        self.reference_period = reference_timer.get_period();
+    }
+
+    /// Update the tick frequency of the reference timer.
+    ///
+    /// # Args
+    /// * `frequency` - The reference timer tick frequency in Hz.
+    pub fn set_reference_frequency(&mut self, frequency: f32) {
+        self.reference_frequency = frequency;
+    }
+
+    /// Get the beat note frequency from the latest timestamp diff.
+    ///
+    /// # Note
+    /// The beat note is counted by the beat timer during a gate period, which is the time between
+    /// two captures. The gate period is given by the reference timer period, its tick frequency
+    /// and the capture prescaler. Each beat timer count corresponds to `beat_prescaler` periods
+    /// of the beat note.
+    ///
+    /// This consumes the latest capture in the same way as [Self::latest_timestamp_diff()].
+    ///
+    /// # Returns
+    /// The beat note frequency in Hz, or NaN if no beat note was counted during the gate period.
+    pub fn beat_frequency(&mut self) -> f32 {
+        let diff = self.latest_timestamp_diff();
+        self.diff_to_frequency(diff)
+    }
+
+    /// Convert a timestamp diff into a beat note frequency.
+    ///
+    /// # Note
+    /// Unlike [Self::beat_frequency()], this does not consume any captures. It can be used to
+    /// convert a diff obtained from [Self::latest_timestamp_diff()].
+    ///
+    /// # Args
+    /// * `diff` - The number of beat timer counts during a gate period.
+    ///
+    /// # Returns
+    /// The beat note frequency in Hz, or NaN if `diff` is zero.
+    pub fn diff_to_frequency(&self, diff: u16) -> f32 {
+        counts_to_frequency(diff, self.beat_prescaler, self.gate_period())
+    }
+}
+
+/// Convert a number of beat timer counts during a gate period into a frequency.
+///
+/// # Args
+/// * `diff` - The number of beat timer counts during the gate period.
+/// * `beat_prescaler` - The ETR prescaler of the beat timer.
+/// * `gate_period` - The time between two captures in seconds.
+///
+/// # Returns
+/// The beat note frequency in Hz, or NaN if `diff` is zero.
+fn counts_to_frequency(
+    diff: u16,
+    beat_prescaler: timers::Prescaler,
+    gate_period: f32,
+) -> f32 {
+    if diff == 0 {
+        return f32::NAN;
+    }
+
+    (diff as u32 * beat_prescaler.ratio()) as f32 / gate_period
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn diff_converts_to_beat_frequency() {
+        // A 100 MHz reference timer with a period of 10000 ticks gates the capture every 100 us.
+        let gate_period = 10_000. / 100e6;
+
+        let frequency =
+            counts_to_frequency(1_000, timers::Prescaler::Div1, gate_period);
+        assert!((frequency - 10e6).abs() < 1.);
+
+        let frequency =
+            counts_to_frequency(1_000, timers::Prescaler::Div8, gate_period);
+        assert!((frequency - 80e6).abs() < 8.);
+
+        assert!(counts_to_frequency(0, timers::Prescaler::Div1, gate_period)
+            .is_nan());
+    }
+
+    #[test]
+    fn capture_settings_for_beat_frequencies() {
+        let gate_period = 1e-3;
+        for (beat_hz, ratio, period, expected_diff) in [
+            (1e3, 1, 3, 1.),
+            (1e6, 1, 4095, 1_000.),
+            (10e6, 1, 65535, 10_000.),
+            (20e6, 2, 65535, 10_000.),
+            (100e6, 8, 65535, 12_500.),
+        ] {
+            let settings =
+                CaptureSettings::compute(beat_hz, gate_period).unwrap();
+            assert_eq!(settings.prescaler.ratio(), ratio, "{beat_hz}");
+            assert_eq!(settings.period, period, "{beat_hz}");
+            assert!(
+                (settings.expected_diff - expected_diff).abs() < 1e-2,
+                "{beat_hz}"
+            );
+        }
+    }
+
+    #[test]
+    fn capture_settings_reject_unmeasurable_beat() {
+        for beat_hz in [0., 500., 200e6, f32::NAN, f32::INFINITY] {
+            assert!(matches!(
+                CaptureSettings::compute(beat_hz, 1e-3),
+                Err(Error::Bounds)
+            ));
+        }
+    }
+
+    #[test]
+    fn phase_unwraps_across_counter_wraps() {
+        // Advance a 16-bit counter by 1000 counts per capture over several wraps.
+        let mut phase = PhaseAccumulator::new(u16::BITS);
+        let mut counter = 0xF000u32;
+        assert_eq!(phase.update(counter), 0);
+
+        for step in 1..=300i64 {
+            counter = (counter + 1000) & 0xFFFF;
+            assert_eq!(phase.update(counter), step * 1000);
+        }
+        assert_eq!(phase.unwrapped_phase(), 300_000);
+        assert_eq!(phase.phase_error(300_100), 100);
+
+        // Running backwards unwraps through the same wraps again.
+        for step in (0..300i64).rev() {
+            counter = counter.wrapping_sub(1000) & 0xFFFF;
+            assert_eq!(phase.update(counter), step * 1000);
+        }
+        assert_eq!(phase.unwrapped_phase(), 0);
+    }
+
+    #[test]
+    fn phase_unwraps_full_width_counter() {
+        let mut phase = PhaseAccumulator::new(u32::BITS);
+        let mut counter = u32::MAX - 5;
+        phase.update(counter);
+
+        for step in 1..=4i64 {
+            counter = counter.wrapping_add(1 << 30);
+            assert_eq!(phase.update(counter), step << 30);
+        }
+    }
 }
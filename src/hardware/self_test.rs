@@ -0,0 +1,119 @@
+//! Board self-tests
+//!
+//! # Design
+//! The self-test exercises each major subsystem in isolation and reports an [Outcome] per
+//! subsystem. A failing subsystem does not prevent the remaining subsystems from being tested.
+//! Subsystems that are not fitted, e.g. Pounder, are skipped. The overall result passes if no
+//! subsystem failed, see [overall()].
+
+/// The plausible range of the CPU die temperature in degrees Celsius. This is the junction
+/// temperature range of the STM32H743.
+pub const CPU_TEMPERATURE_RANGE: core::ops::RangeInclusive<f32> = -40.0..=125.0;
+
+/// The outcome of a self-test.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    /// The subsystem works as expected.
+    Pass,
+
+    /// The subsystem failed the test.
+    Fail,
+
+    /// The subsystem is not present and was not tested.
+    Skip,
+}
+
+impl Outcome {
+    /// Get the outcome of a check.
+    ///
+    /// # Args
+    /// * `passed` - The check passed.
+    pub fn check(passed: bool) -> Self {
+        if passed {
+            Outcome::Pass
+        } else {
+            Outcome::Fail
+        }
+    }
+
+    /// Get the outcome of a test of an optional subsystem.
+    ///
+    /// # Args
+    /// * `passed` - Whether the test passed, or `None` if the subsystem is not present.
+    pub fn optional(passed: Option<bool>) -> Self {
+        passed.map_or(Outcome::Skip, Self::check)
+    }
+}
+
+impl core::fmt::Display for Outcome {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let outcome = match self {
+            Outcome::Pass => "PASS",
+            Outcome::Fail => "FAIL",
+            Outcome::Skip => "SKIP",
+        };
+        f.write_str(outcome)
+    }
+}
+
+/// Aggregate the outcomes of the individual self-tests.
+///
+/// # Args
+/// * `outcomes` - The outcome of each subsystem.
+///
+/// # Returns
+/// [Outcome::Fail] if any subsystem failed, [Outcome::Pass] if at least one subsystem passed
+/// and [Outcome::Skip] if all subsystems were skipped.
+pub fn overall(outcomes: impl IntoIterator<Item = Outcome>) -> Outcome {
+    outcomes
+        .into_iter()
+        .fold(Outcome::Skip, |overall, outcome| match (overall, outcome) {
+            (Outcome::Fail, _) | (_, Outcome::Fail) => Outcome::Fail,
+            (Outcome::Pass, _) | (_, Outcome::Pass) => Outcome::Pass,
+            (Outcome::Skip, Outcome::Skip) => Outcome::Skip,
+        })
+}
+
+/// Check that a CPU die temperature reading is plausible.
+///
+/// # Args
+/// * `temperature` - The temperature in degrees Celsius.
+pub fn cpu_temperature_plausible(temperature: f32) -> bool {
+    CPU_TEMPERATURE_RANGE.contains(&temperature)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn outcome_of_checks() {
+        assert_eq!(Outcome::check(true), Outcome::Pass);
+        assert_eq!(Outcome::check(false), Outcome::Fail);
+        assert_eq!(Outcome::optional(Some(true)), Outcome::Pass);
+        assert_eq!(Outcome::optional(Some(false)), Outcome::Fail);
+        assert_eq!(Outcome::optional(None), Outcome::Skip);
+    }
+
+    #[test]
+    fn overall_outcome_aggregates_subsystems() {
+        use Outcome::*;
+
+        assert_eq!(overall([]), Skip);
+        assert_eq!(overall([Skip, Skip]), Skip);
+        assert_eq!(overall([Pass, Skip, Pass]), Pass);
+        assert_eq!(overall([Skip, Pass]), Pass);
+        assert_eq!(overall([Pass, Fail, Skip]), Fail);
+        assert_eq!(overall([Fail, Pass]), Fail);
+        assert_eq!(overall([Skip, Fail]), Fail);
+    }
+
+    #[test]
+    fn cpu_temperature_range() {
+        assert!(cpu_temperature_plausible(25.));
+        assert!(cpu_temperature_plausible(-40.));
+        assert!(cpu_temperature_plausible(125.));
+        assert!(!cpu_temperature_plausible(126.));
+        assert!(!cpu_temperature_plausible(f32::NAN));
+    }
+}
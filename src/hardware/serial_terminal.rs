@@ -1,10 +1,18 @@
-use super::UsbBus;
+use super::{log_buffer, pounder::Channel, UsbBus};
+use crate::version::VersionInfo;
 use core::fmt::Write;
 
-static OUTPUT_BUFFER: bbqueue::BBBuffer<512> = bbqueue::BBBuffer::new();
+/// The size of the output buffer in bytes. It holds a complete log dump.
+const OUTPUT_CAPACITY: usize = 2048;
+
+static OUTPUT_BUFFER: bbqueue::BBBuffer<OUTPUT_CAPACITY> =
+    bbqueue::BBBuffer::new();
+
+/// The maximum length of a line of input in bytes, excluding the line terminator.
+const LINE_CAPACITY: usize = 256;
 
 pub struct OutputBuffer {
-    producer: bbqueue::Producer<'static, 512>,
+    producer: bbqueue::Producer<'static, OUTPUT_CAPACITY>,
 }
 
 impl Write for OutputBuffer {
@@ -25,11 +33,580 @@ impl Write for OutputBuffer {
     }
 }
 
+/// A command received over the serial terminal.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Command {
+    /// `dds freq <channel> <hz>`: Set the frequency of a pounder DDS channel.
+    DdsFrequency(Channel, f32),
+
+    /// `dds read <channel>`: Report the current settings of a pounder DDS channel.
+    DdsRead(Channel),
+
+    /// `dds reg write <addr_hex> <bytes_hex>`: Write raw bytes to a pounder DDS register.
+    DdsRegisterWrite(u8, RegisterData),
+
+    /// `dds reg read <addr_hex> <len>`: Read raw bytes from a pounder DDS register.
+    DdsRegisterRead(u8, usize),
+
+    /// `status`: Report the pounder attenuator, DDS and clock state as a single line of JSON.
+    Status,
+
+    /// `clock onboard`: Select the pounder on-board reference oscillator.
+    ClockOnboard,
+
+    /// `clock external <hz> [mult]`: Select the pounder external reference clock input with the
+    /// given reference frequency and optional DDS PLL multiplier. The PLL is bypassed if no
+    /// multiplier is given.
+    ClockExternal(f32, Option<u8>),
+
+    /// `get <key>`: Report the value of a setting.
+    Get(Key),
+
+    /// `set <key> <value>`: Change the value of a setting.
+    Set(Setting),
+
+    /// `save`: Persist the servo settings to EEPROM.
+    Save,
+
+    /// `load`: Restore the servo settings from EEPROM.
+    Load,
+
+    /// `list`: Report the values of all settings.
+    List,
+
+    /// `log <level>`: Set the maximum log level, one of `off`, `error`, `warn`, `info`, `debug`
+    /// or `trace`. Release builds only contain messages up to `info`.
+    LogLevel(log::LevelFilter),
+
+    /// `log?`: Report the maximum log level.
+    LogLevelQuery,
+
+    /// `log dump`: Report the most recent log lines, oldest first.
+    LogDump,
+
+    /// `temp`: Report the CPU die temperature and, if pounder is present, the pounder
+    /// temperature.
+    Temperature,
+
+    /// `dac set <0|1> <volts|off>`: Override a DAC output with a static voltage, clamped to its
+    /// soft limits, or return control of the output to the servo.
+    DacSet(usize, Option<f32>),
+
+    /// `cal dac <0|1> <gain> <offset>`: Set the calibration of a DAC output, i.e. its gain in
+    /// codes per volt and its offset in volts, used by `dac set`.
+    CalDac(usize, f32, f32),
+
+    /// `cal adc <0|1> <gain> <offset>`: Set the calibration of an ADC input, i.e. the ratio of
+    /// the actual to the nominal input voltage and the offset in volts, used in telemetry.
+    CalAdc(usize, f32, f32),
+
+    /// `cal power <channel> <dbm>`: Set the output power of a pounder channel at full-scale DDS
+    /// amplitude and without attenuation.
+    CalPower(Channel, f32),
+
+    /// `cal flatness <hz> <db>`: Add a point to the pounder output flatness calibration, the
+    /// output power at a frequency relative to the full-scale power.
+    CalFlatness(f32, f32),
+
+    /// `cal flatness clear`: Remove all points of the pounder output flatness calibration.
+    CalFlatnessClear,
+
+    /// `cal save`: Persist the calibrations to EEPROM, including the pounder calibrations if
+    /// pounder is present.
+    CalSave,
+
+    /// `lock tare`: Take the latest beat note frequency as the servo setpoint.
+    LockTare,
+
+    /// `selftest`: Test the major subsystems and report a PASS, FAIL or SKIP line per subsystem
+    /// and the overall result.
+    SelfTest,
+}
+
+/// The key of a setting accessible over the serial terminal.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Key {
+    /// `setpoint`: The servo setpoint in Hz.
+    Setpoint,
+
+    /// `kp`: The servo proportional gain.
+    Kp,
+
+    /// `ki`: The servo integral gain.
+    Ki,
+
+    /// `attenuation/<channel>`: The attenuation of a pounder channel in dB.
+    Attenuation(Channel),
+
+    /// `clock`: The pounder DDS reference clock source.
+    Clock,
+}
+
+impl Key {
+    /// All keys, in the order they are reported by `list`.
+    pub const ALL: [Key; 8] = [
+        Key::Setpoint,
+        Key::Kp,
+        Key::Ki,
+        Key::Attenuation(Channel::In0),
+        Key::Attenuation(Channel::Out0),
+        Key::Attenuation(Channel::In1),
+        Key::Attenuation(Channel::Out1),
+        Key::Clock,
+    ];
+
+    /// Check whether the setting is a pounder setting, which requires pounder to be present.
+    pub fn is_pounder(&self) -> bool {
+        matches!(self, Key::Attenuation(_) | Key::Clock)
+    }
+
+    fn parse(arg: Option<&str>) -> Result<Self, ParseError> {
+        let key = arg.ok_or(ParseError::MissingArgument)?;
+        match key.split_once('/') {
+            None => match key {
+                "setpoint" => Ok(Key::Setpoint),
+                "kp" => Ok(Key::Kp),
+                "ki" => Ok(Key::Ki),
+                "clock" => Ok(Key::Clock),
+                _ => Err(ParseError::UnknownKey),
+            },
+            Some(("attenuation", channel)) => {
+                Ok(Key::Attenuation(parse_channel(Some(channel))?))
+            }
+            Some(_) => Err(ParseError::UnknownKey),
+        }
+    }
+}
+
+impl core::fmt::Display for Key {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Key::Setpoint => f.write_str("setpoint"),
+            Key::Kp => f.write_str("kp"),
+            Key::Ki => f.write_str("ki"),
+            Key::Attenuation(channel) => {
+                let channel = match channel {
+                    Channel::In0 => "in0",
+                    Channel::Out0 => "out0",
+                    Channel::In1 => "in1",
+                    Channel::Out1 => "out1",
+                };
+                write!(f, "attenuation/{channel}")
+            }
+            Key::Clock => f.write_str("clock"),
+        }
+    }
+}
+
+/// The pounder DDS reference clock source.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ClockSource {
+    /// The on-board reference oscillator.
+    Onboard,
+
+    /// The external reference clock input with its frequency in Hz and an optional DDS PLL
+    /// multiplier.
+    External(f32, Option<u8>),
+}
+
+/// The raw contents of a DDS register.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct RegisterData {
+    data: [u8; RegisterData::CAPACITY],
+    len: usize,
+}
+
+impl RegisterData {
+    /// The maximum number of bytes, which is the width of the widest DDS register.
+    pub const CAPACITY: usize = 4;
+
+    /// Parse register contents from a string of hexadecimal digits, most significant byte first.
+    ///
+    /// # Note
+    /// Each byte must be given as two digits, i.e. the number of digits must be even.
+    ///
+    /// # Args
+    /// * `arg` - The hexadecimal digits, optionally prefixed with `0x`.
+    pub fn parse(arg: Option<&str>) -> Result<Self, ParseError> {
+        let arg = strip_hex_prefix(arg.ok_or(ParseError::MissingArgument)?);
+        if arg.is_empty() || arg.len() % 2 != 0 {
+            return Err(ParseError::InvalidNumber);
+        }
+
+        let len = arg.len() / 2;
+        if len > Self::CAPACITY {
+            return Err(ParseError::InvalidValue);
+        }
+
+        let mut data = [0; Self::CAPACITY];
+        for (byte, digits) in data.iter_mut().zip(arg.as_bytes().chunks(2)) {
+            // Note: Non-ASCII input may be split within a character, which is rejected as well.
+            *byte = core::str::from_utf8(digits)
+                .ok()
+                .and_then(|digits| u8::from_str_radix(digits, 16).ok())
+                .ok_or(ParseError::InvalidNumber)?;
+        }
+
+        Ok(Self { data, len })
+    }
+
+    /// Get the register contents.
+    pub fn as_slice(&self) -> &[u8] {
+        &self.data[..self.len]
+    }
+}
+
+/// A setting together with its new value.
+///
+/// # Note
+/// The value syntax of each key is:
+/// * `setpoint`, `kp`, `ki`, `attenuation/<channel>`: A number.
+/// * `clock`: `onboard` or `external <hz> [mult]`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Setting {
+    Setpoint(f32),
+    Kp(f32),
+    Ki(f32),
+    Attenuation(Channel, f32),
+    Clock(ClockSource),
+}
+
+/// Errors encountered while parsing a serial terminal command.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    UnknownCommand,
+    MissingArgument,
+    UnexpectedArgument,
+    InvalidChannel,
+    InvalidNumber,
+    UnknownKey,
+    InvalidValue,
+}
+
+impl core::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let message = match self {
+            ParseError::UnknownCommand => "unknown command",
+            ParseError::MissingArgument => "missing argument",
+            ParseError::UnexpectedArgument => "too many arguments",
+            ParseError::InvalidChannel => {
+                "invalid channel (expected in0, out0, in1 or out1)"
+            }
+            ParseError::InvalidNumber => "invalid number",
+            ParseError::UnknownKey => {
+                "unknown key (use `list` to show all keys)"
+            }
+            ParseError::InvalidValue => "invalid value",
+        };
+        f.write_str(message)
+    }
+}
+
+impl Command {
+    /// Parse a command from a single line of terminal input.
+    ///
+    /// # Args
+    /// * `line` - The line of input, without the line terminator.
+    pub fn parse(line: &str) -> Result<Self, ParseError> {
+        let mut args = line.split_whitespace();
+
+        let command = match (args.next(), args.next()) {
+            (Some("dds"), Some("freq")) => {
+                let channel = parse_channel(args.next())?;
+                let frequency = parse_number(args.next())?;
+                if frequency < 0. {
+                    return Err(ParseError::InvalidValue);
+                }
+                Command::DdsFrequency(channel, frequency)
+            }
+            (Some("dds"), Some("read")) => {
+                Command::DdsRead(parse_channel(args.next())?)
+            }
+            (Some("dds"), Some("reg")) => match args.next() {
+                Some("write") => {
+                    let address = parse_register_address(args.next())?;
+                    Command::DdsRegisterWrite(
+                        address,
+                        RegisterData::parse(args.next())?,
+                    )
+                }
+                Some("read") => {
+                    let address = parse_register_address(args.next())?;
+                    let len = args
+                        .next()
+                        .ok_or(ParseError::MissingArgument)?
+                        .parse()
+                        .or(Err(ParseError::InvalidNumber))?;
+                    if !(1..=RegisterData::CAPACITY).contains(&len) {
+                        return Err(ParseError::InvalidValue);
+                    }
+                    Command::DdsRegisterRead(address, len)
+                }
+                Some(_) => return Err(ParseError::UnknownCommand),
+                None => return Err(ParseError::MissingArgument),
+            },
+            (Some("status"), None) => Command::Status,
+            (Some("clock"), Some("onboard")) => Command::ClockOnboard,
+            (Some("clock"), Some("external")) => {
+                let (frequency, multiplier) = parse_external_clock(&mut args)?;
+                Command::ClockExternal(frequency, multiplier)
+            }
+            (Some("get"), key) => Command::Get(Key::parse(key)?),
+            (Some("set"), key) => {
+                Command::Set(parse_setting(Key::parse(key)?, &mut args)?)
+            }
+            (Some("save"), None) => Command::Save,
+            (Some("load"), None) => Command::Load,
+            (Some("list"), None) => Command::List,
+            (Some("log"), Some("dump")) => Command::LogDump,
+            (Some("log"), level) => Command::LogLevel(parse_log_level(level)?),
+            (Some("log?"), None) => Command::LogLevelQuery,
+            (Some("temp"), None) => Command::Temperature,
+            (Some("selftest"), None) => Command::SelfTest,
+            (Some("lock"), Some("tare")) => Command::LockTare,
+            (Some("dac"), Some("set")) => {
+                let channel = parse_index(args.next())?;
+                let volts = match args.next() {
+                    Some("off") => None,
+                    arg => Some(parse_number(arg)?),
+                };
+                Command::DacSet(channel, volts)
+            }
+            (Some("cal"), Some("dac")) => {
+                let channel = parse_index(args.next())?;
+                let gain = parse_number(args.next())?;
+                if gain <= 0. {
+                    return Err(ParseError::InvalidValue);
+                }
+                Command::CalDac(channel, gain, parse_number(args.next())?)
+            }
+            (Some("cal"), Some("adc")) => {
+                let channel = parse_index(args.next())?;
+                let gain = parse_number(args.next())?;
+                if gain <= 0. {
+                    return Err(ParseError::InvalidValue);
+                }
+                Command::CalAdc(channel, gain, parse_number(args.next())?)
+            }
+            (Some("cal"), Some("power")) => {
+                let channel = parse_channel(args.next())?;
+                Command::CalPower(channel, parse_number(args.next())?)
+            }
+            (Some("cal"), Some("flatness")) => match args.next() {
+                Some("clear") => Command::CalFlatnessClear,
+                arg => {
+                    let frequency = parse_number(arg)?;
+                    if frequency < 0. {
+                        return Err(ParseError::InvalidValue);
+                    }
+                    Command::CalFlatness(frequency, parse_number(args.next())?)
+                }
+            },
+            (Some("cal"), Some("save")) => Command::CalSave,
+            _ => return Err(ParseError::UnknownCommand),
+        };
+
+        if args.next().is_some() {
+            return Err(ParseError::UnexpectedArgument);
+        }
+
+        Ok(command)
+    }
+}
+
+/// Write a temperature reading as a line of terminal output.
+///
+/// # Args
+/// * `output` - The terminal output to write into.
+/// * `sensor` - The name of the sensor.
+/// * `temperature` - The temperature in degrees Celsius, or the error if the sensor could not be
+///   read.
+pub fn write_temperature<E>(
+    output: &mut impl Write,
+    sensor: &str,
+    temperature: Result<f32, E>,
+) -> core::fmt::Result {
+    match temperature {
+        Ok(celsius) => writeln!(output, "{sensor}: {celsius:.1} °C"),
+        Err(_) => writeln!(output, "{sensor}: unavailable"),
+    }
+}
+
+/// Write the most recent log lines as terminal output.
+///
+/// # Args
+/// * `output` - The terminal output to write into.
+pub fn write_log_dump(output: &mut OutputBuffer) -> core::fmt::Result {
+    match log_buffer::LOG_BUFFER.snapshot() {
+        Some(snapshot) => snapshot
+            .lines()
+            .try_for_each(|line| writeln!(output, "{line}")),
+        None => writeln!(output, "error: log buffer busy"),
+    }
+}
+
+fn parse_number(arg: Option<&str>) -> Result<f32, ParseError> {
+    arg.ok_or(ParseError::MissingArgument)?
+        .parse()
+        .ok()
+        .filter(|value: &f32| value.is_finite())
+        .ok_or(ParseError::InvalidNumber)
+}
+
+/// Parse the index of an ADC or DAC channel, `0` or `1`.
+fn parse_index(arg: Option<&str>) -> Result<usize, ParseError> {
+    match arg {
+        Some("0") => Ok(0),
+        Some("1") => Ok(1),
+        Some(_) => Err(ParseError::InvalidValue),
+        None => Err(ParseError::MissingArgument),
+    }
+}
+
+fn parse_external_clock<'a>(
+    args: &mut impl Iterator<Item = &'a str>,
+) -> Result<(f32, Option<u8>), ParseError> {
+    let frequency = parse_number(args.next())?;
+    let multiplier = args
+        .next()
+        .map(str::parse)
+        .transpose()
+        .or(Err(ParseError::InvalidNumber))?;
+    Ok((frequency, multiplier))
+}
+
+fn parse_setting<'a>(
+    key: Key,
+    args: &mut impl Iterator<Item = &'a str>,
+) -> Result<Setting, ParseError> {
+    let setting = match key {
+        Key::Setpoint => Setting::Setpoint(parse_number(args.next())?),
+        Key::Kp => Setting::Kp(parse_number(args.next())?),
+        Key::Ki => Setting::Ki(parse_number(args.next())?),
+        Key::Attenuation(channel) => {
+            Setting::Attenuation(channel, parse_number(args.next())?)
+        }
+        Key::Clock => match args.next().ok_or(ParseError::MissingArgument)? {
+            "onboard" => Setting::Clock(ClockSource::Onboard),
+            "external" => {
+                let (frequency, multiplier) = parse_external_clock(args)?;
+                Setting::Clock(ClockSource::External(frequency, multiplier))
+            }
+            _ => return Err(ParseError::InvalidValue),
+        },
+    };
+
+    Ok(setting)
+}
+
+fn strip_hex_prefix(arg: &str) -> &str {
+    arg.strip_prefix("0x")
+        .or_else(|| arg.strip_prefix("0X"))
+        .unwrap_or(arg)
+}
+
+// Addresses with the read bit (0x80) set are accepted here and rejected by the DDS interface.
+fn parse_register_address(arg: Option<&str>) -> Result<u8, ParseError> {
+    let arg = strip_hex_prefix(arg.ok_or(ParseError::MissingArgument)?);
+    u8::from_str_radix(arg, 16).or(Err(ParseError::InvalidNumber))
+}
+
+fn parse_log_level(arg: Option<&str>) -> Result<log::LevelFilter, ParseError> {
+    match arg.ok_or(ParseError::MissingArgument)? {
+        "off" => Ok(log::LevelFilter::Off),
+        "error" => Ok(log::LevelFilter::Error),
+        "warn" => Ok(log::LevelFilter::Warn),
+        "info" => Ok(log::LevelFilter::Info),
+        "debug" => Ok(log::LevelFilter::Debug),
+        "trace" => Ok(log::LevelFilter::Trace),
+        _ => Err(ParseError::InvalidValue),
+    }
+}
+
+fn parse_channel(arg: Option<&str>) -> Result<Channel, ParseError> {
+    match arg.ok_or(ParseError::MissingArgument)? {
+        "in0" => Ok(Channel::In0),
+        "out0" => Ok(Channel::Out0),
+        "in1" => Ok(Channel::In1),
+        "out1" => Ok(Channel::Out1),
+        _ => Err(ParseError::InvalidChannel),
+    }
+}
+
+/// The outcome of a byte of terminal input.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum LineEvent {
+    /// The byte was appended to the line and should be echoed.
+    Echo,
+
+    /// The byte terminated a line, which is available from [LineAssembler::line()].
+    Complete,
+
+    /// The line exceeded the line buffer and is discarded up to the next line terminator.
+    Overflow,
+
+    /// The byte was ignored.
+    Ignored,
+}
+
+/// Assembles terminal input into lines, independent of how the input is split into packets.
+struct LineAssembler {
+    line: heapless::Vec<u8, LINE_CAPACITY>,
+    discarding: bool,
+}
+
+impl LineAssembler {
+    const fn new() -> Self {
+        Self {
+            line: heapless::Vec::new(),
+            discarding: false,
+        }
+    }
+
+    /// Handle a single byte of input.
+    ///
+    /// # Note
+    /// If a line exceeds the line buffer, it is discarded up to the next line terminator. Empty
+    /// lines, e.g. the second half of CRLF line endings, are ignored.
+    fn push(&mut self, value: u8) -> LineEvent {
+        match value {
+            b'\r' | b'\n' if self.discarding => {
+                self.discarding = false;
+                LineEvent::Ignored
+            }
+            _ if self.discarding => LineEvent::Ignored,
+            b'\r' | b'\n' if self.line.is_empty() => LineEvent::Ignored,
+            b'\r' | b'\n' => LineEvent::Complete,
+            value => {
+                if self.line.push(value).is_ok() {
+                    LineEvent::Echo
+                } else {
+                    self.line.clear();
+                    self.discarding = true;
+                    LineEvent::Overflow
+                }
+            }
+        }
+    }
+
+    /// Get the completed line. Invalid UTF-8 is returned as an empty line.
+    fn line(&self) -> &str {
+        core::str::from_utf8(&self.line).unwrap_or("")
+    }
+
+    /// Start a new line.
+    fn clear(&mut self) {
+        self.line.clear();
+    }
+}
+
 pub struct SerialTerminal {
     usb_device: usb_device::device::UsbDevice<'static, UsbBus>,
     usb_serial: usbd_serial::SerialPort<'static, UsbBus>,
-    output: bbqueue::Consumer<'static, 512>,
+    output: bbqueue::Consumer<'static, OUTPUT_CAPACITY>,
     buffer: OutputBuffer,
+    assembler: LineAssembler,
+    configured: bool,
 }
 
 impl SerialTerminal {
@@ -44,6 +621,8 @@ impl SerialTerminal {
             usb_device,
             usb_serial,
             output: consumer,
+            assembler: LineAssembler::new(),
+            configured: false,
         }
     }
 
@@ -68,10 +647,75 @@ impl SerialTerminal {
         self.usb_device.state() == usb_device::device::UsbDeviceState::Suspend
     }
 
-    pub fn process(&mut self) {
+    /// Handle a single line of terminal input.
+    fn handle_line<F>(&mut self, handler: &mut F)
+    where
+        F: FnMut(Command, &mut OutputBuffer),
+    {
+        let line = self.assembler.line();
+        if !line.trim().is_empty() {
+            match Command::parse(line) {
+                Ok(command) => handler(command, &mut self.buffer),
+                Err(err) => writeln!(self.buffer, "error: {err}").unwrap(),
+            }
+        }
+
+        self.assembler.clear();
+    }
+
+    /// Handle a single byte of terminal input.
+    ///
+    /// # Note
+    /// If a line exceeds the line buffer, it is discarded up to the next line terminator and an
+    /// error is reported once. Discarded input is not echoed.
+    fn handle_byte<F>(&mut self, value: u8, handler: &mut F)
+    where
+        F: FnMut(Command, &mut OutputBuffer),
+    {
+        match self.assembler.push(value) {
+            LineEvent::Echo => {
+                write!(self.buffer, "{}", value as char).unwrap();
+            }
+            LineEvent::Complete => {
+                writeln!(self.buffer).unwrap();
+                self.handle_line(handler);
+            }
+            LineEvent::Overflow => {
+                writeln!(self.buffer, "\nerror: line too long").unwrap();
+            }
+            LineEvent::Ignored => {}
+        }
+    }
+
+    /// Process the serial terminal.
+    ///
+    /// # Note
+    /// Input is echoed back and collected into lines. Partial lines are buffered across USB
+    /// packets until a line terminator is received. Lines longer than the line buffer are
+    /// discarded with an error. Each complete line is parsed into a [Command] and passed to
+    /// `handler` for execution. Parsing errors are reported on the terminal. The firmware
+    /// [VersionInfo] is written whenever the host configures the device.
+    ///
+    /// # Args
+    /// * `handler` - A function that executes commands. It is provided an output buffer to write
+    ///   the command response into.
+    pub fn process<F>(&mut self, mut handler: F)
+    where
+        F: FnMut(Command, &mut OutputBuffer),
+    {
         self.flush();
 
-        if !self.usb_device.poll(&mut [&mut self.usb_serial]) {
+        let has_data = self.usb_device.poll(&mut [&mut self.usb_serial]);
+
+        // Greet the host with the boot banner whenever the device is (re-)configured.
+        let configured = self.usb_device.state()
+            == usb_device::device::UsbDeviceState::Configured;
+        if configured && !self.configured {
+            writeln!(self.buffer, "{}", VersionInfo::BUILD).unwrap();
+        }
+        self.configured = configured;
+
+        if !has_data {
             return;
         }
 
@@ -79,7 +723,7 @@ impl SerialTerminal {
         match self.usb_serial.read(&mut buffer) {
             Ok(count) => {
                 for &value in &buffer[..count] {
-                    writeln!(self.buffer, "echo: {}", value as char).unwrap();
+                    self.handle_byte(value, &mut handler);
                 }
             }
 
@@ -94,3 +738,390 @@ impl SerialTerminal {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hardware::shared_adc::AdcError;
+
+    /// Feed input to an assembler and collect the completed lines.
+    fn assemble(
+        assembler: &mut LineAssembler,
+        input: &[u8],
+        lines: &mut heapless::Vec<heapless::String<LINE_CAPACITY>, 4>,
+    ) -> heapless::Vec<LineEvent, 512> {
+        let mut events = heapless::Vec::new();
+        for &value in input {
+            let event = assembler.push(value);
+            if event == LineEvent::Complete {
+                lines.push(assembler.line().into()).unwrap();
+                assembler.clear();
+            }
+            events.push(event).unwrap();
+        }
+        events
+    }
+
+    #[test]
+    fn line_split_across_packets() {
+        let mut assembler = LineAssembler::new();
+        let mut lines = heapless::Vec::new();
+
+        assemble(&mut assembler, b"get k", &mut lines);
+        assert!(lines.is_empty());
+        assemble(&mut assembler, b"p\r", &mut lines);
+        assemble(&mut assembler, b"\nsave\r\n", &mut lines);
+
+        assert_eq!(lines.as_slice(), ["get kp", "save"]);
+    }
+
+    #[test]
+    fn empty_lines_are_ignored() {
+        let mut assembler = LineAssembler::new();
+        let mut lines = heapless::Vec::new();
+
+        let events = assemble(&mut assembler, b"\r\n\n\r", &mut lines);
+        assert!(events.iter().all(|&event| event == LineEvent::Ignored));
+        assert!(lines.is_empty());
+    }
+
+    #[test]
+    fn long_line_is_discarded() {
+        let mut assembler = LineAssembler::new();
+        let mut lines = heapless::Vec::new();
+
+        let long = [b'x'; LINE_CAPACITY + 10];
+        let events = assemble(&mut assembler, &long, &mut lines);
+        assert_eq!(
+            events.iter().filter(|&&e| e == LineEvent::Overflow).count(),
+            1
+        );
+        assert_eq!(events[LINE_CAPACITY], LineEvent::Overflow);
+
+        // The rest of the line is dropped up to the terminator, then input resumes.
+        assemble(&mut assembler, b"\nlist\n", &mut lines);
+        assert_eq!(lines.as_slice(), ["list"]);
+    }
+
+    #[test]
+    fn dispatch_settings_commands() {
+        assert_eq!(Command::parse("get kp"), Ok(Command::Get(Key::Kp)));
+        assert_eq!(
+            Command::parse("set setpoint 1.5e6"),
+            Ok(Command::Set(Setting::Setpoint(1.5e6)))
+        );
+        assert_eq!(
+            Command::parse("set attenuation/out1 10"),
+            Ok(Command::Set(Setting::Attenuation(Channel::Out1, 10.)))
+        );
+        assert_eq!(
+            Command::parse("set clock external 10e6 4"),
+            Ok(Command::Set(Setting::Clock(ClockSource::External(
+                10e6,
+                Some(4)
+            ))))
+        );
+        assert_eq!(
+            Command::parse("set clock onboard"),
+            Ok(Command::Set(Setting::Clock(ClockSource::Onboard)))
+        );
+        assert_eq!(Command::parse("save"), Ok(Command::Save));
+        assert_eq!(Command::parse("load"), Ok(Command::Load));
+        assert_eq!(Command::parse("list"), Ok(Command::List));
+    }
+
+    #[test]
+    fn settings_keys_round_trip() {
+        for key in Key::ALL {
+            let mut name: heapless::String<32> = heapless::String::new();
+            write!(name, "{key}").unwrap();
+            assert_eq!(Key::parse(Some(&name)), Ok(key));
+        }
+    }
+
+    #[test]
+    fn reject_malformed_settings() {
+        assert_eq!(Command::parse("get"), Err(ParseError::MissingArgument));
+        assert_eq!(Command::parse("get foo"), Err(ParseError::UnknownKey));
+        assert_eq!(
+            Command::parse("get attenuation/in5"),
+            Err(ParseError::InvalidChannel)
+        );
+        assert_eq!(Command::parse("set kp"), Err(ParseError::MissingArgument));
+        assert_eq!(
+            Command::parse("set kp fast"),
+            Err(ParseError::InvalidNumber)
+        );
+        assert_eq!(
+            Command::parse("set clock internal"),
+            Err(ParseError::InvalidValue)
+        );
+        assert_eq!(
+            Command::parse("set kp 1 2"),
+            Err(ParseError::UnexpectedArgument)
+        );
+        assert_eq!(
+            Command::parse("frobnicate"),
+            Err(ParseError::UnknownCommand)
+        );
+    }
+
+    #[test]
+    fn dds_commands() {
+        assert_eq!(
+            Command::parse("dds freq out0 100e6"),
+            Ok(Command::DdsFrequency(Channel::Out0, 100e6))
+        );
+        assert_eq!(
+            Command::parse("  dds   freq in1 0 "),
+            Ok(Command::DdsFrequency(Channel::In1, 0.))
+        );
+        assert_eq!(
+            Command::parse("dds read in0"),
+            Ok(Command::DdsRead(Channel::In0))
+        );
+    }
+
+    #[test]
+    fn reject_malformed_dds_commands() {
+        assert_eq!(
+            Command::parse("dds freq out0"),
+            Err(ParseError::MissingArgument)
+        );
+        assert_eq!(
+            Command::parse("dds freq out2 1e6"),
+            Err(ParseError::InvalidChannel)
+        );
+        assert_eq!(
+            Command::parse("dds freq out0 1MHz"),
+            Err(ParseError::InvalidNumber)
+        );
+        assert_eq!(
+            Command::parse("dds freq out0 1e6 2e6"),
+            Err(ParseError::UnexpectedArgument)
+        );
+        assert_eq!(
+            Command::parse("dds read"),
+            Err(ParseError::MissingArgument)
+        );
+        assert_eq!(Command::parse("dds"), Err(ParseError::UnknownCommand));
+    }
+
+    #[test]
+    fn reject_out_of_range_dds_frequency() {
+        for arg in ["-1", "nan", "inf", "-inf"] {
+            let mut line: heapless::String<32> = heapless::String::new();
+            write!(line, "dds freq out0 {arg}").unwrap();
+            assert!(Command::parse(&line).is_err(), "{arg}");
+        }
+    }
+
+    #[test]
+    fn calibration_commands() {
+        assert_eq!(
+            Command::parse("cal dac 1 3276.8 -0.01"),
+            Ok(Command::CalDac(1, 3276.8, -0.01))
+        );
+        assert_eq!(
+            Command::parse("cal adc 0 1.01 2e-3"),
+            Ok(Command::CalAdc(0, 1.01, 2e-3))
+        );
+        assert_eq!(
+            Command::parse("cal power out1 9.5"),
+            Ok(Command::CalPower(Channel::Out1, 9.5))
+        );
+        assert_eq!(
+            Command::parse("cal flatness 100e6 -1.5"),
+            Ok(Command::CalFlatness(100e6, -1.5))
+        );
+        assert_eq!(
+            Command::parse("cal flatness clear"),
+            Ok(Command::CalFlatnessClear)
+        );
+        assert_eq!(Command::parse("cal save"), Ok(Command::CalSave));
+        assert_eq!(
+            Command::parse("cal flatness -1 0"),
+            Err(ParseError::InvalidValue)
+        );
+        assert_eq!(
+            Command::parse("cal power out4 0"),
+            Err(ParseError::InvalidChannel)
+        );
+        assert_eq!(
+            Command::parse("cal dac 2 3276.8 0"),
+            Err(ParseError::InvalidValue)
+        );
+        assert_eq!(
+            Command::parse("cal dac 0 0 0"),
+            Err(ParseError::InvalidValue)
+        );
+        assert_eq!(
+            Command::parse("cal adc 1 -1 0"),
+            Err(ParseError::InvalidValue)
+        );
+        assert_eq!(
+            Command::parse("cal dac 0 3276.8"),
+            Err(ParseError::MissingArgument)
+        );
+        assert_eq!(
+            Command::parse("cal save now"),
+            Err(ParseError::UnexpectedArgument)
+        );
+        assert_eq!(
+            Command::parse("dac set 1 off"),
+            Ok(Command::DacSet(1, None))
+        );
+    }
+
+    #[test]
+    fn log_levels() {
+        for (name, level) in [
+            ("off", log::LevelFilter::Off),
+            ("error", log::LevelFilter::Error),
+            ("warn", log::LevelFilter::Warn),
+            ("info", log::LevelFilter::Info),
+            ("debug", log::LevelFilter::Debug),
+            ("trace", log::LevelFilter::Trace),
+        ] {
+            let mut line: heapless::String<16> = heapless::String::new();
+            write!(line, "log {name}").unwrap();
+            assert_eq!(Command::parse(&line), Ok(Command::LogLevel(level)));
+        }
+        assert_eq!(Command::parse("log?"), Ok(Command::LogLevelQuery));
+    }
+
+    #[test]
+    fn reject_invalid_log_levels() {
+        assert_eq!(Command::parse("log"), Err(ParseError::MissingArgument));
+        assert_eq!(
+            Command::parse("log verbose"),
+            Err(ParseError::InvalidValue)
+        );
+        assert_eq!(Command::parse("log INFO"), Err(ParseError::InvalidValue));
+        assert_eq!(
+            Command::parse("log info debug"),
+            Err(ParseError::UnexpectedArgument)
+        );
+        assert_eq!(
+            Command::parse("log? info"),
+            Err(ParseError::UnknownCommand)
+        );
+    }
+
+    #[test]
+    fn dds_register_commands() {
+        let Ok(Command::DdsRegisterWrite(address, data)) =
+            Command::parse("dds reg write 0x04 0x0123abCD")
+        else {
+            panic!("register write not parsed");
+        };
+        assert_eq!(address, 0x04);
+        assert_eq!(data.as_slice(), [0x01, 0x23, 0xAB, 0xCD]);
+
+        let Ok(Command::DdsRegisterWrite(address, data)) =
+            Command::parse("dds reg write 1A 7f")
+        else {
+            panic!("register write not parsed");
+        };
+        assert_eq!(address, 0x1A);
+        assert_eq!(data.as_slice(), [0x7F]);
+
+        assert_eq!(
+            Command::parse("dds reg read 0X0a 3"),
+            Ok(Command::DdsRegisterRead(0x0A, 3))
+        );
+    }
+
+    #[test]
+    fn reject_malformed_dds_register_commands() {
+        // Odd number of digits.
+        assert_eq!(
+            Command::parse("dds reg write 04 123"),
+            Err(ParseError::InvalidNumber)
+        );
+        assert_eq!(
+            Command::parse("dds reg write 04 0x"),
+            Err(ParseError::InvalidNumber)
+        );
+        assert_eq!(
+            Command::parse("dds reg write 04 0g"),
+            Err(ParseError::InvalidNumber)
+        );
+
+        // Out of range.
+        assert_eq!(
+            Command::parse("dds reg write 04 0102030405"),
+            Err(ParseError::InvalidValue)
+        );
+        assert_eq!(
+            Command::parse("dds reg write 100 00"),
+            Err(ParseError::InvalidNumber)
+        );
+        assert_eq!(
+            Command::parse("dds reg read 04 0"),
+            Err(ParseError::InvalidValue)
+        );
+        assert_eq!(
+            Command::parse("dds reg read 04 5"),
+            Err(ParseError::InvalidValue)
+        );
+        assert_eq!(
+            Command::parse("dds reg read 04 -1"),
+            Err(ParseError::InvalidNumber)
+        );
+
+        assert_eq!(
+            Command::parse("dds reg write 04"),
+            Err(ParseError::MissingArgument)
+        );
+        assert_eq!(
+            Command::parse("dds reg peek 04"),
+            Err(ParseError::UnknownCommand)
+        );
+    }
+
+    #[test]
+    fn clock_commands() {
+        assert_eq!(Command::parse("clock onboard"), Ok(Command::ClockOnboard));
+        assert_eq!(
+            Command::parse("clock external 10e6"),
+            Ok(Command::ClockExternal(10e6, None))
+        );
+        assert_eq!(
+            Command::parse("clock external 25e6 20"),
+            Ok(Command::ClockExternal(25e6, Some(20)))
+        );
+
+        assert_eq!(
+            Command::parse("clock external"),
+            Err(ParseError::MissingArgument)
+        );
+        assert_eq!(
+            Command::parse("clock external 10e6 x4"),
+            Err(ParseError::InvalidNumber)
+        );
+        assert_eq!(
+            Command::parse("clock external 10e6 256"),
+            Err(ParseError::InvalidNumber)
+        );
+        assert_eq!(
+            Command::parse("clock external 10e6 4 1"),
+            Err(ParseError::UnexpectedArgument)
+        );
+    }
+
+    #[test]
+    fn temperature_command() {
+        assert_eq!(Command::parse("temp"), Ok(Command::Temperature));
+        assert_eq!(Command::parse("temp cpu"), Err(ParseError::UnknownCommand));
+    }
+
+    #[test]
+    fn temperature_output() {
+        let mut output: heapless::String<64> = heapless::String::new();
+        write_temperature::<AdcError>(&mut output, "cpu", Ok(42.5)).unwrap();
+        write_temperature(&mut output, "pounder", Err(AdcError::InUse))
+            .unwrap();
+        assert_eq!(output.as_str(), "cpu: 42.5 °C\npounder: unavailable\n");
+    }
+}
@@ -6,25 +6,57 @@ use core::{fmt::Write, ptr, slice};
 use stm32h7xx_hal::{
     self as hal,
     ethernet::{self, PHY},
-    gpio::Speed,
+    gpio::{ExtiPin, Speed},
     prelude::*,
+    xspi::XspiExt,
 };
 
+use embedded_hal::blocking::delay::DelayMs;
 use smoltcp_nal::smoltcp;
 
+use crate::version::VersionInfo;
+
 use super::{
     adc, afe, cpu_temp_sensor::CpuTempSensor, dac, delay, design_parameters,
-    eeprom, pounder,
+    eem_gpio::EemGpioDevices, eeprom, log_buffer, pounder,
     pounder::dds_output::DdsOutput, serial_terminal::SerialTerminal,
-    shared_adc::SharedAdc, timers, DigitalInput0, DigitalInput1,
-    EemDigitalInput0, EemDigitalInput1, EemDigitalOutput0, EemDigitalOutput1,
-    EthernetPhy, NetworkStack, SystemTimer, Systick, UsbBus, AFE0, AFE1,
+    shared_adc::SharedAdc, stack, status_leds::FrontPanelLeds, timers,
+    DigitalInput0, DigitalInput1, EthernetPhy, NetworkStack, SystemTimer,
+    Systick, UsbBus, AFE0, AFE1,
 };
 
 const NUM_TCP_SOCKETS: usize = 4;
-const NUM_UDP_SOCKETS: usize = 1;
+const NUM_UDP_SOCKETS: usize = 3;
 const NUM_SOCKETS: usize = NUM_UDP_SOCKETS + NUM_TCP_SOCKETS;
 
+/// The number of CPU cycles to wait for the HSE to become ready (100 ms at the 64 MHz HSI).
+const HSE_STARTUP_CYCLES: u32 = 6_400_000;
+
+/// Configuration of the network sockets.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct NetworkConfig {
+    /// The interval at which keep-alive packets are sent on idle TCP connections, if any.
+    pub tcp_keep_alive: Option<smoltcp::time::Duration>,
+
+    /// The time after which a TCP connection without any response from the remote is aborted,
+    /// if any.
+    ///
+    /// # Note
+    /// An aborted socket is closed and can be reused to connect again. Keep-alive packets are
+    /// required to detect a remote that disappeared while the connection was idle, so the timeout
+    /// should be longer than the keep-alive interval.
+    pub tcp_timeout: Option<smoltcp::time::Duration>,
+}
+
+impl Default for NetworkConfig {
+    fn default() -> Self {
+        Self {
+            tcp_keep_alive: Some(smoltcp::time::Duration::from_secs(10)),
+            tcp_timeout: Some(smoltcp::time::Duration::from_secs(30)),
+        }
+    }
+}
+
 pub struct NetStorage {
     pub ip_addrs: [smoltcp::wire::IpCidr; 1],
 
@@ -74,6 +106,26 @@ impl TcpSocketStorage {
     }
 }
 
+/// Construct a TCP socket with the keep-alive and timeout of the network configuration.
+///
+/// # Args
+/// * `storage` - The buffers of the socket.
+/// * `config` - The network configuration.
+fn tcp_socket<'a>(
+    storage: &'a mut TcpSocketStorage,
+    config: &NetworkConfig,
+) -> smoltcp::socket::tcp::Socket<'a> {
+    let rx_buffer =
+        smoltcp::socket::tcp::SocketBuffer::new(&mut storage.rx_storage[..]);
+    let tx_buffer =
+        smoltcp::socket::tcp::SocketBuffer::new(&mut storage.tx_storage[..]);
+
+    let mut socket = smoltcp::socket::tcp::Socket::new(rx_buffer, tx_buffer);
+    socket.set_keep_alive(config.tcp_keep_alive);
+    socket.set_timeout(config.tcp_timeout);
+    socket
+}
+
 impl Default for NetStorage {
     fn default() -> Self {
         NetStorage {
@@ -94,14 +146,144 @@ pub struct NetworkDevices {
     pub stack: NetworkStack,
     pub phy: EthernetPhy,
     pub mac_address: smoltcp::wire::EthernetAddress,
+
+    /// The address is acquired using DHCP.
+    pub dhcp: bool,
+}
+
+/// A device that did not settle before [StabilizerDevices::wait_ready()] timed out.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum NotReady {
+    /// The AFE supply or the DDS reference clock multiplier is still within its settle time.
+    Settling,
+
+    /// The DAC clear input is still asserted.
+    DacClear,
+}
+
+/// The clock source of the reference timer.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, serde::Serialize)]
+pub enum ReferenceClock {
+    /// The reference timer is clocked from the external reference input on PE7.
+    External,
+
+    /// The reference timer is clocked internally at [design_parameters::TIMER_FREQUENCY], as PE7
+    /// is used by Pounder.
+    Internal,
 }
 
-/// The GPIO pins available on the EEM connector, if Pounder is not present.
-pub struct EemGpioDevices {
-    pub lvds4: EemDigitalInput0,
-    pub lvds5: EemDigitalInput1,
-    pub lvds6: EemDigitalOutput0,
-    pub lvds7: EemDigitalOutput1,
+/// The result of a network self test.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct NetworkStatus {
+    /// The ethernet link is up.
+    pub link_up: bool,
+
+    /// The IPv4 address of the interface, if one is assigned.
+    pub address: Option<smoltcp::wire::Ipv4Address>,
+
+    /// The address is acquired using DHCP.
+    pub dhcp: bool,
+}
+
+impl NetworkStatus {
+    /// Conclude a poll of the network self test.
+    ///
+    /// # Args
+    /// * `link_up` - The ethernet link is up.
+    /// * `address` - The assigned IPv4 address, if any.
+    /// * `dhcp` - The address is acquired using DHCP.
+    /// * `elapsed_ms` - The time since the start of the test in milliseconds.
+    /// * `timeout_ms` - The time to wait for the network in milliseconds.
+    ///
+    /// # Returns
+    /// The status once the network is up or the timeout expired, or `None` while the test
+    /// continues.
+    fn conclude(
+        link_up: bool,
+        address: Option<smoltcp::wire::Ipv4Address>,
+        dhcp: bool,
+        elapsed_ms: u32,
+        timeout_ms: u32,
+    ) -> Option<Self> {
+        let address = address.filter(|address| !address.is_unspecified());
+        ((link_up && address.is_some()) || elapsed_ms >= timeout_ms).then_some(
+            Self {
+                link_up,
+                address,
+                dhcp,
+            },
+        )
+    }
+}
+
+impl NetworkDevices {
+    /// Wait for the network link to come up and an address to be assigned.
+    ///
+    /// # Note
+    /// The RTIC monotonic timer is not running during initialization, so the timeout is measured
+    /// with the provided delay. The time spent polling the PHY and the network stack is not
+    /// accounted for, so the test may take longer than the timeout. If the timeout expires, the
+    /// status at that time is reported.
+    ///
+    /// # Args
+    /// * `delay` - A delay to wait between polls.
+    /// * `timeout_ms` - The time to wait for the network in milliseconds.
+    ///
+    /// # Returns
+    /// The status of the network.
+    pub fn self_test(
+        &mut self,
+        delay: &mut impl DelayMs<u8>,
+        timeout_ms: u32,
+    ) -> NetworkStatus {
+        let mut elapsed = 0;
+        loop {
+            let link_up = self.phy.poll_link();
+
+            // The poll result only indicates socket activity.
+            self.stack.poll().ok();
+            let address = self.stack.interface().ipv4_addr();
+
+            if let Some(status) = NetworkStatus::conclude(
+                link_up, address, self.dhcp, elapsed, timeout_ms,
+            ) {
+                return status;
+            }
+
+            delay.delay_ms(1);
+            elapsed += 1;
+        }
+    }
+}
+
+/// Derive a fallback MAC address from the CPU unique device ID.
+///
+/// # Note
+/// This is used if the EEPROM does not contain a valid EUI-48. See [mac_from_uid()] for the
+/// derivation.
+fn fallback_mac() -> smoltcp::wire::EthernetAddress {
+    mac_from_uid(hal::signature::Uid::read())
+}
+
+/// Derive a MAC address from a 96-bit unique device ID.
+///
+/// # Note
+/// The first octet is fixed to 0x02, which sets the locally administered bit and clears the
+/// multicast bit. The twelve ID bytes are XOR-folded in order into the remaining five octets,
+/// i.e. byte `i` is folded into octet `1 + i % 5`. The address is a pure function of the ID and
+/// thus stable across resets, but not guaranteed to be unique, as 96 bits are folded into 40.
+///
+/// # Args
+/// * `uid` - The unique device ID.
+///
+/// # Returns
+/// The locally administered unicast MAC address.
+pub fn mac_from_uid(uid: &[u8; 12]) -> smoltcp::wire::EthernetAddress {
+    let mut octets = [0x02, 0, 0, 0, 0, 0];
+    for (i, byte) in uid.iter().enumerate() {
+        octets[1 + i % 5] ^= byte;
+    }
+    smoltcp::wire::EthernetAddress(octets)
 }
 
 /// The available hardware interfaces on Stabilizer.
@@ -110,24 +292,505 @@ pub struct StabilizerDevices {
     pub temperature_sensor: CpuTempSensor,
     pub afes: (AFE0, AFE1),
     pub adcs: (adc::Adc0Input, adc::Adc1Input),
+    pub adc_calibration: [adc::AdcCalibration; 2],
     pub dacs: (dac::Dac0Output, dac::Dac1Output),
+    pub dac_control: dac::DacControl,
     pub timestamper: crate::hardware::timers::ReferenceTimer,
+    /// The clock source of the reference timer.
+    pub reference_clock: ReferenceClock,
     pub adc_dac_timer: timers::SamplingTimer,
+    /// The timer slaved to [Self::adc_dac_timer], required to change the sample period.
+    pub adc_dac_shadow_timer: timers::ShadowSamplingTimer,
     pub net: NetworkDevices,
     pub digital_inputs: (DigitalInput0, DigitalInput1),
     pub eem_gpio: EemGpioDevices,
-    pub usb_serial: SerialTerminal,
+    /// The USB serial terminal, or `None` if USB is disabled.
+    pub usb_serial: Option<SerialTerminal>,
+    /// The USB endpoint memory, available for other uses if USB is disabled.
+    pub usb_memory: Option<&'static mut [u32; 1024]>,
+    pub servo_config: eeprom::ServoConfig,
+    pub eeprom_i2c: hal::i2c::I2c<hal::stm32::I2C2>,
+    pub delay: delay::AsmDelay,
+    pub status_leds: FrontPanelLeds,
+    pub iwdg: hal::independent_watchdog::IndependentWatchdog,
+    pub setup_profile: SetupProfile,
+    /// The external crystal failed to start and the clocks are derived from the internal HSI
+    /// oscillator instead. The clock frequencies are unchanged, but their accuracy is reduced.
+    pub hse_fallback: bool,
+    /// The DWT cycle count at which all settle times after power-on and clock configuration
+    /// have elapsed.
+    settle_deadline: u32,
+    batch_size: usize,
+}
+
+impl StabilizerDevices {
+    /// Get the build information of the running firmware.
+    pub fn version(&self) -> VersionInfo {
+        VersionInfo::BUILD
+    }
+
+    /// Get the current sample rate of the ADCs and DACs in Hz.
+    pub fn sample_rate_hz(&self) -> f32 {
+        self.adc_dac_timer.sample_rate_hz()
+    }
+
+    /// Get the number of samples per ADC/DAC batch.
+    pub fn batch_size(&self) -> usize {
+        self.batch_size
+    }
+
+    /// Wait until the analog supplies and clocks have settled after setup.
+    ///
+    /// # Note
+    /// This must be called before the sampling timer is started, so that the control loop never
+    /// operates on an unpowered front-end or an unlocked DDS clock. The settle times are documented
+    /// in [design_parameters::AFE_POWER_SETTLE_TIME] and [design_parameters::DDS_PLL_LOCK_TIME].
+    /// Setup typically takes longer than these, in which case this returns immediately. The
+    /// deadline is tracked with the DWT cycle counter, so this must be called within about 5
+    /// seconds of setup.
+    ///
+    /// # Args
+    /// * `timeout_ms` - The maximum time to wait in milliseconds.
+    ///
+    /// # Returns
+    /// The first condition that was still pending when the timeout expired.
+    pub fn wait_ready(&mut self, timeout_ms: u32) -> Result<(), NotReady> {
+        let cycles_per_ms = design_parameters::SYSCLK.to_Hz() / 1_000;
+        let timeout = timeout_ms.saturating_mul(cycles_per_ms);
+        let start = cortex_m::peripheral::DWT::cycle_count();
+
+        loop {
+            let now = cortex_m::peripheral::DWT::cycle_count();
+            let pending = if timers::wrap::diff_signed(
+                now as u64,
+                self.settle_deadline as u64,
+                32,
+            ) < 0
+            {
+                Some(NotReady::Settling)
+            } else if !self.dac_control.cleared() {
+                Some(NotReady::DacClear)
+            } else {
+                None
+            };
+
+            match pending {
+                None => return Ok(()),
+                Some(condition) if now.wrapping_sub(start) > timeout => {
+                    return Err(condition)
+                }
+                Some(_) => {}
+            }
+        }
+    }
+
+    /// Split the devices into groups that map onto RTIC resources.
+    ///
+    /// # Note
+    /// Each group is typically owned by a single task, so it can be moved into a local resource
+    /// as a whole instead of destructuring every device.
+    pub fn into_resources(self) -> StabilizerResources {
+        StabilizerResources {
+            system: SystemResources {
+                systick: self.systick,
+                temperature_sensor: self.temperature_sensor,
+                servo_config: self.servo_config,
+                eeprom_i2c: self.eeprom_i2c,
+                delay: self.delay,
+                iwdg: self.iwdg,
+            },
+            network: self.net,
+            analog: AnalogResources {
+                afes: self.afes,
+                adcs: self.adcs,
+                adc_calibration: self.adc_calibration,
+                dacs: self.dacs,
+                dac_control: self.dac_control,
+                adc_dac_timer: self.adc_dac_timer,
+                adc_dac_shadow_timer: self.adc_dac_shadow_timer,
+            },
+            timing: TimingResources {
+                timestamper: self.timestamper,
+                digital_inputs: self.digital_inputs,
+                eem_gpio: self.eem_gpio,
+            },
+            ui: UiResources {
+                usb_serial: self.usb_serial,
+                usb_memory: self.usb_memory,
+                status_leds: self.status_leds,
+            },
+        }
+    }
+}
+
+/// The Stabilizer hardware interfaces grouped by their use in an application.
+pub struct StabilizerResources {
+    pub system: SystemResources,
+    pub network: NetworkDevices,
+    pub analog: AnalogResources,
+    pub timing: TimingResources,
+    pub ui: UiResources,
+}
+
+/// The system clock, monitoring and configuration storage interfaces.
+pub struct SystemResources {
+    pub systick: Systick,
+    pub temperature_sensor: CpuTempSensor,
+    pub servo_config: eeprom::ServoConfig,
+    pub eeprom_i2c: hal::i2c::I2c<hal::stm32::I2C2>,
+    pub delay: delay::AsmDelay,
+    pub iwdg: hal::independent_watchdog::IndependentWatchdog,
+}
+
+/// The analog signal path used by the sampling task.
+pub struct AnalogResources {
+    pub afes: (AFE0, AFE1),
+    pub adcs: (adc::Adc0Input, adc::Adc1Input),
+    pub adc_calibration: [adc::AdcCalibration; 2],
+    pub dacs: (dac::Dac0Output, dac::Dac1Output),
+    pub dac_control: dac::DacControl,
+    pub adc_dac_timer: timers::SamplingTimer,
+    pub adc_dac_shadow_timer: timers::ShadowSamplingTimer,
+}
+
+/// The timestamping and digital input interfaces.
+pub struct TimingResources {
+    pub timestamper: crate::hardware::timers::ReferenceTimer,
+    pub digital_inputs: (DigitalInput0, DigitalInput1),
+    pub eem_gpio: EemGpioDevices,
+}
+
+/// The user interfaces.
+pub struct UiResources {
+    /// The USB serial terminal, or `None` if USB is disabled.
+    pub usb_serial: Option<SerialTerminal>,
+    /// The USB endpoint memory, available for other uses if USB is disabled.
+    pub usb_memory: Option<&'static mut [u32; 1024]>,
+    pub status_leds: FrontPanelLeds,
+}
+
+/// The durations of the stages of [setup()] in microseconds.
+///
+/// # Note
+/// The durations are measured with the DWT cycle counter. The clock configuration stage is
+/// converted with the HSI frequency the CPU runs at before the PLLs are configured, so it is
+/// approximate.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct SetupProfile {
+    /// Configuration of the power supply and clocks.
+    pub clocks: u32,
+    /// Loading of the ITCM.
+    pub itcm: u32,
+    /// Configuration of GPIOs, Pounder detection and the timers.
+    pub peripherals: u32,
+    /// Configuration of the SPI interfaces to the ADCs and DACs and of the AFEs.
+    pub spi: u32,
+    /// Reading the EEPROM and configuration of the ethernet interface and network stack.
+    pub ethernet: u32,
+    /// Configuration of Pounder, the internal ADCs and the EEM GPIOs.
+    pub pounder: u32,
+    /// Configuration of USB.
+    pub usb: u32,
+}
+
+impl SetupProfile {
+    /// Get the total duration of setup in microseconds.
+    pub fn total(&self) -> u32 {
+        self.clocks
+            + self.itcm
+            + self.peripherals
+            + self.spi
+            + self.ethernet
+            + self.pounder
+            + self.usb
+    }
+}
+
+/// A stopwatch for the stages of [setup()].
+struct StageTimer {
+    last: u32,
+}
+
+impl StageTimer {
+    /// Start timing the first stage.
+    ///
+    /// # Note
+    /// The DWT cycle counter must be enabled.
+    fn start() -> Self {
+        Self {
+            last: cortex_m::peripheral::DWT::cycle_count(),
+        }
+    }
+
+    /// Finish the current stage and start the next one.
+    ///
+    /// # Args
+    /// * `stage` - The name of the finished stage.
+    /// * `cpu_frequency` - The CPU frequency during the stage in Hz.
+    ///
+    /// # Returns
+    /// The duration of the stage in microseconds.
+    fn lap(&mut self, stage: &str, cpu_frequency: u32) -> u32 {
+        let duration = self
+            .lap_at(cortex_m::peripheral::DWT::cycle_count(), cpu_frequency);
+        log::debug!("setup: {} took {} us", stage, duration);
+        duration
+    }
+
+    /// Finish the current stage at a cycle count and start the next one.
+    ///
+    /// # Args
+    /// * `now` - The current cycle count. It may have wrapped once since the previous lap.
+    /// * `cpu_frequency` - The CPU frequency during the stage in Hz.
+    ///
+    /// # Returns
+    /// The duration of the stage in microseconds.
+    fn lap_at(&mut self, now: u32, cpu_frequency: u32) -> u32 {
+        let duration =
+            now.wrapping_sub(self.last) / (cpu_frequency / 1_000_000);
+        self.last = now;
+        duration
+    }
+}
+
+/// The serial clock frequencies of the ADC and DAC SPI interfaces.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct SpiClocks {
+    pub adc0: hal::time::Hertz,
+    pub adc1: hal::time::Hertz,
+    pub dac0: hal::time::Hertz,
+    pub dac1: hal::time::Hertz,
+}
+
+impl Default for SpiClocks {
+    /// All interfaces run at [design_parameters::ADC_DAC_SCK_MAX].
+    fn default() -> Self {
+        let max = design_parameters::ADC_DAC_SCK_MAX.convert();
+        Self {
+            adc0: max,
+            adc1: max,
+            dac0: max,
+            dac1: max,
+        }
+    }
+}
+
+/// The timing of the ADC SPI interfaces.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct AdcTiming {
+    /// The time in seconds from asserting CSn until the serial clock starts, see
+    /// [design_parameters::ADC_SETUP_TIME].
+    pub setup_time: f32,
+}
+
+impl Default for AdcTiming {
+    fn default() -> Self {
+        Self {
+            setup_time: design_parameters::ADC_SETUP_TIME,
+        }
+    }
+}
+
+/// The configuration of the stabilizer hardware, see [setup].
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct SetupConfig {
+    /// The size of each ADC/DAC batch.
+    pub batch_size: usize,
+
+    /// The number of timer ticks between each sample.
+    pub sample_ticks: u32,
+
+    /// The serial clocks of the ADC and DAC SPI interfaces.
+    pub spi_clocks: SpiClocks,
+
+    /// The timing of the ADC SPI interfaces.
+    pub adc_timing: AdcTiming,
+
+    /// The configuration of the network sockets.
+    pub network_config: NetworkConfig,
+
+    /// Whether to set up the USB serial terminal. If disabled, the USB peripheral is left unused
+    /// and its endpoint memory is returned in `StabilizerDevices::usb_memory` instead.
+    pub usb_enabled: bool,
+}
+
+impl SetupConfig {
+    /// Construct the configuration with the default interface timing and network configuration
+    /// and the USB serial terminal enabled.
+    ///
+    /// # Args
+    /// * `batch_size` - The size of each ADC/DAC batch.
+    /// * `sample_ticks` - The number of timer ticks between each sample.
+    pub fn new(batch_size: usize, sample_ticks: u32) -> Self {
+        Self {
+            batch_size,
+            sample_ticks,
+            spi_clocks: SpiClocks::default(),
+            adc_timing: AdcTiming::default(),
+            network_config: NetworkConfig::default(),
+            usb_enabled: true,
+        }
+    }
+}
+
+/// Hand the USB endpoint memory to the USB serial terminal if it is enabled.
+///
+/// # Args
+/// * `usb_enabled` - Whether the USB serial terminal is enabled.
+/// * `endpoint_memory` - The USB endpoint memory.
+/// * `terminal` - Sets up the USB serial terminal using the endpoint memory.
+///
+/// # Returns
+/// (terminal, memory) where exactly one is `Some`: the terminal if USB is enabled, or the unused
+/// endpoint memory otherwise.
+fn usb_resources<'a, T>(
+    usb_enabled: bool,
+    endpoint_memory: &'a mut [u32; 1024],
+    terminal: impl FnOnce(&'a mut [u32; 1024]) -> T,
+) -> (Option<T>, Option<&'a mut [u32; 1024]>) {
+    if usb_enabled {
+        (Some(terminal(endpoint_memory)), None)
+    } else {
+        (None, Some(endpoint_memory))
+    }
+}
+
+/// The maximum number of serial clock cycles an SPI interface can delay a transfer after
+/// asserting its hardware chip select.
+const MAX_ASSERTION_DELAY_CYCLES: u32 = 15;
+
+/// Get the number of serial clock cycles an SPI interface delays a transfer after asserting its
+/// hardware chip select.
+///
+/// # Note
+/// The HAL rounds any non-zero delay up to the next full serial clock cycle, with one cycle of
+/// margin for delays that are a whole number of cycles.
+///
+/// # Args
+/// * `serial_clock` - The serial clock frequency in Hz.
+/// * `delay` - The requested delay in seconds.
+///
+/// # Returns
+/// The number of serial clock cycles, or `None` if the delay is negative, not finite or longer
+/// than the SPI interface can express at this serial clock.
+pub fn assertion_delay_cycles(serial_clock: u32, delay: f32) -> Option<u32> {
+    if !delay.is_finite() || delay < 0. {
+        return None;
+    }
+
+    if delay == 0. {
+        return Some(0);
+    }
+
+    let cycles = delay * serial_clock as f32;
+    if cycles >= MAX_ASSERTION_DELAY_CYCLES as f32 {
+        return None;
+    }
+
+    Some(cycles as u32 + 1)
+}
+
+/// Resolve the setup time of an ADC SPI interface.
+///
+/// # Note
+/// A setup time that can not be met is limited to the longest delay the SPI interface can
+/// express at its serial clock, and a warning is logged.
+///
+/// # Args
+/// * `name` - The name of the converter for the warning.
+/// * `serial_clock` - The serial clock of the SPI interface.
+/// * `requested` - The requested setup time in seconds.
+///
+/// # Returns
+/// The setup time to configure in seconds.
+fn resolve_setup_time(
+    name: &str,
+    serial_clock: hal::time::Hertz,
+    requested: f32,
+) -> f32 {
+    if assertion_delay_cycles(serial_clock.to_Hz(), requested).is_some() {
+        return requested;
+    }
+
+    let max =
+        (MAX_ASSERTION_DELAY_CYCLES - 1) as f32 / serial_clock.to_Hz() as f32;
+    log::warn!(
+        "{name} setup time of {requested} s not achievable, using {max} s"
+    );
+    max
+}
+
+/// Get the serial clock frequency an SPI interface generates for a requested frequency.
+///
+/// # Note
+/// The serial clock is the SPI kernel clock divided by a power of two from 2 to 256. The HAL
+/// selects the smallest divider that does not exceed the requested frequency, or the largest
+/// divider if the request is below the slowest achievable clock.
+///
+/// # Args
+/// * `kernel_clock` - The SPI kernel clock frequency in Hz.
+/// * `requested` - The requested serial clock frequency in Hz.
+///
+/// # Returns
+/// The achievable serial clock frequency in Hz.
+pub fn achievable_spi_clock(kernel_clock: u32, requested: u32) -> u32 {
+    let divider = (1..=8)
+        .map(|shift| 1u32 << shift)
+        .find(|divider| kernel_clock / divider <= requested)
+        .unwrap_or(256);
+    kernel_clock / divider
+}
+
+/// Resolve the serial clock of an ADC or DAC SPI interface.
+///
+/// # Note
+/// Requests above [design_parameters::ADC_DAC_SCK_MAX] are limited to it. A warning is logged
+/// if the request can not be met exactly.
+///
+/// # Args
+/// * `name` - The name of the converter for the warning.
+/// * `kernel_clock` - The SPI kernel clock.
+/// * `requested` - The requested serial clock.
+///
+/// # Returns
+/// The serial clock to configure.
+fn resolve_spi_clock(
+    name: &str,
+    kernel_clock: hal::time::Hertz,
+    requested: hal::time::Hertz,
+) -> hal::time::Hertz {
+    let max = design_parameters::ADC_DAC_SCK_MAX.to_Hz();
+    let actual =
+        achievable_spi_clock(kernel_clock.to_Hz(), requested.to_Hz().min(max));
+    if actual != requested.to_Hz() {
+        log::warn!(
+            "{name} SPI clock of {} Hz not achievable, using {actual} Hz",
+            requested.to_Hz()
+        );
+    }
+    actual.Hz()
 }
 
 /// The available Pounder-specific hardware interfaces.
 pub struct PounderDevices {
     pub pounder: pounder::PounderDevices,
     pub dds_output: DdsOutput,
+}
 
-    #[cfg(not(feature = "pounder_v1_0"))]
-    pub timestamper: pounder::timestamp::InputCaptureTimer,
+// The size of SRAM3, which holds the ethernet descriptor ring. This must match `memory.x`.
+const SRAM3_SIZE: usize = 32 * 1024;
+
+/// Check whether descriptor rings of the given sizes fit into SRAM3.
+const fn des_ring_fits<const TD: usize, const RD: usize>() -> bool {
+    core::mem::size_of::<ethernet::DesRing<TD, RD>>() <= SRAM3_SIZE
 }
 
+// Verify that the configured descriptor ring sizes fit into SRAM3.
+const _: () = assert!(
+    des_ring_fits::<{ super::TX_DESRING_CNT }, { super::RX_DESRING_CNT }>(),
+    "Ethernet descriptor rings do not fit into SRAM3"
+);
+
 #[link_section = ".sram3.eth"]
 /// Static storage for the ethernet DMA descriptor ring.
 static mut DES_RING: ethernet::DesRing<
@@ -179,30 +842,122 @@ fn load_itcm() {
     cortex_m::asm::isb();
 }
 
+/// Enable the HSE and wait for it to become ready.
+///
+/// # Note
+/// The HAL waits for the HSE indefinitely, so a crystal that does not oscillate would hang
+/// setup. The HSE is disabled again if it does not become ready within [HSE_STARTUP_CYCLES].
+/// The DWT cycle counter must be enabled.
+///
+/// # Args
+/// * `rcc` - The RCC peripheral, before it is constrained.
+///
+/// # Returns
+/// True if the HSE is ready.
+fn start_hse(rcc: &hal::stm32::RCC) -> bool {
+    rcc.cr.modify(|_, w| w.hseon().set_bit());
+
+    let start = cortex_m::peripheral::DWT::cycle_count();
+    while rcc.cr.read().hserdy().bit_is_clear() {
+        let elapsed =
+            cortex_m::peripheral::DWT::cycle_count().wrapping_sub(start);
+        if elapsed > HSE_STARTUP_CYCLES {
+            rcc.cr.modify(|_, w| w.hseon().clear_bit());
+            return false;
+        }
+    }
+
+    true
+}
+
+/// The frequencies of the clock tree.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+struct ClockTree {
+    /// The oscillator the PLLs are derived from.
+    source: u32,
+    sysclk: u32,
+    hclk: u32,
+    /// The kernel clock of the internal ADCs, always derived from the HSI.
+    per_ck: u32,
+    /// The kernel clock of SPI1 to SPI3.
+    pll2_p: u32,
+    /// The kernel clock of SPI4 and SPI5.
+    pll2_q: u32,
+}
+
+impl ClockTree {
+    /// Determine the clock tree.
+    ///
+    /// # Note
+    /// The PLLs synthesize the same frequencies from the HSI as from the HSE, so the clock tree
+    /// and all downstream dividers are unchanged in the fallback.
+    ///
+    /// # Args
+    /// * `hse_ready` - Whether the HSE started, see [start_hse()]. Otherwise, the PLLs are
+    ///   derived from the HSI.
+    fn new(hse_ready: bool) -> Self {
+        Self {
+            source: if hse_ready { 8_000_000 } else { 64_000_000 },
+            sysclk: design_parameters::SYSCLK.to_Hz(),
+            hclk: 200_000_000,
+            per_ck: 64_000_000,
+            pll2_p: 100_000_000,
+            pll2_q: 100_000_000,
+        }
+    }
+}
+
 /// Configure the stabilizer hardware for operation.
 ///
 /// # Note
 /// Refer to [design_parameters::TIMER_FREQUENCY] to determine the frequency of the sampling timer.
 ///
+/// Pounder is detected by probing its MCP23017 GPIO expander on I2C1 (see [pounder::detect]).
+/// If the expander does not acknowledge, none of the Pounder peripherals (QSPI, SPI1, HRTIM) are
+/// configured. If a detected Pounder fails to initialize, the error is logged and `None` is
+/// returned for it instead.
+///
+/// The QSPI IO0 line to the Pounder DDS shares PE7 with the external clock input of the
+/// reference timer. If Pounder is detected, the reference timer is clocked internally at
+/// [design_parameters::TIMER_FREQUENCY] instead.
+///
 /// # Args
 /// * `core` - The cortex-m peripherals.
 /// * `device` - The microcontroller peripherals to be configured.
 /// * `clock` - A `SystemTimer` implementing `Clock`.
-/// * `batch_size` - The size of each ADC/DAC batch.
-/// * `sample_ticks` - The number of timer ticks between each sample.
+/// * `config` - The configuration of the sampling, the SPI interfaces, the network sockets and
+///   the USB serial terminal.
 ///
 /// # Returns
-/// (stabilizer, pounder) where `stabilizer` is a `StabilizerDevices` structure containing all
-/// stabilizer hardware interfaces in a disabled state. `pounder` is an `Option` containing
-/// `Some(devices)` if pounder is detected, where `devices` is a `PounderDevices` structure
-/// containing all of the pounder hardware interfaces in a disabled state.
+/// (stabilizer, pounder, beat_timer) where `stabilizer` is a `StabilizerDevices` structure
+/// containing all stabilizer hardware interfaces in a disabled state. `pounder` is an `Option`
+/// containing `Some(devices)` if pounder is detected, where `devices` is a `PounderDevices`
+/// structure containing all of the pounder hardware interfaces in a disabled state.
+/// `beat_timer` is the input capture timer timestamping the beat note against the reference timer.
+/// It is clocked from the external reference if PE7 is not used by pounder, see
+/// `StabilizerDevices::reference_clock`.
 pub fn setup(
     mut core: stm32h7xx_hal::stm32::CorePeripherals,
     device: stm32h7xx_hal::stm32::Peripherals,
     clock: SystemTimer,
-    batch_size: usize,
-    sample_ticks: u32,
-) -> (StabilizerDevices, crate::hardware::pounder::timestamp::InputCaptureTimer) {
+    config: SetupConfig,
+) -> (
+    StabilizerDevices,
+    Option<PounderDevices>,
+    pounder::timestamp::InputCaptureTimer,
+) {
+    let SetupConfig {
+        batch_size,
+        sample_ticks,
+        spi_clocks,
+        adc_timing,
+        network_config,
+        usb_enabled,
+    } = config;
+
+    // Paint the stack before any deeper calls to cover their stack usage.
+    stack::paint();
+
     // Set up RTT logging
     {
         // Enable debug during WFE/WFI-induced sleep
@@ -238,14 +993,29 @@ pub fn setup(
             );
         }
 
-        static LOGGER: rtt_logger::RTTLogger =
-            rtt_logger::RTTLogger::new(log::LevelFilter::Info);
+        // The logger passes all levels, so the verbosity is controlled by the max level alone,
+        // which can be changed at runtime. Recent lines are also kept for remote retrieval.
+        static LOGGER: log_buffer::BufferedLogger =
+            log_buffer::BufferedLogger::new(
+                log::LevelFilter::Trace,
+                &log_buffer::LOG_BUFFER,
+            );
         log::set_logger(&LOGGER)
-            .map(|()| log::set_max_level(log::LevelFilter::Trace))
+            .map(|()| log::set_max_level(log::LevelFilter::Info))
             .unwrap();
         log::info!("Starting");
     }
 
+    core.DCB.enable_trace();
+    core.DWT.enable_cycle_counter();
+    let mut setup_profile = SetupProfile::default();
+    let mut stages = StageTimer::start();
+
+    let hse_ready = start_hse(&device.RCC);
+    if !hse_ready {
+        log::warn!("HSE failed to start, using HSI with reduced accuracy");
+    }
+
     let pwr = device.PWR.constrain();
     let vos = pwr.freeze();
 
@@ -266,15 +1036,24 @@ pub fn setup(
     device.RCC.d3ccipr.modify(|_, w| w.adcsel().per());
 
     let rcc = device.RCC.constrain();
+    let clock_tree = ClockTree::new(hse_ready);
+    let rcc = if hse_ready {
+        rcc.use_hse(clock_tree.source.Hz())
+    } else {
+        rcc
+    };
     let mut ccdr = rcc
-        .use_hse(8.MHz())
-        .sysclk(design_parameters::SYSCLK.convert())
-        .hclk(200.MHz())
-        .per_ck(64.MHz()) // fixed frequency HSI, only used for internal ADC. This is not the "peripheral" clock for timers and others.
-        .pll2_p_ck(100.MHz())
-        .pll2_q_ck(100.MHz())
+        .sysclk(clock_tree.sysclk.Hz())
+        .hclk(clock_tree.hclk.Hz())
+        .per_ck(clock_tree.per_ck.Hz()) // fixed frequency HSI, only used for internal ADC. This is not the "peripheral" clock for timers and others.
+        .pll2_p_ck(clock_tree.pll2_p.Hz())
+        .pll2_q_ck(clock_tree.pll2_q.Hz())
         .freeze(vos, &device.SYSCFG);
 
+    // The CPU runs from the 64 MHz HSI until the clocks are configured.
+    setup_profile.clocks = stages.lap("clocks", 64_000_000);
+    let cpu_frequency = ccdr.clocks.c_ck().to_Hz();
+
     // Set up USB clocks.
     ccdr.clocks.hsi48_ck().unwrap();
     ccdr.peripheral
@@ -282,6 +1061,7 @@ pub fn setup(
 
     // Before being able to call any code in ITCM, load that code from flash.
     load_itcm();
+    setup_profile.itcm = stages.lap("ITCM", cpu_frequency);
 
     let systick = Systick::new(core.SYST, ccdr.clocks.sysclk().to_Hz());
 
@@ -298,16 +1078,29 @@ pub fn setup(
     let gpiof = device.GPIOF.split(ccdr.peripheral.GPIOF);
     let mut gpiog = device.GPIOG.split(ccdr.peripheral.GPIOG);
 
+    // Probe for Pounder before any of the pins shared with the EEM connector are configured.
+    let mut i2c1 = {
+        let sda = gpiob.pb7.into_alternate().set_open_drain();
+        let scl = gpiob.pb8.into_alternate().set_open_drain();
+        device.I2C1.i2c(
+            (scl, sda),
+            400.kHz(),
+            ccdr.peripheral.I2C1,
+            &ccdr.clocks,
+        )
+    };
+
+    let pounder_present = pounder::detect(&mut i2c1);
+    log::info!("Pounder detected: {}", pounder_present);
+
+    // PE7 is used as QSPI IO0 if Pounder is present and as the reference timer clock otherwise.
+    let mut pe7 = Some(gpioe.pe7);
+
     let dma_streams =
         hal::dma::dma::StreamsTuple::new(device.DMA1, ccdr.peripheral.DMA1);
 
     // Verify that batch period does not exceed RTIC Monotonic timer period.
-    assert!(
-        (batch_size as u32 * sample_ticks) as f32
-            * design_parameters::TIMER_PERIOD
-            * (super::MONOTONIC_FREQUENCY as f32)
-            < 1.
-    );
+    assert!(timers::batch_period_valid(batch_size, sample_ticks));
 
     // Configure timer 2 to trigger conversions for the ADC
     let mut sampling_timer = {
@@ -366,8 +1159,26 @@ pub fn setup(
     let sampling_timer_channels = sampling_timer.channels();
     let shadow_sampling_timer_channels = shadow_sampling_timer.channels();
 
+    // The reference timer is clocked internally if PE7 is used by Pounder.
+    let reference_clock = if pounder_present {
+        log::warn!(
+            "PE7 is used by Pounder, clocking the reference timer internally"
+        );
+        ReferenceClock::Internal
+    } else {
+        ReferenceClock::External
+    };
+
+    let reference_frequency = match reference_clock {
+        ReferenceClock::Internal => {
+            design_parameters::TIMER_FREQUENCY.to_Hz() as f32
+        }
+        ReferenceClock::External => {
+            design_parameters::EXTERNAL_REFERENCE_FREQUENCY.to_Hz() as f32
+        }
+    };
+
     let mut ref_timer = {
-        let _etr_pin = gpioe.pe7.into_alternate::<1>(); //see alternate function table
         // The timer frequency is manually adjusted below, so the 1KHz setting here is a
         // dont-care.
         let mut timer1 =
@@ -376,15 +1187,25 @@ pub fn setup(
                 .timer(1.kHz(), ccdr.peripheral.TIM1, &ccdr.clocks);
         timer1.pause();
 
+        if reference_clock == ReferenceClock::Internal {
+            timer1.set_tick_freq(design_parameters::TIMER_FREQUENCY.convert());
+        }
+
         let mut ref_timer1 = timers::ReferenceTimer::new(timer1);
 
-        ref_timer1.set_external_clock(timers::Prescaler::Div1);
+        if reference_clock == ReferenceClock::External {
+            // Note(unwrap): PE7 is only taken for the QSPI if Pounder is present.
+            let _etr_pin = pe7.take().unwrap().into_alternate::<1>(); //see alternate function table
+            ref_timer1.set_external_clock(timers::Prescaler::Div1);
+        }
 
         ref_timer1.set_period_ticks(1000-1);
 
         ref_timer1
     };
 
+    setup_profile.peripherals = stages.lap("peripherals", cpu_frequency);
+
     // Configure the SPI interfaces to the ADCs and DACs.
     let adcs = {
         let adc0 = {
@@ -392,13 +1213,23 @@ pub fn setup(
             let sck = gpiob.pb10.into_alternate().speed(Speed::VeryHigh);
             let nss = gpiob.pb9.into_alternate().speed(Speed::VeryHigh);
 
+            let serial_clock = resolve_spi_clock(
+                "ADC0",
+                ccdr.clocks.pll2_p_ck().unwrap(),
+                spi_clocks.adc0,
+            );
+
             let config = hal::spi::Config::new(hal::spi::Mode {
                 polarity: hal::spi::Polarity::IdleHigh,
                 phase: hal::spi::Phase::CaptureOnSecondTransition,
             })
             .hardware_cs(hal::spi::HardwareCS {
                 mode: hal::spi::HardwareCSMode::WordTransaction,
-                assertion_delay: design_parameters::ADC_SETUP_TIME,
+                assertion_delay: resolve_setup_time(
+                    "ADC0",
+                    serial_clock,
+                    adc_timing.setup_time,
+                ),
                 polarity: hal::spi::Polarity::IdleHigh,
             })
             .communication_mode(hal::spi::CommunicationMode::Receiver);
@@ -406,7 +1237,7 @@ pub fn setup(
             let spi: hal::spi::Spi<_, _, u16> = device.SPI2.spi(
                 (sck, miso, hal::spi::NoMosi, nss),
                 config,
-                design_parameters::ADC_DAC_SCK_MAX.convert(),
+                serial_clock,
                 ccdr.peripheral.SPI2,
                 &ccdr.clocks,
             );
@@ -419,6 +1250,7 @@ pub fn setup(
                 sampling_timer_channels.ch1,
                 shadow_sampling_timer_channels.ch1,
                 batch_size,
+                sample_ticks,
             )
         };
 
@@ -427,13 +1259,23 @@ pub fn setup(
             let sck = gpioc.pc10.into_alternate().speed(Speed::VeryHigh);
             let nss = gpioa.pa15.into_alternate().speed(Speed::VeryHigh);
 
+            let serial_clock = resolve_spi_clock(
+                "ADC1",
+                ccdr.clocks.pll2_p_ck().unwrap(),
+                spi_clocks.adc1,
+            );
+
             let config = hal::spi::Config::new(hal::spi::Mode {
                 polarity: hal::spi::Polarity::IdleHigh,
                 phase: hal::spi::Phase::CaptureOnSecondTransition,
             })
             .hardware_cs(hal::spi::HardwareCS {
                 mode: hal::spi::HardwareCSMode::WordTransaction,
-                assertion_delay: design_parameters::ADC_SETUP_TIME,
+                assertion_delay: resolve_setup_time(
+                    "ADC1",
+                    serial_clock,
+                    adc_timing.setup_time,
+                ),
                 polarity: hal::spi::Polarity::IdleHigh,
             })
             .communication_mode(hal::spi::CommunicationMode::Receiver);
@@ -441,7 +1283,7 @@ pub fn setup(
             let spi: hal::spi::Spi<_, _, u16> = device.SPI3.spi(
                 (sck, miso, hal::spi::NoMosi, nss),
                 config,
-                design_parameters::ADC_DAC_SCK_MAX.convert(),
+                serial_clock,
                 ccdr.peripheral.SPI3,
                 &ccdr.clocks,
             );
@@ -454,13 +1296,14 @@ pub fn setup(
                 sampling_timer_channels.ch2,
                 shadow_sampling_timer_channels.ch2,
                 batch_size,
+                sample_ticks,
             )
         };
 
         (adc0, adc1)
     };
 
-    let dacs = {
+    let (mut dacs, dac_control) = {
         let mut dac_clr_n = gpioe.pe12.into_push_pull_output();
         dac_clr_n.set_high();
 
@@ -484,7 +1327,11 @@ pub fn setup(
             device.SPI4.spi(
                 (sck, miso, hal::spi::NoMosi, nss),
                 config,
-                design_parameters::ADC_DAC_SCK_MAX.convert(),
+                resolve_spi_clock(
+                    "DAC0",
+                    ccdr.clocks.pll2_q_ck().unwrap(),
+                    spi_clocks.dac0,
+                ),
                 ccdr.peripheral.SPI4,
                 &ccdr.clocks,
             )
@@ -510,7 +1357,11 @@ pub fn setup(
             device.SPI5.spi(
                 (sck, miso, hal::spi::NoMosi, nss),
                 config,
-                design_parameters::ADC_DAC_SCK_MAX.convert(),
+                resolve_spi_clock(
+                    "DAC1",
+                    ccdr.clocks.pll2_q_ck().unwrap(),
+                    spi_clocks.dac1,
+                ),
                 ccdr.peripheral.SPI5,
                 &ccdr.clocks,
             )
@@ -529,14 +1380,13 @@ pub fn setup(
             batch_size,
         );
 
-        dac_clr_n.set_low();
-        // dac0_ldac_n
-        gpioe.pe11.into_push_pull_output().set_low();
-        // dac1_ldac_n
-        gpioe.pe15.into_push_pull_output().set_low();
-        dac_clr_n.set_high();
+        let dac_control = dac::DacControl::new(
+            dac_clr_n,
+            gpioe.pe11.into_push_pull_output(),
+            gpioe.pe15.into_push_pull_output(),
+        );
 
-        (dac0, dac1)
+        ((dac0, dac1), dac_control)
     };
 
     let afes = {
@@ -558,6 +1408,12 @@ pub fn setup(
         (afe0, afe1)
     };
 
+    // The AFE supply settles while the remaining peripherals are configured.
+    let mut settle_deadline =
+        deadline_after(design_parameters::AFE_POWER_SETTLE_TIME);
+
+    setup_profile.spi = stages.lap("SPI", cpu_frequency);
+
     let digital_inputs = {
         let di0 = gpiog.pg9.into_floating_input();
         let di1 = gpioc.pc15.into_floating_input();
@@ -575,12 +1431,38 @@ pub fn setup(
         )
     };
 
-    let mac_addr = smoltcp::wire::EthernetAddress(eeprom::read_eui48(
-        &mut eeprom_i2c,
-        &mut delay,
-    ));
+    let mac_addr = eeprom::read_validated_eui48(&mut eeprom_i2c, &mut delay)
+        .unwrap_or_else(|err| {
+            let mac_addr = fallback_mac();
+            log::warn!(
+                "Invalid EUI48 in EEPROM ({:?}), using {}",
+                err,
+                mac_addr
+            );
+            mac_addr
+        });
     log::info!("EUI48: {}", mac_addr);
 
+    let servo_config = eeprom::read_servo_config(&mut eeprom_i2c)
+        .unwrap_or_else(|| {
+            log::warn!("Invalid servo configuration in EEPROM, using defaults");
+            eeprom::ServoConfig::default()
+        });
+
+    let dac_calibration = eeprom::read_dac_calibration(&mut eeprom_i2c)
+        .unwrap_or_else(|| {
+            log::warn!("Invalid DAC calibration in EEPROM, using defaults");
+            [dac::DacCalibration::default(); 2]
+        });
+    dacs.0.set_calibration(dac_calibration[0]);
+    dacs.1.set_calibration(dac_calibration[1]);
+
+    let adc_calibration = eeprom::read_adc_calibration(&mut eeprom_i2c)
+        .unwrap_or_else(|| {
+            log::warn!("Invalid ADC calibration in EEPROM, using defaults");
+            [adc::AdcCalibration::default(); 2]
+        });
+
     let network_devices = {
         let ethernet_pins = {
             // Reset the PHY before configuring pins.
@@ -672,24 +1554,16 @@ pub fn setup(
         let mut sockets =
             smoltcp::iface::SocketSet::new(&mut store.sockets[..]);
         for storage in store.tcp_socket_storage[..].iter_mut() {
-            let tcp_socket = {
-                let rx_buffer = smoltcp::socket::tcp::SocketBuffer::new(
-                    &mut storage.rx_storage[..],
-                );
-                let tx_buffer = smoltcp::socket::tcp::SocketBuffer::new(
-                    &mut storage.tx_storage[..],
-                );
-
-                smoltcp::socket::tcp::Socket::new(rx_buffer, tx_buffer)
-            };
-
-            sockets.add(tcp_socket);
+            sockets.add(tcp_socket(storage, &network_config));
         }
 
-        if ip_addrs.is_unspecified() {
+        let dhcp = ip_addrs.is_unspecified();
+        if dhcp {
             sockets.add(smoltcp::socket::dhcpv4::Socket::new());
         }
 
+        // The MQTT clients resolve the broker hostname with this socket through their
+        // `NamedBroker`, see `net::NetworkUsers::new()`. The query is retried until it resolves.
         sockets.add(smoltcp::socket::dns::Socket::new(
             &[],
             &mut store.dns_storage[..],
@@ -721,18 +1595,18 @@ pub fn setup(
             stack,
             phy: lan8742a,
             mac_address: mac_addr,
+            dhcp,
         }
     };
 
-    let mut fp_led_0 = gpiod.pd5.into_push_pull_output();
-    let mut fp_led_1 = gpiod.pd6.into_push_pull_output();
-    let mut fp_led_2 = gpiog.pg4.into_push_pull_output();
-    let mut fp_led_3 = gpiod.pd12.into_push_pull_output();
+    setup_profile.ethernet = stages.lap("ethernet", cpu_frequency);
 
-    fp_led_0.set_low();
-    fp_led_1.set_low();
-    fp_led_2.set_low();
-    fp_led_3.set_low();
+    let status_leds = FrontPanelLeds::new(
+        gpiod.pd5.into_push_pull_output(),
+        gpiod.pd6.into_push_pull_output(),
+        gpiog.pg4.into_push_pull_output(),
+        gpiod.pd12.into_push_pull_output(),
+    );
 
     let (adc1, adc2, adc3) = {
         let (mut adc1, mut adc2) = hal::adc::adc12(
@@ -779,6 +1653,248 @@ pub fn setup(
         )
     };
 
+    let pounder = if pounder_present {
+        // Note(unwrap): The shared bus is only created once.
+        let i2c1 = shared_bus::new_atomic_check!(
+            hal::i2c::I2c<hal::stm32::I2C1> = i2c1
+        )
+        .unwrap();
+
+        // A detected but faulty Pounder is disabled instead of failing setup.
+        let pounder = (|| -> Result<PounderDevices, pounder::Error> {
+            let mut pounder = {
+                let io_expander =
+                    mcp230xx::Mcp230xx::new_default(i2c1.acquire_i2c())
+                        .or(Err(pounder::Error::I2c))?;
+
+                let temp_sensor = lm75::Lm75::new(
+                    i2c1.acquire_i2c(),
+                    lm75::Address::default(),
+                );
+
+                let spi = {
+                    let mosi =
+                        gpiod.pd7.into_alternate().speed(Speed::VeryHigh);
+                    let miso =
+                        gpioa.pa6.into_alternate().speed(Speed::VeryHigh);
+                    let sck =
+                        gpiog.pg11.into_alternate().speed(Speed::VeryHigh);
+
+                    let config = hal::spi::Config::new(hal::spi::Mode {
+                        polarity: hal::spi::Polarity::IdleHigh,
+                        phase: hal::spi::Phase::CaptureOnSecondTransition,
+                    });
+
+                    // The maximum frequency of this SPI must be limited due to capacitance on the MISO
+                    // line causing a long RC decay.
+                    device.SPI1.spi(
+                        (sck, miso, mosi),
+                        config,
+                        5.MHz(),
+                        ccdr.peripheral.SPI1,
+                        &ccdr.clocks,
+                    )
+                };
+
+                let pwr0 = adc1.create_channel(gpiof.pf11.into_analog());
+                let pwr1 = adc2.create_channel(gpiof.pf14.into_analog());
+                let aux_adc0 = adc3.create_channel(gpiof.pf3.into_analog());
+                let aux_adc1 = adc3.create_channel(gpiof.pf4.into_analog());
+
+                let mut pounder = pounder::PounderDevices::new(
+                    temp_sensor,
+                    io_expander,
+                    spi,
+                    pwr0,
+                    pwr1,
+                    aux_adc0,
+                    aux_adc1,
+                )?;
+
+                let calibration =
+                    eeprom::read_power_calibration(&mut eeprom_i2c);
+                if calibration.is_none() {
+                    log::warn!(
+                        "Invalid power calibration in EEPROM, using defaults"
+                    );
+                }
+                pounder.set_power_calibration(calibration.unwrap_or_default());
+
+                // The flatness calibration is optional, the output power is assumed flat without it.
+                if let Some(calibration) =
+                    eeprom::read_flatness_calibration(&mut eeprom_i2c)
+                {
+                    pounder.set_flatness_calibration(calibration);
+                }
+
+                // DMA2 streams 0 and 1 are dedicated to the attenuator scan.
+                let dma2_streams = hal::dma::dma::StreamsTuple::new(
+                    device.DMA2,
+                    ccdr.peripheral.DMA2,
+                );
+                pounder
+                    .enable_attenuator_scan(dma2_streams.0, dma2_streams.1)?;
+                pounder
+            };
+
+            let (ad9959, reset_pin) = {
+                let qspi_interface = {
+                    // Instantiate the QUADSPI pins and peripheral interface.
+                    let qspi_pins = {
+                        let _ncs = gpioc
+                            .pc11
+                            .into_alternate::<9>()
+                            .speed(Speed::VeryHigh);
+
+                        let clk =
+                            gpiob.pb2.into_alternate().speed(Speed::VeryHigh);
+                        // Note(unwrap): PE7 is only taken for the reference timer if Pounder is absent.
+                        let io0 = pe7
+                            .take()
+                            .unwrap()
+                            .into_alternate()
+                            .speed(Speed::VeryHigh);
+                        let io1 =
+                            gpioe.pe8.into_alternate().speed(Speed::VeryHigh);
+                        let io2 =
+                            gpioe.pe9.into_alternate().speed(Speed::VeryHigh);
+                        let io3 =
+                            gpioe.pe10.into_alternate().speed(Speed::VeryHigh);
+
+                        (clk, io0, io1, io2, io3)
+                    };
+
+                    let qspi = device.QUADSPI.bank2(
+                        qspi_pins,
+                        design_parameters::POUNDER_QSPI_FREQUENCY.convert(),
+                        &ccdr.clocks,
+                        ccdr.peripheral.QSPI,
+                    );
+
+                    pounder::QspiInterface::new(qspi)?
+                };
+
+                let mut reset_pin = gpiog.pg6.into_push_pull_output().erase();
+                let mut io_update = gpiog.pg7.into_push_pull_output();
+
+                let mut ad9959 = ad9959::Ad9959::new(
+                    qspi_interface,
+                    &mut reset_pin,
+                    &mut io_update,
+                    &mut delay,
+                    ad9959::Mode::FourBitSerial,
+                    design_parameters::DDS_REF_CLK.to_Hz() as f32,
+                    design_parameters::DDS_MULTIPLIER,
+                )
+                .or(Err(pounder::Error::Init(pounder::InitStage::Dds)))?;
+
+                // The reference clock multiplier was just configured and needs to lock.
+                settle_deadline = latest_deadline(
+                    settle_deadline,
+                    deadline_after(design_parameters::DDS_PLL_LOCK_TIME),
+                );
+
+                // Register reads are only possible before the DDS is frozen into streaming mode.
+                match ad9959.dump_registers() {
+                    Ok(registers) => {
+                        log::debug!("DDS registers: {:?}", registers);
+
+                        let tuning = ad9959.tuning();
+                        for (index, channel) in
+                            registers.channels.iter().enumerate()
+                        {
+                            let [_, acr_high, acr_low] = channel.acr;
+                            log::debug!(
+                                "DDS channel {index}: {} Hz, {} turns, amplitude {}",
+                                tuning.frequency(channel.cftw0),
+                                tuning.phase(channel.cpow0),
+                                tuning.amplitude(u16::from_be_bytes([
+                                    acr_high, acr_low
+                                ])),
+                            );
+                        }
+                    }
+                    Err(err) => {
+                        log::warn!("Failed to read DDS registers: {:?}", err)
+                    }
+                }
+
+                let dds_present = ad9959.self_test().unwrap_or(false);
+                if !dds_present {
+                    log::warn!("DDS self test failed");
+                }
+                pounder.set_dds_present(dds_present);
+
+                // Return IO_Update
+                gpiog.pg7 = io_update.into_analog();
+
+                (ad9959, reset_pin)
+            };
+
+            let dds_output = {
+                let io_update_trigger = {
+                    let _io_update =
+                        gpiog.pg7.into_alternate::<2>().speed(Speed::VeryHigh);
+
+                    // Configure the IO_Update signal for the DDS.
+                    let mut hrtimer = pounder::hrtimer::HighResTimerE::new(
+                        device.HRTIM_TIME,
+                        device.HRTIM_MASTER,
+                        device.HRTIM_COMMON,
+                        ccdr.clocks,
+                        ccdr.peripheral.HRTIM,
+                    );
+
+                    // IO_Update occurs after a fixed delay from the QSPI write. Note that the timer
+                    // is triggered after the QSPI write, which can take approximately 120nS, so
+                    // there is additional margin.
+                    hrtimer.configure_single_shot(
+                        pounder::hrtimer::IO_UPDATE_CHANNEL,
+                        design_parameters::POUNDER_IO_UPDATE_DELAY,
+                        design_parameters::POUNDER_IO_UPDATE_DURATION,
+                    );
+
+                    // The default IO_Update pulse must be registered by the DDS at its system clock.
+                    assert!(
+                        design_parameters::POUNDER_IO_UPDATE_DURATION
+                            >= pounder::dds_output::min_io_update_duration(
+                                design_parameters::DDS_SYSTEM_CLK.to_Hz()
+                                    as f32
+                            )
+                    );
+
+                    // Ensure that we have enough time for an IO-update every batch.
+                    let sample_frequency = {
+                        design_parameters::TIMER_FREQUENCY.to_Hz() as f32
+                            / sample_ticks as f32
+                    };
+
+                    let sample_period = 1.0 / sample_frequency;
+                    assert!(
+                        sample_period * batch_size as f32
+                            > design_parameters::POUNDER_IO_UPDATE_DELAY
+                    );
+
+                    hrtimer
+                };
+
+                let (qspi, config) = ad9959.freeze();
+                DdsOutput::new(qspi, io_update_trigger, reset_pin, config)
+            };
+
+            Ok(PounderDevices {
+                pounder,
+                dds_output,
+            })
+        })();
+
+        pounder
+            .map_err(|err| log::error!("Pounder bring-up failed: {}", err))
+            .ok()
+    } else {
+        None
+    };
+
     let beat_timer = {
         let etr_pin = gpioa.pa0.into_alternate();
         // The frequency in the constructor is dont-care, as we will modify the period + clock
@@ -789,7 +1905,8 @@ pub fn setup(
                 .timer(1.kHz(), ccdr.peripheral.TIM8, &ccdr.clocks);
         let mut beat_timer8 = timers::BeatTimer::new(tim8);
 
-        beat_timer8.set_external_clock(timers::Prescaler::Div2);
+        let beat_prescaler = timers::Prescaler::Div2;
+        beat_timer8.set_external_clock(beat_prescaler);
         beat_timer8.start();
 
         beat_timer8.set_period_ticks(u16::MAX);
@@ -800,97 +1917,672 @@ pub fn setup(
             beat_timer8_channels.ch1,
             &mut ref_timer,
             etr_pin,
+            beat_prescaler,
+            reference_frequency,
         )
     };
 
-    let eem_gpio = EemGpioDevices {
-        lvds4: gpiod.pd1.into_floating_input(),
-        lvds5: gpiod.pd2.into_floating_input(),
-        lvds6: gpiod.pd3.into_push_pull_output(),
-        lvds7: gpiod.pd4.into_push_pull_output(),
+    let eem_gpio = {
+        let mut syscfg = device.SYSCFG;
+
+        // LVDS4 (PD1) and LVDS5 (PD2) are routed to EXTI lines 1 and 2 for edge capture.
+        let mut lvds4 = gpiod.pd1.into_floating_input();
+        lvds4.make_interrupt_source(&mut syscfg);
+        let mut lvds5 = gpiod.pd2.into_floating_input();
+        lvds5.make_interrupt_source(&mut syscfg);
+
+        EemGpioDevices::new(
+            lvds4,
+            lvds5,
+            gpiod.pd3.into_push_pull_output(),
+            gpiod.pd4.into_push_pull_output(),
+            device.EXTI,
+        )
     };
 
-    let (usb_device, usb_serial) = {
-        let usb_bus = cortex_m::singleton!(: Option<usb_device::bus::UsbBusAllocator<UsbBus>> = None).unwrap();
-        let endpoint_memory =
-            cortex_m::singleton!(: [u32; 1024] = [0; 1024]).unwrap();
-
-        //let usb_id = gpioa.pa10.into_alternate::<8>();
-        let usb_n = gpioa.pa11.into_alternate();
-        let usb_p = gpioa.pa12.into_alternate();
-
-        let usb = stm32h7xx_hal::usb_hs::USB2::new(
-            device.OTG2_HS_GLOBAL,
-            device.OTG2_HS_DEVICE,
-            device.OTG2_HS_PWRCLK,
-            usb_n,
-            usb_p,
-            ccdr.peripheral.USB2OTG,
-            &ccdr.clocks,
-        );
+    setup_profile.pounder = stages.lap("pounder", cpu_frequency);
 
-        // Generate a device serial number from the MAC address.
-        let serial_number =
-            cortex_m::singleton!(: Option<heapless::String<17>> = None)
+    let endpoint_memory =
+        cortex_m::singleton!(: [u32; 1024] = [0; 1024]).unwrap();
+
+    let (usb_serial, usb_memory) = usb_resources(
+        usb_enabled,
+        endpoint_memory,
+        |endpoint_memory| {
+            let usb_bus = cortex_m::singleton!(: Option<usb_device::bus::UsbBusAllocator<UsbBus>> = None).unwrap();
+
+            //let usb_id = gpioa.pa10.into_alternate::<8>();
+            let usb_n = gpioa.pa11.into_alternate();
+            let usb_p = gpioa.pa12.into_alternate();
+
+            let usb = stm32h7xx_hal::usb_hs::USB2::new(
+                device.OTG2_HS_GLOBAL,
+                device.OTG2_HS_DEVICE,
+                device.OTG2_HS_PWRCLK,
+                usb_n,
+                usb_p,
+                ccdr.peripheral.USB2OTG,
+                &ccdr.clocks,
+            );
+
+            // Generate a device serial number from the MAC address.
+            let serial_number =
+                cortex_m::singleton!(: Option<heapless::String<17>> = None)
+                    .unwrap();
+            {
+                let mut serial_string: heapless::String<17> =
+                    heapless::String::new();
+                let octets = mac_addr.0;
+
+                write!(
+                    serial_string,
+                    "{:02x}-{:02x}-{:02x}-{:02x}-{:02x}-{:02x}",
+                    octets[0],
+                    octets[1],
+                    octets[2],
+                    octets[3],
+                    octets[4],
+                    octets[5]
+                )
                 .unwrap();
-        {
-            let mut serial_string: heapless::String<17> =
-                heapless::String::new();
-            let octets = mac_addr.0;
-
-            write!(
-                serial_string,
-                "{:02x}-{:02x}-{:02x}-{:02x}-{:02x}-{:02x}",
-                octets[0],
-                octets[1],
-                octets[2],
-                octets[3],
-                octets[4],
-                octets[5]
-            )
-            .unwrap();
-            serial_number.replace(serial_string);
-        }
+                serial_number.replace(serial_string);
+            }
 
-        usb_bus.replace(stm32h7xx_hal::usb_hs::UsbBus::new(
-            usb,
-            &mut endpoint_memory[..],
-        ));
+            usb_bus.replace(stm32h7xx_hal::usb_hs::UsbBus::new(
+                usb,
+                &mut endpoint_memory[..],
+            ));
 
-        let serial = usbd_serial::SerialPort::new(usb_bus.as_ref().unwrap());
-        let usb_device = usb_device::device::UsbDeviceBuilder::new(
-            usb_bus.as_ref().unwrap(),
-            usb_device::device::UsbVidPid(0x1209, 0x392F),
-        )
-        .manufacturer("ARTIQ/Sinara")
-        .product("Stabilizer")
-        .serial_number(serial_number.as_ref().unwrap())
-        .device_class(usbd_serial::USB_CLASS_CDC)
-        .build();
+            let serial =
+                usbd_serial::SerialPort::new(usb_bus.as_ref().unwrap());
+            let usb_device = usb_device::device::UsbDeviceBuilder::new(
+                usb_bus.as_ref().unwrap(),
+                usb_device::device::UsbVidPid(0x1209, 0x392F),
+            )
+            .manufacturer("ARTIQ/Sinara")
+            .product("Stabilizer")
+            .serial_number(serial_number.as_ref().unwrap())
+            .device_class(usbd_serial::USB_CLASS_CDC)
+            .build();
+
+            SerialTerminal::new(usb_device, serial)
+        },
+    );
 
-        (usb_device, serial)
-    };
+    setup_profile.usb = stages.lap("USB", cpu_frequency);
+    log::debug!("setup: {} us total", setup_profile.total());
 
     let stabilizer = StabilizerDevices {
         systick,
         afes,
         adcs,
+        adc_calibration,
         dacs,
+        dac_control,
         temperature_sensor: CpuTempSensor::new(
             adc3.create_channel(hal::adc::Temperature::new()),
         ),
         timestamper: ref_timer,
+        reference_clock,
         net: network_devices,
         adc_dac_timer: sampling_timer,
+        adc_dac_shadow_timer: shadow_sampling_timer,
         digital_inputs,
         eem_gpio,
-        usb_serial: SerialTerminal::new(usb_device, usb_serial),
+        usb_serial,
+        usb_memory,
+        servo_config,
+        eeprom_i2c,
+        delay,
+        status_leds,
+        iwdg: hal::independent_watchdog::IndependentWatchdog::new(device.IWDG),
+        setup_profile,
+        hse_fallback: !hse_ready,
+        settle_deadline,
+        batch_size,
     };
 
-    // info!("Version {} {}", build_info::PKG_VERSION, build_info::GIT_VERSION.unwrap());
-    // info!("Built on {}", build_info::BUILT_TIME_UTC);
-    // info!("{} {}", build_info::RUSTC_VERSION, build_info::TARGET);
+    log::info!("{}", stabilizer.version());
     log::info!("setup() complete");
 
-    (stabilizer, beat_timer)
+    (stabilizer, pounder, beat_timer)
+}
+
+/// Compute the DWT cycle count at which a settle time starting now has elapsed.
+///
+/// # Args
+/// * `settle_time` - The settle time in seconds.
+fn deadline_after(settle_time: f32) -> u32 {
+    let cycles =
+        (settle_time * design_parameters::SYSCLK.to_Hz() as f32) as u32;
+    cortex_m::peripheral::DWT::cycle_count().wrapping_add(cycles)
+}
+
+/// Get the later of two DWT cycle count deadlines.
+fn latest_deadline(a: u32, b: u32) -> u32 {
+    if timers::wrap::diff_signed(a as u64, b as u64, 32) < 0 {
+        b
+    } else {
+        a
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn usb_disabled_returns_endpoint_memory() {
+        let config = SetupConfig {
+            usb_enabled: false,
+            ..SetupConfig::new(8, 128)
+        };
+        assert!(SetupConfig::new(8, 128).usb_enabled);
+
+        let mut memory = [0; 1024];
+        let (terminal, memory) =
+            usb_resources(config.usb_enabled, &mut memory, |_| {
+                panic!("USB set up while disabled")
+            });
+        assert!(terminal.is_none());
+
+        // The unused endpoint memory is handed out in full.
+        assert_eq!(memory.map(|memory| memory.len()), Some(1024));
+
+        // Enabled, the terminal takes the endpoint memory instead.
+        let mut memory = [0; 1024];
+        let (terminal, memory) = usb_resources(true, &mut memory, |memory| {
+            memory[0] = 1;
+            memory.len()
+        });
+        assert_eq!(terminal, Some(1024));
+        assert!(memory.is_none());
+    }
+
+    mod remote {
+        use smoltcp_nal::smoltcp::{
+            phy::{
+                self, ChecksumCapabilities, Device, DeviceCapabilities, Medium,
+            },
+            time::Instant,
+            wire::{
+                ArpOperation, ArpPacket, ArpRepr, EthernetAddress,
+                EthernetFrame, EthernetProtocol, EthernetRepr, IpProtocol,
+                Ipv4Address, Ipv4Packet, Ipv4Repr, TcpControl, TcpPacket,
+                TcpRepr, TcpSeqNumber,
+            },
+        };
+
+        pub const LOCAL_MAC: EthernetAddress =
+            EthernetAddress([0x02, 0, 0, 0, 0, 1]);
+        pub const REMOTE_MAC: EthernetAddress =
+            EthernetAddress([0x02, 0, 0, 0, 0, 2]);
+        pub const LOCAL_IP: Ipv4Address = Ipv4Address([10, 0, 0, 1]);
+        pub const REMOTE_IP: Ipv4Address = Ipv4Address([10, 0, 0, 2]);
+
+        const MTU: usize = 1514;
+
+        type Frame = heapless::Vec<u8, MTU>;
+
+        /// An ethernet link to a remote that resolves its address and accepts connections
+        /// while it is alive. It never responds to anything else.
+        pub struct MockRemote {
+            pub alive: bool,
+            rx: heapless::Deque<Frame, 4>,
+        }
+
+        impl MockRemote {
+            pub fn new() -> Self {
+                Self {
+                    alive: true,
+                    rx: heapless::Deque::new(),
+                }
+            }
+
+            fn respond(&mut self, frame: &[u8]) {
+                let frame = EthernetFrame::new_checked(frame).unwrap();
+                if !self.alive {
+                    return;
+                }
+
+                match frame.ethertype() {
+                    EthernetProtocol::Arp => {
+                        let packet =
+                            ArpPacket::new_checked(frame.payload()).unwrap();
+                        let (hardware_addr, protocol_addr) =
+                            match ArpRepr::parse(&packet).unwrap() {
+                                ArpRepr::EthernetIpv4 {
+                                    source_hardware_addr,
+                                    source_protocol_addr,
+                                    ..
+                                } => {
+                                    (source_hardware_addr, source_protocol_addr)
+                                }
+                                _ => return,
+                            };
+                        let reply = ArpRepr::EthernetIpv4 {
+                            operation: ArpOperation::Reply,
+                            source_hardware_addr: REMOTE_MAC,
+                            source_protocol_addr: REMOTE_IP,
+                            target_hardware_addr: hardware_addr,
+                            target_protocol_addr: protocol_addr,
+                        };
+                        self.send(
+                            EthernetProtocol::Arp,
+                            reply.buffer_len(),
+                            |buf| {
+                                reply.emit(&mut ArpPacket::new_unchecked(buf))
+                            },
+                        );
+                    }
+                    EthernetProtocol::Ipv4 => {
+                        let caps = ChecksumCapabilities::default();
+                        let packet =
+                            Ipv4Packet::new_checked(frame.payload()).unwrap();
+                        let ip = Ipv4Repr::parse(&packet, &caps).unwrap();
+                        let segment =
+                            TcpPacket::new_checked(packet.payload()).unwrap();
+                        let tcp = TcpRepr::parse(
+                            &segment,
+                            &ip.src_addr.into(),
+                            &ip.dst_addr.into(),
+                            &caps,
+                        )
+                        .unwrap();
+                        if tcp.control != TcpControl::Syn {
+                            return;
+                        }
+
+                        let reply = TcpRepr {
+                            src_port: tcp.dst_port,
+                            dst_port: tcp.src_port,
+                            control: TcpControl::Syn,
+                            seq_number: TcpSeqNumber(1000),
+                            ack_number: Some(tcp.seq_number + 1),
+                            window_len: 1024,
+                            window_scale: None,
+                            max_seg_size: None,
+                            sack_permitted: false,
+                            sack_ranges: [None; 3],
+                            payload: &[],
+                        };
+                        let ip = Ipv4Repr {
+                            src_addr: REMOTE_IP,
+                            dst_addr: LOCAL_IP,
+                            next_header: IpProtocol::Tcp,
+                            payload_len: reply.buffer_len(),
+                            hop_limit: 64,
+                        };
+                        let len = ip.buffer_len() + reply.buffer_len();
+                        self.send(EthernetProtocol::Ipv4, len, |buf| {
+                            let (header, payload) =
+                                buf.split_at_mut(ip.buffer_len());
+                            ip.emit(
+                                &mut Ipv4Packet::new_unchecked(header),
+                                &caps,
+                            );
+                            reply.emit(
+                                &mut TcpPacket::new_unchecked(payload),
+                                &REMOTE_IP.into(),
+                                &LOCAL_IP.into(),
+                                &caps,
+                            );
+                        });
+                    }
+                    _ => {}
+                }
+            }
+
+            fn send(
+                &mut self,
+                ethertype: EthernetProtocol,
+                len: usize,
+                emit: impl FnOnce(&mut [u8]),
+            ) {
+                let header = EthernetRepr {
+                    src_addr: REMOTE_MAC,
+                    dst_addr: LOCAL_MAC,
+                    ethertype,
+                };
+                let mut frame = Frame::new();
+                frame.resize(header.buffer_len() + len, 0).unwrap();
+                let (buf, payload) = frame.split_at_mut(header.buffer_len());
+                header.emit(&mut EthernetFrame::new_unchecked(buf));
+                emit(payload);
+                self.rx.push_back(frame).unwrap();
+            }
+        }
+
+        pub struct RxToken(Frame);
+
+        impl phy::RxToken for RxToken {
+            fn consume<R, F>(mut self, f: F) -> R
+            where
+                F: FnOnce(&mut [u8]) -> R,
+            {
+                f(&mut self.0)
+            }
+        }
+
+        pub struct TxToken<'a>(&'a mut MockRemote);
+
+        impl phy::TxToken for TxToken<'_> {
+            fn consume<R, F>(self, len: usize, f: F) -> R
+            where
+                F: FnOnce(&mut [u8]) -> R,
+            {
+                let mut frame = [0; MTU];
+                let result = f(&mut frame[..len]);
+                self.0.respond(&frame[..len]);
+                result
+            }
+        }
+
+        impl Device for MockRemote {
+            type RxToken<'a> = RxToken;
+            type TxToken<'a> = TxToken<'a>;
+
+            fn receive(
+                &mut self,
+                _timestamp: Instant,
+            ) -> Option<(RxToken, TxToken<'_>)> {
+                let frame = self.rx.pop_front()?;
+                Some((RxToken(frame), TxToken(self)))
+            }
+
+            fn transmit(&mut self, _timestamp: Instant) -> Option<TxToken<'_>> {
+                Some(TxToken(self))
+            }
+
+            fn capabilities(&self) -> DeviceCapabilities {
+                let mut caps = DeviceCapabilities::default();
+                caps.medium = Medium::Ethernet;
+                caps.max_transmission_unit = MTU;
+                caps
+            }
+        }
+    }
+
+    /// Connect a socket to a remote that disappears once the connection is established, and
+    /// report whether the socket is still open after idling for each of `N` seconds.
+    fn idle_connection<const N: usize>(config: &NetworkConfig) -> [bool; N] {
+        use remote::*;
+        use smoltcp::{
+            iface::{Config, Interface, SocketSet},
+            socket::tcp::{Socket, State},
+            time::{Duration, Instant},
+            wire::{HardwareAddress, IpCidr},
+        };
+
+        let mut device = MockRemote::new();
+        let mut iface = Interface::new(
+            Config::new(HardwareAddress::Ethernet(LOCAL_MAC)),
+            &mut device,
+            Instant::ZERO,
+        );
+        iface.update_ip_addrs(|addrs| {
+            addrs.push(IpCidr::new(LOCAL_IP.into(), 24)).unwrap()
+        });
+
+        let mut storage = [smoltcp::iface::SocketStorage::EMPTY; 1];
+        let mut sockets = SocketSet::new(&mut storage[..]);
+        let mut tcp_storage = TcpSocketStorage::new();
+        let handle = sockets.add(tcp_socket(&mut tcp_storage, config));
+        sockets
+            .get_mut::<Socket>(handle)
+            .connect(iface.context(), (REMOTE_IP, 1883), 49152)
+            .unwrap();
+
+        for _ in 0..4 {
+            iface.poll(Instant::ZERO, &mut device, &mut sockets);
+        }
+        assert_eq!(sockets.get::<Socket>(handle).state(), State::Established);
+
+        device.alive = false;
+        core::array::from_fn(|second| {
+            let now = Instant::ZERO + Duration::from_secs(second as u64 + 1);
+            iface.poll(now, &mut device, &mut sockets);
+            sockets.get::<Socket>(handle).is_open()
+        })
+    }
+
+    #[test]
+    fn idle_connection_is_aborted_after_timeout() {
+        let config = NetworkConfig::default();
+        assert_eq!(
+            config.tcp_timeout,
+            Some(smoltcp::time::Duration::from_secs(30))
+        );
+
+        // Keep-alives go unanswered, so the socket is closed once the timeout elapses since the
+        // remote was last heard.
+        let open: [bool; 31] = idle_connection(&config);
+        assert!(open[..29].iter().all(|open| *open));
+        assert!(!open[29]);
+        assert!(!open[30]);
+
+        // Without a timeout, the socket is held indefinitely.
+        let open: [bool; 60] = idle_connection(&NetworkConfig {
+            tcp_timeout: None,
+            ..config
+        });
+        assert!(open.iter().all(|open| *open));
+    }
+
+    /// Check whether a PLL synthesizes all outputs exactly from a source oscillator.
+    ///
+    /// # Note
+    /// The PLL reference must be within 2 to 16 MHz and the VCO within 192 to 836 MHz.
+    fn pll_synthesizes(source: u32, outputs: &[u32]) -> bool {
+        (1..=63)
+            .filter(|divm| source % divm == 0)
+            .map(|divm| source / divm)
+            .filter(|reference| (2_000_000..=16_000_000).contains(reference))
+            .any(|reference| {
+                (4..=512)
+                    .filter_map(|divn| reference.checked_mul(divn))
+                    .filter(|vco| (192_000_000..=836_000_000).contains(vco))
+                    .any(|vco| {
+                        outputs.iter().all(|output| {
+                            vco % output == 0
+                                && (1..=128).contains(&(vco / output))
+                        })
+                    })
+            })
+    }
+
+    #[test]
+    fn hsi_fallback_keeps_clock_tree() {
+        let hse = ClockTree::new(true);
+        let hsi = ClockTree::new(false);
+        assert_eq!(hse.source, 8_000_000);
+        assert_eq!(hsi.source, 64_000_000);
+
+        // Only the source differs, so the downstream dividers remain valid.
+        assert_eq!(
+            ClockTree {
+                source: hse.source,
+                ..hsi
+            },
+            hse
+        );
+
+        for tree in [hse, hsi] {
+            assert!(pll_synthesizes(tree.source, &[tree.sysclk]));
+            assert!(pll_synthesizes(tree.source, &[tree.pll2_p, tree.pll2_q]));
+            assert_eq!(tree.sysclk % tree.hclk, 0);
+            assert!((tree.sysclk / tree.hclk).is_power_of_two());
+        }
+
+        // A source that does not divide into a valid reference is detected.
+        assert!(!pll_synthesizes(7_000_000, &[hse.sysclk]));
+    }
+
+    #[test]
+    fn reduced_des_ring_fits() {
+        let reduced = ethernet::DesRing::<1, 2>::new();
+
+        assert!(des_ring_fits::<1, 2>());
+        assert!(
+            core::mem::size_of_val(&reduced)
+                < core::mem::size_of::<ethernet::DesRing<4, 4>>()
+        );
+        assert!(des_ring_fits::<4, 4>());
+        assert!(!des_ring_fits::<16, 16>());
+    }
+
+    #[test]
+    fn mac_from_uid_folds_the_id() {
+        let uid = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11];
+        let mac = mac_from_uid(&uid);
+        // Octet 1 is `0 ^ 5 ^ 10`, octet 2 is `1 ^ 6 ^ 11` and so on.
+        assert_eq!(mac.0, [0x02, 15, 12, 5, 11, 13]);
+        assert!(mac.is_unicast());
+        assert!(mac.is_local());
+
+        // Every ID byte contributes to the address.
+        for i in 0..uid.len() {
+            let mut other = uid;
+            other[i] ^= 0x80;
+            assert_ne!(mac_from_uid(&other), mac);
+        }
+
+        let mac = mac_from_uid(&[0xFF; 12]);
+        assert_eq!(mac.0, [0x02, 0xFF, 0xFF, 0, 0, 0]);
+        assert!(mac.is_unicast());
+    }
+
+    #[test]
+    fn network_status_waits_for_address() {
+        let address = smoltcp::wire::Ipv4Address::new(10, 0, 0, 2);
+
+        // The test continues while the address is pending, including while DHCP reports the
+        // unspecified address.
+        assert_eq!(NetworkStatus::conclude(true, None, true, 0, 100), None);
+        assert_eq!(
+            NetworkStatus::conclude(
+                true,
+                Some(smoltcp::wire::Ipv4Address::UNSPECIFIED),
+                true,
+                50,
+                100
+            ),
+            None
+        );
+
+        // Completes as soon as the address is assigned.
+        assert_eq!(
+            NetworkStatus::conclude(true, Some(address), true, 60, 100),
+            Some(NetworkStatus {
+                link_up: true,
+                address: Some(address),
+                dhcp: true,
+            })
+        );
+    }
+
+    #[test]
+    fn network_status_reports_partial_status_on_timeout() {
+        let address = smoltcp::wire::Ipv4Address::new(10, 0, 0, 2);
+
+        // A static address is assigned, but the link is down.
+        assert_eq!(
+            NetworkStatus::conclude(false, Some(address), false, 99, 100),
+            None
+        );
+        assert_eq!(
+            NetworkStatus::conclude(false, Some(address), false, 100, 100),
+            Some(NetworkStatus {
+                link_up: false,
+                address: Some(address),
+                dhcp: false,
+            })
+        );
+
+        assert_eq!(
+            NetworkStatus::conclude(true, None, true, 100, 100),
+            Some(NetworkStatus {
+                link_up: true,
+                address: None,
+                dhcp: true,
+            })
+        );
+    }
+
+    #[test]
+    fn spi_clock_uses_next_lower_divider() {
+        let kernel = 100_000_000;
+        assert_eq!(achievable_spi_clock(kernel, 50_000_000), 50_000_000);
+        assert_eq!(achievable_spi_clock(kernel, 49_000_000), 25_000_000);
+        assert_eq!(achievable_spi_clock(kernel, 200_000_000), 50_000_000);
+        assert_eq!(achievable_spi_clock(kernel, 1_000_000), 781_250);
+
+        // Requests below the slowest clock use the largest divider.
+        assert_eq!(achievable_spi_clock(kernel, 1), 390_625);
+    }
+
+    #[test]
+    fn spi_clock_is_limited_to_maximum() {
+        use hal::time::Hertz;
+
+        let kernel = Hertz::MHz(200);
+        for (requested, actual) in [
+            (50, 50_000_000),
+            (100, 50_000_000),
+            (30, 25_000_000),
+            (10, 6_250_000),
+        ] {
+            assert_eq!(
+                resolve_spi_clock("ADC0", kernel, Hertz::MHz(requested)),
+                Hertz::Hz(actual)
+            );
+        }
+    }
+
+    #[test]
+    fn assertion_delay_rounds_up_to_cycles() {
+        let clock = 50_000_000;
+        assert_eq!(assertion_delay_cycles(clock, 0.), Some(0));
+        assert_eq!(assertion_delay_cycles(clock, 10e-9), Some(1));
+        assert_eq!(assertion_delay_cycles(clock, 30e-9), Some(2));
+        assert_eq!(assertion_delay_cycles(clock, 220e-9), Some(12));
+        assert_eq!(assertion_delay_cycles(clock, 300e-9), None);
+        assert_eq!(assertion_delay_cycles(clock, -1e-9), None);
+        assert_eq!(assertion_delay_cycles(clock, f32::NAN), None);
+        assert_eq!(assertion_delay_cycles(clock, f32::INFINITY), None);
+    }
+
+    #[test]
+    fn setup_time_is_limited_to_maximum_delay() {
+        use hal::time::Hertz;
+
+        assert_eq!(resolve_setup_time("ADC0", Hertz::MHz(50), 220e-9), 220e-9);
+        assert_eq!(resolve_setup_time("ADC0", Hertz::MHz(50), 0.), 0.);
+
+        // At most 14 cycles of 20 ns can be expressed.
+        let max = resolve_setup_time("ADC0", Hertz::MHz(50), 1e-6);
+        assert!((max - 280e-9).abs() < 1e-12);
+        assert!(assertion_delay_cycles(50_000_000, max).is_some());
+    }
+
+    #[test]
+    fn setup_profile_accumulates_stages() {
+        let mut stages = StageTimer {
+            last: u32::MAX - 64_000,
+        };
+        let mut profile = SetupProfile {
+            clocks: stages.lap_at(u32::MAX, 64_000_000),
+            ..Default::default()
+        };
+        assert_eq!(profile.clocks, 1_000);
+
+        // The cycle counter wraps during the ITCM stage.
+        profile.itcm = stages.lap_at(99_999_999, 400_000_000);
+        profile.peripherals = stages.lap_at(100_399_999, 400_000_000);
+        profile.usb = stages.lap_at(100_400_399, 400_000_000);
+        assert_eq!(profile.itcm, 250_000);
+        assert_eq!(profile.peripherals, 1_000);
+        assert_eq!(profile.usb, 1);
+        assert_eq!(profile.total(), 252_001);
+    }
 }
@@ -15,6 +15,11 @@
 /// If the multiple priorities utilize the ADC that results in resource pre-emption, pre-emption is
 /// protected against through the use of an atomic bool. Attempting to utilize the ADC from a
 /// higher priority level while it is in use at a lower level will result in a [AdcError::InUse].
+///
+/// ## Sequences
+/// Multiple channels of the same ADC can be sampled back-to-back with
+/// [SharedAdc::read_sequence()]. The ADC is only locked once for the whole sequence, so no other
+/// conversion can be interleaved and the samples form a coherent snapshot.
 use embedded_hal::adc::{Channel, OneShot};
 use stm32h7xx_hal as hal;
 
@@ -22,6 +27,42 @@ use stm32h7xx_hal as hal;
 pub enum AdcError {
     /// Indicates that the ADC is already in use
     InUse,
+
+    /// Indicates that the number of channels and output samples do not match
+    LengthMismatch,
+}
+
+/// An ADC channel that can be sampled as part of a [SharedAdc::read_sequence()].
+pub trait SequenceChannel<A> {
+    /// Perform a single conversion of the channel.
+    ///
+    /// # Args
+    /// * `adc` - The locked ADC peripheral the channel is attached to.
+    ///
+    /// # Returns
+    /// The raw ADC code measured on the channel.
+    fn convert(&mut self, adc: &mut A) -> u32;
+}
+
+/// Sample multiple channels back-to-back while holding the ADC lock.
+///
+/// # Note
+/// See [SharedAdc::read_sequence()].
+fn convert_sequence<A>(
+    mutex: &spin::Mutex<A>,
+    channels: &mut [&mut dyn SequenceChannel<A>],
+    out: &mut [u32],
+) -> Result<(), AdcError> {
+    if channels.len() != out.len() {
+        return Err(AdcError::LengthMismatch);
+    }
+
+    let mut adc = mutex.try_lock().ok_or(AdcError::InUse)?;
+    for (channel, sample) in channels.iter_mut().zip(out.iter_mut()) {
+        *sample = channel.convert(&mut adc);
+    }
+
+    Ok(())
 }
 
 /// A single channel on an ADC peripheral.
@@ -52,7 +93,23 @@ where
     /// The raw ADC code measured on the channel.
     pub fn read_raw(&mut self) -> Result<u32, AdcError> {
         let mut adc = self.mutex.try_lock().ok_or(AdcError::InUse)?;
-        Ok(adc.read(&mut self.pin).unwrap())
+        Ok(self.convert(&mut adc))
+    }
+}
+
+impl<'a, Adc, PIN> SequenceChannel<hal::adc::Adc<Adc, hal::adc::Enabled>>
+    for AdcChannel<'a, Adc, PIN>
+where
+    PIN: Channel<Adc, ID = u8>,
+    hal::adc::Adc<Adc, hal::adc::Enabled>: OneShot<Adc, u32, PIN>,
+    <hal::adc::Adc<Adc, hal::adc::Enabled> as OneShot<Adc, u32, PIN>>::Error:
+        core::fmt::Debug,
+{
+    fn convert(
+        &mut self,
+        adc: &mut hal::adc::Adc<Adc, hal::adc::Enabled>,
+    ) -> u32 {
+        adc.read(&mut self.pin).unwrap()
     }
 }
 
@@ -93,4 +150,85 @@ impl<Adc> SharedAdc<Adc> {
             mutex: &self.mutex,
         }
     }
+
+    /// Sample multiple channels back-to-back while holding the ADC lock.
+    ///
+    /// # Args
+    /// * `channels` - The channels to sample, in conversion order. All channels must have been
+    ///   created from this ADC.
+    /// * `out` - The buffer to store the raw ADC codes in. Must be of the same length as
+    ///   `channels`.
+    ///
+    /// # Returns
+    /// [AdcError::LengthMismatch] if the lengths of `channels` and `out` differ, or
+    /// [AdcError::InUse] if the ADC is already in use.
+    pub fn read_sequence(
+        &self,
+        channels: &mut [&mut dyn SequenceChannel<
+            hal::adc::Adc<Adc, hal::adc::Enabled>,
+        >],
+        out: &mut [u32],
+    ) -> Result<(), AdcError> {
+        convert_sequence(&self.mutex, channels, out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A mock ADC that hands out increasing conversion indices.
+    #[derive(Default)]
+    struct MockAdc {
+        conversions: u32,
+    }
+
+    /// A mock channel that records the conversion index it was sampled at.
+    struct MockChannel(u32);
+
+    impl SequenceChannel<MockAdc> for MockChannel {
+        fn convert(&mut self, adc: &mut MockAdc) -> u32 {
+            adc.conversions += 1;
+            self.0 * 100 + adc.conversions
+        }
+    }
+
+    #[test]
+    fn sequence_converts_in_order() {
+        let mutex = spin::Mutex::new(MockAdc::default());
+        let (mut a, mut b, mut c) =
+            (MockChannel(7), MockChannel(3), MockChannel(5));
+        let mut out = [0; 3];
+
+        convert_sequence(&mutex, &mut [&mut a, &mut b, &mut c], &mut out)
+            .unwrap();
+        assert_eq!(out, [701, 302, 503]);
+        assert_eq!(mutex.lock().conversions, 3);
+    }
+
+    #[test]
+    fn sequence_rejects_length_mismatch() {
+        let mutex = spin::Mutex::new(MockAdc::default());
+        let (mut a, mut b) = (MockChannel(1), MockChannel(2));
+        let mut out = [0; 3];
+
+        assert!(matches!(
+            convert_sequence(&mutex, &mut [&mut a, &mut b], &mut out),
+            Err(AdcError::LengthMismatch)
+        ));
+        assert_eq!(out, [0; 3]);
+        assert_eq!(mutex.lock().conversions, 0);
+    }
+
+    #[test]
+    fn sequence_reports_busy_adc() {
+        let mutex = spin::Mutex::new(MockAdc::default());
+        let mut a = MockChannel(1);
+        let _guard = mutex.lock();
+
+        assert!(matches!(
+            convert_sequence(&mutex, &mut [&mut a], &mut [0]),
+            Err(AdcError::InUse)
+        ));
+    }
 }
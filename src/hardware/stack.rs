@@ -0,0 +1,110 @@
+//! Stack usage monitoring
+//!
+//! # Design
+//! The main stack grows downwards from the end of RAM towards the end of the statically allocated
+//! data (`__sheap`). There is no heap, so the region in between is only used by the stack.
+//!
+//! During setup, the unused part of this region is painted with a known pattern by [paint()]. The
+//! deepest stack usage since then is found by [stack_high_water()], which searches for the first
+//! word from the bottom of the region that was overwritten. This is a lower bound of the
+//! worst-case usage, as a stack frame may leave parts of the pattern intact.
+//!
+//! Painting must happen as early as possible during setup, before deep call chains run, so that
+//! their usage is covered.
+
+/// The pattern painted onto the unused stack.
+const PAINT: u32 = 0xCCCC_CCCC;
+
+/// The number of bytes below the current stack pointer left unpainted, covering the frame of
+/// [paint()] itself.
+const PAINT_MARGIN: usize = 256;
+
+extern "C" {
+    // Provided by the cortex-m-rt linker script.
+    static mut __sheap: u32;
+    static _stack_start: u32;
+}
+
+/// The stack usage.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct StackUsage {
+    /// The size of the region available to the stack in bytes.
+    pub size: usize,
+
+    /// The deepest usage of the stack in bytes.
+    pub high_water: usize,
+}
+
+/// Paint the unused stack.
+///
+/// # Note
+/// This must be called with interrupts disabled, i.e. during initialization.
+pub(crate) fn paint() {
+    let sp = cortex_m::register::msp::read() as usize;
+
+    // Note(unsafe): Only the addresses of the linker symbols are taken. The region between the end
+    // of the static data and the current stack pointer, minus a margin, is not in use and there is
+    // no heap or interrupt that could use it concurrently.
+    unsafe {
+        let bottom = core::ptr::addr_of_mut!(__sheap);
+        let words = sp.saturating_sub(PAINT_MARGIN + bottom as usize) / 4;
+        for offset in 0..words {
+            bottom.add(offset).write_volatile(PAINT);
+        }
+    }
+}
+
+/// Get the stack usage since the stack was painted during setup.
+pub fn stack_high_water() -> StackUsage {
+    // Note(unsafe): Only the addresses of the linker symbols are taken. The region between them
+    // is valid memory. Only the painted part below the deepest usage is inspected, which the
+    // stack has not reached concurrently.
+    unsafe {
+        let bottom = core::ptr::addr_of!(__sheap);
+        let top = core::ptr::addr_of!(_stack_start);
+        let words = (top as usize - bottom as usize) / 4;
+        let region = core::slice::from_raw_parts(bottom, words);
+
+        StackUsage {
+            size: words * 4,
+            high_water: high_water(region),
+        }
+    }
+}
+
+/// Compute the stack usage of a painted region.
+///
+/// # Args
+/// * `region` - The words of the stack region, lowest address first.
+///
+/// # Returns
+/// The number of bytes above the highest painted word from the bottom of the region.
+fn high_water(region: &[u32]) -> usize {
+    let painted = region.iter().take_while(|&&word| word == PAINT).count();
+    (region.len() - painted) * 4
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn high_water_of_painted_region() {
+        // The stack grows downwards, so the used part is at the end of the region.
+        let mut region = [PAINT; 64];
+        assert_eq!(high_water(&region), 0);
+
+        for word in region[40..].iter_mut() {
+            *word = 0x2000_0000;
+        }
+        assert_eq!(high_water(&region), 24 * 4);
+
+        // A frame that leaves parts of the pattern intact only counts from the deepest write.
+        region[50] = PAINT;
+        region[20] = 0;
+        assert_eq!(high_water(&region), 44 * 4);
+
+        assert_eq!(high_water(&[0; 8]), 32);
+        assert_eq!(high_water(&[]), 0);
+    }
+}
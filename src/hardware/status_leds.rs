@@ -0,0 +1,340 @@
+//! Stabilizer front panel status LEDs
+//!
+//! # Design
+//! The four front panel LEDs are used to indicate the device status. Each LED is assigned a fixed
+//! meaning:
+//!
+//! * LED0 (PD5): Network link is up.
+//! * LED1 (PD6): The servo is locked.
+//! * LED2 (PG4): An error occurred.
+//! * LED3 (PD12): Activity, e.g. data processing.
+//!
+//! ## Patterns
+//! Besides being switched on and off, each LED can show a [Pattern], e.g. a fast blink on an
+//! error or a slow blink while searching. Patterns are timed against the [super::SystemTimer] and
+//! advanced by [StatusLeds::update()], which never blocks and is intended to be called from a
+//! low-rate task. The timing resolution of a pattern is the update period.
+use super::{hal, MONOTONIC_FREQUENCY};
+use embedded_hal::digital::v2::OutputPin;
+
+/// The duration of a [Pattern::Pulse] in milliseconds.
+const PULSE_MS: u32 = 100;
+
+/// A front panel status LED.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Led {
+    Network = 0,
+    Lock = 1,
+    Error = 2,
+    Activity = 3,
+}
+
+impl Led {
+    /// All status LEDs.
+    pub const ALL: [Led; 4] =
+        [Led::Network, Led::Lock, Led::Error, Led::Activity];
+}
+
+/// The pattern shown by a status LED.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Pattern {
+    /// The LED is off.
+    Off,
+
+    /// The LED is on.
+    On,
+
+    /// The LED blinks periodically, starting with the on phase.
+    Blink { on_ms: u32, off_ms: u32 },
+
+    /// The LED is on for a short time once, then off.
+    Pulse,
+}
+
+impl Pattern {
+    /// Get the LED state at a time into the pattern.
+    ///
+    /// # Args
+    /// * `elapsed_ms` - The time since the pattern was set in milliseconds.
+    pub fn state(&self, elapsed_ms: u32) -> bool {
+        match *self {
+            Pattern::Off => false,
+            Pattern::On => true,
+            Pattern::Blink { on_ms, off_ms } => {
+                let period = on_ms.saturating_add(off_ms);
+                period == 0 || elapsed_ms % period < on_ms
+            }
+            Pattern::Pulse => elapsed_ms < PULSE_MS,
+        }
+    }
+}
+
+/// The pattern of a status LED and the time at which it was set.
+#[derive(Copy, Clone, Debug)]
+struct PatternState {
+    pattern: Pattern,
+    start: u32,
+}
+
+/// The front panel status LEDs of Stabilizer.
+pub type FrontPanelLeds = StatusLeds<
+    hal::gpio::gpiod::PD5<hal::gpio::Output<hal::gpio::PushPull>>,
+    hal::gpio::gpiod::PD6<hal::gpio::Output<hal::gpio::PushPull>>,
+    hal::gpio::gpiog::PG4<hal::gpio::Output<hal::gpio::PushPull>>,
+    hal::gpio::gpiod::PD12<hal::gpio::Output<hal::gpio::PushPull>>,
+>;
+
+/// The status LEDs.
+pub struct StatusLeds<N, L, E, A> {
+    network: N,
+    lock: L,
+    error: E,
+    activity: A,
+    patterns: [PatternState; 4],
+    now: u32,
+}
+
+impl<N, L, E, A> StatusLeds<N, L, E, A>
+where
+    N: OutputPin,
+    N::Error: core::fmt::Debug,
+    L: OutputPin,
+    L::Error: core::fmt::Debug,
+    E: OutputPin,
+    E::Error: core::fmt::Debug,
+    A: OutputPin,
+    A::Error: core::fmt::Debug,
+{
+    /// Construct the status LEDs. All LEDs are turned off.
+    ///
+    /// # Args
+    /// * `network` - The LED indicating the network link status.
+    /// * `lock` - The LED indicating the servo lock status.
+    /// * `error` - The LED indicating an error.
+    /// * `activity` - The LED indicating activity.
+    pub fn new(network: N, lock: L, error: E, activity: A) -> Self {
+        let mut leds = Self {
+            network,
+            lock,
+            error,
+            activity,
+            patterns: [PatternState {
+                pattern: Pattern::Off,
+                start: 0,
+            }; 4],
+            now: 0,
+        };
+
+        for led in Led::ALL {
+            leds.set_state(led, false);
+        }
+
+        leds
+    }
+
+    /// Indicate whether the network link is up.
+    pub fn set_network(&mut self, on: bool) {
+        self.set_pattern(Led::Network, on.into());
+    }
+
+    /// Indicate whether the servo is locked.
+    pub fn set_lock(&mut self, on: bool) {
+        self.set_pattern(Led::Lock, on.into());
+    }
+
+    /// Indicate whether an error occurred.
+    pub fn set_error(&mut self, on: bool) {
+        self.set_pattern(Led::Error, on.into());
+    }
+
+    /// Indicate activity.
+    pub fn set_activity(&mut self, on: bool) {
+        self.set_pattern(Led::Activity, on.into());
+    }
+
+    /// Show a pattern on an LED.
+    ///
+    /// # Note
+    /// The pattern starts at the time of the latest [Self::update()] and the LED is switched to
+    /// the initial state of the pattern immediately. Setting the pattern already shown does not
+    /// restart it, so the pattern may be set on every update.
+    ///
+    /// # Args
+    /// * `led` - The LED to show the pattern on.
+    /// * `pattern` - The pattern to show.
+    pub fn set_pattern(&mut self, led: Led, pattern: Pattern) {
+        let state = &mut self.patterns[led as usize];
+        if state.pattern == pattern && pattern != Pattern::Pulse {
+            return;
+        }
+
+        *state = PatternState {
+            pattern,
+            start: self.now,
+        };
+        self.set_state(led, pattern.state(0));
+    }
+
+    /// Get the pattern shown on an LED.
+    pub fn pattern(&self, led: Led) -> Pattern {
+        self.patterns[led as usize].pattern
+    }
+
+    /// Advance the LED patterns.
+    ///
+    /// # Args
+    /// * `now` - The current system timer ticks.
+    pub fn update(&mut self, now: u32) {
+        self.now = now;
+        for led in Led::ALL {
+            let state = self.patterns[led as usize];
+            let elapsed_ms = (now.wrapping_sub(state.start) as u64 * 1_000
+                / MONOTONIC_FREQUENCY as u64)
+                as u32;
+            self.set_state(led, state.pattern.state(elapsed_ms));
+        }
+    }
+
+    /// Switch an LED on or off.
+    fn set_state(&mut self, led: Led, on: bool) {
+        match led {
+            Led::Network => self.network.set_state(on.into()).unwrap(),
+            Led::Lock => self.lock.set_state(on.into()).unwrap(),
+            Led::Error => self.error.set_state(on.into()).unwrap(),
+            Led::Activity => self.activity.set_state(on.into()).unwrap(),
+        }
+    }
+}
+
+impl From<bool> for Pattern {
+    fn from(on: bool) -> Self {
+        if on {
+            Pattern::On
+        } else {
+            Pattern::Off
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::convert::Infallible;
+
+    /// An output pin that records its state.
+    #[derive(Default)]
+    struct MockPin {
+        high: bool,
+    }
+
+    impl OutputPin for MockPin {
+        type Error = Infallible;
+
+        fn set_low(&mut self) -> Result<(), Self::Error> {
+            self.high = false;
+            Ok(())
+        }
+
+        fn set_high(&mut self) -> Result<(), Self::Error> {
+            self.high = true;
+            Ok(())
+        }
+    }
+
+    type MockLeds = StatusLeds<MockPin, MockPin, MockPin, MockPin>;
+
+    fn leds() -> MockLeds {
+        StatusLeds::new(
+            MockPin { high: true },
+            MockPin { high: true },
+            MockPin { high: true },
+            MockPin { high: true },
+        )
+    }
+
+    #[test]
+    fn leds_start_off() {
+        let leds = leds();
+        assert!(!leds.network.high);
+        assert!(!leds.lock.high);
+        assert!(!leds.error.high);
+        assert!(!leds.activity.high);
+        for led in Led::ALL {
+            assert_eq!(leds.pattern(led), Pattern::Off);
+        }
+    }
+
+    #[test]
+    fn leds_switch_individually() {
+        let mut leds = leds();
+        leds.set_lock(true);
+        leds.set_activity(true);
+        assert!(!leds.network.high);
+        assert!(leds.lock.high);
+        assert!(!leds.error.high);
+        assert!(leds.activity.high);
+
+        leds.set_lock(false);
+        assert!(!leds.lock.high);
+        assert_eq!(leds.pattern(Led::Activity), Pattern::On);
+    }
+
+    #[test]
+    fn blink_follows_simulated_time() {
+        let mut leds = leds();
+        leds.update(1_000);
+        leds.set_pattern(
+            Led::Error,
+            Pattern::Blink {
+                on_ms: 200,
+                off_ms: 300,
+            },
+        );
+        assert!(leds.error.high);
+
+        for (elapsed_ms, on) in [
+            (0, true),
+            (199, true),
+            (200, false),
+            (499, false),
+            (500, true),
+            (700, false),
+            (1_000, true),
+        ] {
+            leds.update(1_000 + elapsed_ms * MONOTONIC_FREQUENCY / 1_000);
+            assert_eq!(leds.error.high, on, "at {elapsed_ms} ms");
+        }
+
+        // Setting the same pattern again does not restart it.
+        leds.update(1_200);
+        leds.set_pattern(
+            Led::Error,
+            Pattern::Blink {
+                on_ms: 200,
+                off_ms: 300,
+            },
+        );
+        assert!(!leds.error.high);
+    }
+
+    #[test]
+    fn pulse_expires_and_restarts() {
+        let mut leds = leds();
+        let start = u32::MAX - 50;
+        leds.update(start);
+        leds.set_pattern(Led::Activity, Pattern::Pulse);
+        assert!(leds.activity.high);
+
+        // The pulse is timed across a wrap of the system timer.
+        let ticks =
+            |ms: u32| start.wrapping_add(ms * MONOTONIC_FREQUENCY / 1_000);
+        leds.update(ticks(PULSE_MS - 1));
+        assert!(leds.activity.high);
+        leds.update(ticks(PULSE_MS));
+        assert!(!leds.activity.high);
+
+        // Setting a pulse again restarts it.
+        leds.set_pattern(Led::Activity, Pattern::Pulse);
+        assert!(leds.activity.high);
+    }
+}
@@ -0,0 +1,1039 @@
+//! The sampling timer is used for managing ADC sampling and external reference timestamping.
+use super::hal;
+use num_enum::TryFromPrimitive;
+
+pub mod wrap;
+
+use hal::stm32::{
+    // TIM1 and TIM8 have identical registers.
+    tim1 as __tim1,
+    tim1 as __tim8,
+    tim2 as __tim2,
+    // TIM2 and TIM5 have identical registers.
+    tim3 as __tim3,
+};
+
+/// The event that should generate an external trigger from the peripheral.
+#[allow(dead_code)]
+pub enum TriggerGenerator {
+    Reset = 0b000,
+    Enable = 0b001,
+    Update = 0b010,
+    ComparePulse = 0b011,
+    Ch1Compare = 0b100,
+    Ch2Compare = 0b101,
+    Ch3Compare = 0b110,
+    Ch4Compare = 0b111,
+}
+
+/// Selects the trigger source for the timer peripheral.
+#[allow(dead_code)]
+pub enum TriggerSource {
+    Trigger0 = 0,
+    Trigger1 = 0b01,
+    Trigger2 = 0b10,
+    Trigger3 = 0b11,
+    /// The filtered timer input 1 (TI1FP1).
+    TimerInput1 = 0b101,
+}
+
+/// Prescalers for externally-supplied reference clocks.
+#[allow(dead_code)]
+#[derive(TryFromPrimitive, Copy, Clone, Debug)]
+#[repr(u8)]
+pub enum Prescaler {
+    Div1 = 0b00,
+    Div2 = 0b01,
+    Div4 = 0b10,
+    Div8 = 0b11,
+}
+
+impl Prescaler {
+    /// Get the division ratio of the prescaler.
+    pub fn ratio(&self) -> u32 {
+        1 << (*self as u8)
+    }
+}
+
+/// Optional slave operation modes of a timer.
+#[allow(dead_code)]
+pub enum SlaveMode {
+    Disabled = 0,
+    Reset = 0b0100,
+    Trigger = 0b0110,
+}
+
+/// Optional input capture preconditioning filter configurations.
+#[allow(dead_code)]
+pub enum InputFilter {
+    Div1N1 = 0b0000,
+    Div1N8 = 0b0011,
+}
+
+macro_rules! timer_channels {
+    ($name:ident, $TY:ident, $size:ty) => {
+        paste::paste! {
+
+            /// The timer used for managing ADC sampling.
+            pub struct $name {
+                timer: hal::timer::Timer<hal::stm32::[< $TY >]>,
+                channels: Option<[< $TY:lower >]::Channels>,
+                update_event: Option<[< $TY:lower >]::UpdateEvent>,
+            }
+
+            impl $name {
+                /// Construct the sampling timer.
+                #[allow(dead_code)]
+                pub fn new(mut timer: hal::timer::Timer<hal::stm32::[< $TY>]>) -> Self {
+                    timer.pause();
+
+                    Self {
+                        timer,
+                        // Note(unsafe): Once these channels are taken, we guarantee that we do not
+                        // modify any of the underlying timer channel registers, as ownership of the
+                        // channels is now provided through the associated channel structures. We
+                        // additionally guarantee this can only be called once because there is only
+                        // one Timer2 and this resource takes ownership of it once instantiated.
+                        channels: unsafe { Some([< $TY:lower >]::Channels::new()) },
+                        update_event: unsafe { Some([< $TY:lower >]::UpdateEvent::new()) },
+                    }
+                }
+
+                /// Get the timer capture/compare channels.
+                #[allow(dead_code)]
+                pub fn channels(&mut self) -> [< $TY:lower >]::Channels {
+                    self.channels.take().unwrap()
+                }
+
+                /// Get the timer update event.
+                #[allow(dead_code)]
+                pub fn update_event(&mut self) -> [< $TY:lower >]::UpdateEvent {
+                    self.update_event.take().unwrap()
+                }
+
+                /// Get the period of the timer.
+                #[allow(dead_code)]
+                pub fn get_period(&self) -> $size {
+                    let regs = unsafe { &*hal::stm32::$TY::ptr() };
+                    regs.arr.read().arr().bits()
+                }
+
+                /// Manually set the period of the timer.
+                #[allow(dead_code)]
+                pub fn set_period_ticks(&mut self, period: $size) {
+                    let regs = unsafe { &*hal::stm32::$TY::ptr() };
+                    regs.arr.write(|w| w.arr().bits(period));
+
+                    // Force the new period to take effect immediately.
+                    self.timer.apply_freq();
+                }
+
+                /// Enable preloading of the timer period.
+                ///
+                /// # Note
+                /// Once enabled, writes by [Self::schedule_period_ticks()] take effect at the next
+                /// update event.
+                #[allow(dead_code)]
+                pub fn enable_period_preload(&mut self) {
+                    let regs = unsafe { &*hal::stm32::$TY::ptr() };
+                    regs.cr1.modify(|_, w| w.arpe().set_bit());
+                }
+
+                /// Set the period of the timer without forcing an update event.
+                ///
+                /// # Note
+                /// With period preloading enabled, the period changes at the next update event.
+                #[allow(dead_code)]
+                pub fn schedule_period_ticks(&mut self, period: $size) {
+                    let regs = unsafe { &*hal::stm32::$TY::ptr() };
+                    regs.arr.write(|w| w.arr().bits(period));
+                }
+
+                /// Get the current counter value of the timer.
+                #[allow(dead_code)]
+                pub fn counter(&self) -> $size {
+                    // Note(unsafe): The counter register is only read.
+                    let regs = unsafe { &*hal::stm32::$TY::ptr() };
+                    regs.cnt.read().cnt().bits()
+                }
+
+                /// Clock the timer from an external source.
+                ///
+                /// # Note:
+                /// * Currently, only an external source applied to ETR is supported.
+                ///
+                /// # Args
+                /// * `prescaler` - The prescaler to use for the external source.
+                #[allow(dead_code)]
+                pub fn set_external_clock(&mut self, prescaler: Prescaler) {
+                    let regs = unsafe { &*hal::stm32::$TY::ptr() };
+                    regs.smcr.modify(|_, w| w.etps().bits(prescaler as u8).ece().set_bit());
+
+                    // Clear any other prescaler configuration.
+                    regs.psc.write(|w| w.psc().bits(0));
+                }
+
+                /// Start the timer.
+                #[allow(dead_code)]
+                pub fn start(&mut self) {
+                    // Force a refresh of the frequency settings.
+                    self.timer.apply_freq();
+                    self.timer.reset_counter();
+
+                    self.timer.resume();
+                }
+
+                /// Configure the timer peripheral to generate a trigger based on the provided
+                /// source.
+                #[allow(dead_code)]
+                pub fn generate_trigger(&mut self, source: TriggerGenerator) {
+                    let regs = unsafe { &*hal::stm32::$TY::ptr() };
+                    // Note(unsafe) The TriggerGenerator enumeration is specified such that this is
+                    // always in range.
+                    regs.cr2.modify(|_, w| w.mms().bits(source as u8));
+
+                }
+
+                /// Select a trigger source for the timer peripheral.
+                #[allow(dead_code)]
+                pub fn set_trigger_source(&mut self, source: TriggerSource) {
+                    let regs = unsafe { &*hal::stm32::$TY::ptr() };
+                    // Note(unsafe) The TriggerSource enumeration is specified such that this is
+                    // always in range.
+                    regs.smcr.modify(|_, w| unsafe { w.ts().bits(source as u8) } );
+                }
+
+                #[allow(dead_code)]
+                pub fn set_slave_mode(&mut self, source: TriggerSource, mode: SlaveMode) {
+                    let regs = unsafe { &*hal::stm32::$TY::ptr() };
+                    // Note(unsafe) The TriggerSource and SlaveMode enumerations are specified such
+                    // that they are always in range.
+                    regs.smcr.modify(|_, w| unsafe { w.sms().bits(mode as u8).ts().bits(source as u8) } );
+                }
+            }
+
+            pub mod [< $TY:lower >] {
+                use stm32h7xx_hal as hal;
+                use hal::dma::{traits::TargetAddress, PeripheralToMemory, dma::DMAReq};
+                use hal::stm32::$TY;
+
+                pub struct UpdateEvent {}
+
+                impl UpdateEvent {
+                    /// Create a new update event
+                    ///
+                    /// # Safety
+                    /// This is only safe to call once.
+                    #[allow(dead_code)]
+                    pub unsafe fn new() -> Self {
+                        Self {}
+                    }
+
+                    /// Enable DMA requests upon timer updates.
+                    #[allow(dead_code)]
+                    pub fn listen_dma(&self) {
+                        // Note(unsafe): We perform only atomic operations on the timer registers.
+                        let regs = unsafe { &*<$TY>::ptr() };
+                        regs.dier.modify(|_, w| w.ude().set_bit());
+                    }
+
+                    /// Trigger a DMA request manually
+                    #[allow(dead_code)]
+                    pub fn trigger(&self) {
+                        let regs = unsafe { &*<$TY>::ptr() };
+                        regs.egr.write(|w| w.ug().set_bit());
+                    }
+                }
+
+                /// The channels representing the timer.
+                pub struct Channels {
+                    pub ch1: Channel1,
+                    pub ch2: Channel2,
+                    pub ch3: Channel3,
+                    pub ch4: Channel4,
+                }
+
+                impl Channels {
+                    /// Construct a new set of channels.
+                    ///
+                    /// # Safety
+                    /// This is only safe to call once.
+                    #[allow(dead_code)]
+                    pub unsafe fn new() -> Self {
+                        Self {
+                            ch1: Channel1::new(),
+                            ch2: Channel2::new(),
+                            ch3: Channel3::new(),
+                            ch4: Channel4::new(),
+                        }
+                    }
+                }
+
+                timer_channels!(1, $TY, ccmr1, $size);
+                timer_channels!(2, $TY, ccmr1, $size);
+                timer_channels!(3, $TY, ccmr2, $size);
+                timer_channels!(4, $TY, ccmr2, $size);
+            }
+        }
+    };
+
+    ($index:expr, $TY:ty, $ccmrx:expr, $size:ty) => {
+        paste::paste! {
+            pub use super::[< __ $TY:lower >]::[< $ccmrx _input >]::[< CC $index S_A>] as [< CaptureSource $index >];
+
+            /// A capture/compare channel of the timer.
+            pub struct [< Channel $index >] {}
+
+            /// A capture channel of the timer.
+            pub struct [< Channel $index InputCapture>] {}
+
+            impl [< Channel $index >] {
+                /// Construct a new timer channel.
+                ///
+                /// Note(unsafe): This function must only be called once. Once constructed, the
+                /// constructee guarantees to never modify the timer channel.
+                #[allow(dead_code)]
+                unsafe fn new() -> Self {
+                    Self {}
+                }
+
+                /// Allow the channel to generate DMA requests.
+                #[allow(dead_code)]
+                pub fn listen_dma(&self) {
+                    let regs = unsafe { &*<$TY>::ptr() };
+                    regs.dier.modify(|_, w| w.[< cc $index de >]().set_bit());
+                }
+
+                /// Operate the channel as an output-compare.
+                ///
+                /// # Args
+                /// * `value` - The value to compare the sampling timer's counter against.
+                #[allow(dead_code)]
+                pub fn to_output_compare(&self, value: $size) {
+                    let regs = unsafe { &*<$TY>::ptr() };
+                    let arr = regs.arr.read().bits() as $size;
+                    assert!(value <= arr);
+                    regs.ccr[$index - 1].write(|w| w.ccr().bits(value));
+                    regs.[< $ccmrx _output >]()
+                        .modify(|_, w| unsafe { w.[< cc $index s >]().bits(0) });
+                }
+
+                /// Operate the channel in input-capture mode.
+                ///
+                /// # Args
+                /// * `input` - The input source for the input capture event.
+                #[allow(dead_code)]
+                pub fn into_input_capture(self, input: [< CaptureSource $index >]) -> [< Channel $index InputCapture >]{
+                    let regs = unsafe { &*<$TY>::ptr() };
+
+                    regs.[< $ccmrx _input >]().modify(|_, w| w.[< cc $index s>]().variant(input));
+
+                    [< Channel $index InputCapture >] {}
+                }
+            }
+
+            impl [< Channel $index InputCapture >] {
+                /// Get the latest capture from the channel.
+                #[allow(dead_code)]
+                pub fn latest_capture(&mut self) -> Result<Option<$size>, Option<$size>> {
+                    // Note(unsafe): This channel owns all access to the specific timer channel.
+                    // Only atomic operations on completed on the timer registers.
+                    let regs = unsafe { &*<$TY>::ptr() };
+
+                    if regs.sr.read().[< cc $index if >]().bit_is_set() {
+                        // Read the capture value. Reading the captured value clears the flag in the
+                        // status register automatically.
+                        let result = regs.ccr[$index - 1].read().ccr().bits();
+
+                        // Read SR again to check for a potential over-capture. Return an error in
+                        // that case.
+                        let sr = regs.sr.read();
+                        if sr.[< cc $index of >]().bit_is_set() {
+                            // NOTE(unsafe) write-back is safe
+                            regs.sr.write(|w| unsafe { w.bits(sr.bits()) }.[< cc $index of >]().clear_bit());
+                            Err(Some(result))
+                        } else {
+                            Ok(Some(result))
+                        }
+                    } else {
+                        Ok(None)
+                    }
+                }
+
+                /// Allow the channel to generate DMA requests.
+                #[allow(dead_code)]
+                pub fn listen_dma(&self) {
+                    // Note(unsafe): This channel owns all access to the specific timer channel.
+                    // Only atomic operations on completed on the timer registers.
+                    let regs = unsafe { &*<$TY>::ptr() };
+                    regs.dier.modify(|_, w| w.[< cc $index de >]().set_bit());
+                }
+
+                /// Enable the input capture to begin capturing timer values.
+                #[allow(dead_code)]
+                pub fn enable(&mut self) {
+                    // Read the latest input capture to clear any pending data in the register.
+                    let _ = self.latest_capture();
+
+                    // Note(unsafe): This channel owns all access to the specific timer channel.
+                    // Only atomic operations on completed on the timer registers.
+                    let regs = unsafe { &*<$TY>::ptr() };
+                    regs.ccer.modify(|_, w| w.[< cc $index e >]().set_bit());
+                }
+
+                /// Check if an over-capture event has occurred.
+                #[allow(dead_code)]
+                pub fn check_overcapture(&self) -> bool {
+                    // Note(unsafe): This channel owns all access to the specific timer channel.
+                    // Only atomic operations on completed on the timer registers.
+                    let regs = unsafe { &*<$TY>::ptr() };
+                    regs.sr.read().[< cc $index of >]().bit_is_set()
+                }
+
+                /// Configure the input capture input pre-filter.
+                ///
+                /// # Args
+                /// * `filter` - The desired input filter stage configuration. Defaults to disabled.
+                #[allow(dead_code)]
+                pub fn configure_filter(&mut self, filter: super::InputFilter) {
+                    // Note(unsafe): This channel owns all access to the specific timer channel.
+                    // Only atomic operations on completed on the timer registers.
+                    let regs = unsafe { &*<$TY>::ptr() };
+                    regs.[< $ccmrx _input >]().modify(|_, w| w.[< ic $index f >]().bits(filter as u8));
+                }
+
+                /// Configure the input capture prescaler.
+                ///
+                /// # Args
+                /// * `psc` - Prescaler exponent.
+                #[allow(dead_code)]
+                pub fn configure_prescaler(&mut self, prescaler: super::Prescaler) {
+                    // Note(unsafe): This channel owns all access to the specific timer channel.
+                    // Only atomic operations on completed on the timer registers.
+                    let regs = unsafe { &*<$TY>::ptr() };
+                    // Note(unsafe): Enum values are all valid.
+                    #[allow(unused_unsafe)]
+                    regs.[< $ccmrx _input >]().modify(|_, w| unsafe {
+                        w.[< ic $index psc >]().bits(prescaler as u8)});
+                }
+            }
+
+            // Note(unsafe): This manually implements DMA support for input-capture channels. This
+            // is safe as it is only completed once per channel and each DMA request is allocated to
+            // each channel as the owner.
+            unsafe impl TargetAddress<PeripheralToMemory> for [< Channel $index InputCapture >] {
+                type MemSize = $size;
+
+                const REQUEST_LINE: Option<u8> = Some(DMAReq::[< $TY:camel Ch $index >]as u8);
+
+                fn address(&self) -> usize {
+                    let regs = unsafe { &*<$TY>::ptr() };
+                    &regs.ccr[$index - 1] as *const _ as usize
+                }
+            }
+        }
+    };
+}
+
+timer_channels!(SamplingTimer, TIM2, u32);
+timer_channels!(ShadowSamplingTimer, TIM3, u16);
+
+timer_channels!(ReferenceTimer, TIM1, u16);
+timer_channels!(BeatTimer, TIM8, u16);
+
+/// The number of CPU cycles to wait between the two counter reads of the reference detection.
+const REFERENCE_DETECT_CYCLES: u32 = 4_000;
+
+/// The counter and trigger of the reference timer.
+trait ReferenceCounter {
+    fn counter(&self) -> u16;
+    fn set_slave_mode(&mut self, source: TriggerSource, mode: SlaveMode);
+    fn triggered(&self) -> bool;
+    fn clear_trigger(&mut self);
+}
+
+impl ReferenceCounter for ReferenceTimer {
+    fn counter(&self) -> u16 {
+        self.counter()
+    }
+
+    fn set_slave_mode(&mut self, source: TriggerSource, mode: SlaveMode) {
+        self.set_slave_mode(source, mode)
+    }
+
+    fn triggered(&self) -> bool {
+        // Note(unsafe): The status register is only read.
+        let regs = unsafe { &*hal::stm32::TIM1::ptr() };
+        regs.sr.read().tif().bit_is_set()
+    }
+
+    fn clear_trigger(&mut self) {
+        // Note(unsafe): Only the trigger flag of the timer is cleared.
+        let regs = unsafe { &*hal::stm32::TIM1::ptr() };
+        regs.sr.modify(|_, w| w.tif().clear_bit());
+    }
+}
+
+/// Check whether a counter advances during a detection window.
+///
+/// # Args
+/// * `timer` - The timer to check the counter of.
+/// * `wait` - Waits for the duration of the detection window.
+fn counter_advanced(
+    timer: &impl ReferenceCounter,
+    wait: impl FnOnce(),
+) -> bool {
+    let first = timer.counter();
+    wait();
+    first != timer.counter()
+}
+
+/// Reset a counter on every trigger, discarding any pending trigger, see
+/// [ReferenceTimer::sync_to_external_trigger()].
+///
+/// # Args
+/// * `timer` - The timer to reset the counter of.
+/// * `source` - The trigger input.
+fn reset_on_trigger(timer: &mut impl ReferenceCounter, source: TriggerSource) {
+    timer.set_slave_mode(source, SlaveMode::Reset);
+    timer.clear_trigger();
+}
+
+/// Get the counter value since the most recent trigger, see [ReferenceTimer::sync_offset()].
+///
+/// # Args
+/// * `timer` - The timer reset by the trigger.
+fn offset_from_trigger(timer: &mut impl ReferenceCounter) -> Option<u16> {
+    if !timer.triggered() {
+        return None;
+    }
+
+    let offset = timer.counter();
+    timer.clear_trigger();
+    Some(offset)
+}
+
+impl ReferenceTimer {
+    /// Check whether the reference clock is present.
+    ///
+    /// # Note
+    /// The counter is read twice, [REFERENCE_DETECT_CYCLES] CPU cycles apart (10 us at a 400 MHz
+    /// core clock). The reference is considered present if the counter advanced in between. The
+    /// timer must be running for the detection to succeed.
+    ///
+    /// References slower than one tick per detection window (100 kHz) are reported as absent.
+    /// A floating or noisy clock input that toggles spuriously may be reported as present. The
+    /// counter could also return to its initial value if it advanced by a multiple of the timer
+    /// period in the window, but this requires a clock far above the maximum timer input rate.
+    ///
+    /// # Returns
+    /// True if the timer counter advanced during the detection window.
+    pub fn reference_present(&self) -> bool {
+        counter_advanced(self, || cortex_m::asm::delay(REFERENCE_DETECT_CYCLES))
+    }
+
+    /// Reset the counter on every external sync pulse.
+    ///
+    /// # Note
+    /// Boards fed with the same reference clock and sync pulse then share a common counter phase.
+    /// Without sync pulses, the counter keeps free-running at its period, so sampling is not
+    /// interrupted if the pulse is absent or lost. Any pending sync event is discarded.
+    ///
+    /// For [TriggerSource::TimerInput1], the sync pulse must be routed to the TIM1 CH1 input,
+    /// which is configured by the caller.
+    ///
+    /// # Args
+    /// * `source` - The trigger input carrying the sync pulse.
+    pub fn sync_to_external_trigger(&mut self, source: TriggerSource) {
+        reset_on_trigger(self, source)
+    }
+
+    /// Get the offset of the counter from the most recent sync pulse.
+    ///
+    /// # Note
+    /// The trigger flag is consumed, so each sync pulse is reported once. The offset is only
+    /// unambiguous if it is read within one counter period of the sync pulse.
+    ///
+    /// # Returns
+    /// The number of ticks since the sync pulse reset the counter, or `None` if no sync pulse
+    /// was received since the previous call and the counter is free-running.
+    pub fn sync_offset(&mut self) -> Option<u16> {
+        offset_from_trigger(self)
+    }
+}
+
+/// The smallest supported sample period in timer ticks. The ADC and DAC trigger compare channels
+/// are placed at up to 5 ticks into the sample period.
+const MIN_SAMPLE_TICKS: u32 = 8;
+
+/// The number of ticks before the end of a sample period during which the period is not updated.
+/// This guarantees that no update event occurs between the updates of the two sampling timers.
+const SAMPLE_PERIOD_UPDATE_MARGIN: u32 = 4;
+
+/// Errors encountered while reconfiguring the sample period.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SampleTicksError {
+    /// The sample period is out of the supported range of the sampling timers.
+    Bounds,
+    /// The batch period exceeds the RTIC monotonic timer period.
+    BatchPeriod,
+}
+
+/// Check that a batch period does not exceed the RTIC monotonic timer period.
+///
+/// # Args
+/// * `batch_size` - The number of samples per batch.
+/// * `sample_ticks` - The number of timer ticks per sample.
+pub fn batch_period_valid(batch_size: usize, sample_ticks: u32) -> bool {
+    (batch_size as u32 * sample_ticks) as f32
+        * super::design_parameters::TIMER_PERIOD
+        * (super::MONOTONIC_FREQUENCY as f32)
+        < 1.
+}
+
+/// Compute the sample rate for a sample period.
+///
+/// # Args
+/// * `sample_ticks` - The number of timer ticks per sample.
+///
+/// # Returns
+/// The sample rate in Hz.
+pub fn sample_rate_hz(sample_ticks: u32) -> f32 {
+    super::design_parameters::TIMER_FREQUENCY.to_Hz() as f32
+        / sample_ticks as f32
+}
+
+/// Check that a sample period is supported by the sampling timers and the batch period.
+///
+/// # Args
+/// * `ticks` - The number of timer ticks per sample.
+/// * `batch_size` - The number of samples per batch.
+fn check_sample_ticks(
+    ticks: u32,
+    batch_size: usize,
+) -> Result<(), SampleTicksError> {
+    if !(MIN_SAMPLE_TICKS..=u16::MAX as u32 + 1).contains(&ticks) {
+        return Err(SampleTicksError::Bounds);
+    }
+
+    if !batch_period_valid(batch_size, ticks) {
+        return Err(SampleTicksError::BatchPeriod);
+    }
+
+    Ok(())
+}
+
+/// The period configuration of the sampling timers.
+trait SamplePeriod {
+    fn counter(&self) -> u32;
+    fn period(&self) -> u32;
+    fn enable_period_preload(&mut self);
+    fn schedule_period(&mut self, period: u32);
+}
+
+impl SamplePeriod for SamplingTimer {
+    fn counter(&self) -> u32 {
+        self.counter()
+    }
+
+    fn period(&self) -> u32 {
+        self.get_period()
+    }
+
+    fn enable_period_preload(&mut self) {
+        self.enable_period_preload()
+    }
+
+    fn schedule_period(&mut self, period: u32) {
+        self.schedule_period_ticks(period)
+    }
+}
+
+impl SamplePeriod for ShadowSamplingTimer {
+    fn counter(&self) -> u32 {
+        self.counter() as u32
+    }
+
+    fn period(&self) -> u32 {
+        self.get_period() as u32
+    }
+
+    fn enable_period_preload(&mut self) {
+        self.enable_period_preload()
+    }
+
+    fn schedule_period(&mut self, period: u32) {
+        self.schedule_period_ticks(period as u16)
+    }
+}
+
+/// Preload a new sample period into the sampling timer and its shadow timer.
+///
+/// # Note
+/// The periods are only written once the counter of the sampling timer is outside of
+/// [SAMPLE_PERIOD_UPDATE_MARGIN], so that both timers change period at the same update event.
+/// This must be called with interrupts disabled.
+///
+/// # Args
+/// * `timer` - The sampling timer.
+/// * `shadow` - The timer slaved to the sampling timer.
+/// * `ticks` - The number of timer ticks per sample.
+fn preload_sample_period(
+    timer: &mut impl SamplePeriod,
+    shadow: &mut impl SamplePeriod,
+    ticks: u32,
+) {
+    timer.enable_period_preload();
+    shadow.enable_period_preload();
+
+    let period = timer.period();
+    while timer.counter() >= period.saturating_sub(SAMPLE_PERIOD_UPDATE_MARGIN)
+    {
+    }
+
+    timer.schedule_period(ticks - 1);
+    shadow.schedule_period(ticks - 1);
+}
+
+/// Get the number of timer ticks from a previous counter value to the current one.
+///
+/// # Args
+/// * `timer` - The sampling timer.
+/// * `previous` - The previous counter value.
+fn ticks_since(timer: &impl SamplePeriod, previous: u32) -> u32 {
+    let now = timer.counter();
+    if now >= previous {
+        now - previous
+    } else {
+        // The counter wrapped at the end of the sample period.
+        now + (timer.period() - previous) + 1
+    }
+}
+
+impl SamplingTimer {
+    /// Get the number of timer ticks per sample period.
+    pub fn period_ticks(&self) -> u32 {
+        self.get_period() + 1
+    }
+
+    /// Get the number of timer ticks elapsed since a previous counter value.
+    ///
+    /// # Note
+    /// The counter wraps at the end of every sample period, see [SamplingTimer::counter()]. The
+    /// result is only correct if at most one period boundary passed since the previous value was
+    /// read.
+    ///
+    /// # Args
+    /// * `previous` - A previous value of [SamplingTimer::counter()].
+    pub fn ticks_since(&self, previous: u32) -> u32 {
+        ticks_since(self, previous)
+    }
+
+    /// Get the sample rate in Hz.
+    ///
+    /// # Note
+    /// The rate is computed from the period configured in the timer, so it reflects changes by
+    /// [Self::set_sample_ticks()] once they took effect.
+    pub fn sample_rate_hz(&self) -> f32 {
+        sample_rate_hz(self.period_ticks())
+    }
+
+    /// Change the sample period while sampling.
+    ///
+    /// # Note
+    /// The [ShadowSamplingTimer] is slaved to the sampling timer and is updated to the same
+    /// period. Both periods are preloaded, so the new period takes effect in both timers at the
+    /// next period boundary without a glitch. To prevent a period boundary between the two
+    /// updates, the update is delayed while the counter is within [SAMPLE_PERIOD_UPDATE_MARGIN]
+    /// ticks of the end of the period.
+    ///
+    /// Any application state derived from the sample period, such as signal generator
+    /// configurations, must be updated by the caller.
+    ///
+    /// # Args
+    /// * `shadow` - The shadow sampling timer.
+    /// * `ticks` - The number of timer ticks per sample.
+    /// * `batch_size` - The number of samples per batch.
+    pub fn set_sample_ticks(
+        &mut self,
+        shadow: &mut ShadowSamplingTimer,
+        ticks: u32,
+        batch_size: usize,
+    ) -> Result<(), SampleTicksError> {
+        check_sample_ticks(ticks, batch_size)?;
+
+        cortex_m::interrupt::free(|_| {
+            preload_sample_period(self, shadow, ticks)
+        });
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::cell::RefCell;
+
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+    enum Access {
+        Preload(u8),
+        Schedule(u8, u32),
+    }
+
+    type Log = RefCell<heapless::Vec<Access, 16>>;
+
+    /// A timer with preloaded periods, that advances its counter by one tick per counter read.
+    struct MockTimer<'a> {
+        id: u8,
+        log: &'a Log,
+        counter: RefCell<u32>,
+        period: u32,
+        preload: bool,
+    }
+
+    impl<'a> MockTimer<'a> {
+        fn new(id: u8, log: &'a Log, counter: u32, period: u32) -> Self {
+            Self {
+                id,
+                log,
+                counter: RefCell::new(counter),
+                period,
+                preload: false,
+            }
+        }
+    }
+
+    impl SamplePeriod for MockTimer<'_> {
+        fn counter(&self) -> u32 {
+            let mut counter = self.counter.borrow_mut();
+            let value = *counter;
+            *counter = if value == self.period { 0 } else { value + 1 };
+            value
+        }
+
+        fn period(&self) -> u32 {
+            self.period
+        }
+
+        fn enable_period_preload(&mut self) {
+            self.preload = true;
+            self.log
+                .borrow_mut()
+                .push(Access::Preload(self.id))
+                .unwrap();
+        }
+
+        fn schedule_period(&mut self, period: u32) {
+            // Without preloading, the period would change within the current sample period.
+            assert!(self.preload);
+            self.log
+                .borrow_mut()
+                .push(Access::Schedule(self.id, period))
+                .unwrap();
+        }
+    }
+
+    /// A sampling timer that returns scripted counter values.
+    struct ScriptedTimer<'a> {
+        counts: RefCell<core::slice::Iter<'a, u32>>,
+        period: u32,
+    }
+
+    impl SamplePeriod for ScriptedTimer<'_> {
+        fn counter(&self) -> u32 {
+            *self.counts.borrow_mut().next().unwrap()
+        }
+
+        fn period(&self) -> u32 {
+            self.period
+        }
+
+        fn enable_period_preload(&mut self) {
+            unimplemented!()
+        }
+
+        fn schedule_period(&mut self, _period: u32) {
+            unimplemented!()
+        }
+    }
+
+    #[test]
+    fn ticks_since_wraps_at_period() {
+        let timer = ScriptedTimer {
+            counts: RefCell::new([10, 127, 0, 5, 10].iter()),
+            period: 127,
+        };
+
+        assert_eq!(ticks_since(&timer, 10), 0);
+        assert_eq!(ticks_since(&timer, 10), 117);
+        // The counter wrapped from the last tick of the period to zero.
+        assert_eq!(ticks_since(&timer, 127), 1);
+        assert_eq!(ticks_since(&timer, 120), 13);
+        assert_eq!(ticks_since(&timer, 11), 127);
+    }
+
+    /// A reference timer counter that only advances while waiting or ticking.
+    #[derive(Default)]
+    struct MockCounter {
+        counter: core::cell::Cell<u16>,
+        reset_on_trigger: bool,
+        triggered: bool,
+    }
+
+    impl MockCounter {
+        fn tick(&mut self, ticks: u16) {
+            self.counter.set(self.counter.get().wrapping_add(ticks));
+        }
+
+        /// Receive a trigger pulse.
+        fn trigger(&mut self) {
+            self.triggered = true;
+            if self.reset_on_trigger {
+                self.counter.set(0);
+            }
+        }
+
+        /// Wait while the reference clocks the counter by a number of ticks.
+        fn wait(&self, ticks: u16) -> impl FnOnce() + '_ {
+            move || self.counter.set(self.counter.get().wrapping_add(ticks))
+        }
+    }
+
+    impl ReferenceCounter for MockCounter {
+        fn counter(&self) -> u16 {
+            self.counter.get()
+        }
+
+        fn set_slave_mode(&mut self, _source: TriggerSource, mode: SlaveMode) {
+            self.reset_on_trigger = matches!(mode, SlaveMode::Reset);
+        }
+
+        fn triggered(&self) -> bool {
+            self.triggered
+        }
+
+        fn clear_trigger(&mut self) {
+            self.triggered = false;
+        }
+    }
+
+    #[test]
+    fn counter_resets_on_sync_trigger() {
+        let mut timer = MockCounter::default();
+
+        // A trigger received before synchronizing is discarded.
+        timer.trigger();
+        timer.tick(100);
+        reset_on_trigger(&mut timer, TriggerSource::TimerInput1);
+        assert_eq!(offset_from_trigger(&mut timer), None);
+
+        // Without sync pulses, the counter free-runs.
+        timer.tick(50);
+        assert_eq!(timer.counter(), 150);
+        assert_eq!(offset_from_trigger(&mut timer), None);
+
+        timer.trigger();
+        timer.tick(25);
+        assert_eq!(offset_from_trigger(&mut timer), Some(25));
+        // Each sync pulse is reported once.
+        assert_eq!(offset_from_trigger(&mut timer), None);
+
+        timer.tick(1000);
+        timer.trigger();
+        assert_eq!(offset_from_trigger(&mut timer), Some(0));
+    }
+
+    #[test]
+    fn reference_detection_needs_advancing_counter() {
+        let timer = MockCounter {
+            counter: core::cell::Cell::new(100),
+            ..Default::default()
+        };
+
+        // The counter is frozen without a reference clock.
+        assert!(!counter_advanced(&timer, timer.wait(0)));
+        assert!(counter_advanced(&timer, timer.wait(1)));
+        assert!(counter_advanced(&timer, timer.wait(40)));
+
+        // A counter rolling over in the window still advanced.
+        timer.counter.set(u16::MAX);
+        assert!(counter_advanced(&timer, timer.wait(1)));
+        assert_eq!(timer.counter(), 0);
+    }
+
+    #[test]
+    fn sample_ticks_are_checked() {
+        assert_eq!(check_sample_ticks(128, 8), Ok(()));
+        assert_eq!(check_sample_ticks(8, 1), Ok(()));
+        assert_eq!(check_sample_ticks(u16::MAX as u32 + 1, 1), Ok(()));
+        assert_eq!(check_sample_ticks(7, 1), Err(SampleTicksError::Bounds));
+        assert_eq!(
+            check_sample_ticks(u16::MAX as u32 + 2, 1),
+            Err(SampleTicksError::Bounds)
+        );
+        assert_eq!(
+            check_sample_ticks(2_000, 64),
+            Err(SampleTicksError::BatchPeriod)
+        );
+    }
+
+    #[test]
+    fn sample_period_is_preloaded_in_both_timers() {
+        let log = Log::default();
+        let mut timer = MockTimer::new(2, &log, 10, 127);
+        let mut shadow = MockTimer::new(3, &log, 10, 127);
+
+        preload_sample_period(&mut timer, &mut shadow, 256);
+
+        assert_eq!(
+            log.borrow().as_slice(),
+            [
+                Access::Preload(2),
+                Access::Preload(3),
+                Access::Schedule(2, 255),
+                Access::Schedule(3, 255),
+            ]
+        );
+        // The counter was only read once, as it was outside of the update margin.
+        assert_eq!(*timer.counter.borrow(), 11);
+    }
+
+    #[test]
+    fn sample_period_update_waits_for_period_boundary() {
+        let log = Log::default();
+        let mut timer = MockTimer::new(2, &log, 125, 127);
+        let mut shadow = MockTimer::new(3, &log, 125, 127);
+
+        preload_sample_period(&mut timer, &mut shadow, 64);
+
+        // The update was delayed until the counter wrapped past the end of the period.
+        assert_eq!(*timer.counter.borrow(), 1);
+        assert_eq!(
+            log.borrow().as_slice(),
+            [
+                Access::Preload(2),
+                Access::Preload(3),
+                Access::Schedule(2, 63),
+                Access::Schedule(3, 63),
+            ]
+        );
+    }
+
+    #[test]
+    fn sample_rate_from_ticks() {
+        assert_eq!(sample_rate_hz(100), 1_000_000.);
+        assert_eq!(sample_rate_hz(128), 781_250.);
+        assert_eq!(sample_rate_hz(u16::MAX as u32 + 1), 1_525.878_9);
+    }
+
+    #[test]
+    fn batch_period_below_monotonic_period() {
+        // A batch must complete within a 1 ms monotonic tick, i.e. 100_000 timer ticks.
+        assert!(batch_period_valid(8, 128));
+        assert!(batch_period_valid(1, u16::MAX as u32 + 1));
+        assert!(batch_period_valid(64, 1_000));
+        assert!(!batch_period_valid(64, 2_000));
+        assert!(!batch_period_valid(2, u16::MAX as u32 + 1));
+    }
+}
@@ -0,0 +1,157 @@
+//! Wrapping counter arithmetic
+//!
+//! # Design
+//! The hardware timers are narrow counters that wrap around. Differences between two counter
+//! values are computed modulo the counter range, which is correct as long as the counter wrapped
+//! at most once between them. These helpers centralize that arithmetic so that the masking and
+//! sign handling is not re-implemented for every timer.
+
+/// Compute the difference between two values of a counter of arbitrary width.
+///
+/// # Args
+/// * `now` - The current counter value.
+/// * `prev` - The previous counter value.
+/// * `bits` - The width of the counter in bits, from 1 to 64. The counter wraps at `2^bits`.
+///
+/// # Returns
+/// The number of counts from `prev` to `now`, assuming at most one wrap.
+pub fn diff_wide(now: u64, prev: u64, bits: u32) -> u64 {
+    now.wrapping_sub(prev) & mask(bits)
+}
+
+/// Compute the nearest signed difference between two values of a counter.
+///
+/// # Note
+/// Differences of at least half the counter range are interpreted as negative.
+///
+/// # Args
+/// * `now` - The current counter value.
+/// * `prev` - The previous counter value.
+/// * `bits` - The width of the counter in bits, from 1 to 63.
+///
+/// # Returns
+/// The signed number of counts from `prev` to `now`.
+pub fn diff_signed(now: u64, prev: u64, bits: u32) -> i64 {
+    debug_assert!((1..=63).contains(&bits));
+    let diff = diff_wide(now, prev, bits) as i64;
+    if diff >= 1 << (bits - 1) {
+        diff - (1 << bits)
+    } else {
+        diff
+    }
+}
+
+/// Extend a narrow counter value to a wide counter.
+///
+/// # Args
+/// * `narrow` - The value of the narrow counter. Bits above its width are ignored.
+/// * `overflows` - The number of times the narrow counter wrapped.
+/// * `bits` - The width of the narrow counter in bits, from 1 to 64.
+///
+/// # Returns
+/// The value of the wide counter. It wraps if it exceeds 64 bits.
+pub fn extend(narrow: u64, overflows: u64, bits: u32) -> u64 {
+    let high = if bits < 64 { overflows << bits } else { 0 };
+    high | (narrow & mask(bits))
+}
+
+// The mask of the valid bits of a counter.
+fn mask(bits: u32) -> u64 {
+    debug_assert!((1..=64).contains(&bits));
+    u64::MAX >> (64 - bits)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mask_covers_width() {
+        assert_eq!(mask(1), 0b1);
+        assert_eq!(mask(16), 0xFFFF);
+        assert_eq!(mask(63), u64::MAX >> 1);
+        assert_eq!(mask(64), u64::MAX);
+    }
+
+    #[test]
+    fn diff_without_wrap() {
+        assert_eq!(diff_wide(300, 100, 16), 200);
+        assert_eq!(diff_signed(300, 100, 16), 200);
+        assert_eq!(diff_signed(100, 300, 16), -200);
+        assert_eq!(diff_wide(7, 7, 16), 0);
+    }
+
+    #[test]
+    fn diff_across_one_wrap() {
+        assert_eq!(diff_wide(10, 0xFFF0, 16), 26);
+        assert_eq!(diff_signed(10, 0xFFF0, 16), 26);
+        assert_eq!(diff_signed(0xFFF0, 10, 16), -26);
+
+        // Bits above the counter width are ignored.
+        assert_eq!(diff_wide(0x1_000A, 0xFFF0, 16), 26);
+    }
+
+    #[test]
+    fn diff_at_half_range_is_negative() {
+        // A diff of exactly half the range is ambiguous and interpreted as negative.
+        assert_eq!(diff_wide(0x8000, 0, 16), 0x8000);
+        assert_eq!(diff_signed(0x8000, 0, 16), -0x8000);
+        assert_eq!(diff_signed(0x7FFF, 0, 16), 0x7FFF);
+        assert_eq!(diff_signed(0x8001, 0, 16), -0x7FFF);
+    }
+
+    #[test]
+    fn extend_with_overflows() {
+        assert_eq!(extend(0x1234, 0, 16), 0x1234);
+        assert_eq!(extend(0x1234, 3, 16), 0x3_1234);
+        assert_eq!(extend(0xF_1234, 3, 16), 0x3_1234);
+        assert_eq!(extend(5, 7, 64), 5);
+    }
+
+    #[test]
+    fn diff_wraps_at_every_width() {
+        for bits in [16, 32, 64] {
+            let max = mask(bits);
+            for prev in [0, 1, max / 2, max - 1, max] {
+                for step in [0, 1, 2, max / 2, max - 1, max] {
+                    let now = prev.wrapping_add(step) & max;
+                    assert_eq!(
+                        diff_wide(now, prev, bits),
+                        step,
+                        "{bits} bits from {prev:#x} by {step:#x}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn signed_diff_wraps_at_every_width() {
+        for bits in [16, 32] {
+            let max = mask(bits);
+            let half = 1i64 << (bits - 1);
+            for prev in [0, 1, max / 2, max - 1, max] {
+                for step in [0, 1, -1, half - 1, -(half - 1), -half] {
+                    let now = prev.wrapping_add(step as u64) & max;
+                    assert_eq!(
+                        diff_signed(now, prev, bits),
+                        step,
+                        "{bits} bits from {prev:#x} by {step}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn extend_at_every_width() {
+        assert_eq!(extend(u16::MAX as u64, 1, 16), 0x1_FFFF);
+        assert_eq!(extend(0, 1, 16), 0x1_0000);
+        assert_eq!(extend(u32::MAX as u64, 1, 32), 0x1_FFFF_FFFF);
+        assert_eq!(extend(0, 1, 32), 0x1_0000_0000);
+        assert_eq!(extend(u64::MAX, 1, 64), u64::MAX);
+
+        // The wide counter wraps if it exceeds 64 bits.
+        assert_eq!(extend(1, 1 << 48, 16), 1);
+    }
+}
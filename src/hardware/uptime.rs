@@ -0,0 +1,83 @@
+//! System uptime
+//!
+//! # Design
+//! The [super::SystemTimer] counts [super::MONOTONIC_FREQUENCY] ticks in 32 bits, so it wraps
+//! after about 49.7 days. [Uptime] extends it into a 64-bit counter by accumulating the ticks
+//! elapsed between updates. This is correct across any number of wraps as long as it is updated
+//! at least once per wrap, which a low-rate task such as telemetry easily satisfies.
+use super::{timers::wrap, MONOTONIC_FREQUENCY};
+
+/// The time since boot, extended beyond the range of the system timer.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Uptime {
+    previous: u32,
+    ticks: u64,
+}
+
+impl Uptime {
+    /// Construct the uptime at boot, when the system timer starts at zero.
+    pub const fn new() -> Self {
+        Self {
+            previous: 0,
+            ticks: 0,
+        }
+    }
+
+    /// Update the uptime from the system timer.
+    ///
+    /// # Note
+    /// This must be called at least once per wrap of the system timer. Missed wraps can not be
+    /// detected.
+    ///
+    /// # Args
+    /// * `now` - The current system timer ticks.
+    pub fn update(&mut self, now: u32) {
+        self.ticks += wrap::diff_wide(now as u64, self.previous as u64, 32);
+        self.previous = now;
+    }
+
+    /// Get the uptime in whole seconds as of the latest update.
+    pub fn seconds(&self) -> u64 {
+        self.ticks / MONOTONIC_FREQUENCY as u64
+    }
+
+    /// Get the uptime in milliseconds as of the latest update.
+    pub fn millis(&self) -> u64 {
+        self.ticks * 1_000 / MONOTONIC_FREQUENCY as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn uptime_accumulates_across_wraps() {
+        let mut uptime = Uptime::new();
+        let step = u32::MAX / 3;
+        let mut now = 0u32;
+        let mut expected = 0u64;
+
+        // Three updates per wrap, over several wraps of the system timer.
+        for _ in 0..10 {
+            now = now.wrapping_add(step);
+            expected += step as u64;
+            uptime.update(now);
+            assert_eq!(
+                uptime.millis(),
+                expected * 1_000 / MONOTONIC_FREQUENCY as u64
+            );
+        }
+
+        assert!(expected > 3 * (u32::MAX as u64 + 1));
+        assert_eq!(uptime.seconds(), expected / MONOTONIC_FREQUENCY as u64);
+    }
+
+    #[test]
+    fn uptime_is_unchanged_without_ticks() {
+        let mut uptime = Uptime::new();
+        uptime.update(1_500);
+        uptime.update(1_500);
+        assert_eq!(uptime.millis(), 1_500 * 1_000 / MONOTONIC_FREQUENCY as u64);
+    }
+}
@@ -0,0 +1,150 @@
+//! Servo health watchdog
+//!
+//! # Design
+//! If the real-time servo processing stops, the DDS keeps generating its last output, which may
+//! leave connected hardware in an unsafe state. The [Watchdog] detects a stalled servo: the servo
+//! task kicks the watchdog every processing cycle, and a lower-priority task checks it once per
+//! timeout period. If the watchdog was not kicked since the previous check, the servo is
+//! considered stalled and the application can take safe-state action, e.g. maximizing the
+//! attenuation of all RF channels.
+//!
+//! Because the check runs in a low-priority task, it stops running if the device hangs entirely.
+//! The check task therefore feeds the independent watchdog (IWDG), which resets the device if it
+//! is not fed for [IWDG_TIMEOUT_PERIODS] timeout periods. The IWDG runs from the LSI oscillator,
+//! whose frequency tolerance shortens or lengthens the actual reset timeout, so the margin also
+//! covers that. Any work that delays the check task, such as other tasks of the same or higher
+//! priority, counts against this margin.
+use super::hal;
+
+/// The IWDG timeout in units of the watchdog timeout period.
+pub const IWDG_TIMEOUT_PERIODS: u32 = 4;
+
+/// A state change of the servo health.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum WatchdogEvent {
+    /// The servo stopped kicking the watchdog.
+    Stalled,
+    /// The servo resumed kicking the watchdog after a stall.
+    Recovered,
+}
+
+/// A watchdog monitoring the servo task.
+pub struct Watchdog {
+    iwdg: hal::independent_watchdog::IndependentWatchdog,
+    timeout_ms: u32,
+    health: Health,
+}
+
+/// The servo health as determined from the kicks between two checks.
+#[derive(Debug)]
+struct Health {
+    kicked: bool,
+    stalled: bool,
+}
+
+impl Health {
+    fn new() -> Self {
+        Self {
+            // The servo is not considered stalled until the first check period has passed.
+            kicked: true,
+            stalled: false,
+        }
+    }
+
+    fn check(&mut self) -> Option<WatchdogEvent> {
+        let stalled = !core::mem::replace(&mut self.kicked, false);
+        let event = match (self.stalled, stalled) {
+            (false, true) => Some(WatchdogEvent::Stalled),
+            (true, false) => Some(WatchdogEvent::Recovered),
+            _ => None,
+        };
+
+        self.stalled = stalled;
+        event
+    }
+}
+
+impl Watchdog {
+    /// Construct and start the watchdog.
+    ///
+    /// # Note
+    /// The IWDG is started immediately and must be fed with [Watchdog::feed()] or
+    /// [Watchdog::check()] within [IWDG_TIMEOUT_PERIODS] timeout periods.
+    ///
+    /// # Args
+    /// * `iwdg` - The independent watchdog used to reset the device on a total hang.
+    /// * `timeout_ms` - The time in milliseconds between two checks of the watchdog. The servo
+    ///   must kick the watchdog at least once within this period.
+    pub fn new(
+        mut iwdg: hal::independent_watchdog::IndependentWatchdog,
+        timeout_ms: u32,
+    ) -> Self {
+        iwdg.start(hal::time::MilliSeconds::from_ticks(
+            IWDG_TIMEOUT_PERIODS * timeout_ms,
+        ));
+
+        Self {
+            iwdg,
+            timeout_ms,
+            health: Health::new(),
+        }
+    }
+
+    /// Get the time in milliseconds between two checks of the watchdog.
+    pub fn timeout_ms(&self) -> u32 {
+        self.timeout_ms
+    }
+
+    /// Feed the independent watchdog without checking the servo.
+    ///
+    /// # Note
+    /// This is intended to restart the IWDG timeout once initialization completes, before the
+    /// first check is scheduled.
+    pub fn feed(&mut self) {
+        self.iwdg.feed();
+    }
+
+    /// Indicate that the servo is running.
+    pub fn kick(&mut self) {
+        self.health.kicked = true;
+    }
+
+    /// Check whether the servo kicked the watchdog since the previous check.
+    ///
+    /// # Note
+    /// This must be called once every timeout period. It also feeds the independent watchdog.
+    ///
+    /// # Returns
+    /// The change of the servo health since the previous check, if any.
+    pub fn check(&mut self) -> Option<WatchdogEvent> {
+        self.iwdg.feed();
+        self.health.check()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn first_period_is_not_a_stall() {
+        let mut health = Health::new();
+        assert_eq!(health.check(), None);
+    }
+
+    #[test]
+    fn stall_and_recovery() {
+        let mut health = Health::new();
+        health.kicked = true;
+        assert_eq!(health.check(), None);
+
+        // No kick within a period is reported once.
+        assert_eq!(health.check(), Some(WatchdogEvent::Stalled));
+        assert_eq!(health.check(), None);
+
+        health.kicked = true;
+        assert_eq!(health.check(), Some(WatchdogEvent::Recovered));
+        health.kicked = true;
+        assert_eq!(health.check(), None);
+    }
+}
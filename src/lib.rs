@@ -3,3 +3,5 @@
 
 pub mod hardware;
 pub mod net;
+pub mod servo;
+pub mod version;
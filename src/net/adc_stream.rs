@@ -0,0 +1,302 @@
+//! Stabilizer raw ADC sample streaming
+//!
+//! # Design
+//! Raw ADC0/ADC1 sample batches can be streamed to a remote host for offline analysis. Each batch
+//! is sent as a single UDP packet on a dedicated socket. Packets are always sent in a best-effort
+//! fashion.
+//!
+//! Batches are enqueued from the sample processing path by an [AdcStreamGenerator] and are
+//! transmitted by the [AdcStream] during network processing. Because enqueueing happens in the
+//! sample path, it never blocks. If the queue is full or the UDP socket cannot accept the packet,
+//! the batch is dropped and the dropped-batch counter is incremented.
+//!
+//! ## Frame Format
+//! Each frame consists of a header followed by the samples, all in little-endian.
+//!
+//! * **Magic word 0x0ADC** (u16): a constant to identify Stabilizer raw ADC data.
+//! * **Batch Size** (u16): the number of samples per ADC in the frame.
+//! * **Sequence Number** (u32): the sequence number of the batch.
+//! * **Sample Rate** (u32): the ADC sample rate in Hz.
+//! * **ADC0 Samples** (u16 * batch size): the raw ADC0 codes of the batch.
+//! * **ADC1 Samples** (u16 * batch size): the raw ADC1 codes of the batch.
+use core::sync::atomic::{AtomicU32, Ordering};
+use heapless::spsc::{Consumer, Producer, Queue};
+use smoltcp_nal::embedded_nal::{SocketAddr, UdpClientStack};
+
+use super::{data_stream::StreamTarget, NetworkReference};
+use crate::hardware::design_parameters::MAX_SAMPLE_BUFFER_SIZE;
+
+// Magic first bytes indicating a UDP frame of raw ADC data.
+const MAGIC: u16 = 0x0ADC;
+
+// The size of the header in bytes.
+// The header has a 16-bit magic word, a 16-bit batch size, a 32-bit sequence number and a 32-bit
+// sample rate, which corresponds to 12 bytes.
+const HEADER_SIZE: usize = 12;
+
+// The maximum size of a frame in bytes.
+const FRAME_SIZE: usize =
+    HEADER_SIZE + 2 * MAX_SAMPLE_BUFFER_SIZE * core::mem::size_of::<u16>();
+
+// The number of batches that can be buffered for transmission.
+const QUEUE_SIZE: usize = 8;
+
+// The number of batches dropped since boot.
+static DROPPED_BATCHES: AtomicU32 = AtomicU32::new(0);
+
+/// A batch of raw ADC samples.
+#[derive(Copy, Clone, Debug)]
+pub struct AdcBatch {
+    sequence_number: u32,
+    len: usize,
+    samples: [[u16; MAX_SAMPLE_BUFFER_SIZE]; 2],
+}
+
+impl AdcBatch {
+    /// Construct a batch from the samples of both ADCs.
+    ///
+    /// # Note
+    /// The batch holds as many samples as the shorter of `adc0` and `adc1`, up to
+    /// [MAX_SAMPLE_BUFFER_SIZE].
+    ///
+    /// # Args
+    /// * `sequence_number` - The sequence number of the batch.
+    /// * `adc0` - The samples of ADC0.
+    /// * `adc1` - The samples of ADC1.
+    fn new(sequence_number: u32, adc0: &[u16], adc1: &[u16]) -> Self {
+        let len = adc0.len().min(adc1.len()).min(MAX_SAMPLE_BUFFER_SIZE);
+        let mut batch = AdcBatch {
+            sequence_number,
+            len,
+            samples: [[0; MAX_SAMPLE_BUFFER_SIZE]; 2],
+        };
+        batch.samples[0][..len].copy_from_slice(&adc0[..len]);
+        batch.samples[1][..len].copy_from_slice(&adc1[..len]);
+        batch
+    }
+
+    /// Serialize the batch into a frame.
+    ///
+    /// # Args
+    /// * `sample_rate` - The ADC sample rate in Hz.
+    /// * `buf` - The buffer to serialize the frame into.
+    ///
+    /// # Returns
+    /// The number of bytes of the frame.
+    pub fn pack(&self, sample_rate: u32, buf: &mut [u8; FRAME_SIZE]) -> usize {
+        buf[0..2].copy_from_slice(&MAGIC.to_le_bytes());
+        buf[2..4].copy_from_slice(&(self.len as u16).to_le_bytes());
+        buf[4..8].copy_from_slice(&self.sequence_number.to_le_bytes());
+        buf[8..12].copy_from_slice(&sample_rate.to_le_bytes());
+
+        let mut offset = HEADER_SIZE;
+        for adc in self.samples.iter() {
+            for sample in adc[..self.len].iter() {
+                buf[offset..offset + 2].copy_from_slice(&sample.to_le_bytes());
+                offset += 2;
+            }
+        }
+
+        offset
+    }
+}
+
+/// Configure raw ADC streaming on a device.
+///
+/// # Args
+/// * `stack` - A reference to the shared network stack.
+///
+/// # Returns
+/// (generator, stream) where `generator` can be used to enqueue batches for transmission. The
+/// `stream` is the logical consumer (UDP transmitter) of the enqueued data.
+pub fn setup_adc_streaming(
+    stack: NetworkReference,
+) -> (AdcStreamGenerator, AdcStream) {
+    let queue =
+        cortex_m::singleton!(: Queue<AdcBatch, QUEUE_SIZE> = Queue::new())
+            .unwrap();
+    let (producer, consumer) = queue.split();
+
+    (
+        AdcStreamGenerator { queue: producer },
+        AdcStream::new(stack, consumer),
+    )
+}
+
+/// The producer of raw ADC batches.
+pub struct AdcStreamGenerator {
+    queue: Producer<'static, AdcBatch, QUEUE_SIZE>,
+}
+
+impl AdcStreamGenerator {
+    /// Enqueue a batch of ADC samples for transmission.
+    ///
+    /// # Note
+    /// This never blocks. If the transmit queue is full, the batch is dropped. If the sample
+    /// slices differ in length, the batch is truncated to the shorter one.
+    ///
+    /// # Args
+    /// * `sequence_number` - The sequence number of the batch.
+    /// * `adc0` - The samples of ADC0.
+    /// * `adc1` - The samples of ADC1.
+    pub fn add(&mut self, sequence_number: u32, adc0: &[u16], adc1: &[u16]) {
+        let batch = AdcBatch::new(sequence_number, adc0, adc1);
+        if self.queue.enqueue(batch).is_err() {
+            DROPPED_BATCHES.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// The "consumer" portion of the raw ADC stream.
+///
+/// # Note
+/// This is responsible for consuming batches and sending them over UDP.
+pub struct AdcStream {
+    stack: NetworkReference,
+    socket: Option<<NetworkReference as UdpClientStack>::UdpSocket>,
+    queue: Consumer<'static, AdcBatch, QUEUE_SIZE>,
+    remote: SocketAddr,
+    sample_rate: u32,
+}
+
+impl AdcStream {
+    /// Construct a new raw ADC streamer.
+    ///
+    /// # Args
+    /// * `stack` - A reference to the shared network stack.
+    /// * `consumer` - The read side of the queue containing batches to transmit.
+    fn new(
+        stack: NetworkReference,
+        consumer: Consumer<'static, AdcBatch, QUEUE_SIZE>,
+    ) -> Self {
+        Self {
+            stack,
+            socket: None,
+            queue: consumer,
+            remote: StreamTarget::default().into(),
+            sample_rate: 0,
+        }
+    }
+
+    fn close(&mut self) {
+        if let Some(socket) = self.socket.take() {
+            log::info!("Closing ADC stream");
+            // Note(unwrap): We guarantee that the socket is available above.
+            self.stack.close(socket).unwrap();
+        }
+    }
+
+    // Open new socket.
+    fn open(&mut self) -> Result<(), ()> {
+        // If there is already a socket of if remote address is unspecified,
+        // do not open a new socket.
+        if self.socket.is_some() || self.remote.ip().is_unspecified() {
+            return Err(());
+        }
+
+        log::info!("Opening ADC stream");
+
+        let mut socket = self.stack.socket().or(Err(()))?;
+
+        // Note(unwrap): We only connect with a new socket, so it is guaranteed to not already be
+        // bound.
+        self.stack.connect(&mut socket, self.remote).unwrap();
+
+        self.socket.replace(socket);
+
+        Ok(())
+    }
+
+    /// Configure the ADC sample rate reported in the frame header.
+    ///
+    /// # Args
+    /// * `sample_rate` - The ADC sample rate in Hz.
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.sample_rate = sample_rate;
+    }
+
+    /// Configure the remote endpoint of the stream.
+    ///
+    /// # Args
+    /// * `remote` - The destination to send stream data to.
+    pub fn set_remote(&mut self, remote: SocketAddr) {
+        // Close socket to be reopened if the remote has changed.
+        if remote != self.remote {
+            self.close();
+        }
+        self.remote = remote;
+    }
+
+    /// Get the number of batches dropped since boot.
+    pub fn dropped(&self) -> u32 {
+        DROPPED_BATCHES.load(Ordering::Relaxed)
+    }
+
+    /// Process any batches for transmission.
+    pub fn process(&mut self) {
+        if self.socket.is_none() && self.open().is_err() {
+            // Without a remote, discard any pending batches so that stale data is not sent once
+            // the stream is opened.
+            while self.queue.dequeue().is_some() {}
+            return;
+        }
+
+        // Note(unwrap): The socket is guaranteed to be open above.
+        let handle = self.socket.as_mut().unwrap();
+
+        if let Some(batch) = self.queue.dequeue() {
+            let mut frame = [0u8; FRAME_SIZE];
+            let len = batch.pack(self.sample_rate, &mut frame);
+            if self.stack.send(handle, &frame[..len]).is_err() {
+                DROPPED_BATCHES.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pack_round_trip() {
+        let adc0 = [0x0000, 0x1234, 0xFFFF];
+        let adc1 = [0x8000, 0x7FFF, 0x0001];
+        let batch = AdcBatch::new(0xDEAD_BEEF, &adc0, &adc1);
+
+        let mut frame = [0u8; FRAME_SIZE];
+        let len = batch.pack(100_000, &mut frame);
+        assert_eq!(len, HEADER_SIZE + 2 * 3 * 2);
+
+        // The header is little-endian.
+        assert_eq!(
+            frame[..HEADER_SIZE],
+            [0xDC, 0x0A, 3, 0, 0xEF, 0xBE, 0xAD, 0xDE, 0xA0, 0x86, 0x01, 0]
+        );
+
+        let samples: heapless::Vec<u16, 6> = frame[HEADER_SIZE..len]
+            .chunks_exact(2)
+            .map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]))
+            .collect();
+        assert_eq!(samples[..3], adc0);
+        assert_eq!(samples[3..], adc1);
+    }
+
+    #[test]
+    fn batch_is_truncated_to_shorter_adc() {
+        let batch = AdcBatch::new(0, &[1, 2, 3, 4], &[5, 6]);
+        assert_eq!(batch.len, 2);
+        assert_eq!(batch.samples[0][..2], [1, 2]);
+        assert_eq!(batch.samples[1][..2], [5, 6]);
+
+        let batch = AdcBatch::new(0, &[1], &[5, 6, 7]);
+        assert_eq!(batch.len, 1);
+
+        let long = [0x5555; MAX_SAMPLE_BUFFER_SIZE + 1];
+        let batch = AdcBatch::new(0, &long, &long);
+        assert_eq!(batch.len, MAX_SAMPLE_BUFFER_SIZE);
+
+        let mut frame = [0u8; FRAME_SIZE];
+        assert_eq!(batch.pack(0, &mut frame), FRAME_SIZE);
+    }
+}
@@ -0,0 +1,212 @@
+//! Stabilizer one-shot sample capture
+//!
+//! # Design
+//! Instead of streaming continuously, a short burst of [SampleRecord]s can be captured into RAM
+//! and transmitted afterwards. The [CaptureBuffer] is armed with the number of records to capture.
+//! The sample processing path then pushes one record per cycle until the requested number of
+//! records has been captured, after which further records are ignored. The captured records are
+//! transmitted by draining the buffer, which may happen in several parts.
+//!
+//! The buffer is intended to be shared between the sample processing path and the transmitting
+//! task through the resource lock of the application. Arming, pushing and draining thus never
+//! race. Arming while a capture or drain is in progress discards the captured records and starts
+//! a new capture with the next pushed record.
+use super::sample_record::SampleRecord;
+
+/// A bounded buffer for a one-shot capture of up to `N` sample records.
+pub struct CaptureBuffer<const N: usize> {
+    records: [SampleRecord; N],
+    target: usize,
+    len: usize,
+    drained: usize,
+}
+
+impl<const N: usize> Default for CaptureBuffer<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> CaptureBuffer<N> {
+    /// Construct a disarmed capture buffer.
+    pub const fn new() -> Self {
+        Self {
+            records: [SampleRecord {
+                seq: 0,
+                adc: [0; 2],
+                dac: [0; 2],
+                beat_diff: 0,
+                timer_tick: 0,
+            }; N],
+            target: 0,
+            len: 0,
+            drained: 0,
+        }
+    }
+
+    /// Arm the buffer for a new capture, discarding any previously captured records.
+    ///
+    /// # Args
+    /// * `len` - The number of records to capture. It is limited to the buffer capacity.
+    ///
+    /// # Returns
+    /// The number of records that will be captured.
+    pub fn arm(&mut self, len: usize) -> usize {
+        self.target = len.min(N);
+        self.len = 0;
+        self.drained = 0;
+        self.target
+    }
+
+    /// Add a record to the capture.
+    ///
+    /// # Note
+    /// This is intended to be called once per processing cycle. Records are ignored if the buffer
+    /// is not armed or the capture is complete.
+    ///
+    /// # Args
+    /// * `record` - The record of the current cycle.
+    ///
+    /// # Returns
+    /// True if the record was captured.
+    pub fn push(&mut self, record: SampleRecord) -> bool {
+        if self.len >= self.target {
+            return false;
+        }
+
+        self.records[self.len] = record;
+        self.len += 1;
+        true
+    }
+
+    /// Check whether the armed capture is complete.
+    pub fn is_complete(&self) -> bool {
+        self.target != 0 && self.len == self.target
+    }
+
+    /// Get the capacity of the buffer in records.
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+
+    /// Take the captured records that were not drained yet, oldest first.
+    ///
+    /// # Note
+    /// Records are only yielded once the capture is complete. Records that are not consumed from
+    /// the iterator remain in the buffer for the next drain, so they can be transmitted as
+    /// network capacity becomes available. Once all records are drained, the buffer is disarmed.
+    pub fn drain(&mut self) -> Drain<'_, N> {
+        Drain { buffer: self }
+    }
+}
+
+/// An iterator over the captured records of a [CaptureBuffer].
+pub struct Drain<'a, const N: usize> {
+    buffer: &'a mut CaptureBuffer<N>,
+}
+
+impl<'a, const N: usize> Iterator for Drain<'a, N> {
+    type Item = SampleRecord;
+
+    fn next(&mut self) -> Option<SampleRecord> {
+        let buffer = &mut *self.buffer;
+        if !buffer.is_complete() || buffer.drained == buffer.len {
+            return None;
+        }
+
+        let record = buffer.records[buffer.drained];
+        buffer.drained += 1;
+        if buffer.drained == buffer.len {
+            buffer.arm(0);
+        }
+
+        Some(record)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(seq: u32) -> SampleRecord {
+        SampleRecord {
+            seq,
+            adc: [0; 2],
+            dac: [0; 2],
+            beat_diff: 0,
+            timer_tick: seq * 8,
+        }
+    }
+
+    fn seqs<const N: usize>(drain: Drain<'_, N>) -> heapless::Vec<u32, N> {
+        drain.map(|record| record.seq).collect()
+    }
+
+    #[test]
+    fn disarmed_buffer_ignores_records() {
+        let mut buffer = CaptureBuffer::<4>::new();
+        assert!(!buffer.push(record(0)));
+        assert!(!buffer.is_complete());
+        assert_eq!(buffer.drain().next(), None);
+    }
+
+    #[test]
+    fn capture_stops_when_full() {
+        let mut buffer = CaptureBuffer::<4>::new();
+        assert_eq!(buffer.arm(10), 4);
+
+        for seq in 0..4 {
+            assert!(!buffer.is_complete());
+            assert!(buffer.push(record(seq)));
+        }
+        assert!(buffer.is_complete());
+        assert!(!buffer.push(record(4)));
+
+        assert_eq!(seqs(buffer.drain()), [0, 1, 2, 3]);
+        assert!(!buffer.is_complete());
+        assert!(!buffer.push(record(5)));
+    }
+
+    #[test]
+    fn incomplete_capture_is_not_drained() {
+        let mut buffer = CaptureBuffer::<4>::new();
+        buffer.arm(3);
+        buffer.push(record(0));
+        buffer.push(record(1));
+        assert_eq!(buffer.drain().next(), None);
+
+        buffer.push(record(2));
+        assert_eq!(seqs(buffer.drain()), [0, 1, 2]);
+    }
+
+    #[test]
+    fn partial_drain_resumes_in_order() {
+        let mut buffer = CaptureBuffer::<4>::new();
+        buffer.arm(4);
+        for seq in 10..14 {
+            buffer.push(record(seq));
+        }
+
+        assert_eq!(buffer.drain().next(), Some(record(10)));
+        let mut drain = buffer.drain();
+        assert_eq!(drain.next(), Some(record(11)));
+        assert_eq!(drain.next(), Some(record(12)));
+        assert_eq!(seqs(buffer.drain()), [13]);
+        assert_eq!(buffer.drain().next(), None);
+    }
+
+    #[test]
+    fn rearm_during_drain_discards_records() {
+        let mut buffer = CaptureBuffer::<4>::new();
+        buffer.arm(2);
+        buffer.push(record(0));
+        buffer.push(record(1));
+        assert_eq!(buffer.drain().next(), Some(record(0)));
+
+        assert_eq!(buffer.arm(2), 2);
+        assert_eq!(buffer.drain().next(), None);
+        buffer.push(record(20));
+        buffer.push(record(21));
+        assert_eq!(seqs(buffer.drain()), [20, 21]);
+    }
+}
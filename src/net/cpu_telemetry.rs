@@ -0,0 +1,134 @@
+//! Stabilizer CPU temperature telemetry over UDP
+//!
+//! # Design
+//! In addition to the MQTT telemetry, the CPU die temperature and device uptime can be reported
+//! as a small JSON datagram to a configurable UDP endpoint. This allows simple monitoring tools to
+//! record the device temperature without requiring an MQTT broker.
+//!
+//! Datagrams are sent in a best-effort fashion. A single UDP socket is opened once a remote is
+//! configured and is reused for all subsequent datagrams.
+//!
+//! ## Format
+//! `{"cpu_temp_c": <temperature>, "uptime_s": <uptime>}`
+//!
+//! * `<temperature>` is the CPU junction temperature in degrees Celsius.
+//! * `<uptime>` is the number of seconds since the device booted.
+use heapless::Vec;
+use serde::Serialize;
+use smoltcp_nal::embedded_nal::{SocketAddr, UdpClientStack};
+
+use super::{data_stream::StreamTarget, NetworkReference};
+
+/// The CPU telemetry reported in each datagram.
+#[derive(Serialize, Copy, Clone, Debug)]
+pub struct CpuTelemetry {
+    /// The CPU temperature in degrees Celsius.
+    pub cpu_temp_c: f32,
+
+    /// The device uptime in seconds.
+    pub uptime_s: u32,
+}
+
+/// A client for reporting CPU telemetry over UDP.
+pub struct CpuTelemetryClient {
+    stack: NetworkReference,
+    socket: Option<<NetworkReference as UdpClientStack>::UdpSocket>,
+    remote: SocketAddr,
+}
+
+impl CpuTelemetryClient {
+    /// Construct a new CPU telemetry client.
+    ///
+    /// # Args
+    /// * `stack` - A reference to the shared network stack.
+    pub fn new(stack: NetworkReference) -> Self {
+        Self {
+            stack,
+            socket: None,
+            remote: StreamTarget::default().into(),
+        }
+    }
+
+    fn close(&mut self) {
+        if let Some(socket) = self.socket.take() {
+            // Note(unwrap): We guarantee that the socket is available above.
+            self.stack.close(socket).unwrap();
+        }
+    }
+
+    // Open new socket.
+    fn open(&mut self) -> Result<(), ()> {
+        // If there is already a socket of if remote address is unspecified,
+        // do not open a new socket.
+        if self.socket.is_some() || self.remote.ip().is_unspecified() {
+            return Err(());
+        }
+
+        let mut socket = self.stack.socket().or(Err(()))?;
+
+        // Note(unwrap): We only connect with a new socket, so it is guaranteed to not already be
+        // bound.
+        self.stack.connect(&mut socket, self.remote).unwrap();
+
+        self.socket.replace(socket);
+
+        Ok(())
+    }
+
+    /// Configure the remote endpoint of the telemetry.
+    ///
+    /// # Args
+    /// * `remote` - The destination to send telemetry to.
+    pub fn set_remote(&mut self, remote: SocketAddr) {
+        // Close socket to be reopened if the remote has changed.
+        if remote != self.remote {
+            self.close();
+        }
+        self.remote = remote;
+    }
+
+    /// Publish CPU telemetry over UDP.
+    ///
+    /// # Note
+    /// Telemetry is reported in a "best-effort" fashion. If no remote is configured or the
+    /// transmission fails, the telemetry is silently dropped.
+    ///
+    /// # Args
+    /// * `telemetry` - The telemetry to report.
+    pub fn publish(&mut self, telemetry: &CpuTelemetry) {
+        if self.socket.is_none() && self.open().is_err() {
+            return;
+        }
+
+        let data: Vec<u8, 128> = serde_json_core::to_vec(telemetry).unwrap();
+
+        // Note(unwrap): The socket is guaranteed to be open above.
+        let socket = self.socket.as_mut().unwrap();
+        self.stack.send(socket, &data).ok();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn telemetry_serialization() {
+        let telemetry = CpuTelemetry {
+            cpu_temp_c: 42.5,
+            uptime_s: 3600,
+        };
+        let json: heapless::String<128> =
+            serde_json_core::to_string(&telemetry).unwrap();
+        assert_eq!(json.as_str(), r#"{"cpu_temp_c":42.5,"uptime_s":3600}"#);
+
+        // The serialized telemetry must fit into a datagram.
+        let data: Vec<u8, 128> = serde_json_core::to_vec(&CpuTelemetry {
+            cpu_temp_c: -f32::MAX,
+            uptime_s: u32::MAX,
+        })
+        .unwrap();
+        assert!(data.starts_with(br#"{"cpu_temp_c":-"#));
+        assert!(data.ends_with(br#","uptime_s":4294967295}"#));
+    }
+}
@@ -19,6 +19,11 @@
 //! * **Sequence Number** (u32): an the sequence number of the first batch in the frame.
 //!   This can be used to determine if and how many stream batches are lost.
 //!
+//! ## Decimation
+//! The stream can be decimated to only contain one in every N batches, see
+//! [FrameGenerator::set_decimation()]. Batches dropped by decimation are not assigned a sequence
+//! number, so gaps in the sequence still indicate lost batches.
+//!
 //! # Example
 //! A sample Python script is available in `scripts/stream_throughput.py` to demonstrate reception
 //! of livestreamed data.
@@ -194,6 +199,65 @@ impl StreamFrame {
     }
 }
 
+/// Selects one in every N batches.
+#[derive(Copy, Clone, Debug)]
+pub struct Decimator {
+    factor: u32,
+    count: u32,
+}
+
+impl Default for Decimator {
+    fn default() -> Self {
+        Self::new(1)
+    }
+}
+
+impl Decimator {
+    /// Construct a decimator.
+    ///
+    /// # Args
+    /// * `factor` - The decimation factor N. Factors of 0 and 1 select every batch.
+    pub fn new(factor: u32) -> Self {
+        Self {
+            factor: factor.max(1),
+            count: 0,
+        }
+    }
+
+    /// Change the decimation factor.
+    ///
+    /// # Note
+    /// If the factor changes, the next batch is selected and every N-th batch after it.
+    /// Setting the current factor again has no effect, so this may be called for every batch.
+    ///
+    /// # Args
+    /// * `factor` - The decimation factor N. Factors of 0 and 1 select every batch.
+    pub fn set_factor(&mut self, factor: u32) {
+        let factor = factor.max(1);
+        if factor != self.factor {
+            *self = Self::new(factor);
+        }
+    }
+
+    /// Get the decimation factor.
+    pub fn factor(&self) -> u32 {
+        self.factor
+    }
+
+    /// Advance by one batch.
+    ///
+    /// # Returns
+    /// True if the batch is selected.
+    pub fn select(&mut self) -> bool {
+        let selected = self.count == 0;
+        self.count += 1;
+        if self.count == self.factor {
+            self.count = 0;
+        }
+        selected
+    }
+}
+
 /// The data generator for a stream.
 pub struct FrameGenerator {
     queue: Producer<'static, StreamFrame, FRAME_QUEUE_SIZE>,
@@ -201,6 +265,7 @@ pub struct FrameGenerator {
     current_frame: Option<StreamFrame>,
     sequence_number: u32,
     format: u8,
+    decimator: Decimator,
 }
 
 impl FrameGenerator {
@@ -214,6 +279,7 @@ impl FrameGenerator {
             format: StreamFormat::Unknown.into(),
             current_frame: None,
             sequence_number: 0,
+            decimator: Decimator::default(),
         }
     }
 
@@ -229,8 +295,23 @@ impl FrameGenerator {
         self.format = format.into();
     }
 
+    /// Set the decimation of the stream.
+    ///
+    /// # Note
+    /// Only one in every `n` batches passed to [FrameGenerator::add()] is added to the stream.
+    /// Setting the current decimation again has no effect, so this may be called for every batch.
+    ///
+    /// # Args
+    /// * `n` - The decimation factor. Factors of 0 and 1 disable decimation.
+    pub fn set_decimation(&mut self, n: u32) {
+        self.decimator.set_factor(n);
+    }
+
     /// Add a batch to the current stream frame.
     ///
+    /// # Note
+    /// If the batch is dropped by decimation, the closure is not called.
+    ///
     /// # Args
     /// * `f` - A closure that will be provided the buffer to write batch data into.
     ///         Returns the number of bytes written.
@@ -238,6 +319,10 @@ impl FrameGenerator {
     where
         F: FnMut(&mut [MaybeUninit<u8>]) -> usize,
     {
+        if !self.decimator.select() {
+            return;
+        }
+
         let sequence_number = self.sequence_number;
         self.sequence_number = self.sequence_number.wrapping_add(1);
 
@@ -371,3 +456,49 @@ impl DataStream {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn selected<const N: usize>(decimator: &mut Decimator) -> [bool; N] {
+        core::array::from_fn(|_| decimator.select())
+    }
+
+    #[test]
+    fn decimator_selects_every_nth_batch() {
+        let mut decimator = Decimator::new(3);
+        assert_eq!(
+            selected::<7>(&mut decimator),
+            [true, false, false, true, false, false, true]
+        );
+    }
+
+    #[test]
+    fn decimator_factor_zero_and_one_select_all() {
+        for factor in [0, 1] {
+            let mut decimator = Decimator::new(factor);
+            assert_eq!(decimator.factor(), 1);
+            assert_eq!(selected::<3>(&mut decimator), [true; 3]);
+        }
+    }
+
+    #[test]
+    fn decimator_factor_change_resets_phase() {
+        let mut decimator = Decimator::new(4);
+        assert_eq!(selected::<2>(&mut decimator), [true, false]);
+
+        // The same factor keeps the phase.
+        decimator.set_factor(4);
+        assert_eq!(selected::<3>(&mut decimator), [false, false, true]);
+
+        decimator.select();
+        decimator.set_factor(2);
+        assert_eq!(selected::<4>(&mut decimator), [true, false, true, false]);
+
+        // Factor 0 is treated as factor 1.
+        decimator.set_factor(0);
+        assert_eq!(decimator.factor(), 1);
+        assert_eq!(selected::<2>(&mut decimator), [true, true]);
+    }
+}
@@ -9,11 +9,17 @@ pub use heapless;
 pub use miniconf;
 pub use serde;
 
+pub mod adc_stream;
+pub mod capture;
+pub mod cpu_telemetry;
 pub mod data_stream;
 pub mod network_processor;
+pub mod sample_record;
 pub mod telemetry;
 
 use crate::hardware::{EthernetPhy, NetworkManager, NetworkStack, SystemTimer};
+use adc_stream::{AdcStream, AdcStreamGenerator};
+use cpu_telemetry::CpuTelemetryClient;
 use data_stream::{DataStream, FrameGenerator};
 use network_processor::NetworkProcessor;
 use telemetry::TelemetryClient;
@@ -46,6 +52,17 @@ pub enum UpdateState {
     Updated,
 }
 
+/// A change of the ethernet link state.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum LinkChange {
+    /// The link came up.
+    Up,
+    /// The link went down.
+    Down,
+    /// The link state did not change.
+    NoChange,
+}
+
 pub enum NetworkState {
     SettingsChanged(String<128>),
     Updated,
@@ -70,6 +87,9 @@ where
     stream: DataStream,
     generator: Option<FrameGenerator>,
     pub telemetry: TelemetryClient<T>,
+    pub cpu_telemetry: CpuTelemetryClient,
+    pub adc_stream: AdcStream,
+    adc_generator: Option<AdcStreamGenerator>,
 }
 
 impl<S, T, const Y: usize> NetworkUsers<S, T, Y>
@@ -150,12 +170,21 @@ where
         let (generator, stream) =
             data_stream::setup_streaming(stack_manager.acquire_stack());
 
+        let cpu_telemetry =
+            CpuTelemetryClient::new(stack_manager.acquire_stack());
+
+        let (adc_generator, adc_stream) =
+            adc_stream::setup_adc_streaming(stack_manager.acquire_stack());
+
         NetworkUsers {
             miniconf: settings,
             processor,
             telemetry,
+            cpu_telemetry,
             stream,
             generator: Some(generator),
+            adc_stream,
+            adc_generator: Some(adc_generator),
         }
     }
 
@@ -172,6 +201,18 @@ where
         generator
     }
 
+    /// Enable raw ADC sample streaming.
+    ///
+    /// # Args
+    /// * `sample_rate` - The ADC sample rate in Hz.
+    pub fn configure_adc_streaming(
+        &mut self,
+        sample_rate: u32,
+    ) -> AdcStreamGenerator {
+        self.adc_stream.set_sample_rate(sample_rate);
+        self.adc_generator.take().unwrap()
+    }
+
     /// Direct the stream to the provided remote target.
     ///
     /// # Args
@@ -196,6 +237,11 @@ where
             self.stream.process();
         }
 
+        // Update the raw ADC stream.
+        if self.adc_generator.is_none() {
+            self.adc_stream.process();
+        }
+
         // Poll for incoming data.
         let poll_result = match self.processor.update() {
             UpdateState::NoChange => NetworkState::NoChange,
@@ -3,9 +3,49 @@
 //! # Design
 //! The network processir is a small taks to regularly process incoming data over ethernet, handle
 //! the ethernet PHY state, and reset the network as appropriate.
-use super::{NetworkReference, UpdateState};
+use super::{LinkChange, NetworkReference, UpdateState};
 use crate::hardware::EthernetPhy;
 
+/// An ethernet PHY that reports the link state.
+pub trait LinkStatus {
+    /// Poll the PHY for the current link state.
+    fn poll_link(&mut self) -> bool;
+}
+
+impl LinkStatus for EthernetPhy {
+    fn poll_link(&mut self) -> bool {
+        EthernetPhy::poll_link(self)
+    }
+}
+
+/// Poll the link state and determine how it changed, see [NetworkProcessor::handle_link()].
+///
+/// # Args
+/// * `phy` - The ethernet PHY to poll.
+/// * `network_was_reset` - Whether the network was reset when the link last went down. It is
+///   updated with the new link state.
+fn link_change(
+    phy: &mut impl LinkStatus,
+    network_was_reset: &mut bool,
+) -> LinkChange {
+    let link_up = phy.poll_link();
+    match (link_up, *network_was_reset) {
+        (true, true) => {
+            log::warn!("Network link UP");
+            *network_was_reset = false;
+            LinkChange::Up
+        }
+        // Only reset the network stack once per link reconnection. This prevents us from
+        // sending an excessive number of DHCP requests.
+        (false, false) => {
+            log::warn!("Network link DOWN");
+            *network_was_reset = true;
+            LinkChange::Down
+        }
+        _ => LinkChange::NoChange,
+    }
+}
+
 /// Processor for managing network hardware.
 pub struct NetworkProcessor {
     pub stack: NetworkReference,
@@ -26,7 +66,9 @@ impl NetworkProcessor {
         Self {
             stack,
             phy,
-            network_was_reset: false,
+            // The link is considered down until the first link poll, so that an initially
+            // connected link is reported as it comes up.
+            network_was_reset: true,
         }
     }
 
@@ -35,24 +77,29 @@ impl NetworkProcessor {
     /// # Note
     /// This may take non-trivial amounts of time to communicate with the PHY. As such, this should
     /// only be called as often as necessary (e.g. once per second or so).
-    pub fn handle_link(&mut self) {
+    ///
+    /// When the link goes down, the network stack is reset, which closes stale sockets and
+    /// restarts DHCP. Address acquisition thus starts over once the link comes back up.
+    ///
+    /// # Returns
+    /// The change of the link state since the last call.
+    pub fn handle_link(&mut self) -> LinkChange {
         // If the PHY indicates there's no more ethernet link, reset the DHCP server in the network
         // stack.
-        let link_up = self.phy.poll_link();
-        match (link_up, self.network_was_reset) {
-            (true, true) => {
-                log::warn!("Network link UP");
-                self.network_was_reset = false;
-            }
-            // Only reset the network stack once per link reconnection. This prevents us from
-            // sending an excessive number of DHCP requests.
-            (false, false) => {
-                log::warn!("Network link DOWN");
-                self.network_was_reset = true;
-                self.stack.lock(|stack| stack.handle_link_reset());
-            }
-            _ => {}
-        };
+        let change = link_change(&mut self.phy, &mut self.network_was_reset);
+        if change == LinkChange::Down {
+            self.stack.lock(|stack| stack.handle_link_reset());
+        }
+        change
+    }
+
+    /// Check whether the ethernet link is up.
+    ///
+    /// # Note
+    /// This reports the link state as of the latest [NetworkProcessor::handle_link()] and does not
+    /// communicate with the PHY.
+    pub fn link_up(&self) -> bool {
+        !self.network_was_reset
     }
 
     /// Process and update the state of the network.
@@ -71,3 +118,41 @@ impl NetworkProcessor {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A PHY reporting a scripted sequence of link states.
+    struct ScriptedPhy<'a>(core::slice::Iter<'a, bool>);
+
+    impl LinkStatus for ScriptedPhy<'_> {
+        fn poll_link(&mut self) -> bool {
+            *self.0.next().unwrap()
+        }
+    }
+
+    #[test]
+    fn link_changes_follow_phy() {
+        let script = [false, true, true, false, false, true, false];
+        let mut phy = ScriptedPhy(script.iter());
+        let mut network_was_reset = true;
+
+        let changes: [LinkChange; 7] = core::array::from_fn(|_| {
+            link_change(&mut phy, &mut network_was_reset)
+        });
+        assert_eq!(
+            changes,
+            [
+                LinkChange::NoChange,
+                LinkChange::Up,
+                LinkChange::NoChange,
+                LinkChange::Down,
+                LinkChange::NoChange,
+                LinkChange::Up,
+                LinkChange::Down,
+            ]
+        );
+        assert!(network_was_reset);
+    }
+}
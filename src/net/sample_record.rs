@@ -0,0 +1,140 @@
+//! Stabilizer per-cycle sample records
+//!
+//! # Design
+//! A [SampleRecord] combines the ADC inputs, the commanded DAC outputs and the beat note
+//! measurement of a single processing cycle into one coherent record for data logging. Records
+//! are assembled in the sample processing path, so the assembly only copies the first sample of
+//! each batch and never blocks.
+//!
+//! ## Record Format
+//! A record is packed into [SampleRecord::SIZE] bytes, all in little-endian.
+//!
+//! * **Sequence Number** (u32): the sequence number of the batch.
+//! * **ADC0, ADC1** (i16 * 2): the first input sample of the batch as a signed code.
+//! * **DAC0, DAC1** (u16 * 2): the first commanded output code of the batch.
+//! * **Beat Diff** (u16): the beat timer counts between the two latest beat note captures.
+//! * **Timer Tick** (u32): the sampling timer tick of the batch.
+use crate::hardware::{adc::AdcCode, pounder::timestamp::InputCaptureTimer};
+
+/// The inputs, outputs and beat note measurement of a single processing cycle.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct SampleRecord {
+    /// The sequence number of the batch.
+    pub seq: u32,
+
+    /// The first ADC0 and ADC1 sample of the batch.
+    pub adc: [i16; 2],
+
+    /// The first DAC0 and DAC1 output code of the batch.
+    pub dac: [u16; 2],
+
+    /// The beat timer counts between the two latest beat note captures.
+    pub beat_diff: u16,
+
+    /// The sampling timer tick of the batch.
+    pub timer_tick: u32,
+}
+
+impl SampleRecord {
+    /// The size of a packed record in bytes.
+    pub const SIZE: usize = 4 + 2 * 2 + 2 * 2 + 2 + 4;
+
+    /// Assemble a record from the current batch.
+    ///
+    /// # Note
+    /// This takes the latest beat note capture from the timer, see
+    /// [InputCaptureTimer::latest_timestamp_diff()].
+    ///
+    /// # Args
+    /// * `seq` - The sequence number of the batch.
+    /// * `adcs` - The ADC0 and ADC1 samples of the batch. Must not be empty.
+    /// * `dacs` - The DAC0 and DAC1 output codes of the batch. Must not be empty.
+    /// * `beat_timer` - The beat note input capture timer.
+    /// * `timer_tick` - The sampling timer tick of the batch.
+    pub fn assemble(
+        seq: u32,
+        adcs: [&[u16]; 2],
+        dacs: [&[u16]; 2],
+        beat_timer: &mut InputCaptureTimer,
+        timer_tick: u32,
+    ) -> Self {
+        Self {
+            seq,
+            adc: adcs.map(|adc| AdcCode(adc[0]).into()),
+            dac: dacs.map(|dac| dac[0]),
+            beat_diff: beat_timer.latest_timestamp_diff(),
+            timer_tick,
+        }
+    }
+
+    /// Serialize the record.
+    ///
+    /// # Args
+    /// * `buf` - The buffer to serialize the record into.
+    pub fn pack(&self, buf: &mut [u8; Self::SIZE]) {
+        buf[0..4].copy_from_slice(&self.seq.to_le_bytes());
+        buf[4..6].copy_from_slice(&self.adc[0].to_le_bytes());
+        buf[6..8].copy_from_slice(&self.adc[1].to_le_bytes());
+        buf[8..10].copy_from_slice(&self.dac[0].to_le_bytes());
+        buf[10..12].copy_from_slice(&self.dac[1].to_le_bytes());
+        buf[12..14].copy_from_slice(&self.beat_diff.to_le_bytes());
+        buf[14..18].copy_from_slice(&self.timer_tick.to_le_bytes());
+    }
+
+    /// Deserialize a record.
+    ///
+    /// # Args
+    /// * `buf` - The serialized record.
+    pub fn unpack(buf: &[u8; Self::SIZE]) -> Self {
+        let u16_at =
+            |offset: usize| u16::from_le_bytes([buf[offset], buf[offset + 1]]);
+        let u32_at = |offset: usize| {
+            // Note(unwrap): The slice is guaranteed to be 4 bytes long.
+            u32::from_le_bytes(buf[offset..offset + 4].try_into().unwrap())
+        };
+
+        Self {
+            seq: u32_at(0),
+            adc: [u16_at(4) as i16, u16_at(6) as i16],
+            dac: [u16_at(8), u16_at(10)],
+            beat_diff: u16_at(12),
+            timer_tick: u32_at(14),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pack_round_trip() {
+        let record = SampleRecord {
+            seq: 0x1234_5678,
+            adc: [-2, i16::MIN],
+            dac: [0xABCD, 0],
+            beat_diff: 0x0102,
+            timer_tick: 0xDEAD_BEEF,
+        };
+
+        let mut buf = [0; SampleRecord::SIZE];
+        record.pack(&mut buf);
+        assert_eq!(
+            buf,
+            [
+                0x78, 0x56, 0x34, 0x12, // seq
+                0xFE, 0xFF, 0x00, 0x80, // adc
+                0xCD, 0xAB, 0x00, 0x00, // dac
+                0x02, 0x01, // beat_diff
+                0xEF, 0xBE, 0xAD, 0xDE, // timer_tick
+            ]
+        );
+        assert_eq!(SampleRecord::unpack(&buf), record);
+    }
+
+    #[test]
+    fn negative_adc_code_is_signed() {
+        let code: i16 = AdcCode(0xFFFE).into();
+        assert_eq!(code, -2);
+    }
+}
@@ -14,7 +14,13 @@ use heapless::{String, Vec};
 use serde::Serialize;
 
 use super::NetworkReference;
-use crate::hardware::{adc::AdcCode, afe::Gain, dac::DacCode, SystemTimer};
+use crate::hardware::{
+    adc::{Adc0Input, Adc1Input, AdcCalibration, AdcCode},
+    afe::Gain,
+    dac::{Dac0Output, Dac1Output, DacCode},
+    pounder::timestamp::CaptureStatistics,
+    SystemTimer,
+};
 
 /// The telemetry client for reporting telemetry data over MQTT.
 pub struct TelemetryClient<T: Serialize> {
@@ -42,6 +48,47 @@ pub struct TelemetryBuffer {
     pub dacs: [DacCode; 2],
     /// The latest digital input states during processing.
     pub digital_inputs: [bool; 2],
+    /// The latest beat note frequency in Hz.
+    pub beat_frequency: f32,
+    /// The latest beat note capture statistics.
+    pub capture_statistics: CaptureStatistics,
+    /// The time since boot in seconds.
+    pub uptime: u64,
+}
+
+/// The beat note timestamping quality reported as telemetry.
+#[derive(Serialize, Copy, Clone, Debug)]
+pub struct TimestampTelemetry {
+    /// The smallest timestamp diff of the latest statistics window in counts.
+    pub diff_min: Option<u16>,
+
+    /// The largest timestamp diff of the latest statistics window in counts.
+    pub diff_max: Option<u16>,
+
+    /// The mean timestamp diff of the latest statistics window in counts.
+    pub diff_mean: f32,
+
+    /// The variance of the timestamp diffs of the latest statistics window in counts squared.
+    pub diff_variance: f32,
+
+    /// The number of overwritten captures since boot.
+    pub overcaptures: u32,
+}
+
+impl TimestampTelemetry {
+    /// Construct the timestamp telemetry from a snapshot of the capture statistics.
+    ///
+    /// # Args
+    /// * `statistics` - The capture statistics.
+    pub fn new(statistics: &CaptureStatistics) -> Self {
+        Self {
+            diff_min: statistics.diffs.min(),
+            diff_max: statistics.diffs.max(),
+            diff_mean: statistics.diffs.mean(),
+            diff_variance: statistics.diffs.variance(),
+            overcaptures: statistics.overcaptures,
+        }
+    }
 }
 
 /// The telemetry structure is data that is ultimately reported as telemetry over MQTT.
@@ -60,8 +107,23 @@ pub struct Telemetry {
     /// Most recent digital input assertion state.
     pub digital_inputs: [bool; 2],
 
-    /// The CPU temperature in degrees Celsius.
+    /// Most recent beat note frequency in Hz.
+    pub beat_frequency: f32,
+
+    /// The CPU temperature in degrees Celsius, or NaN if it could not be sampled.
     pub cpu_temp: f32,
+
+    /// The number of ADC0/ADC1 SPI RX FIFO overruns since boot.
+    pub adc_overruns: [u32; 2],
+
+    /// The number of DAC0/DAC1 DMA underruns since boot.
+    pub dac_underruns: [u32; 2],
+
+    /// The beat note timestamping quality.
+    pub timestamp: TimestampTelemetry,
+
+    /// The time since boot in seconds.
+    pub uptime: u64,
 }
 
 impl Default for TelemetryBuffer {
@@ -70,6 +132,9 @@ impl Default for TelemetryBuffer {
             adcs: [AdcCode(0), AdcCode(0)],
             dacs: [DacCode(0), DacCode(0)],
             digital_inputs: [false, false],
+            beat_frequency: f32::NAN,
+            capture_statistics: CaptureStatistics::default(),
+            uptime: 0,
         }
     }
 }
@@ -80,19 +145,37 @@ impl TelemetryBuffer {
     /// # Args
     /// * `afe0` - The current AFE configuration for channel 0.
     /// * `afe1` - The current AFE configuration for channel 1.
+    /// * `adc_calibration` - The calibrations of the ADC inputs.
     /// * `cpu_temp` - The current CPU temperature.
     ///
     /// # Returns
     /// The finalized telemetry structure that can be serialized and reported.
-    pub fn finalize(self, afe0: Gain, afe1: Gain, cpu_temp: f32) -> Telemetry {
-        let in0_volts = Into::<f32>::into(self.adcs[0]) / afe0.as_multiplier();
-        let in1_volts = Into::<f32>::into(self.adcs[1]) / afe1.as_multiplier();
+    pub fn finalize(
+        self,
+        afe0: Gain,
+        afe1: Gain,
+        adc_calibration: &[AdcCalibration; 2],
+        cpu_temp: f32,
+    ) -> Telemetry {
+        let in0_volts = adc_calibration[0].codes_to_volts(self.adcs[0].0, afe0);
+        let in1_volts = adc_calibration[1].codes_to_volts(self.adcs[1].0, afe1);
 
         Telemetry {
             cpu_temp,
             adcs: [in0_volts, in1_volts],
             dacs: [self.dacs[0].into(), self.dacs[1].into()],
             digital_inputs: self.digital_inputs,
+            beat_frequency: self.beat_frequency,
+            adc_overruns: [
+                Adc0Input::overrun_count(),
+                Adc1Input::overrun_count(),
+            ],
+            dac_underruns: [
+                Dac0Output::underrun_count(),
+                Dac1Output::underrun_count(),
+            ],
+            timestamp: TimestampTelemetry::new(&self.capture_statistics),
+            uptime: self.uptime,
         }
     }
 }
@@ -167,3 +250,51 @@ impl<T: Serialize> TelemetryClient<T> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn telemetry_serialization() {
+        let telemetry = Telemetry {
+            adcs: [1.5, -0.25],
+            dacs: [0.5, -2.],
+            digital_inputs: [true, false],
+            beat_frequency: 10e6,
+            cpu_temp: 42.5,
+            adc_overruns: [1, 2],
+            dac_underruns: [3, 4],
+            timestamp: TimestampTelemetry {
+                diff_min: Some(998),
+                diff_max: Some(1002),
+                diff_mean: 1000.5,
+                diff_variance: 2.25,
+                overcaptures: 5,
+            },
+            uptime: 3600,
+        };
+
+        let json: String<512> = serde_json_core::to_string(&telemetry).unwrap();
+        assert_eq!(
+            json.as_str(),
+            concat!(
+                r#"{"adcs":[1.5,-0.25],"dacs":[0.5,-2.0],"digital_inputs":[true,false],"#,
+                r#""beat_frequency":10000000.0,"cpu_temp":42.5,"adc_overruns":[1,2],"#,
+                r#""dac_underruns":[3,4],"timestamp":{"diff_min":998,"diff_max":1002,"#,
+                r#""diff_mean":1000.5,"diff_variance":2.25,"overcaptures":5},"#,
+                r#""uptime":3600}"#
+            )
+        );
+    }
+
+    #[test]
+    fn empty_statistics_serialize_as_null() {
+        let timestamp = TimestampTelemetry::new(&CaptureStatistics::default());
+        let json: String<256> = serde_json_core::to_string(&timestamp).unwrap();
+        assert_eq!(
+            json.as_str(),
+            r#"{"diff_min":null,"diff_max":null,"diff_mean":null,"diff_variance":null,"overcaptures":0}"#
+        );
+    }
+}
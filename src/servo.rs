@@ -0,0 +1,410 @@
+//! Offset frequency servo
+//!
+//! # Design
+//! The offset stabilizer measures the beat note between two lasers against a reference clock and
+//! corrects the DDS output frequency to keep the beat note at a setpoint. This module provides the
+//! controller computing that correction.
+//!
+//! The [PiController] is a proportional-integral controller operating on frequencies in Hz. The
+//! error is computed as `setpoint - measured` and the output is a DDS frequency correction. The
+//! output is clamped to configurable limits. To prevent integrator windup, the integrator is only
+//! updated while the output is not saturated.
+//!
+//! The [LockController] acquires the lock. It sweeps the DDS frequency in coarse steps until the
+//! beat note is within a capture range of the setpoint, then hands over to the [PiController]. The
+//! lock is declared once the error stays below a threshold for a number of consecutive updates.
+//! If the beat note leaves the capture range, the loss of lock is reported and the search resumes.
+
+/// A proportional-integral controller with anti-windup output limits.
+#[derive(Copy, Clone, Debug)]
+pub struct PiController {
+    /// The proportional gain.
+    pub kp: f32,
+
+    /// The integral gain per update.
+    pub ki: f32,
+
+    /// The desired beat frequency in Hz.
+    pub setpoint: f32,
+
+    /// The accumulated integral term of the output in Hz.
+    pub integrator: f32,
+
+    /// The (lower, upper) limits of the output correction in Hz.
+    pub limits: (f32, f32),
+}
+
+impl PiController {
+    /// Construct a new controller.
+    ///
+    /// # Args
+    /// * `kp` - The proportional gain.
+    /// * `ki` - The integral gain per update.
+    /// * `setpoint` - The desired beat frequency in Hz.
+    /// * `limits` - The (lower, upper) limits of the output correction in Hz.
+    pub fn new(kp: f32, ki: f32, setpoint: f32, limits: (f32, f32)) -> Self {
+        Self {
+            kp,
+            ki,
+            setpoint,
+            integrator: 0.,
+            limits,
+        }
+    }
+
+    /// Update the controller with a new measurement.
+    ///
+    /// # Note
+    /// If the output is saturated at either limit, the integrator is not updated with the current
+    /// error. This prevents the integrator from winding up while the actuator is at its limit.
+    ///
+    /// # Args
+    /// * `measured` - The measured beat frequency in Hz.
+    ///
+    /// # Returns
+    /// The DDS frequency correction in Hz, clamped to the output limits.
+    pub fn update(&mut self, measured: f32) -> f32 {
+        let (min, max) = self.limits;
+        let error = self.setpoint - measured;
+        let proportional = self.kp * error;
+
+        let integrator = self.integrator + self.ki * error;
+        let output = proportional + integrator;
+
+        if output > max {
+            max
+        } else if output < min {
+            min
+        } else {
+            self.integrator = integrator;
+            output
+        }
+    }
+
+    /// Reset the controller state.
+    pub fn reset(&mut self) {
+        self.integrator = 0.;
+    }
+
+    /// Take the current measurement as the new setpoint.
+    ///
+    /// # Note
+    /// The integrator is cleared, so the next update with the same measurement has zero error and
+    /// zero output. The measurement must be valid, i.e. a beat note must be measured.
+    ///
+    /// # Args
+    /// * `measured` - The measured beat frequency in Hz.
+    pub fn tare(&mut self, measured: f32) {
+        self.setpoint = measured;
+        self.reset();
+    }
+}
+
+/// The state of the lock acquisition.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum LockState {
+    /// The DDS frequency is swept to find the beat note.
+    Search,
+
+    /// The servo is engaged, but the error has not settled yet.
+    Engaging,
+
+    /// The error has settled below the lock threshold.
+    Locked,
+
+    /// The beat note was lost. The search resumes on the next update.
+    Unlocked,
+}
+
+/// The configuration of the lock acquisition.
+#[derive(Copy, Clone, Debug)]
+pub struct LockConfig {
+    /// The (lower, upper) limits of the DDS frequency sweep in Hz.
+    pub search_range: (f32, f32),
+
+    /// The DDS frequency step per update during the search in Hz.
+    pub search_step: f32,
+
+    /// The maximum distance of the beat frequency from the setpoint at which the servo is engaged
+    /// in Hz. The lock is lost if the distance exceeds it.
+    pub capture_range: f32,
+
+    /// The maximum error to consider the servo settled in Hz.
+    pub lock_threshold: f32,
+
+    /// The number of consecutive settled updates required to declare the lock.
+    pub lock_cycles: u32,
+}
+
+/// A state machine acquiring and maintaining the lock of the beat note.
+#[derive(Copy, Clone, Debug)]
+pub struct LockController {
+    /// The lock acquisition configuration.
+    pub config: LockConfig,
+
+    /// The servo used once the beat note is within the capture range.
+    pub servo: PiController,
+
+    state: LockState,
+    search_frequency: f32,
+    settled_cycles: u32,
+    frequency: f32,
+}
+
+impl LockController {
+    /// Construct a new controller in the [LockState::Search] state.
+    ///
+    /// # Args
+    /// * `config` - The lock acquisition configuration.
+    /// * `servo` - The servo. Its output limits apply relative to the DDS frequency at which the
+    ///   beat note was captured.
+    pub fn new(config: LockConfig, servo: PiController) -> Self {
+        let search_frequency = config.search_range.0;
+        Self {
+            config,
+            servo,
+            state: LockState::Search,
+            search_frequency,
+            settled_cycles: 0,
+            frequency: search_frequency,
+        }
+    }
+
+    /// Update the controller with a new measurement.
+    ///
+    /// # Note
+    /// A non-finite beat frequency, e.g. if no beat note was measured, is considered to be outside
+    /// of the capture range.
+    ///
+    /// # Args
+    /// * `beat` - The measured beat frequency in Hz.
+    ///
+    /// # Returns
+    /// The new state. The DDS frequency to apply is available from [LockController::frequency()].
+    pub fn update(&mut self, beat: f32) -> LockState {
+        let error = self.servo.setpoint - beat;
+        let captured = error > -self.config.capture_range
+            && error < self.config.capture_range;
+
+        self.state = match self.state {
+            LockState::Search | LockState::Unlocked if captured => {
+                self.servo.reset();
+                self.settled_cycles = 0;
+                self.track(beat)
+            }
+            LockState::Search | LockState::Unlocked => {
+                self.search();
+                LockState::Search
+            }
+            LockState::Engaging | LockState::Locked if captured => {
+                self.track(beat)
+            }
+            LockState::Engaging | LockState::Locked => {
+                self.frequency = self.search_frequency;
+                LockState::Unlocked
+            }
+        };
+
+        self.state
+    }
+
+    /// Get the current state.
+    pub fn state(&self) -> LockState {
+        self.state
+    }
+
+    /// Get the DDS frequency to apply in Hz.
+    pub fn frequency(&self) -> f32 {
+        self.frequency
+    }
+
+    /// Restart the search from the lower limit of the search range.
+    pub fn restart(&mut self) {
+        self.state = LockState::Search;
+        self.search_frequency = self.config.search_range.0;
+        self.frequency = self.search_frequency;
+        self.settled_cycles = 0;
+        self.servo.reset();
+    }
+
+    // Advance the sweep by one step, wrapping around at the upper limit of the search range.
+    fn search(&mut self) {
+        let (min, max) = self.config.search_range;
+        self.search_frequency += self.config.search_step;
+        if self.search_frequency > max {
+            self.search_frequency = min;
+        }
+        self.frequency = self.search_frequency;
+    }
+
+    // Run the servo around the frequency at which the beat note was captured.
+    fn track(&mut self, beat: f32) -> LockState {
+        self.frequency = self.search_frequency + self.servo.update(beat);
+
+        let error = self.servo.setpoint - beat;
+        if error > -self.config.lock_threshold
+            && error < self.config.lock_threshold
+        {
+            self.settled_cycles = self.settled_cycles.saturating_add(1);
+        } else {
+            self.settled_cycles = 0;
+        }
+
+        if self.settled_cycles >= self.config.lock_cycles {
+            LockState::Locked
+        } else {
+            LockState::Engaging
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pi_step_response() {
+        let mut servo = PiController::new(0.5, 0.1, 100., (-1e3, 1e3));
+
+        // A constant error of 10 Hz: the proportional term is constant and the integrator grows
+        // linearly.
+        assert_eq!(servo.update(90.), 6.);
+        assert_eq!(servo.update(90.), 7.);
+        assert_eq!(servo.update(90.), 8.);
+        assert_eq!(servo.integrator, 3.);
+
+        // Without an error, only the integrator remains.
+        assert_eq!(servo.update(100.), 3.);
+
+        servo.reset();
+        assert_eq!(servo.update(100.), 0.);
+    }
+
+    #[test]
+    fn pi_output_saturates() {
+        let mut servo = PiController::new(1., 0., 0., (-5., 10.));
+
+        assert_eq!(servo.update(-100.), 10.);
+        assert_eq!(servo.update(100.), -5.);
+        assert_eq!(servo.update(-3.), 3.);
+    }
+
+    #[test]
+    fn pi_integrator_does_not_wind_up() {
+        let mut servo = PiController::new(0., 1., 0., (-10., 10.));
+
+        assert_eq!(servo.update(-4.), 4.);
+        assert_eq!(servo.update(-4.), 8.);
+        for _ in 0..100 {
+            assert_eq!(servo.update(-4.), 10.);
+        }
+        // The integrator stopped at the last unsaturated output.
+        assert_eq!(servo.integrator, 8.);
+
+        // The output leaves saturation as soon as the error reverses.
+        assert_eq!(servo.update(4.), 4.);
+    }
+
+    #[test]
+    fn pi_tare_zeroes_error() {
+        let mut servo = PiController::new(1., 1., 0., (-10., 10.));
+        servo.update(-2.);
+
+        servo.tare(42.);
+        assert_eq!(servo.setpoint, 42.);
+        assert_eq!(servo.update(42.), 0.);
+    }
+
+    const CONFIG: LockConfig = LockConfig {
+        search_range: (0., 100.),
+        search_step: 10.,
+        capture_range: 5.,
+        lock_threshold: 1.,
+        lock_cycles: 3,
+    };
+
+    fn controller() -> LockController {
+        LockController::new(CONFIG, PiController::new(0., 0., 50., (-5., 5.)))
+    }
+
+    #[test]
+    fn lock_searches_without_beat() {
+        let mut lock = controller();
+        assert_eq!(lock.state(), LockState::Search);
+        assert_eq!(lock.frequency(), 0.);
+
+        for step in 1..=10 {
+            assert_eq!(lock.update(f32::NAN), LockState::Search);
+            assert_eq!(lock.frequency(), step as f32 * 10.);
+        }
+
+        // The sweep wraps around at the upper limit.
+        assert_eq!(lock.update(f32::NAN), LockState::Search);
+        assert_eq!(lock.frequency(), 0.);
+    }
+
+    #[test]
+    fn lock_search_engage_lock_unlock() {
+        let mut lock = controller();
+
+        // The beat note is outside of the capture range.
+        assert_eq!(lock.update(20.), LockState::Search);
+        assert_eq!(lock.update(60.), LockState::Search);
+        let captured = lock.frequency();
+
+        // Captured, but the error has not settled.
+        assert_eq!(lock.update(53.), LockState::Engaging);
+        assert_eq!(lock.frequency(), captured);
+
+        // The lock is declared after settling for the configured number of updates.
+        assert_eq!(lock.update(50.5), LockState::Engaging);
+        assert_eq!(lock.update(49.5), LockState::Engaging);
+        assert_eq!(lock.update(50.), LockState::Locked);
+        assert_eq!(lock.update(50.), LockState::Locked);
+
+        // An excursion within the capture range drops back to engaging.
+        assert_eq!(lock.update(52.), LockState::Engaging);
+        assert_eq!(lock.update(50.), LockState::Engaging);
+
+        // Losing the beat note reports the loss and resumes the search.
+        assert_eq!(lock.update(f32::NAN), LockState::Unlocked);
+        assert_eq!(lock.frequency(), captured);
+        assert_eq!(lock.update(f32::NAN), LockState::Search);
+        assert_eq!(lock.frequency(), captured + 10.);
+    }
+
+    #[test]
+    fn lock_recaptures_after_unlock() {
+        let mut lock = controller();
+        lock.update(50.);
+        assert_eq!(lock.update(80.), LockState::Unlocked);
+        assert_eq!(lock.update(51.), LockState::Engaging);
+    }
+
+    #[test]
+    fn lock_applies_servo_correction() {
+        let mut lock = LockController::new(
+            CONFIG,
+            PiController::new(1., 0., 50., (-2., 2.)),
+        );
+
+        lock.update(f32::NAN);
+        assert_eq!(lock.update(49.), LockState::Engaging);
+        assert_eq!(lock.frequency(), 11.);
+
+        // The correction is limited around the captured frequency.
+        lock.update(46.);
+        assert_eq!(lock.frequency(), 12.);
+    }
+
+    #[test]
+    fn lock_restart() {
+        let mut lock = controller();
+        lock.update(f32::NAN);
+        lock.update(50.);
+
+        lock.restart();
+        assert_eq!(lock.state(), LockState::Search);
+        assert_eq!(lock.frequency(), 0.);
+    }
+}
@@ -0,0 +1,66 @@
+//! Firmware build information
+//!
+//! # Design
+//! The build information is captured at compile time without a build script, so that it does not
+//! depend on the time or the state of the build machine. The crate version is provided by cargo.
+//! The git version is taken from the `GIT_VERSION` environment variable at build time, e.g.
+//! `GIT_VERSION=$(git describe --always --dirty) cargo build`, and is absent if it is not set.
+use serde::Serialize;
+
+/// Information identifying the firmware build.
+#[derive(Copy, Clone, Debug, Serialize)]
+pub struct VersionInfo {
+    /// The crate version.
+    pub version: &'static str,
+
+    /// The git version of the built source tree, if provided at build time.
+    pub git_version: Option<&'static str>,
+}
+
+impl VersionInfo {
+    /// The information of the running firmware.
+    pub const BUILD: Self = Self {
+        version: env!("CARGO_PKG_VERSION"),
+        git_version: option_env!("GIT_VERSION"),
+    };
+}
+
+impl core::fmt::Display for VersionInfo {
+    /// Format the information as a single line boot banner.
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "Stabilizer {}", self.version)?;
+        if let Some(git_version) = self.git_version {
+            write!(f, " ({git_version})")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::fmt::Write;
+
+    #[test]
+    fn build_information_is_populated() {
+        assert_eq!(VersionInfo::BUILD.version, env!("CARGO_PKG_VERSION"));
+        assert!(!VersionInfo::BUILD.version.is_empty());
+        assert_eq!(VersionInfo::BUILD.git_version, option_env!("GIT_VERSION"));
+    }
+
+    #[test]
+    fn display_with_and_without_git_version() {
+        let mut info = VersionInfo {
+            version: "1.2.3",
+            git_version: Some("v1.2.3-4-gabcdef0-dirty"),
+        };
+        let mut banner: heapless::String<64> = heapless::String::new();
+        write!(banner, "{info}").unwrap();
+        assert_eq!(banner, "Stabilizer 1.2.3 (v1.2.3-4-gabcdef0-dirty)");
+
+        info.git_version = None;
+        banner.clear();
+        write!(banner, "{info}").unwrap();
+        assert_eq!(banner, "Stabilizer 1.2.3");
+    }
+}